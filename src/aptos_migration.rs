@@ -0,0 +1,205 @@
+//! Migration of the storage of an upstream Aptos Jellyfish Merkle tree to this crate's format.
+//!
+//! Aptos and this crate store the same nodes under the same [`NodeKey`] encoding, and Aptos
+//! encodes internal nodes, including their leaf counts, in this crate's
+//! [unversioned format](crate::storage::NodeFormat::Unversioned). They differ in leaves: an Aptos leaf holds
+//! its key hash and value hash as length-prefixed BCS byte strings, followed by the BCS encoding of
+//! the state key it stands for and the version of its value, which Aptos stores in a separate
+//! table keyed by state key. Here, a leaf only holds its two hashes, and values are stored by key
+//! hash and version.
+//!
+//! An [`AptosMigration`] reads the nodes of an Aptos tree through an [`AptosTreeReader`], and
+//! writes them, along with the values of their leaves, to a [`TreeWriter`], one version at a time.
+//! Each migrated node is hashed with the hasher of the new tree, and checked against the hash
+//! recorded for it by its parent, so that a migration either reproduces the upstream tree or
+//! fails. The hasher must therefore hash nodes and values the way the Aptos release does, e.g. a
+//! [`Personalized`](crate::Personalized) SHA3-256 with the seeds of
+//! `compat::AptosHasher` and the value seed of the release.
+
+use core::marker::PhantomData;
+
+use anyhow::{ensure, format_err, Result};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{NodeBatch, TreeWriter},
+    KeyHash, OwnedValue, RootHash, SimpleHasher, ValueHash, Version,
+};
+
+/// The tag of leaves in the Aptos node encoding.
+const APTOS_LEAF_TAG: u8 = 2;
+
+/// The number of nodes after which a snapshot migration writes the nodes it has migrated.
+const SNAPSHOT_WRITE_BATCH_SIZE: usize = 10_000;
+
+/// Read access to the storage of an upstream Aptos Jellyfish Merkle tree.
+pub trait AptosTreeReader {
+    /// Returns the Aptos encoding of the node stored under `encoded_node_key`, the Aptos encoding
+    /// of its node key, which is also the one of [`NodeKey::encode`].
+    fn get_aptos_node_option(&self, encoded_node_key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Returns the value of the state key whose BCS encoding is `encoded_state_key`, as written at
+    /// `version`.
+    fn get_aptos_value_option(
+        &self,
+        encoded_state_key: &[u8],
+        version: Version,
+    ) -> Result<Option<OwnedValue>>;
+}
+
+/// Copies the nodes of an Aptos tree to the storage of a tree using the hasher `H`.
+pub struct AptosMigration<'a, S, W, H> {
+    source: &'a S,
+    target: &'a W,
+    _hasher: PhantomData<H>,
+}
+
+impl<'a, S, W, H> AptosMigration<'a, S, W, H>
+where
+    S: AptosTreeReader,
+    W: TreeWriter,
+    H: SimpleHasher,
+{
+    /// Creates a migration from `source` to `target`.
+    pub fn new(source: &'a S, target: &'a W) -> Self {
+        Self {
+            source,
+            target,
+            _hasher: PhantomData,
+        }
+    }
+
+    /// Migrates the nodes written at `version`, and returns the root hash of the tree at that
+    /// version.
+    ///
+    /// Nodes written at earlier versions are not read again, so they must already have been
+    /// migrated, either version by version or by [`migrate_snapshot`](Self::migrate_snapshot).
+    pub fn migrate_version(&self, version: Version) -> Result<RootHash> {
+        let mut batch = NodeBatch::default();
+        let root_hash =
+            self.migrate_node(&NodeKey::new_empty_path(version), version, &mut batch)?;
+        self.target.write_node_batch(&batch)?;
+        Ok(RootHash(root_hash))
+    }
+
+    /// Migrates all the nodes of the tree at `version`, whatever their version, and returns its
+    /// root hash. This starts a migration from a snapshot, the following versions being then
+    /// migrated with [`migrate_version`](Self::migrate_version).
+    ///
+    /// Nodes are written in several batches, so the target holds part of the tree if the migration
+    /// fails.
+    pub fn migrate_snapshot(&self, version: Version) -> Result<RootHash> {
+        let mut batch = NodeBatch::default();
+        let root_hash = self.migrate_subtree(&NodeKey::new_empty_path(version), &mut batch)?;
+        self.target.write_node_batch(&batch)?;
+        Ok(RootHash(root_hash))
+    }
+
+    /// Migrates the node under `node_key` and its descendants written at `version`, and returns its
+    /// hash.
+    fn migrate_node(
+        &self,
+        node_key: &NodeKey,
+        version: Version,
+        batch: &mut NodeBatch,
+    ) -> Result<[u8; 32]> {
+        let node = self.read_node(node_key, batch)?;
+        if let Node::Internal(internal_node) = &node {
+            for (nibble, child) in internal_node.children_sorted() {
+                if child.version == version {
+                    let child_key = node_key.gen_child_node_key(child.version, nibble);
+                    let child_hash = self.migrate_node(&child_key, version, batch)?;
+                    check_child_hash(&child_key, child_hash, child.hash)?;
+                }
+            }
+        }
+        let hash = node.hash::<H>();
+        batch.insert_node(node_key.clone(), node);
+        Ok(hash)
+    }
+
+    /// Migrates the node under `node_key` and all its descendants, and returns its hash.
+    fn migrate_subtree(&self, node_key: &NodeKey, batch: &mut NodeBatch) -> Result<[u8; 32]> {
+        let node = self.read_node(node_key, batch)?;
+        if let Node::Internal(internal_node) = &node {
+            for (nibble, child) in internal_node.children_sorted() {
+                let child_key = node_key.gen_child_node_key(child.version, nibble);
+                let child_hash = self.migrate_subtree(&child_key, batch)?;
+                check_child_hash(&child_key, child_hash, child.hash)?;
+            }
+        }
+        let hash = node.hash::<H>();
+        batch.insert_node(node_key.clone(), node);
+        if batch.nodes().len() >= SNAPSHOT_WRITE_BATCH_SIZE {
+            self.target.write_node_batch(batch)?;
+            batch.clear();
+        }
+        Ok(hash)
+    }
+
+    /// Reads and converts the node under `node_key`, adding the value of a leaf to `batch`.
+    fn read_node(&self, node_key: &NodeKey, batch: &mut NodeBatch) -> Result<Node> {
+        let encoded_node = self
+            .source
+            .get_aptos_node_option(&node_key.encode()?)?
+            .ok_or_else(|| format_err!("Missing node {:?} in the Aptos tree.", node_key))?;
+        if encoded_node.first() != Some(&APTOS_LEAF_TAG) {
+            return Node::decode(&encoded_node);
+        }
+
+        let (leaf, encoded_state_key, value_version) = decode_aptos_leaf(&encoded_node[1..])?;
+        let value = self
+            .source
+            .get_aptos_value_option(encoded_state_key, value_version)?
+            .ok_or_else(|| {
+                format_err!(
+                    "Missing value of leaf {:?} at version {} in the Aptos tree.",
+                    node_key,
+                    value_version
+                )
+            })?;
+        ensure!(
+            ValueHash::with::<H>(&value) == leaf.value_hash(),
+            "The value of leaf {:?} does not hash to the value hash of the leaf.",
+            node_key
+        );
+        batch.insert_value(value_version, leaf.key_hash(), value);
+        Ok(Node::Leaf(leaf))
+    }
+}
+
+fn check_child_hash(child_key: &NodeKey, hash: [u8; 32], recorded_hash: [u8; 32]) -> Result<()> {
+    ensure!(
+        hash == recorded_hash,
+        "Node {:?} hashes to {} in the new tree, but to {} in the Aptos tree.",
+        child_key,
+        hex::encode(hash),
+        hex::encode(recorded_hash)
+    );
+    Ok(())
+}
+
+/// Decodes an Aptos leaf, without its tag, into a leaf of this crate, the encoding of its state
+/// key and the version of its value.
+fn decode_aptos_leaf(data: &[u8]) -> Result<(LeafNode, &[u8], Version)> {
+    let (key_hash, data) = decode_hash_value(data)?;
+    let (value_hash, data) = decode_hash_value(data)?;
+    ensure!(
+        data.len() > 8,
+        "An Aptos leaf ends with a state key and a version."
+    );
+    let (encoded_state_key, version) = data.split_at(data.len() - 8);
+    let version = u64::from_le_bytes(version.try_into().expect("The version has 8 bytes."));
+    let leaf = LeafNode::new(KeyHash(key_hash), ValueHash(value_hash));
+    Ok((leaf, encoded_state_key, version))
+}
+
+/// Decodes a BCS-encoded Aptos `HashValue`, which is a byte string of length 32.
+fn decode_hash_value(data: &[u8]) -> Result<([u8; 32], &[u8])> {
+    ensure!(
+        data.len() > 32 && data[0] == 32,
+        "Expected a 32-byte hash in an Aptos leaf."
+    );
+    let hash = data[1..33].try_into().expect("The hash has 32 bytes.");
+    Ok((hash, &data[33..]))
+}
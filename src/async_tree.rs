@@ -0,0 +1,356 @@
+//! This module implements [`AsyncJellyfishMerkleTree`], an asynchronous front-end to
+//! [`JellyfishMerkleTree`] for storage which can only be read asynchronously.
+//!
+//! Rather than duplicating the tree algorithms, every operation first fetches the nodes it is
+//! expected to touch from the [`AsyncTreeReader`], and then runs the synchronous algorithm against
+//! those prefetched nodes. Should the algorithm need a node or value that was not prefetched (for
+//! example a sibling leaf that moves up after a deletion), it is fetched and the operation is
+//! retried.
+
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{AsyncTreeReader, TreeReader, TreeUpdateBatch},
+    types::{
+        nibble::ROOT_NIBBLE_HEIGHT,
+        proof::{SparseMerkleMultiProof, SparseMerkleProof},
+        Version, PRE_GENESIS_VERSION,
+    },
+    Bytes32Ext, JellyfishMerkleTree, KeyHash, OwnedValue, PhantomHasher, RootHash, SimpleHasher,
+};
+
+/// An asynchronous Jellyfish Merkle tree, parameterized by an [`AsyncTreeReader`] `R` and a
+/// [`SimpleHasher`] `H`. It offers the same operations as [`JellyfishMerkleTree`], but awaits
+/// storage reads instead of blocking on them.
+pub struct AsyncJellyfishMerkleTree<'a, R, H: SimpleHasher> {
+    reader: &'a R,
+    _phantom_hasher: PhantomHasher<H>,
+}
+
+impl<'a, R, H> AsyncJellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + AsyncTreeReader,
+    H: SimpleHasher,
+{
+    /// Creates an `AsyncJellyfishMerkleTree` backed by the given [`AsyncTreeReader`].
+    pub fn new(reader: &'a R) -> Self {
+        Self {
+            reader,
+            _phantom_hasher: Default::default(),
+        }
+    }
+
+    /// Returns the value (if applicable) and the corresponding merkle proof.
+    ///
+    /// See [`JellyfishMerkleTree::get_with_proof`].
+    pub async fn get_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let mut prefetched = PrefetchedReader::default();
+        self.prefetch_paths(&mut prefetched, version, &[key], false, Some(version))
+            .await?;
+        self.run(prefetched, |tree| tree.get_with_proof(key, version))
+            .await
+    }
+
+    /// Returns the merkle proof of `key` at `version`, without reading its value.
+    ///
+    /// See [`JellyfishMerkleTree::get_proof`].
+    pub async fn get_proof(&self, key: KeyHash, version: Version) -> Result<SparseMerkleProof<H>> {
+        let mut prefetched = PrefetchedReader::default();
+        self.prefetch_paths(&mut prefetched, version, &[key], false, None)
+            .await?;
+        self.run(prefetched, |tree| tree.get_proof(key, version))
+            .await
+    }
+
+    /// Returns the values (if applicable) of all `keys` with their merkle proofs.
+    ///
+    /// See [`JellyfishMerkleTree::multi_get_with_proof`].
+    #[allow(clippy::type_complexity)]
+    pub async fn multi_get_with_proof(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<Vec<(Option<OwnedValue>, SparseMerkleProof<H>)>> {
+        let mut prefetched = PrefetchedReader::default();
+        self.prefetch_paths(&mut prefetched, version, keys, false, Some(version))
+            .await?;
+        self.run(prefetched, |tree| tree.multi_get_with_proof(keys, version))
+            .await
+    }
+
+    /// Returns the values (if applicable) of all `keys` and a single multiproof for all of them.
+    ///
+    /// See [`JellyfishMerkleTree::get_with_multiproof`].
+    pub async fn get_with_multiproof(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<(Vec<Option<OwnedValue>>, SparseMerkleMultiProof<H>)> {
+        let mut prefetched = PrefetchedReader::default();
+        self.prefetch_paths(&mut prefetched, version, keys, false, Some(version))
+            .await?;
+        self.run(prefetched, |tree| tree.get_with_multiproof(keys, version))
+            .await
+    }
+
+    /// Returns the values (if applicable) of all `keys`, in the same order, without any proof.
+    ///
+    /// See [`JellyfishMerkleTree::multi_get`].
+    pub async fn multi_get(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<Vec<Option<OwnedValue>>> {
+        let mut prefetched = PrefetchedReader::default();
+        self.prefetch_paths(&mut prefetched, version, keys, false, Some(version))
+            .await?;
+        self.run(prefetched, |tree| tree.multi_get(keys, version))
+            .await
+    }
+
+    /// Returns the value (if applicable), without any proof.
+    pub async fn get(&self, key: KeyHash, version: Version) -> Result<Option<OwnedValue>> {
+        self.reader.get_value_option(version, key).await
+    }
+
+    /// Returns the root hash of the tree at `version`.
+    pub async fn get_root_hash(&self, version: Version) -> Result<RootHash> {
+        if let Some(root_hash) = self.reader.get_indexed_root_hash(version).await? {
+            return Ok(root_hash);
+        }
+        let root_node = self
+            .reader
+            .get_node(&NodeKey::new_empty_path(version))
+            .await?;
+        Ok(RootHash(root_node.hash::<H>()))
+    }
+
+    /// Returns the root hash of the tree at `version`, or `None` if there is no such root.
+    pub async fn get_root_hash_option(&self, version: Version) -> Result<Option<RootHash>> {
+        if let Some(root_hash) = self.reader.get_indexed_root_hash(version).await? {
+            return Ok(Some(root_hash));
+        }
+        let root_node = self
+            .reader
+            .get_node_option(&NodeKey::new_empty_path(version))
+            .await?;
+        Ok(root_node.map(|n| RootHash(n.hash::<H>())))
+    }
+
+    /// This is a convenient function that calls
+    /// [`put_value_sets`](AsyncJellyfishMerkleTree::put_value_sets) with a single `value_set`.
+    pub async fn put_value_set(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let (root_hashes, tree_update_batch) =
+            self.put_value_sets(vec![value_set], version).await?;
+        assert_eq!(
+            root_hashes.len(),
+            1,
+            "root_hashes must consist of a single value.",
+        );
+        Ok((root_hashes[0], tree_update_batch))
+    }
+
+    /// Returns the new nodes and values in a batch after applying `value_sets`, one version after
+    /// the other starting at `first_version`.
+    ///
+    /// See [`JellyfishMerkleTree::put_value_sets`].
+    pub async fn put_value_sets(
+        &self,
+        value_sets: impl IntoIterator<Item = impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>>,
+        first_version: Version,
+    ) -> Result<(Vec<RootHash>, TreeUpdateBatch)> {
+        let value_sets: Vec<Vec<(KeyHash, Option<OwnedValue>)>> = value_sets
+            .into_iter()
+            .map(|value_set| value_set.into_iter().collect())
+            .collect();
+
+        // All the updates are applied on top of the tree at the version preceding the batch.
+        let base_version = first_version.checked_sub(1).unwrap_or(PRE_GENESIS_VERSION);
+        let keys: Vec<KeyHash> = value_sets
+            .iter()
+            .flatten()
+            .map(|(key, _)| *key)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let has_deletions = value_sets
+            .iter()
+            .flatten()
+            .any(|(_, value)| value.is_none());
+        let mut prefetched = PrefetchedReader::default();
+        self.prefetch_paths(&mut prefetched, base_version, &keys, has_deletions, None)
+            .await?;
+
+        self.run(prefetched, |tree| {
+            tree.put_value_sets(value_sets.clone(), first_version)
+        })
+        .await
+    }
+
+    /// Fetches all the nodes on the paths of `keys` in the tree at `version`. If `sibling_leaves`
+    /// is set, also fetches the leaves hanging off those paths, which are moved up when a key is
+    /// deleted. If `value_version` is given, also fetches the values of the keys found in the tree.
+    async fn prefetch_paths(
+        &self,
+        prefetched: &mut PrefetchedReader,
+        version: Version,
+        keys: &[KeyHash],
+        sibling_leaves: bool,
+        value_version: Option<Version>,
+    ) -> Result<()> {
+        // The paths are walked down breadth first, so that every node is fetched only once.
+        let mut level = BTreeMap::new();
+        level.insert(NodeKey::new_empty_path(version), keys.to_vec());
+        for depth in 0..=ROOT_NIBBLE_HEIGHT {
+            if level.is_empty() {
+                break;
+            }
+            let mut next_level = BTreeMap::<NodeKey, Vec<KeyHash>>::new();
+            for (node_key, keys) in level {
+                let node = self.fetch_node(prefetched, &node_key).await?;
+                match node {
+                    Some(Node::Internal(internal_node)) => {
+                        for (nibble, child) in internal_node.children_sorted() {
+                            let child_key = node_key.gen_child_node_key(child.version, nibble);
+                            let child_keys: Vec<KeyHash> = keys
+                                .iter()
+                                .filter(|key| key.0.get_nibble(depth) == nibble)
+                                .copied()
+                                .collect();
+                            if !child_keys.is_empty() {
+                                next_level.insert(child_key, child_keys);
+                            } else if sibling_leaves && child.is_leaf() {
+                                self.fetch_node(prefetched, &child_key).await?;
+                            }
+                        }
+                    }
+                    Some(Node::Leaf(leaf_node)) => {
+                        if let Some(value_version) = value_version {
+                            if keys.contains(&leaf_node.key_hash()) {
+                                self.fetch_value(prefetched, value_version, leaf_node.key_hash())
+                                    .await?;
+                            }
+                        }
+                    }
+                    Some(Node::Null) | None => {}
+                }
+            }
+            level = next_level;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` against a synchronous tree backed by `prefetched`, fetching whatever else it needs
+    /// and retrying until it doesn't miss anything anymore.
+    async fn run<T>(
+        &self,
+        mut prefetched: PrefetchedReader,
+        f: impl Fn(&JellyfishMerkleTree<PrefetchedReader, H>) -> Result<T>,
+    ) -> Result<T> {
+        loop {
+            let result = f(&JellyfishMerkleTree::new(&prefetched));
+            let (missing_nodes, missing_values) = prefetched.take_missing();
+            if missing_nodes.is_empty() && missing_values.is_empty() {
+                return result;
+            }
+            for node_key in missing_nodes {
+                self.fetch_node(&mut prefetched, &node_key).await?;
+            }
+            for (version, key_hash) in missing_values {
+                self.fetch_value(&mut prefetched, version, key_hash).await?;
+            }
+        }
+    }
+
+    async fn fetch_node(
+        &self,
+        prefetched: &mut PrefetchedReader,
+        node_key: &NodeKey,
+    ) -> Result<Option<Node>> {
+        if let Some(node) = prefetched.nodes.get(node_key) {
+            return Ok(node.clone());
+        }
+        let node = self.reader.get_node_option(node_key).await?;
+        prefetched.nodes.insert(node_key.clone(), node.clone());
+        Ok(node)
+    }
+
+    async fn fetch_value(
+        &self,
+        prefetched: &mut PrefetchedReader,
+        version: Version,
+        key_hash: KeyHash,
+    ) -> Result<()> {
+        if let Entry::Vacant(entry) = prefetched.values.entry((version, key_hash)) {
+            entry.insert(self.reader.get_value_option(version, key_hash).await?);
+        }
+        Ok(())
+    }
+}
+
+/// A [`TreeReader`] serving nodes and values that have already been fetched from an
+/// [`AsyncTreeReader`], and recording the ones that have not.
+#[derive(Default)]
+struct PrefetchedReader {
+    nodes: HashMap<NodeKey, Option<Node>>,
+    values: HashMap<(Version, KeyHash), Option<OwnedValue>>,
+    missing_nodes: RefCell<BTreeSet<NodeKey>>,
+    missing_values: RefCell<BTreeSet<(Version, KeyHash)>>,
+}
+
+impl PrefetchedReader {
+    /// Returns and clears the nodes and values that were requested but not available.
+    fn take_missing(&mut self) -> (BTreeSet<NodeKey>, BTreeSet<(Version, KeyHash)>) {
+        (self.missing_nodes.take(), self.missing_values.take())
+    }
+}
+
+impl TreeReader for PrefetchedReader {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.nodes.get(node_key) {
+            Some(node) => Ok(node.clone()),
+            None => {
+                self.missing_nodes.borrow_mut().insert(node_key.clone());
+                bail!("Node {:?} has not been fetched yet.", node_key)
+            }
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        match self.values.get(&(max_version, key_hash)) {
+            Some(value) => Ok(value.clone()),
+            None => {
+                self.missing_values
+                    .borrow_mut()
+                    .insert((max_version, key_hash));
+                bail!(
+                    "Value with max_version {} and key hash {:?} has not been fetched yet.",
+                    max_version,
+                    key_hash
+                )
+            }
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        bail!("Getting the rightmost leaf is not supported by the asynchronous tree.")
+    }
+}
@@ -0,0 +1,160 @@
+//! A [`Pruner`] draining stale nodes continuously on a tokio runtime, with a budget of deletions
+//! per second.
+//!
+//! Pruning a large backlog of stale nodes at once saturates the disk, and slows down the commits
+//! of new versions running at the same time. A [`BackgroundPruner`] instead runs as a task in the
+//! background, and deletes stale nodes in small batches, sleeping between them so as to stay
+//! within [`BackgroundPrunerConfig::max_deletes_per_second`]. The version to prune up to is raised
+//! with [`BackgroundPruner::set_least_readable_version`], typically after every commit.
+//!
+//! The storage is accessed synchronously, on tokio's blocking thread pool.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{format_err, Result};
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+
+use crate::{
+    pruner::{Pruner, PruningStats, StaleNodeIndexStore, VersionPins},
+    storage::TreeReader,
+    types::Version,
+};
+
+/// The settings of a [`BackgroundPruner`].
+#[derive(Clone, Debug)]
+pub struct BackgroundPrunerConfig {
+    /// The number of stale nodes deleted at once.
+    pub batch_size: usize,
+    /// The maximal number of stale nodes deleted per second, or `None` to delete them as fast as
+    /// possible.
+    pub max_deletes_per_second: Option<u64>,
+}
+
+impl Default for BackgroundPrunerConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1_000,
+            max_deletes_per_second: None,
+        }
+    }
+}
+
+/// A handle to a task pruning a store in the background, see the [module](self) documentation.
+///
+/// The task stops once the handle is shut down with [`shutdown`](BackgroundPruner::shutdown) or
+/// dropped, or when pruning fails.
+pub struct BackgroundPruner {
+    least_readable_version: watch::Sender<Option<Version>>,
+    task: JoinHandle<Result<PruningStats>>,
+}
+
+impl BackgroundPruner {
+    /// Spawns a task pruning `store` with `config` on the current tokio runtime. Nothing is pruned
+    /// until a version is given to [`set_least_readable_version`](Self::set_least_readable_version).
+    ///
+    /// If `version_pins` are given, nothing is pruned past the oldest pinned version, as with
+    /// [`Pruner::with_version_pins`].
+    pub fn spawn<S>(
+        store: Arc<S>,
+        config: BackgroundPrunerConfig,
+        version_pins: Option<VersionPins>,
+    ) -> Self
+    where
+        S: TreeReader + StaleNodeIndexStore + Send + Sync + 'static,
+    {
+        assert!(config.batch_size > 0, "The batch size must be positive.");
+        assert!(
+            config.max_deletes_per_second != Some(0),
+            "The deletion budget must be positive."
+        );
+        let (least_readable_version, receiver) = watch::channel(None);
+        let task = tokio::spawn(run(store, config, version_pins, receiver));
+        Self {
+            least_readable_version,
+            task,
+        }
+    }
+
+    /// Prunes all the nodes which became stale at or before `least_readable_version`, keeping the
+    /// tree readable at `least_readable_version` and every later version. Lower versions than the
+    /// current one are ignored, while the current one resumes pruning, e.g. once a version that
+    /// held it back was unpinned.
+    pub fn set_least_readable_version(&self, least_readable_version: Version) {
+        self.least_readable_version.send_if_modified(|current| {
+            let modified = current.is_none_or(|current| least_readable_version >= current);
+            if modified {
+                *current = Some(least_readable_version);
+            }
+            modified
+        });
+    }
+
+    /// Returns true if the task stopped, because pruning failed.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops the task once its current batch is deleted, and returns what it reclaimed overall,
+    /// or the error it stopped on.
+    pub async fn shutdown(self) -> Result<PruningStats> {
+        drop(self.least_readable_version);
+        self.task
+            .await
+            .map_err(|error| format_err!("The background pruner panicked: {}", error))?
+    }
+}
+
+async fn run<S>(
+    store: Arc<S>,
+    config: BackgroundPrunerConfig,
+    version_pins: Option<VersionPins>,
+    mut least_readable_version: watch::Receiver<Option<Version>>,
+) -> Result<PruningStats>
+where
+    S: TreeReader + StaleNodeIndexStore + Send + Sync + 'static,
+{
+    let mut stats = PruningStats::default();
+    // The time before which no more deletions fit in the budget.
+    let mut next_deletion = Instant::now();
+    loop {
+        // Wait for a new version to prune up to, or for the handle to be dropped.
+        if least_readable_version.changed().await.is_err() {
+            return Ok(stats);
+        }
+        loop {
+            let Some(target) = *least_readable_version.borrow_and_update() else {
+                break;
+            };
+            let store = store.clone();
+            let version_pins = version_pins.clone();
+            let batch_size = config.batch_size;
+            let started = Instant::now();
+            let (batch_stats, more) = tokio::task::spawn_blocking(move || {
+                let mut pruner = Pruner::new(&*store).with_batch_size(batch_size);
+                if let Some(version_pins) = &version_pins {
+                    pruner = pruner.with_version_pins(version_pins);
+                }
+                pruner.prune_next_batch(target)
+            })
+            .await
+            .map_err(|error| format_err!("Pruning a batch panicked: {}", error))??;
+
+            if let Some(max_deletes_per_second) = config.max_deletes_per_second {
+                let budget = Duration::from_secs_f64(
+                    batch_stats.pruned_nodes as f64 / max_deletes_per_second as f64,
+                );
+                next_deletion = next_deletion.max(started) + budget;
+                tokio::time::sleep_until(next_deletion).await;
+            }
+            stats += batch_stats;
+
+            // Stop between batches once the handle is dropped.
+            if least_readable_version.has_changed().is_err() {
+                return Ok(stats);
+            }
+            if !more {
+                break;
+            }
+        }
+    }
+}
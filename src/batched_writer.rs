@@ -0,0 +1,146 @@
+//! A [`TreeWriter`] splitting writes into bounded batches.
+//!
+//! Some key-value stores limit the size of a single write batch, while the
+//! [`TreeUpdateBatch`](crate::storage::TreeUpdateBatch) of a genesis import or of a large
+//! restoration can hold millions of nodes. A [`BatchedWriter`] accumulates the nodes and values
+//! written through it, and writes them to the underlying [`TreeWriter`] whenever they reach a
+//! number of items or of bytes, so that no write exceeds these limits.
+//!
+//! Writes are no longer atomic as a whole: after a crash, the underlying storage may hold part of
+//! a batch. This suits imports which are restarted from scratch, or resumed with
+//! [`JellyfishMerkleRestore`](crate::restore::JellyfishMerkleRestore), when interrupted.
+
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{format_err, Result};
+use bytes::Bytes;
+
+use crate::storage::{NodeBatch, TreeWriter};
+
+/// The default maximum number of nodes and values written at once by a [`BatchedWriter`].
+pub const DEFAULT_MAX_BATCH_ITEMS: usize = 10_000;
+/// The default maximum number of bytes written at once by a [`BatchedWriter`].
+pub const DEFAULT_MAX_BATCH_BYTES: usize = 64 << 20;
+
+/// A [`TreeWriter`] which buffers the nodes and values written through it, and writes them to
+/// another [`TreeWriter`] in batches of bounded size.
+///
+/// Writes are buffered until a batch is full, so [`flush`](BatchedWriter::flush) must be called
+/// once everything has been written.
+pub struct BatchedWriter<W> {
+    writer: W,
+    max_items: usize,
+    max_bytes: usize,
+    pending: Mutex<PendingBatch>,
+}
+
+#[derive(Default)]
+struct PendingBatch {
+    node_batch: NodeBatch,
+    num_items: usize,
+    num_bytes: usize,
+}
+
+impl<W: TreeWriter> BatchedWriter<W> {
+    /// Creates a writer to `writer` with the default limits, [`DEFAULT_MAX_BATCH_ITEMS`] and
+    /// [`DEFAULT_MAX_BATCH_BYTES`].
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            max_items: DEFAULT_MAX_BATCH_ITEMS,
+            max_bytes: DEFAULT_MAX_BATCH_BYTES,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Sets the maximum number of nodes and values written at once.
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        assert!(
+            max_items > 0,
+            "The maximum number of items must be positive."
+        );
+        self.max_items = max_items;
+        self
+    }
+
+    /// Sets the maximum number of bytes written at once, counting the encoded nodes and their
+    /// keys, and the values and their keys. A single item larger than this is written alone.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        assert!(
+            max_bytes > 0,
+            "The maximum number of bytes must be positive."
+        );
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Writes all the buffered nodes and values to the underlying writer.
+    pub fn flush(&self) -> Result<()> {
+        let mut pending = self.lock()?;
+        self.flush_pending(&mut pending)
+    }
+
+    /// Returns the underlying writer.
+    pub fn inner(&self) -> &W {
+        &self.writer
+    }
+
+    /// Flushes the buffered nodes and values, and returns the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, PendingBatch>> {
+        self.pending
+            .lock()
+            .map_err(|_| format_err!("The pending batch lock is poisoned."))
+    }
+
+    fn flush_pending(&self, pending: &mut PendingBatch) -> Result<()> {
+        if pending.num_items > 0 {
+            self.writer.write_node_batch(&pending.node_batch)?;
+            *pending = PendingBatch::default();
+        }
+        Ok(())
+    }
+
+    /// Flushes the pending batch if it reached one of the limits.
+    fn flush_if_full(&self, pending: &mut PendingBatch) -> Result<()> {
+        if pending.num_items >= self.max_items || pending.num_bytes >= self.max_bytes {
+            self.flush_pending(pending)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: TreeWriter> TreeWriter for BatchedWriter<W> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut pending = self.lock()?;
+        for (node_key, node) in node_batch.nodes() {
+            let num_bytes = node_key.encode()?.len() + node.encode()?.len();
+            if pending.num_bytes + num_bytes > self.max_bytes {
+                self.flush_pending(&mut pending)?;
+            }
+            pending
+                .node_batch
+                .insert_node(node_key.clone(), node.clone());
+            pending.num_items += 1;
+            pending.num_bytes += num_bytes;
+            self.flush_if_full(&mut pending)?;
+        }
+        for ((version, key_hash), value) in node_batch.values() {
+            let num_bytes = 8 + key_hash.0.len() + value.as_ref().map_or(0, Bytes::len);
+            if pending.num_bytes + num_bytes > self.max_bytes {
+                self.flush_pending(&mut pending)?;
+            }
+            pending
+                .node_batch
+                .extend([], [((*version, *key_hash), value.clone())]);
+            pending.num_items += 1;
+            pending.num_bytes += num_bytes;
+            self.flush_if_full(&mut pending)?;
+        }
+        Ok(())
+    }
+}
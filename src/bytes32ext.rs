@@ -1,10 +1,19 @@
-use std::ops::Index;
+use core::ops::Index;
 
+#[cfg(feature = "std")]
 use mirai_annotations::*;
 
+// `mirai_annotations` needs `std`; without it, assumptions are simply not checked.
+#[cfg(not(feature = "std"))]
+macro_rules! assume {
+    ($condition:expr) => {};
+}
+
 pub trait Bytes32Ext: Index<usize> + Sized {
     /// Returns the `index`-th nibble.
     fn get_nibble(&self, index: usize) -> crate::types::nibble::Nibble;
+    /// Returns the `index`-th bit, starting from the most significant bit of the first byte.
+    fn get_bit(&self, index: usize) -> bool;
     /// Returns the length of common prefix of `self` and `other` in bits.
     fn common_prefix_bits_len(&self, other: &[u8; 32]) -> usize;
     /// Returns a `HashValueBitIterator` over all the bits that represent this hash value.
@@ -16,18 +25,24 @@ pub trait Bytes32Ext: Index<usize> + Sized {
         self.common_prefix_bits_len(other) / 4
     }
     /// Constructs a `HashValue` from an iterator of bits.
+    #[cfg(test)]
     fn from_bit_iter(iter: impl ExactSizeIterator<Item = bool>) -> Option<Self>;
 }
 
 impl Bytes32Ext for [u8; 32] {
     fn get_nibble(&self, index: usize) -> crate::types::nibble::Nibble {
-        crate::types::nibble::Nibble::from(if index % 2 == 0 {
+        crate::types::nibble::Nibble::from(if index.is_multiple_of(2) {
             self[index / 2] >> 4
         } else {
             self[index / 2] & 0x0F
         })
     }
 
+    fn get_bit(&self, index: usize) -> bool {
+        assume!(index < 32 * 8); // assumed precondition
+        self[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
     fn common_prefix_bits_len(&self, other: &[u8; 32]) -> usize {
         self.iter_bits()
             .zip(other.iter_bits())
@@ -42,11 +57,12 @@ impl Bytes32Ext for [u8; 32] {
     fn nibble(&self, index: usize) -> u8 {
         assume!(index < 32 * 2); // assumed precondition
         let pos = index / 2;
-        let shift = if index % 2 == 0 { 4 } else { 0 };
+        let shift = if index.is_multiple_of(2) { 4 } else { 0 };
         (self[pos] >> shift) & 0x0f
     }
 
     /// Constructs a `HashValue` from an iterator of bits.
+    #[cfg(test)]
     fn from_bit_iter(iter: impl ExactSizeIterator<Item = bool>) -> Option<Self> {
         if iter.len() != 256 {
             return None;
@@ -66,7 +82,7 @@ impl Bytes32Ext for [u8; 32] {
 pub struct HashValueBitIterator<'a> {
     /// The reference to the bytes that represent the `HashValue`.
     hash_bytes: &'a [u8],
-    pos: std::ops::Range<usize>,
+    pos: core::ops::Range<usize>,
     // invariant hash_bytes.len() == HashValue::LENGTH;
     // invariant pos.end == hash_bytes.len() * 8;
 }
@@ -91,7 +107,7 @@ impl<'a> HashValueBitIterator<'a> {
     }
 }
 
-impl<'a> std::iter::Iterator for HashValueBitIterator<'a> {
+impl<'a> core::iter::Iterator for HashValueBitIterator<'a> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -103,10 +119,10 @@ impl<'a> std::iter::Iterator for HashValueBitIterator<'a> {
     }
 }
 
-impl<'a> std::iter::DoubleEndedIterator for HashValueBitIterator<'a> {
+impl<'a> core::iter::DoubleEndedIterator for HashValueBitIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
         self.pos.next_back().map(|x| self.get_bit(x))
     }
 }
 
-impl<'a> std::iter::ExactSizeIterator for HashValueBitIterator<'a> {}
+impl<'a> core::iter::ExactSizeIterator for HashValueBitIterator<'a> {}
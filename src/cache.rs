@@ -0,0 +1,155 @@
+//! An in-memory cache of nodes in front of a [`TreeReader`].
+//!
+//! Every read of the tree walks down from the root, so the internal nodes near the root of recent
+//! versions are read again and again. [`CachedTreeReader`] keeps the most recently used nodes in
+//! memory, so that only the nodes missing from the cache are read from the underlying storage.
+//!
+//! Nodes are never modified once written, so cached nodes cannot become outdated. Nodes which
+//! were pruned from the underlying storage may still be read from the cache until they are
+//! evicted.
+
+use std::{num::NonZeroUsize, ops::Range, sync::Mutex};
+
+use anyhow::{format_err, Result};
+use lru::LruCache;
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    reader::read_missing_nodes,
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeWriter},
+    KeyHash, OwnedValue, RootHash, Version,
+};
+
+/// A [`TreeReader`] caching up to a given number of nodes read from another [`TreeReader`],
+/// evicting the least recently used ones first.
+///
+/// When the underlying storage is also a [`TreeWriter`], nodes written through the cache are
+/// cached as well, since the nodes of the latest version are the most likely to be read next.
+pub struct CachedTreeReader<R> {
+    reader: R,
+    cache: Mutex<LruCache<NodeKey, Node>>,
+}
+
+impl<R> CachedTreeReader<R> {
+    /// Creates a cache of up to `capacity` nodes in front of `reader`.
+    pub fn new(reader: R, capacity: NonZeroUsize) -> Self {
+        Self {
+            reader,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn inner(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the underlying reader, dropping the cache.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the number of nodes currently cached.
+    pub fn len(&self) -> usize {
+        self.lock().map_or(0, |cache| cache.len())
+    }
+
+    /// Returns true if no node is currently cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Evicts all the cached nodes.
+    pub fn clear(&self) -> Result<()> {
+        self.lock()?.clear();
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, LruCache<NodeKey, Node>>> {
+        self.cache
+            .lock()
+            .map_err(|_| format_err!("The node cache lock is poisoned."))
+    }
+}
+
+impl<R: TreeReader> TreeReader for CachedTreeReader<R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        if let Some(node) = self.lock()?.get(node_key) {
+            return Ok(Some(node.clone()));
+        }
+        // The lock is not held while reading from the underlying storage, so that concurrent
+        // reads of other nodes are not serialized behind it.
+        let node = self.reader.get_node_option(node_key)?;
+        if let Some(node) = &node {
+            self.lock()?.put(node_key.clone(), node.clone());
+        }
+        Ok(node)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let mut nodes: Vec<Option<Node>> = {
+            let mut cache = self.lock()?;
+            node_keys
+                .iter()
+                .map(|node_key| cache.get(node_key).cloned())
+                .collect()
+        };
+        // As for a single node, the lock is not held while reading the missing nodes.
+        let read_indices = read_missing_nodes(&self.reader, node_keys, &mut nodes)?;
+        let mut cache = self.lock()?;
+        for i in read_indices {
+            if let Some(node) = &nodes[i] {
+                cache.put(node_keys[i].clone(), node.clone());
+            }
+        }
+        Ok(nodes)
+    }
+
+    // `get_encoded_node_option` is deliberately not forwarded: the read path prefers encoded
+    // nodes, which would then always be read from the underlying storage instead of the cache.
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.reader.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.reader.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.reader.get_indexed_root_hashes(versions, limit)
+    }
+}
+
+impl<R: TreeWriter> TreeWriter for CachedTreeReader<R> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.reader.write_node_batch(node_batch)?;
+        let mut cache = self.lock()?;
+        for (node_key, node) in node_batch.nodes() {
+            cache.put(node_key.clone(), node.clone());
+        }
+        Ok(())
+    }
+}
+
+impl<R: HasPreimage> HasPreimage for CachedTreeReader<R> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.reader.preimage(key_hash)
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.reader.preimages(key_hashes)
+    }
+}
@@ -0,0 +1,198 @@
+//! Trees splitting large values into chunks in the value store.
+//!
+//! A [`ChunkedValueTree`] wraps a [`JellyfishMerkleTree`], and stores any value larger than its
+//! threshold as chunks of at most that many bytes, so that multi-megabyte values do not turn into
+//! equally large entries of the value store. The leaf of such a value commits to a manifest made of
+//! the length of the value and the hashes of its chunks, while the chunks are written to the value
+//! store alone, under a key derived from their hash, and are never part of the tree. Reads
+//! reassemble the chunks, checking each of them against its hash.
+//!
+//! Every value, chunked or not, is stored behind a one byte tag, so a [`ChunkedValueTree`] can only
+//! read values which it wrote. [`leaf_value`] computes the value committed to by the leaf of a
+//! value, which is what its proofs are verified against.
+//!
+//! Chunks are addressed by their contents, so identical chunks are stored once per version which
+//! writes them, and are left in place when the values which referenced them are overwritten.
+
+use anyhow::{bail, ensure, format_err, Result};
+
+use crate::{
+    proof::SparseMerkleProof,
+    storage::{TreeReader, TreeUpdateBatch},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// The domain separator prepended to a chunk before hashing it into the key it is stored under.
+const CHUNK_DOMAIN_SEPARATOR: &[u8] = b"JMT::Chunk";
+
+/// The tag of values stored in their leaf.
+const INLINE_TAG: u8 = 0;
+/// The tag of values stored as chunks.
+const CHUNKED_TAG: u8 = 1;
+
+/// A [`JellyfishMerkleTree`] storing values larger than a threshold as chunks, see the
+/// [module](self) documentation.
+pub struct ChunkedValueTree<'a, R, H: SimpleHasher> {
+    tree: JellyfishMerkleTree<'a, R, H>,
+    reader: &'a R,
+    threshold: usize,
+}
+
+impl<'a, R, H> ChunkedValueTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Creates a `ChunkedValueTree` backed by the given [`TreeReader`], storing values longer than
+    /// `threshold` bytes as chunks of `threshold` bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `threshold` is zero.
+    pub fn new(reader: &'a R, threshold: usize) -> Self {
+        assert!(threshold > 0, "The chunking threshold must be positive.");
+        Self {
+            tree: JellyfishMerkleTree::new(reader),
+            reader,
+            threshold,
+        }
+    }
+
+    /// Returns the underlying tree, whose leaves hold the values as encoded by [`leaf_value`].
+    pub fn inner(&self) -> &JellyfishMerkleTree<'a, R, H> {
+        &self.tree
+    }
+
+    /// Returns the length above which values are chunked, which is also the length of chunks.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Puts the values of `value_set` at `version`, as [`JellyfishMerkleTree::put_value_set`]
+    /// does. The chunks of large values are added to the values of the returned batch.
+    pub fn put_value_set(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let mut chunks = vec![];
+        let value_set: Vec<_> = value_set
+            .into_iter()
+            .map(|(key_hash, value)| {
+                let value = value.map(|value| {
+                    encode::<H>(&value, self.threshold, |chunk_key, chunk| {
+                        chunks.push((chunk_key, chunk.to_vec()))
+                    })
+                });
+                (key_hash, value)
+            })
+            .collect();
+
+        let (root_hash, mut batch) = self.tree.put_value_set(value_set, version)?;
+        for (chunk_key, chunk) in chunks {
+            batch.node_batch.insert_value(version, chunk_key, chunk);
+        }
+        Ok((root_hash, batch))
+    }
+
+    /// Returns the value of `key` at `version`, reassembled from its chunks if it was chunked.
+    pub fn get(&self, key: KeyHash, version: Version) -> Result<Option<OwnedValue>> {
+        self.tree
+            .get(key, version)?
+            .map(|leaf_value| self.decode(&leaf_value, version))
+            .transpose()
+    }
+
+    /// Returns the value of `key` at `version` and the corresponding merkle proof, which is
+    /// verified against the [`leaf_value`] of the value.
+    pub fn get_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let (leaf_value, proof) = self.tree.get_with_proof(key, version)?;
+        let value = leaf_value
+            .map(|leaf_value| self.decode(&leaf_value, version))
+            .transpose()?;
+        Ok((value, proof))
+    }
+
+    /// Returns the root hash of the tree at `version`.
+    pub fn get_root_hash(&self, version: Version) -> Result<RootHash> {
+        self.tree.get_root_hash(version)
+    }
+
+    fn decode(&self, leaf_value: &[u8], version: Version) -> Result<OwnedValue> {
+        match leaf_value.split_first() {
+            Some((&INLINE_TAG, value)) => Ok(value.to_vec()),
+            Some((&CHUNKED_TAG, manifest)) => {
+                ensure!(
+                    manifest.len() >= 8 && (manifest.len() - 8).is_multiple_of(32),
+                    "Invalid chunk manifest of {} bytes.",
+                    manifest.len()
+                );
+                let (len, chunk_keys) = manifest.split_at(8);
+                let len = u64::from_be_bytes(len.try_into().expect("8 bytes")) as usize;
+                let mut value = Vec::with_capacity(len);
+                for chunk_key in chunk_keys.chunks(32) {
+                    let chunk_key = KeyHash(chunk_key.try_into().expect("32 bytes"));
+                    let chunk = self
+                        .reader
+                        .get_value_option(version, chunk_key)?
+                        .ok_or_else(|| format_err!("Missing chunk {:?}.", chunk_key))?;
+                    ensure!(
+                        hash_chunk::<H>(&chunk) == chunk_key,
+                        "Chunk {:?} does not match its hash.",
+                        chunk_key
+                    );
+                    value.extend_from_slice(&chunk);
+                }
+                ensure!(
+                    value.len() == len,
+                    "Chunked value of {} bytes, expected {}.",
+                    value.len(),
+                    len
+                );
+                Ok(value)
+            }
+            Some((tag, _)) => bail!("Unknown chunked value tag {}.", tag),
+            None => bail!("Empty chunked value."),
+        }
+    }
+}
+
+/// Returns the value committed to by the leaf of `value` in a [`ChunkedValueTree`] with the given
+/// `threshold`: the tagged value if it is not chunked, or the manifest of its chunks otherwise.
+pub fn leaf_value<H: SimpleHasher>(value: &[u8], threshold: usize) -> OwnedValue {
+    encode::<H>(value, threshold, |_, _| {})
+}
+
+/// Encodes `value` as stored in its leaf, passing each of its chunks to `on_chunk` if it is
+/// chunked.
+fn encode<H: SimpleHasher>(
+    value: &[u8],
+    threshold: usize,
+    mut on_chunk: impl FnMut(KeyHash, &[u8]),
+) -> OwnedValue {
+    if value.len() <= threshold {
+        let mut encoded = Vec::with_capacity(value.len() + 1);
+        encoded.push(INLINE_TAG);
+        encoded.extend_from_slice(value);
+        return encoded;
+    }
+    let mut manifest = vec![CHUNKED_TAG];
+    manifest.extend_from_slice(&(value.len() as u64).to_be_bytes());
+    for chunk in value.chunks(threshold) {
+        let chunk_key = hash_chunk::<H>(chunk);
+        manifest.extend_from_slice(&chunk_key.0);
+        on_chunk(chunk_key, chunk);
+    }
+    manifest
+}
+
+fn hash_chunk<H: SimpleHasher>(chunk: &[u8]) -> KeyHash {
+    let mut hasher = H::new();
+    hasher.update(CHUNK_DOMAIN_SEPARATOR);
+    hasher.update(chunk);
+    KeyHash(hasher.finalize())
+}
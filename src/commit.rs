@@ -0,0 +1,369 @@
+//! Crash-consistent commits of tree updates, through a write-ahead log.
+//!
+//! Persisting a [`TreeUpdateBatch`] together with the preimages of its keys and its new root hash
+//! usually takes several writes, and a crash in between leaves the storage torn. A
+//! [`WriteAheadCommitter`] first records the whole [`Commit`] durably in a [`WriteAheadLog`], then
+//! applies it to a [`CommitTarget`], and finally clears the log. On restart,
+//! [`WriteAheadCommitter::recover`] applies again any commit left in the log, which is why applying
+//! a commit must be idempotent.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{ensure, format_err, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
+
+use crate::{
+    node_type::{Node, NodeKey},
+    storage::{
+        NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, StaleNodeKind, TreeUpdateBatch,
+    },
+    types::Version,
+    KeyHash, RootHash,
+};
+
+/// Everything written to storage when committing a version of the tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Commit {
+    /// The version committed.
+    pub version: Version,
+    /// The root hash of the tree at `version`.
+    pub root_hash: RootHash,
+    /// The updates to the tree.
+    pub batch: TreeUpdateBatch,
+    /// The preimages of the key hashes written at `version`.
+    pub preimages: Vec<(KeyHash, Vec<u8>)>,
+}
+
+impl Commit {
+    /// Creates a commit of `batch`, the updates producing `root_hash` at `version`, without any
+    /// preimage.
+    pub fn new(version: Version, root_hash: RootHash, batch: TreeUpdateBatch) -> Self {
+        Self {
+            version,
+            root_hash,
+            batch,
+            preimages: vec![],
+        }
+    }
+
+    /// Adds the preimages of key hashes to the commit.
+    pub fn with_preimages(
+        mut self,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Self {
+        self.preimages.extend(preimages);
+        self
+    }
+
+    /// Serializes the commit, so that it can be staged in a [`WriteAheadLog`].
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        out.write_u64::<BigEndian>(self.version)?;
+        out.extend_from_slice(&self.root_hash.0);
+
+        let node_batch = &self.batch.node_batch;
+        write_len(&mut out, node_batch.nodes().len())?;
+        for (node_key, node) in node_batch.nodes() {
+            write_bytes(&mut out, &node_key.encode()?)?;
+            write_bytes(&mut out, &node.encode()?)?;
+        }
+        write_len(&mut out, node_batch.values().len())?;
+        for ((version, key_hash), value) in node_batch.values() {
+            out.write_u64::<BigEndian>(*version)?;
+            out.extend_from_slice(&key_hash.0);
+            match value {
+                Some(value) => {
+                    out.write_u8(1)?;
+                    write_bytes(&mut out, value)?;
+                }
+                None => out.write_u8(0)?,
+            }
+        }
+        write_len(&mut out, node_batch.root_hashes().len())?;
+        for (version, root_hash) in node_batch.root_hashes() {
+            out.write_u64::<BigEndian>(*version)?;
+            out.extend_from_slice(&root_hash.0);
+        }
+
+        write_len(&mut out, self.batch.stale_node_index_batch.len())?;
+        for stale_node_index in &self.batch.stale_node_index_batch {
+            out.write_u64::<BigEndian>(stale_node_index.stale_since_version)?;
+            write_bytes(&mut out, &stale_node_index.node_key.encode()?)?;
+            match stale_node_index.node_kind {
+                StaleNodeKind::Internal => out.write_u8(0)?,
+                StaleNodeKind::Leaf { key_hash } => {
+                    out.write_u8(1)?;
+                    out.extend_from_slice(&key_hash.0);
+                }
+            }
+        }
+
+        write_len(&mut out, self.batch.node_stats.len())?;
+        for node_stats in &self.batch.node_stats {
+            for count in [
+                node_stats.new_nodes,
+                node_stats.new_leaves,
+                node_stats.stale_nodes,
+                node_stats.stale_leaves,
+            ] {
+                out.write_u64::<BigEndian>(count as u64)?;
+            }
+        }
+
+        write_len(&mut out, self.preimages.len())?;
+        for (key_hash, preimage) in &self.preimages {
+            out.extend_from_slice(&key_hash.0);
+            write_bytes(&mut out, preimage)?;
+        }
+        Ok(out)
+    }
+
+    /// Recovers a commit serialized by [`encode`](Commit::encode).
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        let reader = &mut bytes;
+        let version = reader.read_u64::<BigEndian>()?;
+        let root_hash = RootHash(read_hash(reader)?);
+
+        let mut nodes = vec![];
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let node_key = NodeKey::decode(read_bytes(reader)?)?;
+            nodes.push((node_key, Node::decode(read_bytes(reader)?)?));
+        }
+        let mut values = vec![];
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let version = reader.read_u64::<BigEndian>()?;
+            let key_hash = KeyHash(read_hash(reader)?);
+            let value = match reader.read_u8()? {
+                0 => None,
+                1 => Some(Bytes::copy_from_slice(read_bytes(reader)?)),
+                tag => return Err(format_err!("Invalid value tag {}.", tag)),
+            };
+            values.push(((version, key_hash), value));
+        }
+        let mut node_batch = NodeBatch::default();
+        node_batch.extend(nodes, values);
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let version = reader.read_u64::<BigEndian>()?;
+            node_batch.insert_root_hash(version, RootHash(read_hash(reader)?));
+        }
+
+        let mut stale_node_index_batch = StaleNodeIndexBatch::new();
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let stale_since_version = reader.read_u64::<BigEndian>()?;
+            let node_key = NodeKey::decode(read_bytes(reader)?)?;
+            let node_kind = match reader.read_u8()? {
+                0 => StaleNodeKind::Internal,
+                1 => StaleNodeKind::Leaf {
+                    key_hash: KeyHash(read_hash(reader)?),
+                },
+                tag => return Err(format_err!("Invalid stale node kind tag {}.", tag)),
+            };
+            stale_node_index_batch.insert(StaleNodeIndex {
+                stale_since_version,
+                node_key,
+                node_kind,
+            });
+        }
+
+        let mut node_stats = vec![];
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let mut read_count =
+                || -> Result<usize> { Ok(reader.read_u64::<BigEndian>()? as usize) };
+            node_stats.push(NodeStats {
+                new_nodes: read_count()?,
+                new_leaves: read_count()?,
+                stale_nodes: read_count()?,
+                stale_leaves: read_count()?,
+            });
+        }
+
+        let mut preimages = vec![];
+        for _ in 0..reader.read_u32::<BigEndian>()? {
+            let key_hash = KeyHash(read_hash(reader)?);
+            preimages.push((key_hash, read_bytes(reader)?.to_vec()));
+        }
+        ensure!(
+            reader.is_empty(),
+            "{} trailing bytes after the encoded commit.",
+            reader.len()
+        );
+
+        Ok(Self {
+            version,
+            root_hash,
+            batch: TreeUpdateBatch {
+                node_batch,
+                stale_node_index_batch,
+                node_stats,
+            },
+            preimages,
+        })
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> Result<()> {
+    out.write_u32::<BigEndian>(u32::try_from(len)?)?;
+    Ok(())
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> Result<()> {
+    write_len(out, bytes.len())?;
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn read_bytes<'a>(reader: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = reader.read_u32::<BigEndian>()? as usize;
+    ensure!(
+        reader.len() >= len,
+        "Not enough bytes left: expected {}, found {}.",
+        len,
+        reader.len()
+    );
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Ok(bytes)
+}
+
+fn read_hash(reader: &mut &[u8]) -> Result<[u8; 32]> {
+    let mut hash = [0; 32];
+    io::Read::read_exact(reader, &mut hash)?;
+    Ok(hash)
+}
+
+/// Defines the storage a [`WriteAheadCommitter`] applies commits to.
+pub trait CommitTarget {
+    /// Writes the nodes, values, stale node indices, preimages and root hash of `commit`.
+    ///
+    /// This may be called again with the same commit after a crash interrupted it, so it must be
+    /// idempotent.
+    fn apply_commit(&self, commit: &Commit) -> Result<()>;
+}
+
+impl<T: CommitTarget + ?Sized> CommitTarget for &T {
+    fn apply_commit(&self, commit: &Commit) -> Result<()> {
+        (**self).apply_commit(commit)
+    }
+}
+
+/// Defines a durable log holding at most one staged commit.
+pub trait WriteAheadLog {
+    /// Durably records an encoded commit, replacing any previous one. This must be atomic: after a
+    /// crash, the log holds either the previous commit or the new one, in full.
+    fn stage(&self, encoded_commit: &[u8]) -> Result<()>;
+
+    /// Returns the encoded commit staged and not cleared since, if any.
+    fn staged(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Clears the staged commit, once it has been applied.
+    fn clear(&self) -> Result<()>;
+}
+
+/// A [`WriteAheadLog`] kept in a single file, which is replaced atomically by renaming a
+/// temporary file over it.
+pub struct FileWriteAheadLog {
+    path: PathBuf,
+}
+
+impl FileWriteAheadLog {
+    /// Creates a log stored at `path`. The file `path` with the `tmp` extension is used while
+    /// staging.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn sync_parent_dir(&self) -> Result<()> {
+        // Directories can only be synced this way on Unix.
+        #[cfg(unix)]
+        if let Some(parent) = self.path.parent() {
+            let parent = if parent.as_os_str().is_empty() {
+                std::path::Path::new(".")
+            } else {
+                parent
+            };
+            fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl WriteAheadLog for FileWriteAheadLog {
+    fn stage(&self, encoded_commit: &[u8]) -> Result<()> {
+        let temporary_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(encoded_commit)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, &self.path)?;
+        self.sync_parent_dir()
+    }
+
+    fn staged(&self) -> Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(encoded_commit) => Ok(Some(encoded_commit)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn clear(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => self.sync_parent_dir(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Commits to a [`CommitTarget`] through a [`WriteAheadLog`], so that a commit interrupted by a
+/// crash can be completed on restart with [`recover`](WriteAheadCommitter::recover).
+pub struct WriteAheadCommitter<L, T> {
+    log: L,
+    target: T,
+}
+
+impl<L, T> WriteAheadCommitter<L, T>
+where
+    L: WriteAheadLog,
+    T: CommitTarget,
+{
+    /// Creates a committer staging commits in `log` before applying them to `target`.
+    pub fn new(log: L, target: T) -> Self {
+        Self { log, target }
+    }
+
+    /// Returns the storage commits are applied to.
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    /// Applies the commit left in the log by an interrupted [`commit`](WriteAheadCommitter::commit),
+    /// if any, and returns its version. This should be called on startup, before any new commit.
+    pub fn recover(&self) -> Result<Option<Version>> {
+        let encoded_commit = match self.log.staged()? {
+            Some(encoded_commit) => encoded_commit,
+            None => return Ok(None),
+        };
+        let commit = Commit::decode(&encoded_commit)?;
+        self.target.apply_commit(&commit)?;
+        self.log.clear()?;
+        Ok(Some(commit.version))
+    }
+
+    /// Stages `commit` in the log, applies it to the target, and clears the log. If this fails
+    /// after staging, the commit is completed by the next call to
+    /// [`recover`](WriteAheadCommitter::recover).
+    pub fn commit(&self, commit: &Commit) -> Result<()> {
+        ensure!(
+            self.log.staged()?.is_none(),
+            "A commit is already staged; it must be recovered first."
+        );
+        self.log.stage(&commit.encode()?)?;
+        self.target.apply_commit(commit)?;
+        self.log.clear()
+    }
+}
@@ -0,0 +1,92 @@
+//! Hashers computing the same node hashes as the upstream Diem and Aptos Jellyfish Merkle trees.
+//!
+//! This crate and its upstreams share the same tree: nodes of arity 16, and internal nodes hashed
+//! as binary trees of their children. They differ in how a node is hashed, and in the placeholder
+//! hash of empty subtrees. Upstream, the hash of a node is the SHA3-256 hash of a seed followed by
+//! the two hashes it commits to, the seed being itself the SHA3-256 hash of a prefix,
+//! `DIEM::HASH::` or `APTOS::`, followed by the name of the hashed type, and the placeholder hash
+//! is `SPARSE_MERKLE_PLACEHOLDER_HASH` padded with zero bytes, see [`UPSTREAM_PLACEHOLDER_HASH`].
+//! These are the domain separators and placeholder hash of [`DiemHasher`] and [`AptosHasher`], so
+//! that a tree using one of them has the root hashes of its upstream, given the same key hashes
+//! and value hashes, and its [`SparseMerkleProof`](crate::proof::SparseMerkleProof)s check against
+//! upstream root hashes.
+//!
+//! Key hashes are computed by the application, and can be given as is to the tree. Value hashes
+//! are computed by the tree, as the hash of the value preceded by the value domain separator:
+//! Diem account state blobs are hashed that way with the `AccountStateBlob` seed, which
+//! [`DiemHasher`] uses. The hash of an Aptos state value depends on its format, which changed
+//! across Aptos releases, so [`AptosHasher`] leaves values unseparated; a tree matching a given
+//! release can use its own [`DomainSeparators`] with the seed of [`seed`].
+
+use sha3::Sha3_256;
+
+use crate::{DomainSeparators, Personalized, SimpleHasher};
+
+/// The prefix of the seeds of the Diem hashers.
+pub const DIEM_HASH_PREFIX: &[u8] = b"DIEM::HASH::";
+
+/// The prefix of the seeds of the Aptos hashers.
+pub const APTOS_HASH_PREFIX: &[u8] = b"APTOS::";
+
+/// The hash of empty subtrees in the Diem and Aptos trees, which pad the literal to 32 bytes with
+/// zeros where this crate pads it with underscores.
+pub const UPSTREAM_PLACEHOLDER_HASH: [u8; 32] = *b"SPARSE_MERKLE_PLACEHOLDER_HASH\0\0";
+
+/// Returns the seed upstream hashers prepend to the values of the type named `type_name`, for
+/// the prefix `hash_prefix`.
+pub fn seed(hash_prefix: &[u8], type_name: &[u8]) -> [u8; 32] {
+    let mut hasher = <Sha3_256 as SimpleHasher>::new();
+    hasher.update(hash_prefix);
+    hasher.update(type_name);
+    hasher.finalize()
+}
+
+/// The domain separators of the Diem Jellyfish Merkle tree.
+pub struct Diem;
+
+impl DomainSeparators for Diem {
+    /// `seed(DIEM_HASH_PREFIX, b"SparseMerkleInternal")`
+    const INTERNAL: &'static [u8] = &[
+        0xb9, 0xf9, 0x8b, 0xf3, 0xb8, 0x01, 0x1a, 0x98, 0xdc, 0x40, 0x40, 0x81, 0x24, 0xa0, 0x72,
+        0xf0, 0xc6, 0xae, 0xe6, 0x6f, 0xc5, 0x9a, 0x8c, 0x11, 0x31, 0x35, 0xf8, 0xa3, 0xf2, 0xd0,
+        0x63, 0xf5,
+    ];
+    /// `seed(DIEM_HASH_PREFIX, b"SparseMerkleLeafNode")`
+    const LEAF: &'static [u8] = &[
+        0x06, 0xca, 0x1d, 0x10, 0x58, 0x6b, 0x13, 0xec, 0x30, 0x2b, 0xda, 0x6f, 0x75, 0x05, 0xcb,
+        0x9f, 0x44, 0x35, 0x19, 0x44, 0x3c, 0xa1, 0x44, 0x2f, 0x2d, 0x45, 0xc2, 0x40, 0xb5, 0xbb,
+        0x47, 0x17,
+    ];
+    /// `seed(DIEM_HASH_PREFIX, b"AccountStateBlob")`
+    const VALUE: &'static [u8] = &[
+        0x12, 0xec, 0x28, 0x90, 0x62, 0x0f, 0x12, 0xd8, 0x35, 0x8c, 0x60, 0x0e, 0xa5, 0xf3, 0x29,
+        0xdb, 0xa4, 0xd7, 0x0a, 0xd3, 0xd1, 0x4f, 0xf5, 0x91, 0xb0, 0x08, 0xa8, 0xab, 0xb3, 0xdb,
+        0xad, 0xb6,
+    ];
+    const PLACEHOLDER: [u8; 32] = UPSTREAM_PLACEHOLDER_HASH;
+}
+
+/// The domain separators of the Aptos Jellyfish Merkle tree.
+pub struct Aptos;
+
+impl DomainSeparators for Aptos {
+    /// `seed(APTOS_HASH_PREFIX, b"SparseMerkleInternal")`
+    const INTERNAL: &'static [u8] = &[
+        0x67, 0x38, 0xdb, 0x12, 0x46, 0x43, 0xf4, 0x27, 0xd7, 0xb7, 0xd9, 0x2c, 0x0c, 0xc1, 0x1a,
+        0xc9, 0xef, 0x1c, 0xaa, 0xaa, 0xec, 0x2a, 0xc7, 0x71, 0x45, 0x64, 0x6e, 0x1d, 0x29, 0x3e,
+        0x6b, 0x38,
+    ];
+    /// `seed(APTOS_HASH_PREFIX, b"SparseMerkleLeafNode")`
+    const LEAF: &'static [u8] = &[
+        0x9d, 0xf5, 0xce, 0x42, 0x6e, 0x6c, 0x3c, 0xc7, 0xa6, 0x48, 0x88, 0x6c, 0x12, 0xb6, 0x61,
+        0xd7, 0xd8, 0x7c, 0xba, 0x44, 0xce, 0x9b, 0x7c, 0x3b, 0x85, 0x79, 0xba, 0xc5, 0xb8, 0xe8,
+        0x51, 0x9b,
+    ];
+    const PLACEHOLDER: [u8; 32] = UPSTREAM_PLACEHOLDER_HASH;
+}
+
+/// The hasher of the Diem Jellyfish Merkle tree.
+pub type DiemHasher = Personalized<Sha3_256, Diem>;
+
+/// The hasher of the Aptos Jellyfish Merkle tree.
+pub type AptosHasher = Personalized<Sha3_256, Aptos>;
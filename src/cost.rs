@@ -0,0 +1,184 @@
+//! Counting of the work done by operations on a tree, to build gas models of state access on real
+//! numbers.
+//!
+//! Costs are counted on the current thread by instrumented hashers and storage: a
+//! [`CountingHasher`] counts its hash invocations, and a [`CostCounter`] counts the nodes and
+//! values read from the storage it wraps. [`measure`] returns the cost of a call, such as
+//! [`get_with_proof`](crate::JellyfishMerkleTree::get_with_proof),
+//! [`put_value_set`](crate::JellyfishMerkleTree::put_value_set) or the verification of a proof,
+//! made with a tree using both:
+//!
+//! ```
+//! use jmt::{
+//!     cost::{measure, CostCounter, CountingHasher},
+//!     mock::MockTreeStore,
+//!     storage::TreeWriter,
+//!     JellyfishMerkleTree, KeyHash,
+//! };
+//!
+//! type Hasher = CountingHasher<sha2::Sha256>;
+//!
+//! let store = CostCounter::new(MockTreeStore::default());
+//! let tree = JellyfishMerkleTree::<_, Hasher>::new(&store);
+//! let key = KeyHash::with::<Hasher>(b"key");
+//!
+//! let (result, put_cost) = measure(|| tree.put_value_set([(key, Some(b"value".to_vec()))], 0));
+//! let (root_hash, batch) = result.unwrap();
+//! store.inner().write_node_batch(&batch.node_batch).unwrap();
+//! assert!(put_cost.hashes > 0);
+//!
+//! let (result, read_cost) = measure(|| tree.get_with_proof(key, 0));
+//! let (value, proof) = result.unwrap();
+//! assert!(read_cost.node_reads > 0);
+//! assert_eq!(read_cost.value_reads, 1);
+//!
+//! let (result, verify_cost) = measure(|| proof.verify(root_hash, key, value));
+//! result.unwrap();
+//! assert_eq!(verify_cost.node_reads, 0);
+//! ```
+//!
+//! Counting hashers produce the same hashes as the hashers they wrap, so the trees and proofs of
+//! an instrumented tree are those of the tree without instrumentation. Work done on other threads,
+//! such as by `par_put_value_set` of the `rayon` feature, is not counted by [`measure`].
+
+use std::{cell::Cell, ops::Range};
+
+use anyhow::Result;
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::TreeReader,
+    KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+thread_local! {
+    /// The costs counted on this thread so far.
+    static COST: Cell<Cost> = const { Cell::new(Cost::ZERO) };
+}
+
+/// The work done by an operation, see [`measure`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Cost {
+    /// The number of digests computed by [`CountingHasher`]s.
+    pub hashes: u64,
+    /// The number of nodes read through [`CostCounter`]s, including those read in batches.
+    pub node_reads: u64,
+    /// The number of values read through [`CostCounter`]s.
+    pub value_reads: u64,
+}
+
+impl Cost {
+    const ZERO: Cost = Cost {
+        hashes: 0,
+        node_reads: 0,
+        value_reads: 0,
+    };
+}
+
+/// Calls `f`, and returns its result with the cost of the work it did on the current thread.
+///
+/// Calls can be nested, the cost of an inner call being included in that of the outer one.
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Cost) {
+    let before = COST.with(Cell::get);
+    let result = f();
+    let after = COST.with(Cell::get);
+    let cost = Cost {
+        hashes: after.hashes - before.hashes,
+        node_reads: after.node_reads - before.node_reads,
+        value_reads: after.value_reads - before.value_reads,
+    };
+    (result, cost)
+}
+
+fn count(add: impl FnOnce(&mut Cost)) {
+    COST.with(|cost| {
+        let mut counted = cost.get();
+        add(&mut counted);
+        cost.set(counted);
+    })
+}
+
+/// A [`SimpleHasher`] counting the digests computed by `H`, with the same domain separators.
+pub struct CountingHasher<H>(H);
+
+impl<H: SimpleHasher> SimpleHasher for CountingHasher<H> {
+    const INTERNAL_DOMAIN_SEPARATOR: &'static [u8] = H::INTERNAL_DOMAIN_SEPARATOR;
+    const LEAF_DOMAIN_SEPARATOR: &'static [u8] = H::LEAF_DOMAIN_SEPARATOR;
+    const VALUE_DOMAIN_SEPARATOR: &'static [u8] = H::VALUE_DOMAIN_SEPARATOR;
+    const PLACEHOLDER_HASH: [u8; 32] = H::PLACEHOLDER_HASH;
+
+    fn new() -> Self {
+        Self(H::new())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data)
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        count(|cost| cost.hashes += 1);
+        self.0.finalize()
+    }
+}
+
+/// A [`TreeReader`] counting the nodes and values read from another [`TreeReader`].
+pub struct CostCounter<R> {
+    reader: R,
+}
+
+impl<R> CostCounter<R> {
+    /// Creates a counter of the reads made from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    /// Returns the underlying reader.
+    pub fn inner(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: TreeReader> TreeReader for CostCounter<R> {
+    // `get_encoded_node_option` is deliberately not forwarded, so that every node read goes
+    // through `get_node_option` or `get_nodes` and is counted once.
+
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        count(|cost| cost.node_reads += 1);
+        self.reader.get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        count(|cost| cost.node_reads += node_keys.len() as u64);
+        self.reader.get_nodes(node_keys)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        count(|cost| cost.value_reads += 1);
+        self.reader.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.reader.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.reader.get_indexed_root_hashes(versions, limit)
+    }
+}
@@ -0,0 +1,89 @@
+//! Trees in which every key is present, with a default value unless another one was put.
+//!
+//! Some protocols model their state as a total map, e.g. a token ledger in which every account
+//! has a balance of zero until it receives tokens. A [`DefaultValueTree`] wraps a
+//! [`JellyfishMerkleTree`] with such semantics: putting the default value of a key removes it from
+//! the underlying tree, and reading a key missing from the underlying tree returns the default
+//! value. Proofs returned by a [`DefaultValueTree`] are verified with
+//! [`SparseMerkleProof::verify_with_default`], which accepts a non-inclusion proof as a proof that
+//! a key has the default value.
+//!
+//! Since the default value is never stored, the root hash of a [`DefaultValueTree`] only depends on
+//! the keys which do not have the default value.
+
+use anyhow::Result;
+
+use crate::{
+    proof::SparseMerkleProof,
+    storage::{TreeReader, TreeUpdateBatch},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// A [`JellyfishMerkleTree`] in which every key has `default_value` unless another value was put,
+/// see the [module](self) documentation.
+pub struct DefaultValueTree<'a, R, H: SimpleHasher> {
+    tree: JellyfishMerkleTree<'a, R, H>,
+    default_value: OwnedValue,
+}
+
+impl<'a, R, H> DefaultValueTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Creates a `DefaultValueTree` backed by the given [`TreeReader`], in which absent keys have
+    /// `default_value`.
+    pub fn new(reader: &'a R, default_value: OwnedValue) -> Self {
+        Self {
+            tree: JellyfishMerkleTree::new(reader),
+            default_value,
+        }
+    }
+
+    /// Returns the underlying tree, which only holds the values other than the default one.
+    pub fn inner(&self) -> &JellyfishMerkleTree<'a, R, H> {
+        &self.tree
+    }
+
+    /// Returns the value of absent keys.
+    pub fn default_value(&self) -> &[u8] {
+        &self.default_value
+    }
+
+    /// Puts the values of `value_set` at `version`, as [`JellyfishMerkleTree::put_value_set`]
+    /// does. Keys set to the default value are removed from the underlying tree.
+    pub fn put_value_set(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, OwnedValue)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let value_set = value_set
+            .into_iter()
+            .map(|(key_hash, value)| (key_hash, (value != self.default_value).then_some(value)));
+        self.tree.put_value_set(value_set, version)
+    }
+
+    /// Returns the value of `key` at `version`.
+    pub fn get(&self, key: KeyHash, version: Version) -> Result<OwnedValue> {
+        Ok(self
+            .tree
+            .get(key, version)?
+            .unwrap_or_else(|| self.default_value.clone()))
+    }
+
+    /// Returns the value of `key` at `version` and the corresponding merkle proof, to be verified
+    /// with [`SparseMerkleProof::verify_with_default`].
+    pub fn get_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(OwnedValue, SparseMerkleProof<H>)> {
+        let (value, proof) = self.tree.get_with_proof(key, version)?;
+        Ok((value.unwrap_or_else(|| self.default_value.clone()), proof))
+    }
+
+    /// Returns the root hash of the tree at `version`.
+    pub fn get_root_hash(&self, version: Version) -> Result<RootHash> {
+        self.tree.get_root_hash(version)
+    }
+}
@@ -0,0 +1,131 @@
+//! The failure modes of a tree, its storage and its proofs which callers may want to handle.
+
+use alloc::{boxed::Box, string::String};
+use core::fmt;
+
+#[cfg(feature = "std")]
+use crate::node_type::NodeKey;
+use crate::{types::Version, KeyHash};
+
+/// A failure of a tree, of its storage or of a proof, which callers can match on.
+///
+/// The fallible functions of this crate return [`anyhow::Error`]s, so that storage can fail with
+/// errors of its own. Those failing in one of the modes below carry a `JmtError`, which
+/// [`JmtError::find`] retrieves, even once context was added to the error. A missing root is
+/// reported by a [`MissingRootError`](crate::MissingRootError) instead.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum JmtError {
+    /// A node referred to by the tree is missing from the storage.
+    #[cfg(feature = "std")]
+    MissingNode { node_key: NodeKey },
+    /// No value of a key at or before a version is in the storage, although a leaf refers to it.
+    MissingValue {
+        key_hash: KeyHash,
+        max_version: Version,
+    },
+    /// A version older than the least readable one was requested.
+    VersionPruned {
+        version: Version,
+        least_readable_version: Version,
+    },
+    /// The storage holds data which does not make a valid tree.
+    Corruption(String),
+    /// A proof does not verify, or is malformed.
+    InvalidProof(String),
+    /// The storage failed, such as the sled or RocksDB database of a built-in store.
+    Storage(Box<dyn core::error::Error + Send + Sync>),
+}
+
+impl JmtError {
+    /// Wraps an error of the storage.
+    pub fn storage(error: impl core::error::Error + Send + Sync + 'static) -> Self {
+        Self::Storage(Box::new(error))
+    }
+
+    /// Returns the `JmtError` carried by `error`, if any.
+    pub fn find(error: &anyhow::Error) -> Option<&JmtError> {
+        error.downcast_ref()
+    }
+}
+
+impl fmt::Display for JmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            JmtError::MissingNode { node_key } => write!(f, "Missing node at {:?}.", node_key),
+            JmtError::MissingValue {
+                key_hash,
+                max_version,
+            } => write!(
+                f,
+                "Missing value with max_version {} and key hash {:?}.",
+                max_version, key_hash
+            ),
+            JmtError::VersionPruned {
+                version,
+                least_readable_version,
+            } => write!(
+                f,
+                "Version {} is pruned, the least readable version is {}.",
+                version, least_readable_version
+            ),
+            JmtError::Corruption(message) | JmtError::InvalidProof(message) => f.write_str(message),
+            JmtError::Storage(error) => write!(f, "Storage error: {}", error),
+        }
+    }
+}
+
+impl core::error::Error for JmtError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            JmtError::Storage(error) => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+/// Like [`anyhow::ensure`], but failing with a [`JmtError::InvalidProof`].
+macro_rules! ensure_valid_proof {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::JmtError::InvalidProof(alloc::format!($($arg)+)).into());
+        }
+    };
+}
+
+/// Like [`anyhow::bail`], but failing with a [`JmtError::InvalidProof`].
+macro_rules! bail_invalid_proof {
+    ($($arg:tt)+) => {
+        return Err($crate::JmtError::InvalidProof(alloc::format!($($arg)+)).into())
+    };
+}
+
+/// Like [`anyhow::format_err`], but returning a [`JmtError::InvalidProof`].
+macro_rules! invalid_proof {
+    ($($arg:tt)+) => {
+        anyhow::Error::from($crate::JmtError::InvalidProof(alloc::format!($($arg)+)))
+    };
+}
+
+/// Like [`anyhow::ensure`], but failing with a [`JmtError::Corruption`].
+#[cfg(feature = "std")]
+macro_rules! ensure_not_corrupted {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::JmtError::Corruption(alloc::format!($($arg)+)).into());
+        }
+    };
+}
+
+/// Like [`anyhow::bail`], but failing with a [`JmtError::Corruption`].
+#[cfg(feature = "std")]
+macro_rules! bail_corrupted {
+    ($($arg:tt)+) => {
+        return Err($crate::JmtError::Corruption(alloc::format!($($arg)+)).into())
+    };
+}
+
+#[cfg(feature = "std")]
+pub(crate) use {bail_corrupted, ensure_not_corrupted};
+pub(crate) use {bail_invalid_proof, ensure_valid_proof, invalid_proof};
@@ -0,0 +1,277 @@
+//! An encoding of the proofs of a tree using [`Keccak256`] which contracts on Ethereum can verify
+//! cheaply.
+//!
+//! [`encode_proof`] lays a [`SparseMerkleProof`] out in 32-byte words, so that a Solidity verifier
+//! reads each field from calldata with a single `calldataload`, and leaves out the siblings which
+//! are the placeholder hash of empty subtrees, which are most of the siblings of a proof:
+//!
+//! ```text
+//! offset  size      field
+//! 0       32        header: byte 0 is 1 if the proof has a leaf and 0 otherwise, bytes 30 and 31
+//!                   hold the number n <= 256 of siblings as a big-endian u16, the others are 0
+//! 32      32        bitmap: bit i, from the least significant bit of the big-endian word, is set
+//!                   if sibling i is not the placeholder hash
+//! 64      64        key hash and value hash of the leaf, if the proof has one
+//! ...     32 each   the siblings which are not the placeholder hash, from the leaf up to the root
+//! ```
+//!
+//! A proof is verified against a root hash, a key hash `key` and, for a membership proof, a value,
+//! as follows, where `||` concatenates bytes, `LEAF` and `INTERNAL` are
+//! [`LEAF_DOMAIN_SEPARATOR`] and [`INTERNAL_DOMAIN_SEPARATOR`], and `PLACEHOLDER` is the 32 bytes
+//! of `SPARSE_MERKLE_PLACEHOLDER_HASH`:
+//!
+//! ```text
+//! node = hasLeaf ? keccak256(LEAF || leafKeyHash || leafValueHash) : PLACEHOLDER
+//! for i in 0..n:
+//!     sibling = (bitmap >> i) & 1 == 1 ? next sibling word : PLACEHOLDER
+//!     if (uint256(key) >> (256 - n + i)) & 1 == 1:
+//!         node = keccak256(INTERNAL || sibling || node)
+//!     else:
+//!         node = keccak256(INTERNAL || node || sibling)
+//! require every word was read, and node == root
+//! membership of value: require hasLeaf and leafKeyHash == key
+//!                             and leafValueHash == keccak256(value)
+//! non-membership:      require not hasLeaf
+//!                             or leafKeyHash != key and (leafKeyHash ^ key) >> (256 - n) == 0
+//! ```
+//!
+//! [`verify_encoded_proof`] implements this algorithm, as a reference for on-chain verifiers.
+
+use alloc::vec::Vec;
+
+use anyhow::Result;
+
+use crate::{
+    error::{bail_invalid_proof, ensure_valid_proof},
+    proof::{
+        SparseMerkleLeafNode, SparseMerkleProof, INTERNAL_DOMAIN_SEPARATOR, LEAF_DOMAIN_SEPARATOR,
+    },
+    Bytes32Ext, Keccak256, KeyHash, RootHash, SimpleHasher, ValueHash,
+    SPARSE_MERKLE_PLACEHOLDER_HASH,
+};
+
+/// The number of bytes of a word of the encoding.
+const WORD_LEN: usize = 32;
+
+/// The maximal number of siblings of a proof, one per bit of a key hash.
+const MAX_SIBLINGS: usize = 256;
+
+/// Encodes `proof` in the layout of the [module](self) documentation.
+pub fn encode_proof(proof: &SparseMerkleProof<Keccak256>) -> Vec<u8> {
+    let siblings = proof.siblings();
+    let mut header = [0u8; WORD_LEN];
+    header[0] = proof.leaf().is_some() as u8;
+    header[30..].copy_from_slice(&(siblings.len() as u16).to_be_bytes());
+    let mut bitmap = [0u8; WORD_LEN];
+    for (i, sibling) in siblings.iter().enumerate() {
+        if *sibling != SPARSE_MERKLE_PLACEHOLDER_HASH {
+            bitmap[WORD_LEN - 1 - i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(encoded_len(proof));
+    bytes.extend_from_slice(&header);
+    bytes.extend_from_slice(&bitmap);
+    if let Some(leaf) = proof.leaf() {
+        bytes.extend_from_slice(&leaf.key_hash().0);
+        bytes.extend_from_slice(&leaf.value_hash().0);
+    }
+    for sibling in siblings {
+        if *sibling != SPARSE_MERKLE_PLACEHOLDER_HASH {
+            bytes.extend_from_slice(sibling);
+        }
+    }
+    bytes
+}
+
+/// Returns the length of the encoding of `proof` by [`encode_proof`].
+pub fn encoded_len(proof: &SparseMerkleProof<Keccak256>) -> usize {
+    let num_words = 2
+        + 2 * proof.leaf().is_some() as usize
+        + proof
+            .siblings()
+            .iter()
+            .filter(|sibling| **sibling != SPARSE_MERKLE_PLACEHOLDER_HASH)
+            .count();
+    num_words * WORD_LEN
+}
+
+/// Decodes a proof encoded by [`encode_proof`].
+pub fn decode_proof(bytes: &[u8]) -> Result<SparseMerkleProof<Keccak256>> {
+    let mut words = Words::new(bytes)?;
+    let (has_leaf, num_siblings) = read_header(words.next()?)?;
+    let bitmap = read_bitmap(words.next()?, num_siblings)?;
+    let leaf = if has_leaf {
+        Some(SparseMerkleLeafNode::new(
+            KeyHash(words.next()?),
+            ValueHash(words.next()?),
+        ))
+    } else {
+        None
+    };
+    let siblings = (0..num_siblings)
+        .map(|i| {
+            if is_set(&bitmap, i) {
+                words.next()
+            } else {
+                Ok(SPARSE_MERKLE_PLACEHOLDER_HASH)
+            }
+        })
+        .collect::<Result<_>>()?;
+    words.finish()?;
+    Ok(SparseMerkleProof::new(leaf, siblings))
+}
+
+/// Verifies a proof encoded by [`encode_proof`] against `expected_root_hash`, as the algorithm of
+/// the [module](self) documentation does: if `value` is given, that `key` has `value`, and
+/// otherwise that `key` does not exist.
+pub fn verify_encoded_proof<V: AsRef<[u8]>>(
+    bytes: &[u8],
+    expected_root_hash: RootHash,
+    key: KeyHash,
+    value: Option<V>,
+) -> Result<()> {
+    let mut words = Words::new(bytes)?;
+    let (has_leaf, num_siblings) = read_header(words.next()?)?;
+    let bitmap = read_bitmap(words.next()?, num_siblings)?;
+    let leaf = if has_leaf {
+        Some((words.next()?, words.next()?))
+    } else {
+        None
+    };
+
+    let mut node = match leaf {
+        Some((leaf_key_hash, leaf_value_hash)) => {
+            keccak256(&[LEAF_DOMAIN_SEPARATOR, &leaf_key_hash, &leaf_value_hash])
+        }
+        None => SPARSE_MERKLE_PLACEHOLDER_HASH,
+    };
+    for i in 0..num_siblings {
+        let sibling = if is_set(&bitmap, i) {
+            words.next()?
+        } else {
+            SPARSE_MERKLE_PLACEHOLDER_HASH
+        };
+        // Bit `256 - n + i` from the least significant bit is bit `n - 1 - i` from the most
+        // significant bit, at the depth of the sibling.
+        node = if key.0.get_bit(num_siblings - 1 - i) {
+            keccak256(&[INTERNAL_DOMAIN_SEPARATOR, &sibling, &node])
+        } else {
+            keccak256(&[INTERNAL_DOMAIN_SEPARATOR, &node, &sibling])
+        };
+    }
+    words.finish()?;
+    ensure_valid_proof!(
+        node == expected_root_hash.0,
+        "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+        node,
+        expected_root_hash,
+    );
+
+    match (value, leaf) {
+        (Some(value), Some((leaf_key_hash, leaf_value_hash))) => {
+            ensure_valid_proof!(
+                leaf_key_hash == key.0,
+                "Keys do not match. Key in proof: {:?}. Expected key: {:?}.",
+                KeyHash(leaf_key_hash),
+                key,
+            );
+            ensure_valid_proof!(
+                leaf_value_hash == keccak256(&[value.as_ref()]),
+                "Value hashes do not match.",
+            );
+        }
+        (Some(_), None) => {
+            bail_invalid_proof!("Expected inclusion proof. Found non-inclusion proof.")
+        }
+        (None, Some((leaf_key_hash, _))) => {
+            ensure_valid_proof!(
+                leaf_key_hash != key.0,
+                "Expected non-inclusion proof, but key exists in proof.",
+            );
+            ensure_valid_proof!(
+                key.0.common_prefix_bits_len(&leaf_key_hash) >= num_siblings,
+                "The key in proof is not in the subtree of {:?}.",
+                key,
+            );
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// Returns whether the proof has a leaf and its number of siblings, checking that the unused
+/// bytes of `header` are 0.
+fn read_header(header: [u8; WORD_LEN]) -> Result<(bool, usize)> {
+    let num_siblings = u16::from_be_bytes([header[30], header[31]]) as usize;
+    ensure_valid_proof!(
+        num_siblings <= MAX_SIBLINGS,
+        "Encoded proof has more than {} ({}) siblings.",
+        MAX_SIBLINGS,
+        num_siblings,
+    );
+    ensure_valid_proof!(
+        header[1..30].iter().all(|byte| *byte == 0),
+        "Encoded proof has non-zero padding bytes in its header."
+    );
+    match header[0] {
+        0 => Ok((false, num_siblings)),
+        1 => Ok((true, num_siblings)),
+        tag => bail_invalid_proof!("Invalid leaf tag {} in encoded proof.", tag),
+    }
+}
+
+/// Returns the bitmap of the siblings, checking that no bit is set beyond the last sibling.
+fn read_bitmap(bitmap: [u8; WORD_LEN], num_siblings: usize) -> Result<[u8; WORD_LEN]> {
+    ensure_valid_proof!(
+        (num_siblings..MAX_SIBLINGS).all(|i| !is_set(&bitmap, i)),
+        "Encoded proof has bits set beyond its {} siblings.",
+        num_siblings,
+    );
+    Ok(bitmap)
+}
+
+/// Returns whether bit `i` of `bitmap`, from its least significant bit, is set.
+fn is_set(bitmap: &[u8; WORD_LEN], i: usize) -> bool {
+    bitmap[WORD_LEN - 1 - i / 8] & (1 << (i % 8)) != 0
+}
+
+fn keccak256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = <Keccak256 as SimpleHasher>::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+/// Reads the words of an encoded proof in order.
+struct Words<'b> {
+    rest: &'b [u8],
+}
+
+impl<'b> Words<'b> {
+    fn new(bytes: &'b [u8]) -> Result<Self> {
+        ensure_valid_proof!(
+            bytes.len().is_multiple_of(WORD_LEN),
+            "Encoded proof has {} bytes, which is not a whole number of words.",
+            bytes.len(),
+        );
+        Ok(Self { rest: bytes })
+    }
+
+    fn next(&mut self) -> Result<[u8; WORD_LEN]> {
+        ensure_valid_proof!(!self.rest.is_empty(), "Encoded proof is truncated.");
+        let (word, rest) = self.rest.split_at(WORD_LEN);
+        self.rest = rest;
+        Ok(word.try_into().expect("Read a word."))
+    }
+
+    fn finish(self) -> Result<()> {
+        ensure_valid_proof!(
+            self.rest.is_empty(),
+            "Encoded proof has {} trailing bytes.",
+            self.rest.len(),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! Competing, uncommitted versions of a tree.
+//!
+//! Consensus protocols may have to build several blocks at the same height before one of them is
+//! finalized. A [`ForkedTree`] keeps such branches in memory on top of the committed storage:
+//! each branch applies one value set on top of either the committed tree or another branch, so
+//! branches form a tree of versions rooted at the latest committed version, and divergent
+//! children of one version can coexist.
+//!
+//! Nodes are still identified by their version and nibble path. Branches at the same version
+//! create nodes with the same [`NodeKey`]s, which is why they are never written to storage before
+//! being finalized: [`ForkedTree::finalize`] returns the updates of the chosen branch and its
+//! ancestors, to be written in order, and drops every branch which does not descend from it.
+//! Since discarded branches never reach the storage, there is nothing to garbage collect there.
+
+use std::{collections::BTreeMap, marker::PhantomData, ops::Range};
+
+use anyhow::{ensure, format_err, Result};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    overlay::pending_value,
+    reader::read_missing_nodes,
+    storage::{HasPreimage, TreeReader, TreeUpdateBatch},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// Identifies a branch of a [`ForkedTree`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct BranchId(u64);
+
+struct Branch {
+    parent: Option<BranchId>,
+    version: Version,
+    root_hash: RootHash,
+    batch: TreeUpdateBatch,
+}
+
+/// Uncommitted branches of a tree, on top of a [`TreeReader`] holding the committed versions.
+pub struct ForkedTree<R, H: SimpleHasher> {
+    reader: R,
+    branches: BTreeMap<BranchId, Branch>,
+    next_branch_id: u64,
+    _phantom_hasher: PhantomData<H>,
+}
+
+impl<R, H> ForkedTree<R, H>
+where
+    R: TreeReader,
+    H: SimpleHasher,
+{
+    /// Creates a tree without any branch on top of `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            branches: BTreeMap::new(),
+            next_branch_id: 0,
+            _phantom_hasher: PhantomData,
+        }
+    }
+
+    /// Applies `value_set` at `version` on top of `parent`, or on top of the committed tree if
+    /// `parent` is `None`, and returns the new branch along with its root hash.
+    ///
+    /// `version` must follow the version of `parent`, or the latest committed version.
+    pub fn branch(
+        &mut self,
+        parent: Option<BranchId>,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(BranchId, RootHash)> {
+        if let Some(parent) = parent {
+            let parent_version = self.get_branch(parent)?.version;
+            ensure!(
+                parent_version.checked_add(1) == Some(version),
+                "Branch version {} does not follow the version {} of its parent.",
+                version,
+                parent_version
+            );
+        }
+        let (root_hash, batch) = JellyfishMerkleTree::<_, H>::new(&self.reader_at(parent)?)
+            .put_value_set(value_set, version)?;
+
+        let branch_id = BranchId(self.next_branch_id);
+        self.next_branch_id += 1;
+        self.branches.insert(
+            branch_id,
+            Branch {
+                parent,
+                version,
+                root_hash,
+                batch,
+            },
+        );
+        Ok((branch_id, root_hash))
+    }
+
+    /// Returns a [`TreeReader`] over the state of `branch`, or over the committed tree if
+    /// `branch` is `None`, for use with a [`JellyfishMerkleTree`].
+    pub fn reader_at(&self, branch: Option<BranchId>) -> Result<BranchReader<'_, R>> {
+        let mut batches = vec![];
+        let mut next = branch;
+        while let Some(branch_id) = next {
+            let branch = self.get_branch(branch_id)?;
+            batches.push(&branch.batch);
+            next = branch.parent;
+        }
+        Ok(BranchReader::new(&self.reader, batches))
+    }
+
+    /// Returns the version of `branch`.
+    pub fn version(&self, branch: BranchId) -> Result<Version> {
+        Ok(self.get_branch(branch)?.version)
+    }
+
+    /// Returns the root hash of the tree at `branch`.
+    pub fn root_hash(&self, branch: BranchId) -> Result<RootHash> {
+        Ok(self.get_branch(branch)?.root_hash)
+    }
+
+    /// Returns the parent of `branch`, or `None` if it was applied on top of the committed tree.
+    pub fn parent(&self, branch: BranchId) -> Result<Option<BranchId>> {
+        Ok(self.get_branch(branch)?.parent)
+    }
+
+    /// Returns all the uncommitted branches, in the order they were created.
+    pub fn branches(&self) -> impl Iterator<Item = BranchId> + '_ {
+        self.branches.keys().copied()
+    }
+
+    /// Finalizes `branch`: returns the version, root hash and updates of `branch` and of its
+    /// uncommitted ancestors, oldest first, and drops every branch which does not descend from
+    /// `branch`. The branches descending from it are kept, on top of the committed tree.
+    ///
+    /// The returned updates must be written to the underlying storage, in order, before the
+    /// remaining branches are read or extended.
+    pub fn finalize(
+        &mut self,
+        branch: BranchId,
+    ) -> Result<Vec<(Version, RootHash, TreeUpdateBatch)>> {
+        self.get_branch(branch)?;
+        let descendants: Vec<BranchId> = self
+            .branches()
+            .filter(|branch_id| *branch_id != branch && self.descends_from(*branch_id, branch))
+            .collect();
+
+        let mut finalized = vec![];
+        let mut next = Some(branch);
+        while let Some(branch_id) = next {
+            let branch = self
+                .branches
+                .remove(&branch_id)
+                .expect("Ancestors of an existing branch must exist.");
+            next = branch.parent;
+            finalized.push((branch.version, branch.root_hash, branch.batch));
+        }
+        finalized.reverse();
+
+        self.branches
+            .retain(|branch_id, _| descendants.contains(branch_id));
+        for child in self.branches.values_mut() {
+            if child.parent == Some(branch) {
+                child.parent = None;
+            }
+        }
+        Ok(finalized)
+    }
+
+    /// Drops `branch` and all the branches descending from it.
+    pub fn discard(&mut self, branch: BranchId) -> Result<()> {
+        self.get_branch(branch)?;
+        let discarded: Vec<BranchId> = self
+            .branches()
+            .filter(|branch_id| self.descends_from(*branch_id, branch))
+            .collect();
+        for branch_id in discarded {
+            self.branches.remove(&branch_id);
+        }
+        Ok(())
+    }
+
+    /// Returns the underlying reader.
+    pub fn inner(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_branch(&self, branch: BranchId) -> Result<&Branch> {
+        self.branches
+            .get(&branch)
+            .ok_or_else(|| format_err!("Unknown branch {:?}.", branch))
+    }
+
+    /// Returns true if `ancestor` is `branch` or one of its ancestors.
+    fn descends_from(&self, branch: BranchId, ancestor: BranchId) -> bool {
+        let mut next = Some(branch);
+        while let Some(branch_id) = next {
+            if branch_id == ancestor {
+                return true;
+            }
+            next = self
+                .branches
+                .get(&branch_id)
+                .and_then(|branch| branch.parent);
+        }
+        false
+    }
+}
+
+/// A [`TreeReader`] over the state of one branch of a [`ForkedTree`]: the updates of the branch
+/// and of its uncommitted ancestors, on top of the committed tree.
+pub struct BranchReader<'a, R> {
+    reader: &'a R,
+    /// The updates of the branch and of its ancestors, newest first.
+    batches: Vec<&'a TreeUpdateBatch>,
+}
+
+impl<'a, R> BranchReader<'a, R> {
+    /// Creates a reader over `batches`, newest first, on top of `reader`.
+    pub(crate) fn new(reader: &'a R, batches: Vec<&'a TreeUpdateBatch>) -> Self {
+        Self { reader, batches }
+    }
+}
+
+impl<R: TreeReader> TreeReader for BranchReader<'_, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self
+            .batches
+            .iter()
+            .find_map(|batch| batch.node_batch.get_node(node_key))
+        {
+            Some(node) => Ok(Some(node.clone())),
+            None => self.reader.get_node_option(node_key),
+        }
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let mut nodes: Vec<Option<Node>> = node_keys
+            .iter()
+            .map(|node_key| {
+                self.batches
+                    .iter()
+                    .find_map(|batch| batch.node_batch.get_node(node_key))
+                    .cloned()
+            })
+            .collect();
+        read_missing_nodes(self.reader, node_keys, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        // Uncommitted nodes are not kept encoded: returning `None` makes the read path fall back
+        // to `get_node_option`.
+        if self
+            .batches
+            .iter()
+            .any(|batch| batch.node_batch.get_node(node_key).is_some())
+        {
+            return Ok(None);
+        }
+        self.reader.get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        match self
+            .batches
+            .iter()
+            .find_map(|batch| pending_value(&batch.node_batch, max_version, key_hash))
+        {
+            Some(value) => Ok(value.as_deref().map(<[u8]>::to_vec)),
+            None => self.reader.get_value_option(max_version, key_hash),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.reader.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.reader.get_indexed_root_hashes(versions, limit)
+    }
+}
+
+impl<R: HasPreimage> HasPreimage for BranchReader<'_, R> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.reader.preimage(key_hash)
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.reader.preimages(key_hashes)
+    }
+}
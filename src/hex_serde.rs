@@ -0,0 +1,72 @@
+//! Serde representations of hashes as hex strings in human-readable formats such as JSON, for use
+//! with `#[serde(with = "...")]`. Binary formats keep encoding hashes as 32-byte tuples.
+
+/// A single 32-byte hash.
+pub(crate) mod hash {
+    use alloc::string::String;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        hash: &[u8; 32],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(hash))
+        } else {
+            hash.serialize(serializer)
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; 32], D::Error> {
+        if deserializer.is_human_readable() {
+            decode(&String::deserialize(deserializer)?)
+        } else {
+            <[u8; 32]>::deserialize(deserializer)
+        }
+    }
+
+    pub(super) fn decode<E: Error>(hex: &str) -> Result<[u8; 32], E> {
+        let mut hash = [0; 32];
+        hex::decode_to_slice(hex, &mut hash).map_err(|error| {
+            E::custom(format_args!(
+                "invalid 32-byte hex hash {:?}: {}",
+                hex, error
+            ))
+        })?;
+        Ok(hash)
+    }
+}
+
+/// A list of 32-byte hashes.
+pub(crate) mod hashes {
+    use alloc::{string::String, vec::Vec};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        hashes: &[[u8; 32]],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(hashes.iter().map(hex::encode))
+        } else {
+            hashes.serialize(serializer)
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<[u8; 32]>, D::Error> {
+        if deserializer.is_human_readable() {
+            Vec::<String>::deserialize(deserializer)?
+                .iter()
+                .map(|hex| super::hash::decode(hex))
+                .collect()
+        } else {
+            Vec::deserialize(deserializer)
+        }
+    }
+}
@@ -1,19 +1,65 @@
-use anyhow::{anyhow, Result};
+use alloc::vec;
 
-use crate::{storage::TreeReader, JellyfishMerkleTree, KeyHash, SimpleHasher, Version};
+#[cfg(feature = "std")]
+use anyhow::{anyhow, bail, Result};
+use sha2::{Sha256, Sha512_256};
 
+#[cfg(feature = "std")]
+use crate::{
+    error::{bail_invalid_proof, invalid_proof},
+    proof::{ExclusionProof, SparseMerkleProof},
+    storage::TreeReader,
+    Bytes32Ext, JellyfishMerkleTree, JmtError, KeyHash, RootHash, ValueHash, Version,
+};
+use crate::{DomainSeparators, Personalized, SimpleHasher};
+
+/// A [`SimpleHasher`] which computes the same hash function as an [`ics23::HashOp`], so that
+/// proofs from a tree using it can be checked by ics23 verifiers.
+pub trait Ics23Hasher: SimpleHasher {
+    /// The ics23 hash operation matching this hasher.
+    const HASH_OP: ics23::HashOp;
+}
+
+impl Ics23Hasher for Sha256 {
+    const HASH_OP: ics23::HashOp = ics23::HashOp::Sha256;
+}
+
+impl Ics23Hasher for Sha512_256 {
+    const HASH_OP: ics23::HashOp = ics23::HashOp::Sha512256;
+}
+
+/// Keccak-256 is the hash function of the `KECCAK` hash operation for the ics23 verifiers of
+/// EVM chains. The Rust implementation of ics23 computes SHA3-512 for that operation instead, so
+/// proofs from a tree using Keccak-256 are checked natively with [`verify_ics23_membership`].
+#[cfg(feature = "keccak")]
+impl Ics23Hasher for sha3::Keccak256 {
+    const HASH_OP: ics23::HashOp = ics23::HashOp::Keccak;
+}
+
+impl<H: Ics23Hasher, D: DomainSeparators> Ics23Hasher for Personalized<H, D> {
+    const HASH_OP: ics23::HashOp = {
+        assert!(
+            D::VALUE.is_empty(),
+            "ics23 leaf operations cannot prepend a domain separator to values."
+        );
+        H::HASH_OP
+    };
+}
+
+#[cfg(feature = "std")]
 impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
 where
     R: 'a + TreeReader,
-    H: SimpleHasher,
+    H: Ics23Hasher,
 {
     /// Returns the value and an [`ics23::ExistenceProof`].
     pub fn get_with_ics23_proof(
         &self,
-        key: Vec<u8>,
+        key: impl AsRef<[u8]>,
         version: Version,
     ) -> Result<ics23::ExistenceProof> {
-        let key_hash = KeyHash::with::<H>(key.as_slice());
+        let key = key.as_ref();
+        let key_hash = KeyHash::with::<H>(key);
         let (value, proof) = self.get_with_proof(key_hash, version)?;
         let value = value.ok_or_else(|| {
             anyhow!(
@@ -22,80 +68,328 @@ where
             )
         })?;
 
-        let mut path = Vec::new();
-        let mut skip = 256 - proof.siblings().len();
-        let mut sibling_idx = 0;
+        Ok(existence_proof(
+            key.to_vec(),
+            key_hash,
+            value,
+            &proof,
+            leaf_op::<H>(),
+        ))
+    }
+
+    /// Returns an [`ics23::CommitmentProof`] holding an [`ics23::BatchProof`] with an
+    /// [`ics23::ExistenceProof`] for each of `keys`, so that they can all be verified at once with
+    /// [`ics23::verify_batch_membership`].
+    pub fn get_with_ics23_proofs(
+        &self,
+        keys: impl IntoIterator<Item = impl AsRef<[u8]>>,
+        version: Version,
+    ) -> Result<ics23::CommitmentProof> {
+        let entries = keys
+            .into_iter()
+            .map(|key| {
+                Ok(ics23::BatchEntry {
+                    proof: Some(ics23::batch_entry::Proof::Exist(
+                        self.get_with_ics23_proof(key, version)?,
+                    )),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Batch(ics23::BatchProof {
+                entries,
+            })),
+        })
+    }
 
-        for byte_idx in (0..32).rev() {
-            // The JMT proofs iterate over the bits in MSB order
-            for bit_idx in 0..8 {
-                if skip > 0 {
-                    skip -= 1;
-                    continue;
+    /// Returns an [`ics23::CommitmentProof`] holding an [`ics23::NonExistenceProof`] of `key` in the
+    /// tree at `version`, which must be empty, e.g. at genesis.
+    ///
+    /// The empty tree has no leaf to stand as a neighbor of `key`, so the proof has neither a left
+    /// nor a right neighbor, and only holds against the placeholder root hash. ics23 verifiers
+    /// reject such proofs, which are checked with [`verify_ics23_non_membership`] instead.
+    pub fn get_ics23_nonexistence_proof(
+        &self,
+        key: impl AsRef<[u8]>,
+        version: Version,
+    ) -> Result<ics23::CommitmentProof> {
+        let root_hash = self.get_root_hash(version)?;
+        if root_hash.0 != H::PLACEHOLDER_HASH {
+            return Err(anyhow!(
+                "Nonexistence proofs are only supported against the empty tree, but the tree at \
+                 version {} has root hash {:?}.",
+                version,
+                root_hash
+            ));
+        }
+        Ok(ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Nonexist(
+                ics23::NonExistenceProof {
+                    key: key.as_ref().to_vec(),
+                    left: None,
+                    right: None,
+                },
+            )),
+        })
+    }
+
+    /// Returns the length of the protobuf encoding of the proof
+    /// [`get_with_ics23_proof`](JellyfishMerkleTree::get_with_ics23_proof) would return for `key`
+    /// at `version`, without computing it: none of the siblings of the proof is hashed.
+    pub fn estimate_ics23_proof_size(
+        &self,
+        key: impl AsRef<[u8]>,
+        version: Version,
+    ) -> Result<usize> {
+        let key = key.as_ref();
+        let key_hash = KeyHash::with::<H>(key);
+        let (leaf, sibling_presence) = self.get_proof_shape(key_hash, version)?;
+        if leaf.map(|leaf| leaf.key_hash()) != Some(key_hash) {
+            return Err(anyhow!(
+                "Requested proof of inclusion for non-existent key {:?}",
+                key
+            ));
+        }
+        let value_len = self.reader().get_value(version, key_hash)?.len();
+
+        // Inner operations hold the hash operation, the domain separator and the sibling, either
+        // in their prefix or in their suffix.
+        let hash_op_len = 2;
+        let left_inner_op_len =
+            hash_op_len + delimited_len(H::INTERNAL_DOMAIN_SEPARATOR.len() + 32);
+        let right_inner_op_len =
+            hash_op_len + delimited_len(H::INTERNAL_DOMAIN_SEPARATOR.len()) + delimited_len(32);
+        let path_len: usize = (0..sibling_presence.len())
+            .map(|depth| {
+                // The path starts from the bottom of the tree, like the siblings.
+                let depth = sibling_presence.len() - 1 - depth;
+                if key_hash.0.get_bit(depth) {
+                    delimited_len(left_inner_op_len)
                 } else {
-                    let bit = (key_hash.0[byte_idx] >> bit_idx) & 0x1;
-                    // ICS23 InnerOp computes
-                    //    hash( prefix || current || suffix )
-                    // so we want to construct (prefix, suffix) so that this is
-                    // the correct hash-of-internal-node
-                    let (prefix, suffix) = if bit == 1 {
-                        // We want hash( domsep || sibling || current )
-                        // so prefix = domsep || sibling
-                        //    suffix = (empty)
-                        let mut prefix = Vec::with_capacity(16 + 32);
-                        prefix.extend_from_slice(b"JMT::IntrnalNode");
-                        prefix.extend_from_slice(&proof.siblings()[sibling_idx]);
-                        (prefix, Vec::new())
-                    } else {
-                        // We want hash( domsep || current || sibling )
-                        // so prefix = domsep
-                        //    suffix = sibling
-                        let prefix = b"JMT::IntrnalNode".to_vec();
-                        let suffix = proof.siblings()[sibling_idx].to_vec();
-                        (prefix, suffix)
-                    };
-                    path.push(ics23::InnerOp {
-                        hash: ics23::HashOp::Sha256.into(),
-                        prefix,
-                        suffix,
-                    });
-                    sibling_idx += 1;
+                    delimited_len(right_inner_op_len)
                 }
-            }
-        }
+            })
+            .sum();
+        let leaf_op_len = 3 * hash_op_len + delimited_len(H::LEAF_DOMAIN_SEPARATOR.len());
 
-        Ok(ics23::ExistenceProof {
-            key,
+        Ok(optional_delimited_len(key.len())
+            + optional_delimited_len(value_len)
+            + delimited_len(leaf_op_len)
+            + path_len)
+    }
+
+    /// Same as [`get_with_ics23_proofs`](JellyfishMerkleTree::get_with_ics23_proofs), but returns
+    /// an [`ics23::CompressedBatchProof`], in which the inner operations shared by several keys
+    /// are only included once.
+    /// Proofs generated separately are combined in the same way with [`compress_ics23_proofs`].
+    pub fn get_with_compressed_ics23_proofs(
+        &self,
+        keys: impl IntoIterator<Item = impl AsRef<[u8]>>,
+        version: Version,
+    ) -> Result<ics23::CommitmentProof> {
+        ics23::compress(&self.get_with_ics23_proofs(keys, version)?)
+    }
+
+    /// Same as [`get_with_ics23_proof`](JellyfishMerkleTree::get_with_ics23_proof), but for a key
+    /// given by its hash, which the proof holds in place of the key, so that the preimages of the
+    /// keys are not needed. The proof is checked against [`ics23_key_hash_spec_for`].
+    pub fn get_with_ics23_key_hash_proof(
+        &self,
+        key_hash: KeyHash,
+        version: Version,
+    ) -> Result<ics23::ExistenceProof> {
+        let (value, proof) = self.get_with_proof(key_hash, version)?;
+        let value = value.ok_or_else(|| {
+            anyhow!(
+                "Requested proof of inclusion for non-existent key hash {:?}",
+                key_hash
+            )
+        })?;
+        Ok(existence_proof(
+            key_hash.0.to_vec(),
+            key_hash,
             value,
-            path,
-            leaf: Some(ics23::LeafOp {
-                hash: ics23::HashOp::Sha256.into(),
-                prehash_key: ics23::HashOp::Sha256.into(),
-                prehash_value: ics23::HashOp::Sha256.into(),
-                length: ics23::LengthOp::NoPrefix.into(),
-                prefix: b"JMT::LeafNode".to_vec(),
-            }),
+            &proof,
+            key_hash_leaf_op::<H>(),
+        ))
+    }
+
+    /// Returns an [`ics23::CommitmentProof`] holding an [`ics23::NonExistenceProof`] of
+    /// `key_hash` in the tree at `version`, whose neighbors are the proofs of the closest keys on
+    /// either side of `key_hash`, as returned by
+    /// [`get_with_ics23_key_hash_proof`](JellyfishMerkleTree::get_with_ics23_key_hash_proof).
+    ///
+    /// Leaves are ordered by key hash, so ics23 verifiers check the neighbors of such proofs
+    /// against [`ics23_key_hash_spec_for`] without any preimage. As with
+    /// [`get_ics23_nonexistence_proof`](JellyfishMerkleTree::get_ics23_nonexistence_proof), the
+    /// proof has no neighbors if the tree is empty at `version`.
+    pub fn get_ics23_key_hash_nonexistence_proof(
+        &self,
+        key_hash: KeyHash,
+        version: Version,
+    ) -> Result<ics23::CommitmentProof> {
+        let neighbor_proof = |proof: SparseMerkleProof<H>| -> Result<ics23::ExistenceProof> {
+            let neighbor = proof
+                .leaf()
+                .ok_or_else(|| JmtError::Corruption("The proof of a neighbor has no leaf.".into()))?
+                .key_hash();
+            let value = self.reader().get_value(version, neighbor)?;
+            Ok(existence_proof(
+                neighbor.0.to_vec(),
+                neighbor,
+                value,
+                &proof,
+                key_hash_leaf_op::<H>(),
+            ))
+        };
+
+        let (left, right) = if self.get_root_hash(version)?.0 == H::PLACEHOLDER_HASH {
+            (None, None)
+        } else {
+            match self.get_with_exclusion_proof(key_hash, version)? {
+                Ok(_) => bail!("Key hash {:?} exists at version {}.", key_hash, version),
+                Err(ExclusionProof::Leftmost {
+                    leftmost_right_proof,
+                }) => (None, Some(neighbor_proof(leftmost_right_proof)?)),
+                Err(ExclusionProof::Middle {
+                    leftmost_right_proof,
+                    rightmost_left_proof,
+                }) => (
+                    Some(neighbor_proof(rightmost_left_proof)?),
+                    Some(neighbor_proof(leftmost_right_proof)?),
+                ),
+                Err(ExclusionProof::Rightmost {
+                    rightmost_left_proof,
+                }) => (Some(neighbor_proof(rightmost_left_proof)?), None),
+            }
+        };
+        Ok(ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Nonexist(
+                ics23::NonExistenceProof {
+                    key: key_hash.0.to_vec(),
+                    left,
+                    right,
+                },
+            )),
         })
     }
 }
 
+/// Returns the [`ics23::ExistenceProof`] of `key`, whose hash is `key_hash`, from its proof in
+/// the tree, with `leaf` as the leaf operation.
+#[cfg(feature = "std")]
+fn existence_proof<H: Ics23Hasher>(
+    key: Vec<u8>,
+    key_hash: KeyHash,
+    value: Vec<u8>,
+    proof: &SparseMerkleProof<H>,
+    leaf: ics23::LeafOp,
+) -> ics23::ExistenceProof {
+    let mut path = Vec::new();
+    let mut skip = 256 - proof.siblings().len();
+    let mut sibling_idx = 0;
+
+    for byte_idx in (0..32).rev() {
+        // The JMT proofs iterate over the bits in MSB order
+        for bit_idx in 0..8 {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            } else {
+                let bit = (key_hash.0[byte_idx] >> bit_idx) & 0x1;
+                // ICS23 InnerOp computes
+                //    hash( prefix || current || suffix )
+                // so we want to construct (prefix, suffix) so that this is
+                // the correct hash-of-internal-node
+                let (prefix, suffix) = if bit == 1 {
+                    // We want hash( domsep || sibling || current )
+                    // so prefix = domsep || sibling
+                    //    suffix = (empty)
+                    let mut prefix = Vec::with_capacity(H::INTERNAL_DOMAIN_SEPARATOR.len() + 32);
+                    prefix.extend_from_slice(H::INTERNAL_DOMAIN_SEPARATOR);
+                    prefix.extend_from_slice(&proof.siblings()[sibling_idx]);
+                    (prefix, Vec::new())
+                } else {
+                    // We want hash( domsep || current || sibling )
+                    // so prefix = domsep
+                    //    suffix = sibling
+                    let prefix = H::INTERNAL_DOMAIN_SEPARATOR.to_vec();
+                    let suffix = proof.siblings()[sibling_idx].to_vec();
+                    (prefix, suffix)
+                };
+                path.push(ics23::InnerOp {
+                    hash: H::HASH_OP.into(),
+                    prefix,
+                    suffix,
+                });
+                sibling_idx += 1;
+            }
+        }
+    }
+
+    ics23::ExistenceProof {
+        key,
+        value,
+        path,
+        leaf: Some(leaf),
+    }
+}
+
+/// Returns the length of a protobuf field holding `len` bytes, with a field number below 16.
+#[cfg(feature = "std")]
+fn delimited_len(len: usize) -> usize {
+    let varint_len = (usize::BITS - (len | 1).leading_zeros()).div_ceil(7) as usize;
+    1 + varint_len + len
+}
+
+/// Same as [`delimited_len`], for a field left out of the encoding when it is empty.
+#[cfg(feature = "std")]
+fn optional_delimited_len(len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        delimited_len(len)
+    }
+}
+
+fn leaf_op<H: Ics23Hasher>() -> ics23::LeafOp {
+    ics23::LeafOp {
+        hash: H::HASH_OP.into(),
+        prehash_key: H::HASH_OP.into(),
+        prehash_value: H::HASH_OP.into(),
+        length: ics23::LengthOp::NoPrefix.into(),
+        prefix: H::LEAF_DOMAIN_SEPARATOR.to_vec(),
+    }
+}
+
+/// The leaf operation of the proofs holding key hashes in place of keys, which are not hashed
+/// again.
+fn key_hash_leaf_op<H: Ics23Hasher>() -> ics23::LeafOp {
+    ics23::LeafOp {
+        prehash_key: ics23::HashOp::NoHash.into(),
+        ..leaf_op::<H>()
+    }
+}
+
+/// Returns the [`ics23::ProofSpec`] of a tree using [`Sha256`], which is the same as
+/// [`ics23_spec_for::<Sha256>`](ics23_spec_for).
 pub fn ics23_spec() -> ics23::ProofSpec {
+    ics23_spec_for::<Sha256>()
+}
+
+/// Returns the [`ics23::ProofSpec`] of a tree using the hasher `H`.
+pub fn ics23_spec_for<H: Ics23Hasher>() -> ics23::ProofSpec {
     ics23::ProofSpec {
-        leaf_spec: Some(ics23::LeafOp {
-            hash: ics23::HashOp::Sha256.into(),
-            prehash_key: ics23::HashOp::Sha256.into(),
-            prehash_value: ics23::HashOp::Sha256.into(),
-            length: ics23::LengthOp::NoPrefix.into(),
-            prefix: b"JMT::LeafNode".to_vec(),
-        }),
+        leaf_spec: Some(leaf_op::<H>()),
         inner_spec: Some(ics23::InnerSpec {
             // This is the only field we're sure about
-            hash: ics23::HashOp::Sha256.into(),
+            hash: H::HASH_OP.into(),
             // These fields are apparently used for neighbor tests in range proofs,
             // and could be wrong:
             child_order: vec![0, 1], //where exactly does this need to be true?
-            min_prefix_length: 16,   //what is this?
-            max_prefix_length: 48,   //and this?
+            min_prefix_length: H::INTERNAL_DOMAIN_SEPARATOR.len() as i32,
+            max_prefix_length: H::INTERNAL_DOMAIN_SEPARATOR.len() as i32 + 32,
             child_size: 32,
             empty_child: vec![], //check JMT repo to determine if special value used here
         }),
@@ -106,13 +400,262 @@ pub fn ics23_spec() -> ics23::ProofSpec {
     }
 }
 
+/// Returns the [`ics23::ProofSpec`] of the proofs of a tree using the hasher `H` which hold key
+/// hashes in place of keys, as returned by
+/// [`get_with_ics23_key_hash_proof`](JellyfishMerkleTree::get_with_ics23_key_hash_proof) and
+/// [`get_ics23_key_hash_nonexistence_proof`](JellyfishMerkleTree::get_ics23_key_hash_nonexistence_proof).
+///
+/// The leaf operation does not hash the key, so verifiers compare the key hashes of the neighbors
+/// of nonexistence proofs, in the order of the leaves of the tree. Empty subtrees are hashed to
+/// the placeholder hash, which is the empty child of the spec, so that neighbors separated by
+/// empty subtrees are still recognized as such.
+pub fn ics23_key_hash_spec_for<H: Ics23Hasher>() -> ics23::ProofSpec {
+    let spec = ics23_spec_for::<H>();
+    ics23::ProofSpec {
+        leaf_spec: Some(key_hash_leaf_op::<H>()),
+        inner_spec: spec.inner_spec.map(|inner_spec| ics23::InnerSpec {
+            empty_child: H::PLACEHOLDER_HASH.to_vec(),
+            ..inner_spec
+        }),
+        ..spec
+    }
+}
+
+/// Combines `proofs` into an [`ics23::CompressedBatchProof`], in which the inner operations shared
+/// by several entries are only included once, e.g. to send the proofs of several keys in a single
+/// IBC packet.
+///
+/// Each proof may hold an existence or nonexistence proof, or a batch or compressed batch of
+/// them, whose entries are all included, in order.
+#[cfg(feature = "std")]
+pub fn compress_ics23_proofs(
+    proofs: impl IntoIterator<Item = ics23::CommitmentProof>,
+) -> Result<ics23::CompressedBatchProof> {
+    let mut entries = Vec::new();
+    for proof in proofs {
+        match ics23::decompress(&proof)?.proof {
+            Some(ics23::commitment_proof::Proof::Exist(existence_proof)) => {
+                entries.push(ics23::BatchEntry {
+                    proof: Some(ics23::batch_entry::Proof::Exist(existence_proof)),
+                })
+            }
+            Some(ics23::commitment_proof::Proof::Nonexist(nonexistence_proof)) => {
+                entries.push(ics23::BatchEntry {
+                    proof: Some(ics23::batch_entry::Proof::Nonexist(nonexistence_proof)),
+                })
+            }
+            Some(ics23::commitment_proof::Proof::Batch(batch)) => entries.extend(batch.entries),
+            _ => bail_invalid_proof!("Not an existence, nonexistence or batch proof."),
+        }
+    }
+
+    let batch = ics23::CommitmentProof {
+        proof: Some(ics23::commitment_proof::Proof::Batch(ics23::BatchProof {
+            entries,
+        })),
+    };
+    match ics23::compress(&batch)?.proof {
+        Some(ics23::commitment_proof::Proof::Compressed(compressed)) => Ok(compressed),
+        _ => unreachable!("Compressing a batch proof returns a compressed batch proof."),
+    }
+}
+
+/// Splits `proof` back into one [`ics23::CommitmentProof`] per entry, holding its existence or
+/// nonexistence proof, in the order of the entries.
+#[cfg(feature = "std")]
+pub fn decompress_ics23_proofs(
+    proof: &ics23::CompressedBatchProof,
+) -> Result<Vec<ics23::CommitmentProof>> {
+    let compressed = ics23::CommitmentProof {
+        proof: Some(ics23::commitment_proof::Proof::Compressed(proof.clone())),
+    };
+    let entries = match ics23::decompress(&compressed)?.proof {
+        Some(ics23::commitment_proof::Proof::Batch(batch)) => batch.entries,
+        _ => unreachable!("Decompressing a compressed batch proof returns a batch proof."),
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let proof = match entry.proof {
+                Some(ics23::batch_entry::Proof::Exist(existence_proof)) => {
+                    ics23::commitment_proof::Proof::Exist(existence_proof)
+                }
+                Some(ics23::batch_entry::Proof::Nonexist(nonexistence_proof)) => {
+                    ics23::commitment_proof::Proof::Nonexist(nonexistence_proof)
+                }
+                None => bail_invalid_proof!("Empty entry in a compressed batch proof."),
+            };
+            Ok(ics23::CommitmentProof { proof: Some(proof) })
+        })
+        .collect()
+}
+
+/// Checks that `proof` proves that `key` is mapped to `value` in the tree whose root hash is
+/// `root_hash`, without going through the ics23 verifier and its host functions.
+///
+/// `proof` may be an existence proof, as returned by
+/// [`get_with_ics23_proof`](JellyfishMerkleTree::get_with_ics23_proof), or a batch or compressed
+/// batch proof holding an existence proof for `key`. Only proofs of the shape generated by this
+/// crate for the hasher `H` are accepted.
+#[cfg(feature = "std")]
+pub fn verify_ics23_membership<H: Ics23Hasher>(
+    proof: &ics23::CommitmentProof,
+    root_hash: RootHash,
+    key: &[u8],
+    value: &[u8],
+) -> Result<()> {
+    verify_ics23_batch_membership::<H>(proof, root_hash, [(key, value)])
+}
+
+/// Checks that `proof` proves that each of the keys of `items` is mapped to its value in the tree
+/// whose root hash is `root_hash`, as [`verify_ics23_membership`] does for a single key.
+#[cfg(feature = "std")]
+pub fn verify_ics23_batch_membership<H: Ics23Hasher>(
+    proof: &ics23::CommitmentProof,
+    root_hash: RootHash,
+    items: impl IntoIterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+) -> Result<()> {
+    let proof = ics23::decompress(proof)?;
+    let existence_proofs = match &proof.proof {
+        Some(ics23::commitment_proof::Proof::Exist(existence_proof)) => vec![existence_proof],
+        Some(ics23::commitment_proof::Proof::Batch(batch)) => batch
+            .entries
+            .iter()
+            .filter_map(|entry| match &entry.proof {
+                Some(ics23::batch_entry::Proof::Exist(existence_proof)) => Some(existence_proof),
+                _ => None,
+            })
+            .collect(),
+        _ => bail_invalid_proof!("Not an existence or batch proof."),
+    };
+
+    for (key, value) in items {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        let existence_proof = existence_proofs
+            .iter()
+            .find(|existence_proof| existence_proof.key == key)
+            .ok_or_else(|| invalid_proof!("No existence proof for key {:?}.", key))?;
+        verify_existence::<H>(existence_proof, root_hash, key, value)?;
+    }
+    Ok(())
+}
+
+/// Checks that `proof` proves that `key` is not in the tree whose root hash is `root_hash`.
+///
+/// Only the proofs against the empty tree returned by
+/// [`get_ics23_nonexistence_proof`](JellyfishMerkleTree::get_ics23_nonexistence_proof), which
+/// have no neighbors, are supported. `proof` may also be a batch or compressed batch proof holding
+/// such a proof for `key`.
+#[cfg(feature = "std")]
+pub fn verify_ics23_non_membership<H: Ics23Hasher>(
+    proof: &ics23::CommitmentProof,
+    root_hash: RootHash,
+    key: &[u8],
+) -> Result<()> {
+    let proof = ics23::decompress(proof)?;
+    let nonexistence_proof = match &proof.proof {
+        Some(ics23::commitment_proof::Proof::Nonexist(nonexistence_proof))
+            if nonexistence_proof.key == key =>
+        {
+            Some(nonexistence_proof)
+        }
+        Some(ics23::commitment_proof::Proof::Batch(batch)) => {
+            batch.entries.iter().find_map(|entry| match &entry.proof {
+                Some(ics23::batch_entry::Proof::Nonexist(nonexistence_proof))
+                    if nonexistence_proof.key == key =>
+                {
+                    Some(nonexistence_proof)
+                }
+                _ => None,
+            })
+        }
+        _ => None,
+    }
+    .ok_or_else(|| invalid_proof!("No nonexistence proof for key {:?}.", key))?;
+
+    if nonexistence_proof.left.is_some() || nonexistence_proof.right.is_some() {
+        bail_invalid_proof!("Only nonexistence proofs against the empty tree are supported.");
+    }
+    if root_hash.0 != H::PLACEHOLDER_HASH {
+        bail_invalid_proof!(
+            "A nonexistence proof without neighbors only holds against the empty tree, not root \
+             hash {:?}.",
+            root_hash
+        );
+    }
+    Ok(())
+}
+
+/// Recomputes the root hash committed to by an existence proof, checking that each of its
+/// operations hashes a node of the tree the way this crate does.
+#[cfg(feature = "std")]
+fn verify_existence<H: Ics23Hasher>(
+    proof: &ics23::ExistenceProof,
+    root_hash: RootHash,
+    key: &[u8],
+    value: &[u8],
+) -> Result<()> {
+    if proof.value != value {
+        bail_invalid_proof!("The proof is for another value of key {:?}.", key);
+    }
+    if proof.leaf.as_ref() != Some(&leaf_op::<H>()) {
+        bail_invalid_proof!("Unexpected leaf operation.");
+    }
+    if proof.path.len() > 256 {
+        bail_invalid_proof!("The proof has more than 256 inner operations.");
+    }
+
+    let key_hash = KeyHash::with::<H>(key);
+    let mut hasher = H::new();
+    hasher.update(H::LEAF_DOMAIN_SEPARATOR);
+    hasher.update(&key_hash.0);
+    hasher.update(&ValueHash::with::<H>(value).0);
+    let mut current_hash = hasher.finalize();
+
+    // The operations go from the leaf up to the root, so the first one is at the depth of the
+    // leaf.
+    for (depth, op) in (0..proof.path.len()).rev().zip(&proof.path) {
+        let is_right_child = (key_hash.0[depth / 8] >> (7 - depth % 8)) & 1 == 1;
+        let expected_prefix_len = if is_right_child {
+            H::INTERNAL_DOMAIN_SEPARATOR.len() + 32
+        } else {
+            H::INTERNAL_DOMAIN_SEPARATOR.len()
+        };
+        if op.hash != H::HASH_OP as i32
+            || !op.prefix.starts_with(H::INTERNAL_DOMAIN_SEPARATOR)
+            || op.prefix.len() != expected_prefix_len
+            || op.prefix.len() + op.suffix.len() != H::INTERNAL_DOMAIN_SEPARATOR.len() + 32
+        {
+            bail_invalid_proof!(
+                "Unexpected inner operation at depth {} of the proof.",
+                depth
+            );
+        }
+        let mut hasher = H::new();
+        hasher.update(&op.prefix);
+        hasher.update(&current_hash);
+        hasher.update(&op.suffix);
+        current_hash = hasher.finalize();
+    }
+
+    if current_hash != root_hash.0 {
+        bail_invalid_proof!(
+            "The proof of key {:?} does not match the root hash {:?}.",
+            key,
+            root_hash
+        );
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use ics23::HostFunctionsManager;
-    use sha2::Sha256;
 
     use super::*;
-    use crate::{mock::MockTreeStore, KeyHash, Sha256JMT};
+    use crate::{mock::MockTreeStore, proof::SparseMerkleProof, KeyHash, Sha256JMT};
 
     #[test]
     fn test_jmt_ics23_existence() {
@@ -135,7 +678,7 @@ mod tests {
         let (new_root_hash, batch) = tree.put_value_set(kvs, 0).unwrap();
         db.write_tree_update_batch(batch).unwrap();
 
-        let existence_proof = tree.get_with_ics23_proof(b"key".to_vec(), 0).unwrap();
+        let existence_proof = tree.get_with_ics23_proof(b"key", 0).unwrap();
 
         let commitment_proof = ics23::CommitmentProof {
             proof: Some(ics23::commitment_proof::Proof::Exist(existence_proof)),
@@ -150,6 +693,473 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_jmt_ics23_existence_sha512_256() {
+        let db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::<_, Sha512_256>::new(&db);
+
+        let kvs = (0..20).map(|i| {
+            (
+                KeyHash::with::<Sha512_256>(format!("key{}", i)),
+                Some(format!("value{}", i).into_bytes()),
+            )
+        });
+        let (root_hash, batch) = tree.put_value_set(kvs, 0).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let existence_proof = tree.get_with_ics23_proof(b"key7", 0).unwrap();
+        let commitment_proof = ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Exist(existence_proof)),
+        };
+
+        assert!(ics23::verify_membership::<HostFunctionsManager>(
+            &commitment_proof,
+            &ics23_spec_for::<Sha512_256>(),
+            &root_hash.0.to_vec(),
+            b"key7",
+            b"value7",
+        ));
+        // The proof does not match the spec of another hasher.
+        assert!(!ics23::verify_membership::<HostFunctionsManager>(
+            &commitment_proof,
+            &ics23_spec(),
+            &root_hash.0.to_vec(),
+            b"key7",
+            b"value7",
+        ));
+    }
+
+    #[test]
+    fn test_jmt_ics23_batch_existence() {
+        let db = MockTreeStore::default();
+        let tree = Sha256JMT::new(&db);
+
+        let kvs: Vec<(Vec<u8>, Vec<u8>)> = (0..50)
+            .map(|i| {
+                (
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+            })
+            .collect();
+        let (root_hash, batch) = tree
+            .put_value_set(
+                kvs.iter()
+                    .map(|(key, value)| (KeyHash::with::<Sha256>(key), Some(value.clone()))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let keys = || kvs.iter().map(|(key, _)| key);
+        let items = kvs
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+            .collect();
+        let batch_proof = tree.get_with_ics23_proofs(keys(), 0).unwrap();
+        let compressed_proof = tree.get_with_compressed_ics23_proofs(keys(), 0).unwrap();
+        // The paths of the keys share their upper inner operations, which are only stored once.
+        let num_inner_ops = match &batch_proof.proof {
+            Some(ics23::commitment_proof::Proof::Batch(batch)) => batch
+                .entries
+                .iter()
+                .map(|entry| match &entry.proof {
+                    Some(ics23::batch_entry::Proof::Exist(exist)) => exist.path.len(),
+                    _ => panic!("Expected existence proofs."),
+                })
+                .sum(),
+            _ => panic!("Expected a batch proof."),
+        };
+        match &compressed_proof.proof {
+            Some(ics23::commitment_proof::Proof::Compressed(compressed)) => {
+                assert!(compressed.lookup_inners.len() < num_inner_ops)
+            }
+            _ => panic!("Expected a compressed batch proof."),
+        }
+
+        for proof in [&batch_proof, &compressed_proof] {
+            assert!(ics23::verify_batch_membership::<HostFunctionsManager>(
+                proof,
+                &ics23_spec(),
+                &root_hash.0.to_vec(),
+                BTreeMap::clone(&items),
+            ));
+        }
+
+        // A proof for some of the keys does not prove any other key.
+        let partial_proof = tree.get_with_ics23_proofs([b"key0"], 0).unwrap();
+        assert!(!ics23::verify_batch_membership::<HostFunctionsManager>(
+            &partial_proof,
+            &ics23_spec(),
+            &root_hash.0.to_vec(),
+            items,
+        ));
+    }
+
+    #[test]
+    fn test_jmt_ics23_native_verification() {
+        let db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::<_, Sha512_256>::new(&db);
+
+        let kvs: Vec<(Vec<u8>, Vec<u8>)> = (0..30)
+            .map(|i| {
+                (
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+            })
+            .collect();
+        let (root_hash, batch) = tree
+            .put_value_set(
+                kvs.iter()
+                    .map(|(key, value)| (KeyHash::with::<Sha512_256>(key), Some(value.clone()))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let existence_proof = tree.get_with_ics23_proof(b"key3", 0).unwrap();
+        let mut proof = ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Exist(
+                existence_proof.clone(),
+            )),
+        };
+        verify_ics23_membership::<Sha512_256>(&proof, root_hash, b"key3", b"value3").unwrap();
+        assert!(
+            verify_ics23_membership::<Sha512_256>(&proof, root_hash, b"key3", b"value4").is_err()
+        );
+        assert!(
+            verify_ics23_membership::<Sha512_256>(&proof, root_hash, b"key4", b"value3").is_err()
+        );
+        assert!(verify_ics23_membership::<Sha256>(&proof, root_hash, b"key3", b"value3").is_err());
+
+        // Moving the sibling of an inner operation to the other side changes the root hash, and
+        // no longer follows the path of the key.
+        let mut tampered_proof = existence_proof;
+        let op = &mut tampered_proof.path[0];
+        if op.suffix.is_empty() {
+            op.suffix = op
+                .prefix
+                .split_off(Sha512_256::INTERNAL_DOMAIN_SEPARATOR.len());
+        } else {
+            op.prefix.append(&mut op.suffix);
+        }
+        proof.proof = Some(ics23::commitment_proof::Proof::Exist(tampered_proof));
+        assert!(
+            verify_ics23_membership::<Sha512_256>(&proof, root_hash, b"key3", b"value3").is_err()
+        );
+
+        let keys = || kvs.iter().map(|(key, _)| key);
+        let items = || kvs.iter().map(|(key, value)| (key, value));
+        for proof in [
+            tree.get_with_ics23_proofs(keys(), 0).unwrap(),
+            tree.get_with_compressed_ics23_proofs(keys(), 0).unwrap(),
+        ] {
+            verify_ics23_batch_membership::<Sha512_256>(&proof, root_hash, items()).unwrap();
+            verify_ics23_membership::<Sha512_256>(&proof, root_hash, b"key9", b"value9").unwrap();
+            assert!(verify_ics23_batch_membership::<Sha512_256>(
+                &proof,
+                RootHash([0; 32]),
+                items()
+            )
+            .is_err());
+        }
+        let partial_proof = tree.get_with_ics23_proofs([b"key0"], 0).unwrap();
+        assert!(
+            verify_ics23_batch_membership::<Sha512_256>(&partial_proof, root_hash, items())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_jmt_ics23_nonexistence_empty_tree() {
+        let db = MockTreeStore::default();
+        let tree = Sha256JMT::new(&db);
+
+        let (root_hash, batch) = tree.put_value_set([], 0).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        assert_eq!(root_hash.0, Sha256::PLACEHOLDER_HASH);
+
+        let proof = tree.get_ics23_nonexistence_proof(b"key", 0).unwrap();
+        verify_ics23_non_membership::<Sha256>(&proof, root_hash, b"key").unwrap();
+        assert!(verify_ics23_non_membership::<Sha256>(&proof, root_hash, b"other key").is_err());
+        assert!(verify_ics23_membership::<Sha256>(&proof, root_hash, b"key", b"value").is_err());
+
+        let (root_hash, batch) = tree
+            .put_value_set([(KeyHash::with::<Sha256>(b"other key"), Some(vec![1]))], 1)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        assert!(verify_ics23_non_membership::<Sha256>(&proof, root_hash, b"key").is_err());
+        assert!(tree.get_ics23_nonexistence_proof(b"key", 1).is_err());
+    }
+
+    #[test]
+    fn test_jmt_ics23_key_hash_nonexistence() {
+        let db = MockTreeStore::default();
+        let tree = Sha256JMT::new(&db);
+
+        // No preimage is stored for the keys of the tree.
+        let key_hashes: Vec<KeyHash> = (0..50)
+            .map(|i| KeyHash::with::<Sha256>(format!("key{}", i)))
+            .collect();
+        let (root_hash, batch) = tree
+            .put_value_set(
+                key_hashes
+                    .iter()
+                    .map(|key_hash| (*key_hash, Some(key_hash.0[..4].to_vec()))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let spec = ics23_key_hash_spec_for::<Sha256>();
+        let root = root_hash.0.to_vec();
+
+        let existence_proof = tree
+            .get_with_ics23_key_hash_proof(key_hashes[7], 0)
+            .unwrap();
+        let commitment_proof = ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Exist(existence_proof)),
+        };
+        assert!(ics23::verify_membership::<HostFunctionsManager>(
+            &commitment_proof,
+            &spec,
+            &root,
+            &key_hashes[7].0,
+            &key_hashes[7].0[..4],
+        ));
+        assert!(!ics23::verify_membership::<HostFunctionsManager>(
+            &commitment_proof,
+            &ics23_spec(),
+            &root,
+            &key_hashes[7].0,
+            &key_hashes[7].0[..4],
+        ));
+
+        // Missing keys on either side of all the keys of the tree, and between them.
+        let missing_key_hashes = (0..20)
+            .map(|i| KeyHash::with::<Sha256>(format!("missing key{}", i)))
+            .chain([KeyHash([0; 32]), KeyHash([0xff; 32])]);
+        for missing_key_hash in missing_key_hashes {
+            let proof = tree
+                .get_ics23_key_hash_nonexistence_proof(missing_key_hash, 0)
+                .unwrap();
+            assert!(ics23::verify_non_membership::<HostFunctionsManager>(
+                &proof,
+                &spec,
+                &root,
+                &missing_key_hash.0,
+            ));
+            assert!(!ics23::verify_non_membership::<HostFunctionsManager>(
+                &proof,
+                &spec,
+                &root,
+                &key_hashes[0].0,
+            ));
+        }
+        assert!(tree
+            .get_ics23_key_hash_nonexistence_proof(key_hashes[0], 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_compress_ics23_proofs() {
+        let db = MockTreeStore::default();
+        let tree = Sha256JMT::new(&db);
+
+        let kvs: Vec<(Vec<u8>, Vec<u8>)> = (0..30)
+            .map(|i| {
+                (
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
+            })
+            .collect();
+        let (root_hash, batch) = tree
+            .put_value_set(
+                kvs.iter()
+                    .map(|(key, value)| (KeyHash::with::<Sha256>(key), Some(value.clone()))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let missing_key_hash = KeyHash::with::<Sha256>(b"missing key");
+        let proofs: Vec<ics23::CommitmentProof> = kvs[..10]
+            .iter()
+            .map(|(key, _)| ics23::CommitmentProof {
+                proof: Some(ics23::commitment_proof::Proof::Exist(
+                    tree.get_with_ics23_proof(key, 0).unwrap(),
+                )),
+            })
+            .chain([tree
+                .get_ics23_key_hash_nonexistence_proof(missing_key_hash, 0)
+                .unwrap()])
+            .collect();
+        // Batches are flattened into the entries of the compressed proof.
+        let batch_proof = tree
+            .get_with_ics23_proofs(kvs[10..].iter().map(|(key, _)| key), 0)
+            .unwrap();
+        let compressed =
+            compress_ics23_proofs(proofs.iter().cloned().chain([batch_proof])).unwrap();
+        assert_eq!(compressed.entries.len(), kvs.len() + 1);
+        let num_inner_ops: usize = proofs
+            .iter()
+            .map(|proof| match &proof.proof {
+                Some(ics23::commitment_proof::Proof::Exist(existence_proof)) => {
+                    existence_proof.path.len()
+                }
+                _ => 0,
+            })
+            .sum();
+        assert!(compressed.lookup_inners.len() < num_inner_ops);
+
+        let decompressed = decompress_ics23_proofs(&compressed).unwrap();
+        assert_eq!(decompressed.len(), kvs.len() + 1);
+        assert_eq!(&decompressed[..proofs.len()], &proofs[..]);
+        for ((key, value), proof) in kvs.iter().zip(decompressed.iter().take(10)) {
+            verify_ics23_membership::<Sha256>(proof, root_hash, key, value).unwrap();
+        }
+        assert!(ics23::verify_non_membership::<HostFunctionsManager>(
+            &decompressed[10],
+            &ics23_key_hash_spec_for::<Sha256>(),
+            &root_hash.0.to_vec(),
+            &missing_key_hash.0,
+        ));
+        for ((key, value), proof) in kvs[10..].iter().zip(&decompressed[11..]) {
+            verify_ics23_membership::<Sha256>(proof, root_hash, key, value).unwrap();
+        }
+
+        let empty = ics23::CommitmentProof { proof: None };
+        assert!(compress_ics23_proofs([empty]).is_err());
+    }
+
+    #[test]
+    fn test_jmt_ics23_personalized_domain_separators() {
+        struct TestChain;
+
+        impl DomainSeparators for TestChain {
+            const INTERNAL: &'static [u8] = b"TestChain::Internal";
+            const LEAF: &'static [u8] = b"TestChain::Leaf";
+        }
+
+        type TestChainHasher = Personalized<Sha256, TestChain>;
+
+        let db = MockTreeStore::default();
+        let tree = JellyfishMerkleTree::<_, TestChainHasher>::new(&db);
+        let default_db = MockTreeStore::default();
+        let default_tree = Sha256JMT::new(&default_db);
+        let value_set: Vec<_> = (0..20)
+            .map(|i| (KeyHash::with::<Sha256>(format!("key{}", i)), Some(vec![i])))
+            .collect();
+        let (root_hash, batch) = tree.put_value_set(value_set.clone(), 0).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        let (default_root_hash, batch) = default_tree.put_value_set(value_set, 0).unwrap();
+        default_db.write_tree_update_batch(batch).unwrap();
+        assert_ne!(root_hash, default_root_hash);
+
+        // Native proofs only verify with the hasher of their tree.
+        let key = KeyHash::with::<Sha256>("key3");
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        proof.verify(root_hash, key, value.as_ref()).unwrap();
+        let (value, default_proof) = default_tree.get_with_proof(key, 0).unwrap();
+        let replayed_proof: SparseMerkleProof<TestChainHasher> =
+            SparseMerkleProof::from_compact_bytes(&default_proof.to_compact_bytes()).unwrap();
+        assert!(replayed_proof
+            .verify(default_root_hash, key, value.as_ref())
+            .is_err());
+
+        // So do ics23 proofs, whose spec carries the domain separators.
+        let existence_proof = tree.get_with_ics23_proof(b"key3", 0).unwrap();
+        let commitment_proof = ics23::CommitmentProof {
+            proof: Some(ics23::commitment_proof::Proof::Exist(existence_proof)),
+        };
+        assert!(ics23::verify_membership::<HostFunctionsManager>(
+            &commitment_proof,
+            &ics23_spec_for::<TestChainHasher>(),
+            &root_hash.0.to_vec(),
+            b"key3",
+            &[3],
+        ));
+        assert!(!ics23::verify_membership::<HostFunctionsManager>(
+            &commitment_proof,
+            &ics23_spec(),
+            &root_hash.0.to_vec(),
+            b"key3",
+            &[3],
+        ));
+        verify_ics23_membership::<TestChainHasher>(&commitment_proof, root_hash, b"key3", &[3])
+            .unwrap();
+        assert!(
+            verify_ics23_membership::<Sha256>(&commitment_proof, root_hash, b"key3", &[3]).is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "keccak")]
+    fn test_jmt_ics23_keccak() {
+        use crate::{Keccak256, Keccak256JMT};
+
+        assert_eq!(
+            hex::encode(Keccak256::hash(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+
+        let db = MockTreeStore::default();
+        let tree = Keccak256JMT::new(&db);
+        let (root_hash, batch) = tree
+            .put_value_set(
+                (0..30u8).map(|i| (KeyHash::with::<Keccak256>([i]), Some(vec![i; 4]))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        let spec = ics23_spec_for::<Keccak256>();
+        assert_eq!(
+            spec.inner_spec.as_ref().unwrap().hash,
+            ics23::HashOp::Keccak as i32
+        );
+        for i in 0..30u8 {
+            let existence_proof = tree.get_with_ics23_proof([i], 0).unwrap();
+            assert_eq!(existence_proof.leaf, spec.leaf_spec);
+            let proof = ics23::CommitmentProof {
+                proof: Some(ics23::commitment_proof::Proof::Exist(existence_proof)),
+            };
+            verify_ics23_membership::<Keccak256>(&proof, root_hash, &[i], &[i; 4]).unwrap();
+            assert!(
+                verify_ics23_membership::<Keccak256>(&proof, root_hash, &[i], &[0; 5]).is_err()
+            );
+            assert!(verify_ics23_membership::<Sha256>(&proof, root_hash, &[i], &[i; 4]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_estimate_ics23_proof_size() {
+        use prost::Message;
+
+        let db = MockTreeStore::default();
+        let tree = Sha256JMT::new(&db);
+        let keys: Vec<Vec<u8>> = (0..200).map(|i| format!("key{}", i).into_bytes()).collect();
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter()
+                    .enumerate()
+                    .map(|(i, key)| (KeyHash::with::<Sha256>(key), Some(vec![0; i * 3]))),
+                0,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+
+        for key in &keys {
+            let proof = tree.get_with_ics23_proof(key, 0).unwrap();
+            assert_eq!(
+                tree.estimate_ics23_proof_size(key, 0).unwrap(),
+                proof.encoded_len()
+            );
+        }
+        assert!(tree.estimate_ics23_proof_size(b"missing", 0).is_err());
+    }
+
     #[test]
     fn test_jmt_ics23_existence_random_keys() {
         let db = MockTreeStore::default();
@@ -0,0 +1,47 @@
+//! Encodings shared by the key-value store backends.
+
+use anyhow::{format_err, Result};
+
+use crate::{types::Version, KeyHash, OwnedValue, RootHash};
+
+/// Encodes the key of a value so that all the versions of one key hash are stored contiguously,
+/// ordered by version.
+pub(crate) fn encode_value_key(version: Version, key_hash: KeyHash) -> Vec<u8> {
+    let mut key = key_hash.0.to_vec();
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+/// Values are prefixed with a tag, so that deleting a key can be told apart from setting it to an
+/// empty value.
+pub(crate) fn encode_value(value: Option<&[u8]>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut bytes = Vec::with_capacity(value.len() + 1);
+            bytes.push(1);
+            bytes.extend_from_slice(value);
+            bytes
+        }
+        None => vec![0],
+    }
+}
+
+pub(crate) fn decode_value(bytes: &[u8]) -> Result<Option<OwnedValue>> {
+    match bytes.split_first() {
+        Some((0, [])) => Ok(None),
+        Some((1, value)) => Ok(Some(value.to_vec())),
+        _ => Err(format_err!("Invalid encoded value {:?}.", bytes)),
+    }
+}
+
+pub(crate) fn decode_version(bytes: &[u8]) -> Result<Version> {
+    Ok(Version::from_be_bytes(bytes.try_into().map_err(|_| {
+        format_err!("Invalid encoded version {:?}.", bytes)
+    })?))
+}
+
+pub(crate) fn decode_root_hash(bytes: &[u8]) -> Result<RootHash> {
+    Ok(RootHash(bytes.try_into().map_err(|_| {
+        format_err!("Invalid encoded root hash {:?}.", bytes)
+    })?))
+}
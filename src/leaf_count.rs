@@ -0,0 +1,148 @@
+//! Authenticated leaf counts.
+//!
+//! With the `leaf-count-commitment` feature, every node hash commits to the number of leaves
+//! below the node: the last 8 bytes of the hash of a node are replaced by its leaf count, as a
+//! big-endian `u64`. A leaf counts for 1, an empty subtree is the placeholder hash of the
+//! [`SimpleHasher`] and counts for 0, and an internal binary node counts for the sum of its
+//! children. The remaining 24 bytes of the digest are those of the usual node hash, with the
+//! domain separators of the hasher.
+//!
+//! The [`RootHash`] of a tree therefore commits to its number of keys, which [`leaf_count`] reads
+//! back without any proof, and the siblings of any [`SparseMerkleProof`] commit to the number of
+//! keys on each side of the path, which authenticates the rank of a key hash with a [`RankProof`].
+//! Proofs are served by the usual read path, e.g. [`JellyfishMerkleTree::get_with_proof`], and the
+//! counts are those the [`InternalNode`](crate::storage::Node::Internal)s already carry.
+//!
+//! Enabling the feature changes every root hash, and truncating the digests leaves 96 bits of
+//! collision resistance. Proofs of trees hashed with the feature are not understood by verifiers
+//! that do not know about it: the ICS23 specs, the EVM verifier and the upstream hashers of the
+//! `compat` module do not apply.
+
+use anyhow::{ensure, format_err, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{bail_corrupted, ensure_valid_proof},
+    node_type::{Node, NodeKey},
+    proof::{leaf_count_of, SparseMerkleProof},
+    storage::TreeReader,
+    Bytes32Ext, JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// Returns the number of keys of the tree committed to by `root_hash`.
+pub fn leaf_count<H: SimpleHasher>(root_hash: RootHash) -> u64 {
+    leaf_count_of::<H>(&root_hash.0)
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Returns the key hash of rank `rank` at `version`, i.e. the `rank + 1`-th smallest key hash
+    /// of the tree, its value and a proof of its rank.
+    ///
+    /// The leaf is found with the leaf counts stored in the internal nodes, so nodes written
+    /// without leaf counts cannot serve rank proofs.
+    pub fn get_with_rank_proof(
+        &self,
+        rank: u64,
+        version: Version,
+    ) -> Result<(KeyHash, OwnedValue, RankProof<H>)> {
+        let mut rank_in_subtree = rank;
+        let mut node_key = NodeKey::new_empty_path(version);
+        let key_hash = loop {
+            match self.reader().get_node(&node_key)? {
+                Node::Leaf(leaf) => {
+                    ensure!(
+                        rank_in_subtree == 0,
+                        "Rank {} is out of the leaves of the tree.",
+                        rank
+                    );
+                    break leaf.key_hash();
+                }
+                Node::Internal(internal) => {
+                    let mut next = None;
+                    for (nibble, child) in internal.children_sorted() {
+                        let count = child
+                            .leaf_count()
+                            .ok_or_else(|| format_err!("Node {:?} has no leaf count.", node_key))?
+                            as u64;
+                        if rank_in_subtree < count {
+                            next = Some(node_key.gen_child_node_key(child.version, nibble));
+                            break;
+                        }
+                        rank_in_subtree -= count;
+                    }
+                    node_key = next.ok_or_else(|| {
+                        format_err!("Rank {} is out of the leaves of the tree.", rank)
+                    })?;
+                }
+                Node::Null if node_key.nibble_path().num_nibbles() == 0 => {
+                    return Err(format_err!("Rank {} is out of the empty tree.", rank));
+                }
+                Node::Null => bail_corrupted!("Unexpected null node {:?}.", node_key),
+            }
+        };
+        let (value, proof) = self.get_with_proof(key_hash, version)?;
+        let value = value.ok_or_else(|| format_err!("Key {:?} has no value.", key_hash))?;
+        Ok((key_hash, value, RankProof { proof }))
+    }
+}
+
+/// A proof that a key hash is of a given rank among the key hashes of a tree, i.e. that exactly
+/// `rank` key hashes of the tree are smaller.
+///
+/// This is the inclusion proof of the key hash: the siblings to the left of its path commit to
+/// the number of smaller key hashes.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct RankProof<H: SimpleHasher> {
+    proof: SparseMerkleProof<H>,
+}
+
+impl<H: SimpleHasher> core::fmt::Debug for RankProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RankProof")
+            .field("proof", &self.proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> RankProof<H> {
+    /// Returns the inclusion proof of the key hash.
+    pub fn proof(&self) -> &SparseMerkleProof<H> {
+        &self.proof
+    }
+
+    /// Verifies that `key_hash` is of rank `rank` in the tree committed to by
+    /// `expected_root_hash`, with the value `value`.
+    pub fn verify(
+        &self,
+        expected_root_hash: RootHash,
+        rank: u64,
+        key_hash: KeyHash,
+        value: impl AsRef<[u8]>,
+    ) -> Result<()> {
+        self.proof
+            .verify(expected_root_hash, key_hash, Some(value))?;
+
+        let siblings = self.proof.siblings();
+        let bits = key_hash.0.iter_bits().rev().skip(256 - siblings.len());
+        let actual_rank = siblings
+            .iter()
+            .zip(bits)
+            .filter(|(_, bit)| *bit)
+            .fold(0u64, |rank, (sibling, _)| {
+                rank.saturating_add(leaf_count_of::<H>(sibling))
+            });
+        ensure_valid_proof!(
+            actual_rank == rank,
+            "Key {:?} is of rank {}, not {}.",
+            key_hash,
+            actual_rank,
+            rank
+        );
+        Ok(())
+    }
+}
@@ -2,6 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 #![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "std"), no_std)]
+// Without `std`, only proof verification is compiled, which leaves the tree-building helpers unused.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
 
 //! This module implements [`JellyfishMerkleTree`] backed by storage module. The tree itself doesn't
 //! persist anything, but realizes the logic of R/W only. The write path will produce all the
@@ -60,6 +63,20 @@
 //! jellyfish is an [`InternalNode`] while each tentacle is a [`LeafNode`]. It is noted that
 //! Jellyfish merkle doesn't have a counterpart for `extension` node of ethereum patricia merkle.
 //!
+//! Disabling the default `std` feature leaves only the proof types in [`proof`], the [`light`]
+//! client helpers, the hashing primitives and [`KeyHash`], which then compile with `no_std` and
+//! `alloc`, so that proofs can be verified in constrained environments. This is also the build to
+//! use on `wasm32-unknown-unknown`, e.g. in browsers or CosmWasm contracts; enabling `ics23`
+//! alongside it keeps `ics23_spec` and `ics23_spec_for`, but not the proof generation methods.
+//!
+//! The default features are only `std`, so that consumers needing the tree and its proofs alone
+//! do not pull in heavier dependencies. Generating and verifying ics23 proofs requires the `ics23`
+//! feature, and exporting Prometheus counters of the bytes encoded and of the nodes read from
+//! storage requires the `metrics` feature.
+//!
+//! The `testing` feature exposes the `testing` module, with the `proptest` strategies and
+//! helpers used by this crate's own tests, for downstream crates testing their storage.
+//!
 //! [`JellyfishMerkleTree`]: struct.JellyfishMerkleTree.html
 //! [`new`]: struct.JellyfishMerkleTree.html#method.new
 //! [`put_value_sets`]: struct.JellyfishMerkleTree.html#method.put_value_sets
@@ -69,54 +86,170 @@
 //! [`InternalNode`]: node_type/struct.InternalNode.html
 //! [`LeafNode`]: node_type/struct.LeafNode.html
 
-use std::fmt::Debug;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
 use sha2::digest::generic_array::GenericArray;
 use sha2::digest::OutputSizeUser;
 use sha2::Digest;
+#[cfg(feature = "std")]
 use thiserror::Error;
 
+#[cfg(feature = "async")]
+mod async_tree;
 mod bytes32ext;
+mod error;
+mod hex_serde;
 #[cfg(feature = "ics23")]
 mod ics23_impl;
+#[cfg(feature = "std")]
 mod iterator;
+#[cfg(any(feature = "rocksdb", feature = "sled"))]
+mod kv_encoding;
+#[cfg(feature = "metrics")]
 mod metrics;
+#[cfg(feature = "std")]
 mod node_type;
+#[cfg(feature = "std")]
 mod reader;
+#[cfg(feature = "std")]
 mod tree;
+#[cfg(feature = "std")]
 mod tree_cache;
 mod types;
+#[cfg(feature = "std")]
 mod writer;
 
+#[cfg(feature = "std")]
+pub mod aptos_migration;
+#[cfg(feature = "tokio")]
+pub mod background_pruner;
+#[cfg(feature = "std")]
+pub mod batched_writer;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod chunked;
+#[cfg(feature = "std")]
+pub mod commit;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "std")]
+pub mod cost;
+#[cfg(feature = "std")]
+pub mod default_value;
+#[cfg(feature = "keccak")]
+pub mod evm;
+#[cfg(feature = "std")]
+pub mod fork;
+#[cfg(feature = "leaf-count-commitment")]
+pub mod leaf_count;
+pub mod light;
+#[cfg(feature = "std")]
+pub mod memory_store;
+#[cfg(feature = "std")]
 pub mod mock;
+#[cfg(feature = "moka")]
+pub mod moka_cache;
+#[cfg(feature = "std")]
+pub mod overlay;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
+#[cfg(feature = "std")]
+pub mod preimage_index;
+#[cfg(feature = "proto")]
+pub mod proto;
+#[cfg(feature = "std")]
+pub mod pruner;
+#[cfg(feature = "std")]
 pub mod restore;
-
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb_store;
+#[cfg(feature = "sled")]
+pub mod sled_store;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod subscription;
+#[cfg(feature = "std")]
+pub mod subtree;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod tree_snapshot;
+#[cfg(feature = "std")]
+pub mod typed;
+#[cfg(feature = "std")]
+pub mod visualize;
+#[cfg(feature = "std")]
+pub mod witness;
+#[cfg(feature = "std")]
+pub mod write_behind;
+
+#[cfg(feature = "async")]
+pub use async_tree::AsyncJellyfishMerkleTree;
 use bytes32ext::Bytes32Ext;
+pub use error::JmtError;
+#[cfg(all(feature = "ics23", feature = "std"))]
+pub use ics23_impl::{
+    compress_ics23_proofs, decompress_ics23_proofs, verify_ics23_batch_membership,
+    verify_ics23_membership, verify_ics23_non_membership,
+};
 #[cfg(feature = "ics23")]
-pub use ics23_impl::ics23_spec;
+pub use ics23_impl::{ics23_key_hash_spec_for, ics23_spec, ics23_spec_for, Ics23Hasher};
+#[cfg(feature = "std")]
 pub use iterator::JellyfishMerkleIterator;
-pub use tree::{JellyfishMerkleTree, Sha256JMT};
+#[cfg(all(feature = "keccak", feature = "std"))]
+pub use tree::Keccak256JMT;
+#[cfg(feature = "std")]
+pub use tree::{JellyfishMerkleTree, ProofSizeEstimate, Sha256JMT, TreeStats, ValueChange};
+#[cfg(feature = "std")]
 use types::nibble::ROOT_NIBBLE_HEIGHT;
 pub use types::proof;
 pub use types::Version;
 
+/// The Keccak-256 hash function used by Ethereum, which can be used as the hasher of a tree.
+#[cfg(feature = "keccak")]
+pub use sha3::Keccak256;
+
 /// Contains types used to bridge a [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
 /// to the backing storage recording the tree's internal data.
+///
+/// Values are always stored outside of the tree's nodes: a
+/// [`LeafNode`](crate::storage::LeafNode) only holds the hash of its key and the hash of its value,
+/// and the values of a [`NodeBatch`](crate::storage::NodeBatch) are written apart from its nodes,
+/// keyed by key hash and version. Stores keep them in a dedicated column or prefix, e.g.
+/// `jmt_values` in the RocksDB store of the `rocksdb` feature, so that the size of
+/// node pages does not depend on the size of values, and reading a leaf never reads its value.
+#[cfg(feature = "std")]
 pub mod storage {
-    pub use node_type::{LeafNode, Node, NodeDecodeError, NodeKey};
-    pub use reader::TreeReader;
+    pub use node_type::{
+        ChildRef, InternalNodeRef, LeafNode, Node, NodeDecodeError, NodeFormat, NodeKey, NodeRef,
+    };
+    #[cfg(feature = "async")]
+    pub use reader::AsyncTreeReader;
+    pub use reader::{HasPreimage, HasPreimageIndex, PreimageIterator, TreeReader};
+    pub use types::nibble::{nibble_path::NibblePath, Nibble};
+    #[cfg(feature = "async")]
+    pub use writer::AsyncTreeWriter;
     pub use writer::{
-        NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, TreeUpdateBatch, TreeWriter,
+        NodeBatch, NodeBatchEntry, NodeKind, NodeStats, StaleNodeIndex, StaleNodeIndexBatch,
+        StaleNodeKind, TreeUpdateBatch, TreeWriter,
     };
 
     use super::*;
 }
 
-#[cfg(any(test, feature = "fuzzing"))]
+#[cfg(test)]
 mod tests;
 
 /// An error that occurs when the state root for a requested version is missing (e.g., because it was pruned).
+#[cfg(feature = "std")]
 #[derive(Error, Debug)]
 #[error("Missing state root node at version {version}, probably pruned.")]
 pub struct MissingRootError {
@@ -125,6 +258,7 @@ pub struct MissingRootError {
 
 // TODO: reorg
 
+/// The default [`SimpleHasher::PLACEHOLDER_HASH`].
 const SPARSE_MERKLE_PLACEHOLDER_HASH: [u8; 32] = *b"SPARSE_MERKLE_PLACEHOLDER_HASH__";
 
 /// An owned value stored in the [`JellyfishMerkleTree`].
@@ -135,27 +269,42 @@ use proptest_derive::Arbitrary;
 
 /// A root of a [`JellyfishMerkleTree`].
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
-pub struct RootHash(pub [u8; 32]);
+pub struct RootHash(#[serde(with = "hex_serde::hash")] pub [u8; 32]);
 
 /// A hashed key used to index a [`JellyfishMerkleTree`].
 ///
 /// The [`JellyfishMerkleTree`] only stores key hashes, not full keys.  Byte
 /// keys can be converted to a [`KeyHash`] using the provided `From` impl.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
-pub struct KeyHash(pub [u8; 32]);
+pub struct KeyHash(#[serde(with = "hex_serde::hash")] pub [u8; 32]);
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 // This needs to be public for the fuzzing/Arbitrary feature, but we don't
 // really want it to be, so #[doc(hidden)] is the next best thing.
 #[doc(hidden)]
-pub struct ValueHash(pub [u8; 32]);
+pub struct ValueHash(#[serde(with = "hex_serde::hash")] pub [u8; 32]);
 
 impl ValueHash {
     pub fn with<H: SimpleHasher>(value: impl AsRef<[u8]>) -> Self {
-        Self(H::hash(value))
+        let mut hasher = H::new();
+        hasher.update(H::VALUE_DOMAIN_SEPARATOR);
+        hasher.update(value.as_ref());
+        Self(hasher.finalize())
     }
 }
 
@@ -167,36 +316,57 @@ impl KeyHash {
         tracing::debug!(key = ?EscapedByteSlice(key.as_ref()), ?key_hash);
         key_hash
     }
+
+    /// Uses a key narrower than a key hash as a key hash, by padding it with zeros on the right.
+    /// This suits keys which are already uniformly distributed, such as 20-byte addresses, and
+    /// which then need not be hashed again.
+    ///
+    /// The width of the keys does not change the size of proofs: a leaf sits right below the
+    /// first level at which its key hash differs from all the others, so a proof in a tree of `n`
+    /// keys holds about `log2(n)` siblings, whether the keys have 160 or 256 significant bits.
+    pub fn from_padded<const N: usize>(key: [u8; N]) -> Self {
+        const { assert!(N <= 32, "A key hash has at most 32 bytes.") };
+        let mut key_hash = [0; 32];
+        key_hash[..N].copy_from_slice(&key);
+        Self(key_hash)
+    }
+
+    /// Returns the first `N` bytes of the key hash, e.g. the key padded by
+    /// [`from_padded`](KeyHash::from_padded).
+    pub fn truncated<const N: usize>(&self) -> [u8; N] {
+        const { assert!(N <= 32, "A key hash has at most 32 bytes.") };
+        self.0[..N].try_into().expect("N is at most 32.")
+    }
 }
 
-impl std::fmt::Debug for KeyHash {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Debug for KeyHash {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("KeyHash")
-            .field(&hex::encode(&self.0))
+            .field(&hex::encode(self.0))
             .finish()
     }
 }
 
-impl std::fmt::Debug for ValueHash {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Debug for ValueHash {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("ValueHash")
-            .field(&hex::encode(&self.0))
+            .field(&hex::encode(self.0))
             .finish()
     }
 }
 
-impl std::fmt::Debug for RootHash {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Debug for RootHash {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("RootHash")
-            .field(&hex::encode(&self.0))
+            .field(&hex::encode(self.0))
             .finish()
     }
 }
 
 struct EscapedByteSlice<'a>(&'a [u8]);
 
-impl<'a> std::fmt::Debug for EscapedByteSlice<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<'a> core::fmt::Debug for EscapedByteSlice<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "b\"")?;
         for &b in self.0 {
             // https://doc.rust-lang.org/reference/tokens.html#byte-escapes
@@ -226,6 +396,15 @@ impl<'a> std::fmt::Debug for EscapedByteSlice<'a> {
 /// A minimal trait representing a hash function. We implement our own
 /// rather than relying on `Digest` for broader compatibility.
 pub trait SimpleHasher: Sized {
+    /// The domain separator prepended to the children of an internal node before hashing them.
+    const INTERNAL_DOMAIN_SEPARATOR: &'static [u8] = proof::INTERNAL_DOMAIN_SEPARATOR;
+    /// The domain separator prepended to the key and value hashes of a leaf before hashing them.
+    const LEAF_DOMAIN_SEPARATOR: &'static [u8] = proof::LEAF_DOMAIN_SEPARATOR;
+    /// The domain separator prepended to a value before hashing it, empty by default.
+    const VALUE_DOMAIN_SEPARATOR: &'static [u8] = &[];
+    /// The hash of an empty subtree, which is also the root hash of the empty tree.
+    const PLACEHOLDER_HASH: [u8; 32] = SPARSE_MERKLE_PLACEHOLDER_HASH;
+
     /// Creates a new hasher with default state.
     fn new() -> Self;
     /// Ingests the provided data, updating the hasher's state.
@@ -240,15 +419,30 @@ pub trait SimpleHasher: Sized {
     }
 }
 
-/// A wrapper around `std::marker::Phatomdata` which implements
+/// A wrapper around `core::marker::Phatomdata` which implements
 /// Debug, PartialEq, Eq, and Clone  This allows higher level
 /// structs to derive these traits even if the concrete hasher does not
-/// implement them.
+/// implement them. It is also `Send` and `Sync` whatever the hasher, since
+/// it never holds one.
 #[derive(Clone, Eq, Serialize, Deserialize)]
-pub struct PhantomHasher<H: SimpleHasher>(std::marker::PhantomData<H>);
+pub struct PhantomHasher<H: SimpleHasher>(core::marker::PhantomData<fn() -> H>);
+
+#[cfg(feature = "borsh")]
+impl<H: SimpleHasher> borsh::BorshSerialize for PhantomHasher<H> {
+    fn serialize<W: borsh::io::Write>(&self, _writer: &mut W) -> borsh::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<H: SimpleHasher> borsh::BorshDeserialize for PhantomHasher<H> {
+    fn deserialize_reader<R: borsh::io::Read>(_reader: &mut R) -> borsh::io::Result<Self> {
+        Ok(Self::default())
+    }
+}
 
 impl<H: SimpleHasher> Debug for PhantomHasher<H> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("PhantomHasher")
             .field(&stringify!(H))
             .finish()
@@ -263,7 +457,7 @@ impl<H: SimpleHasher> PartialEq for PhantomHasher<H> {
 
 impl<H: SimpleHasher> Default for PhantomHasher<H> {
     fn default() -> Self {
-        Self(std::marker::PhantomData)
+        Self(core::marker::PhantomData)
     }
 }
 
@@ -283,3 +477,82 @@ where
         self.finalize().into()
     }
 }
+
+/// The domain separators of a [`Personalized`] hasher.
+pub trait DomainSeparators {
+    /// The domain separator prepended to the children of an internal node before hashing them.
+    const INTERNAL: &'static [u8];
+    /// The domain separator prepended to the key and value hashes of a leaf before hashing them.
+    const LEAF: &'static [u8];
+    /// The domain separator prepended to a value before hashing it. ics23 proofs cannot express
+    /// it, so it must be left empty for [`Personalized`] to be an `Ics23Hasher`.
+    const VALUE: &'static [u8] = &[];
+    /// The hash of an empty subtree, see [`SimpleHasher::PLACEHOLDER_HASH`].
+    const PLACEHOLDER: [u8; 32] = SPARSE_MERKLE_PLACEHOLDER_HASH;
+}
+
+/// The hasher `H` with the domain separators `D` instead of the default ones, so that the node
+/// hashes, and hence the proofs, of a tree using it are only valid for that tree.
+///
+/// Two deployments of this crate using different domain separators cannot replay each other's
+/// proofs, even for identical contents:
+///
+/// ```
+/// use jmt::{DomainSeparators, Personalized};
+///
+/// struct MyChain;
+///
+/// impl DomainSeparators for MyChain {
+///     const INTERNAL: &'static [u8] = b"MyChain::Internal";
+///     const LEAF: &'static [u8] = b"MyChain::Leaf";
+/// }
+///
+/// type MyChainHasher = Personalized<sha2::Sha256, MyChain>;
+/// ```
+pub struct Personalized<H, D> {
+    hasher: H,
+    _domain_separators: core::marker::PhantomData<D>,
+}
+
+impl<H: SimpleHasher, D: DomainSeparators> SimpleHasher for Personalized<H, D> {
+    const INTERNAL_DOMAIN_SEPARATOR: &'static [u8] = D::INTERNAL;
+    const LEAF_DOMAIN_SEPARATOR: &'static [u8] = D::LEAF;
+    const VALUE_DOMAIN_SEPARATOR: &'static [u8] = D::VALUE;
+    const PLACEHOLDER_HASH: [u8; 32] = D::PLACEHOLDER;
+
+    fn new() -> Self {
+        Self {
+            hasher: H::new(),
+            _domain_separators: core::marker::PhantomData,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data)
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        self.hasher.finalize()
+    }
+}
+
+/// The BLAKE3 hash function, which hashes much faster than SHA-256 in software and can be used as
+/// the hasher of a tree.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Default)]
+pub struct Blake3(blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl SimpleHasher for Blake3 {
+    fn new() -> Self {
+        Self(blake3::Hasher::new())
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
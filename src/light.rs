@@ -0,0 +1,107 @@
+//! Tracking of a trusted root hash by light clients, which verify proofs without any storage.
+//!
+//! A [`RootTracker`] holds the latest root hash a light client trusts, together with its version.
+//! It is advanced either by checkpoints, which the client trusts from elsewhere, e.g. signed by
+//! validators, or by [`SparseMerkleUpdateProof`]s from the trusted root to a new one. The values
+//! of keys are then verified against the trusted root with
+//! [`verify_membership`](RootTracker::verify_membership) and
+//! [`verify_non_membership`](RootTracker::verify_non_membership).
+//!
+//! This module only depends on the proof types, so it is available without the `std` feature.
+
+use anyhow::Result;
+
+use crate::{
+    error::ensure_valid_proof,
+    proof::{SparseMerkleProof, SparseMerkleUpdateProof},
+    types::Version,
+    KeyHash, RootHash, SimpleHasher,
+};
+
+/// The latest root hash trusted by a light client, see the [module](self) documentation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RootTracker {
+    version: Version,
+    root_hash: RootHash,
+}
+
+impl RootTracker {
+    /// Creates a tracker trusting `root_hash` as the root hash of the tree at `version`.
+    pub fn new(version: Version, root_hash: RootHash) -> Self {
+        Self { version, root_hash }
+    }
+
+    /// Returns the version of the trusted root hash.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the trusted root hash.
+    pub fn root_hash(&self) -> RootHash {
+        self.root_hash
+    }
+
+    /// Trusts `root_hash` as the root hash of the tree at `version`, and returns whether the
+    /// trusted root advanced.
+    ///
+    /// Checkpoints older than the trusted root are ignored, but a checkpoint at the version of the
+    /// trusted root with another root hash is an error, since both cannot be trusted.
+    pub fn checkpoint(&mut self, version: Version, root_hash: RootHash) -> Result<bool> {
+        if version == self.version {
+            ensure_valid_proof!(
+                root_hash == self.root_hash,
+                "Checkpoint {:?} conflicts with trusted root hash {:?} at version {}.",
+                root_hash,
+                self.root_hash,
+                version,
+            );
+        }
+        if version <= self.version {
+            return Ok(false);
+        }
+        self.version = version;
+        self.root_hash = root_hash;
+        Ok(true)
+    }
+
+    /// Verifies that applying `updates` to the trusted root yields `new_root_hash`, as
+    /// [`SparseMerkleUpdateProof::verify_update`] does, and trusts it as the root hash at
+    /// `version`, which must be later than the version of the trusted root.
+    pub fn apply_update<H: SimpleHasher, V: AsRef<[u8]>>(
+        &mut self,
+        version: Version,
+        new_root_hash: RootHash,
+        updates: impl IntoIterator<Item = (KeyHash, Option<V>)>,
+        proof: &SparseMerkleUpdateProof<H>,
+    ) -> Result<()> {
+        ensure_valid_proof!(
+            version > self.version,
+            "Update to version {} does not follow trusted version {}.",
+            version,
+            self.version,
+        );
+        proof.verify_update(self.root_hash, new_root_hash, updates)?;
+        self.version = version;
+        self.root_hash = new_root_hash;
+        Ok(())
+    }
+
+    /// Verifies that `key` has `value` in the tree at the trusted root.
+    pub fn verify_membership<H: SimpleHasher, V: AsRef<[u8]>>(
+        &self,
+        key: KeyHash,
+        value: V,
+        proof: &SparseMerkleProof<H>,
+    ) -> Result<()> {
+        proof.verify_existence(self.root_hash, key, value)
+    }
+
+    /// Verifies that `key` does not exist in the tree at the trusted root.
+    pub fn verify_non_membership<H: SimpleHasher>(
+        &self,
+        key: KeyHash,
+        proof: &SparseMerkleProof<H>,
+    ) -> Result<()> {
+        proof.verify_nonexistence(self.root_hash, key)
+    }
+}
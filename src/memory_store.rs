@@ -0,0 +1,445 @@
+//! A concurrent, in-memory tree store.
+//!
+//! [`MemoryTreeStore`] keeps all the nodes, values, stale node indices, root hashes and key
+//! preimages of a tree in memory, for services which hold their whole state in RAM. Unlike
+//! [`MockTreeStore`](crate::mock::MockTreeStore), it is meant for production use:
+//!
+//! - Nodes, values and preimages are spread over independently locked shards, so that concurrent
+//!   readers and writers rarely wait on one another.
+//! - It keeps track of the approximate memory it uses, and can be given a limit beyond which
+//!   writes are rejected instead of exhausting the memory of the process.
+//! - Stale nodes can be deleted with a [`Pruner`](crate::pruner::Pruner), which releases their
+//!   memory, and readers can pin the versions they still need against it.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, BTreeSet, HashMap},
+    hash::BuildHasher,
+    mem,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use anyhow::{format_err, Result};
+use bytes::Bytes;
+
+use crate::{
+    commit::{Commit, CommitTarget},
+    node_type::{Child, LeafNode, Node, NodeKey},
+    pruner::{HasVersionPins, StaleNodeIndexStore, VersionPins},
+    storage::{
+        HasPreimage, HasPreimageIndex, NodeBatch, PreimageIterator, StaleNodeIndex, TreeReader,
+        TreeUpdateBatch, TreeWriter,
+    },
+    types::Version,
+    KeyHash, OwnedValue, RootHash,
+};
+
+/// The default number of shards of a [`MemoryTreeStore`].
+pub const DEFAULT_NUM_SHARDS: usize = 16;
+
+#[derive(Default)]
+struct Shard {
+    nodes: HashMap<NodeKey, Node>,
+    values: HashMap<KeyHash, BTreeMap<Version, Option<Bytes>>>,
+    preimages: HashMap<KeyHash, Vec<u8>>,
+}
+
+/// A [`TreeReader`] and [`TreeWriter`] keeping a tree in memory, safe to share between threads.
+pub struct MemoryTreeStore {
+    shards: Vec<RwLock<Shard>>,
+    stale_node_indices: RwLock<BTreeSet<StaleNodeIndex>>,
+    root_hashes: RwLock<BTreeMap<Version, RootHash>>,
+    hasher: RandomState,
+    memory_usage: AtomicUsize,
+    memory_limit: usize,
+    version_pins: VersionPins,
+}
+
+impl Default for MemoryTreeStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryTreeStore {
+    /// Creates an empty store with [`DEFAULT_NUM_SHARDS`] shards and no memory limit.
+    pub fn new() -> Self {
+        Self {
+            shards: (0..DEFAULT_NUM_SHARDS).map(|_| RwLock::default()).collect(),
+            stale_node_indices: RwLock::default(),
+            root_hashes: RwLock::default(),
+            hasher: RandomState::new(),
+            memory_usage: AtomicUsize::new(0),
+            memory_limit: usize::MAX,
+            version_pins: VersionPins::new(),
+        }
+    }
+
+    /// Sets the number of independently locked shards. This must be set before anything is
+    /// written to the store.
+    pub fn with_num_shards(mut self, num_shards: usize) -> Self {
+        assert!(num_shards > 0, "The number of shards must be positive.");
+        assert!(
+            self.memory_usage() == 0,
+            "The number of shards must be set before writing to the store."
+        );
+        self.shards = (0..num_shards).map(|_| RwLock::default()).collect();
+        self
+    }
+
+    /// Sets the approximate number of bytes the store may use. Writes which would exceed it fail,
+    /// without writing anything.
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// Returns the approximate number of bytes used by the nodes, values, stale node indices, root
+    /// hashes and preimages held by the store. The overhead of the underlying maps is not
+    /// counted.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of nodes held by the store.
+    pub fn num_nodes(&self) -> Result<usize> {
+        let mut num_nodes = 0;
+        for shard in &self.shards {
+            num_nodes += read(shard)?.nodes.len();
+        }
+        Ok(num_nodes)
+    }
+
+    /// Writes all the nodes, values, stale node indices and root hashes of `batch`, and the given
+    /// root hashes.
+    pub fn write_tree_update_batch(
+        &self,
+        batch: TreeUpdateBatch,
+        root_hashes: impl IntoIterator<Item = (Version, RootHash)>,
+    ) -> Result<()> {
+        let root_hashes: Vec<_> = root_hashes.into_iter().collect();
+        let size = node_batch_size(&batch.node_batch)
+            + batch
+                .stale_node_index_batch
+                .iter()
+                .map(stale_node_index_size)
+                .sum::<usize>()
+            + root_hashes.len() * mem::size_of::<(Version, RootHash)>();
+        self.reserve(size)?;
+
+        self.insert_node_batch(&batch.node_batch)?;
+        let mut released = 0;
+        let mut stale_node_indices = write(&self.stale_node_indices)?;
+        for stale_node_index in batch.stale_node_index_batch {
+            let size = stale_node_index_size(&stale_node_index);
+            if !stale_node_indices.insert(stale_node_index) {
+                released += size;
+            }
+        }
+        drop(stale_node_indices);
+        let mut recorded_root_hashes = write(&self.root_hashes)?;
+        for (version, root_hash) in root_hashes {
+            if recorded_root_hashes.insert(version, root_hash).is_some() {
+                released += mem::size_of::<(Version, RootHash)>();
+            }
+        }
+        self.release(released);
+        Ok(())
+    }
+
+    /// Records the preimages of key hashes, so that they can be looked up through
+    /// [`HasPreimage`].
+    pub fn put_key_preimages(
+        &self,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Result<()> {
+        let preimages: Vec<_> = preimages.into_iter().collect();
+        self.reserve(
+            preimages
+                .iter()
+                .map(|(_, preimage)| preimage_size(preimage))
+                .sum(),
+        )?;
+        let mut released = 0;
+        for (key_hash, preimage) in preimages {
+            if let Some(replaced) = write(self.key_hash_shard(key_hash))?
+                .preimages
+                .insert(key_hash, preimage)
+            {
+                released += preimage_size(&replaced);
+            }
+        }
+        self.release(released);
+        Ok(())
+    }
+
+    /// Returns the root hash recorded for `version`, if any.
+    pub fn get_root_hash_option(&self, version: Version) -> Result<Option<RootHash>> {
+        Ok(read(&self.root_hashes)?.get(&version).copied())
+    }
+
+    /// Returns the latest version for which a root hash was recorded, if any.
+    pub fn get_latest_version(&self) -> Result<Option<Version>> {
+        Ok(read(&self.root_hashes)?.keys().next_back().copied())
+    }
+
+    fn node_shard(&self, node_key: &NodeKey) -> &RwLock<Shard> {
+        // Node keys share long prefixes, so they are spread by hash rather than by path.
+        &self.shards[self.hasher.hash_one(node_key) as usize % self.shards.len()]
+    }
+
+    fn key_hash_shard(&self, key_hash: KeyHash) -> &RwLock<Shard> {
+        &self.shards[usize::from(key_hash.0[0]) % self.shards.len()]
+    }
+
+    /// Accounts for `size` more bytes, or fails if that would exceed the memory limit.
+    fn reserve(&self, size: usize) -> Result<()> {
+        self.memory_usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                usage
+                    .checked_add(size)
+                    .filter(|usage| *usage <= self.memory_limit)
+            })
+            .map_err(|usage| {
+                format_err!(
+                    "Writing {} bytes would exceed the memory limit of {} bytes, with {} bytes in \
+                     use.",
+                    size,
+                    self.memory_limit,
+                    usage
+                )
+            })?;
+        Ok(())
+    }
+
+    fn release(&self, size: usize) {
+        self.memory_usage.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Inserts the nodes and values of `node_batch`, whose size must have been reserved.
+    fn insert_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut released = 0;
+        for (node_key, node) in node_batch.nodes() {
+            if let Some(replaced) = write(self.node_shard(node_key))?
+                .nodes
+                .insert(node_key.clone(), node.clone())
+            {
+                released += node_size(node_key, &replaced);
+            }
+        }
+        for ((version, key_hash), value) in node_batch.values() {
+            if let Some(replaced) = write(self.key_hash_shard(*key_hash))?
+                .values
+                .entry(*key_hash)
+                .or_default()
+                .insert(*version, value.clone())
+            {
+                released += value_size(&replaced);
+            }
+        }
+        let mut root_hashes = write(&self.root_hashes)?;
+        for (version, root_hash) in node_batch.root_hashes() {
+            if root_hashes.insert(*version, *root_hash).is_some() {
+                released += mem::size_of::<(Version, RootHash)>();
+            }
+        }
+        drop(root_hashes);
+        self.release(released);
+        Ok(())
+    }
+}
+
+impl TreeReader for MemoryTreeStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        Ok(read(self.node_shard(node_key))?
+            .nodes
+            .get(node_key)
+            .cloned())
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        Ok(read(self.key_hash_shard(key_hash))?
+            .values
+            .get(&key_hash)
+            .and_then(|versions| versions.range(..=max_version).next_back())
+            .and_then(|(_, value)| value.as_deref().map(<[u8]>::to_vec)))
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        let mut latest_version = None;
+        for shard in &self.shards {
+            let shard_latest_version = read(shard)?.nodes.keys().map(NodeKey::version).max();
+            latest_version = latest_version.max(shard_latest_version);
+        }
+        let Some(latest_version) = latest_version else {
+            return Ok(None);
+        };
+
+        let mut rightmost: Option<(NodeKey, LeafNode)> = None;
+        for shard in &self.shards {
+            for (node_key, node) in &read(shard)?.nodes {
+                if let Node::Leaf(leaf_node) = node {
+                    if node_key.version() == latest_version
+                        && rightmost.as_ref().is_none_or(|(_, rightmost_leaf)| {
+                            leaf_node.key_hash() > rightmost_leaf.key_hash()
+                        })
+                    {
+                        rightmost = Some((node_key.clone(), leaf_node.clone()));
+                    }
+                }
+            }
+        }
+        Ok(rightmost)
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.get_root_hash_option(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        Ok(read(&self.root_hashes)?
+            .range(versions)
+            .take(limit)
+            .map(|(version, root_hash)| (*version, *root_hash))
+            .collect())
+    }
+}
+
+impl TreeWriter for MemoryTreeStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.reserve(node_batch_size(node_batch))?;
+        self.insert_node_batch(node_batch)
+    }
+}
+
+impl HasPreimage for MemoryTreeStore {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        Ok(read(self.key_hash_shard(key_hash))?
+            .preimages
+            .get(&key_hash)
+            .cloned())
+    }
+}
+
+impl HasPreimageIndex for MemoryTreeStore {
+    fn preimages_with_prefix(&self, prefix: &[u8]) -> Result<PreimageIterator<'_>> {
+        let mut preimages = vec![];
+        for shard in &self.shards {
+            preimages.extend(
+                read(shard)?
+                    .preimages
+                    .iter()
+                    .filter(|(_, preimage)| preimage.starts_with(prefix))
+                    .map(|(key_hash, preimage)| (preimage.clone(), *key_hash)),
+            );
+        }
+        preimages.sort();
+        Ok(Box::new(preimages.into_iter().map(Ok)))
+    }
+}
+
+impl HasVersionPins for MemoryTreeStore {
+    fn version_pins(&self) -> &VersionPins {
+        &self.version_pins
+    }
+}
+
+impl StaleNodeIndexStore for MemoryTreeStore {
+    fn get_stale_node_indices_after(
+        &self,
+        after: Option<&StaleNodeIndex>,
+        max_stale_since_version: Version,
+        limit: usize,
+    ) -> Result<Vec<StaleNodeIndex>> {
+        let stale_node_indices = read(&self.stale_node_indices)?;
+        Ok(stale_node_indices
+            .iter()
+            .skip_while(|index| after.is_some_and(|after| *index <= after))
+            .take_while(|index| index.stale_since_version <= max_stale_since_version)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn delete_stale_nodes(&self, stale_node_indices: &[StaleNodeIndex]) -> Result<()> {
+        let mut released = 0;
+        for stale_node_index in stale_node_indices {
+            let node_key = &stale_node_index.node_key;
+            if let Some(node) = write(self.node_shard(node_key))?.nodes.remove(node_key) {
+                released += node_size(node_key, &node);
+            }
+        }
+        let mut indices = write(&self.stale_node_indices)?;
+        for stale_node_index in stale_node_indices {
+            if indices.remove(stale_node_index) {
+                released += stale_node_index_size(stale_node_index);
+            }
+        }
+        self.release(released);
+        Ok(())
+    }
+}
+
+impl CommitTarget for MemoryTreeStore {
+    /// Writing the same commit again stores the same records, so this is idempotent.
+    fn apply_commit(&self, commit: &Commit) -> Result<()> {
+        self.write_tree_update_batch(commit.batch.clone(), [(commit.version, commit.root_hash)])?;
+        self.put_key_preimages(commit.preimages.iter().cloned())
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> Result<RwLockReadGuard<'_, T>> {
+    lock.read()
+        .map_err(|_| format_err!("A memory tree store lock is poisoned."))
+}
+
+fn write<T>(lock: &RwLock<T>) -> Result<RwLockWriteGuard<'_, T>> {
+    lock.write()
+        .map_err(|_| format_err!("A memory tree store lock is poisoned."))
+}
+
+fn node_batch_size(node_batch: &NodeBatch) -> usize {
+    node_batch
+        .nodes()
+        .iter()
+        .map(|(node_key, node)| node_size(node_key, node))
+        .chain(node_batch.values().values().map(value_size))
+        .sum::<usize>()
+        + node_batch.root_hashes().len() * mem::size_of::<(Version, RootHash)>()
+}
+
+fn node_key_size(node_key: &NodeKey) -> usize {
+    mem::size_of::<NodeKey>() + node_key.nibble_path().bytes().len()
+}
+
+fn node_size(node_key: &NodeKey, node: &Node) -> usize {
+    let children_size = match node {
+        // The children of internal nodes are boxed.
+        Node::Internal(_) => mem::size_of::<[Option<Child>; 16]>(),
+        _ => 0,
+    };
+    node_key_size(node_key) + mem::size_of::<Node>() + children_size
+}
+
+fn value_size(value: &Option<Bytes>) -> usize {
+    mem::size_of::<(KeyHash, Version, Option<Bytes>)>() + value.as_ref().map_or(0, Bytes::len)
+}
+
+fn stale_node_index_size(stale_node_index: &StaleNodeIndex) -> usize {
+    mem::size_of::<Version>() + node_key_size(&stale_node_index.node_key)
+}
+
+fn preimage_size(preimage: &[u8]) -> usize {
+    mem::size_of::<(KeyHash, Vec<u8>)>() + preimage.len()
+}
@@ -3,15 +3,23 @@
 
 //! A mock, in-memory tree store useful for testing.
 
-use std::collections::{hash_map::Entry, BTreeSet, HashMap};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+    ops::{Bound, Range},
+};
 
 use anyhow::{bail, ensure, Result};
+use bytes::Bytes;
 
 use crate::{
     node_type::{LeafNode, Node, NodeKey},
-    storage::{NodeBatch, StaleNodeIndex, TreeReader, TreeUpdateBatch, TreeWriter},
+    pruner::{HasVersionPins, StaleNodeIndexStore, VersionPins},
+    storage::{
+        HasPreimage, HasPreimageIndex, NodeBatch, PreimageIterator, StaleNodeIndex, TreeReader,
+        TreeUpdateBatch, TreeWriter,
+    },
     types::Version,
-    KeyHash, OwnedValue,
+    KeyHash, RootHash,
 };
 
 mod rwlock;
@@ -21,7 +29,9 @@ use rwlock::RwLock;
 struct MockTreeStoreInner {
     nodes: HashMap<NodeKey, Node>,
     stale_nodes: BTreeSet<StaleNodeIndex>,
-    value_history: HashMap<KeyHash, Vec<(Version, Option<OwnedValue>)>>,
+    value_history: HashMap<KeyHash, Vec<(Version, Option<Bytes>)>>,
+    preimages: HashMap<KeyHash, Vec<u8>>,
+    root_hashes: BTreeMap<Version, RootHash>,
 }
 
 /// A mock, in-memory tree store useful for testing.
@@ -32,6 +42,7 @@ struct MockTreeStoreInner {
 pub struct MockTreeStore {
     data: RwLock<MockTreeStoreInner>,
     allow_overwrite: bool,
+    version_pins: VersionPins,
 }
 
 impl Default for MockTreeStore {
@@ -39,6 +50,7 @@ impl Default for MockTreeStore {
         Self {
             data: RwLock::new(Default::default()),
             allow_overwrite: false,
+            version_pins: VersionPins::new(),
         }
     }
 }
@@ -65,6 +77,25 @@ impl TreeReader for MockTreeStore {
         Ok(node_key_and_node)
     }
 
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        Ok(self.data.read().root_hashes.get(&version).copied())
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        Ok(self
+            .data
+            .read()
+            .root_hashes
+            .range(versions)
+            .take(limit)
+            .map(|(version, root_hash)| (*version, *root_hash))
+            .collect())
+    }
+
     fn get_value_option(
         &self,
         max_version: Version,
@@ -74,7 +105,7 @@ impl TreeReader for MockTreeStore {
             Some(version_history) => {
                 for (version, value) in version_history.iter().rev() {
                     if *version <= max_version {
-                        return Ok(value.clone());
+                        return Ok(value.as_deref().map(<[u8]>::to_vec));
                     }
                 }
                 Ok(None)
@@ -101,16 +132,83 @@ impl TreeWriter for MockTreeStore {
                 value.clone(),
             )?
         }
+        locked.root_hashes.extend(node_batch.root_hashes());
+        Ok(())
+    }
+}
+
+impl HasPreimage for MockTreeStore {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.read().preimages.get(&key_hash).cloned())
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        let locked = self.data.read();
+        Ok(key_hashes
+            .iter()
+            .map(|key_hash| locked.preimages.get(key_hash).cloned())
+            .collect())
+    }
+}
+
+impl HasPreimageIndex for MockTreeStore {
+    fn preimages_with_prefix(&self, prefix: &[u8]) -> Result<PreimageIterator<'_>> {
+        let mut preimages: Vec<(Vec<u8>, KeyHash)> = self
+            .data
+            .read()
+            .preimages
+            .iter()
+            .filter(|(_, preimage)| preimage.starts_with(prefix))
+            .map(|(key_hash, preimage)| (preimage.clone(), *key_hash))
+            .collect();
+        preimages.sort();
+        Ok(Box::new(preimages.into_iter().map(Ok)))
+    }
+}
+
+impl HasVersionPins for MockTreeStore {
+    fn version_pins(&self) -> &VersionPins {
+        &self.version_pins
+    }
+}
+
+impl StaleNodeIndexStore for MockTreeStore {
+    fn get_stale_node_indices_after(
+        &self,
+        after: Option<&StaleNodeIndex>,
+        max_stale_since_version: Version,
+        limit: usize,
+    ) -> Result<Vec<StaleNodeIndex>> {
+        let locked = self.data.read();
+        let stale_nodes = match after {
+            Some(after) => locked
+                .stale_nodes
+                .range((Bound::Excluded(after), Bound::Unbounded)),
+            None => locked.stale_nodes.range(..),
+        };
+        Ok(stale_nodes
+            .take_while(|index| index.stale_since_version <= max_stale_since_version)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn delete_stale_nodes(&self, stale_node_indices: &[StaleNodeIndex]) -> Result<()> {
+        let mut locked = self.data.write();
+        for index in stale_node_indices {
+            locked.nodes.remove(&index.node_key);
+            locked.stale_nodes.remove(index);
+        }
         Ok(())
     }
 }
 
 /// Place a value into the provided value history map. Versions must be pushed in non-decreasing order per key.
 pub fn put_value(
-    value_history: &mut HashMap<KeyHash, Vec<(Version, Option<OwnedValue>)>>,
+    value_history: &mut HashMap<KeyHash, Vec<(Version, Option<Bytes>)>>,
     version: Version,
     key: KeyHash,
-    value: Option<OwnedValue>,
+    value: Option<Bytes>,
 ) -> Result<()> {
     match value_history.entry(key) {
         Entry::Occupied(mut occupied) => {
@@ -152,7 +250,24 @@ impl MockTreeStore {
                 v.insert(leaf.into());
             }
         }
-        put_value(&mut locked.value_history, version, key_hash, Some(value))
+        put_value(
+            &mut locked.value_history,
+            version,
+            key_hash,
+            Some(value.into()),
+        )
+    }
+
+    /// Records the preimages of key hashes, so that they can be looked up through
+    /// [`HasPreimage`].
+    pub fn put_key_preimages(&self, preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>) {
+        self.data.write().preimages.extend(preimages);
+    }
+
+    /// Records the root hash of each of the given versions, so that they are read from this
+    /// index instead of from the root nodes.
+    pub fn put_root_hashes(&self, root_hashes: impl IntoIterator<Item = (Version, RootHash)>) {
+        self.data.write().root_hashes.extend(root_hashes);
     }
 
     fn put_stale_node_index(&self, index: StaleNodeIndex) -> Result<()> {
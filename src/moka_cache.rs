@@ -0,0 +1,133 @@
+//! A concurrent cache of nodes in front of an [`AsyncTreeReader`], backed by [`moka`].
+//!
+//! [`CachedTreeReader`](crate::cache::CachedTreeReader) serializes every read behind a single
+//! lock, which becomes contended on servers answering many proof requests at once.
+//! [`MokaTreeReader`] instead caches nodes in a [`moka::future::Cache`], which can be read and
+//! written concurrently from any number of tasks, and evicts nodes by size, by age, or both.
+//!
+//! Nodes are never modified once written, so cached nodes cannot become outdated, but nodes which
+//! were pruned from the underlying storage may still be read from the cache. Stores which prune
+//! their nodes should pass the [`StaleNodeIndex`] entries they delete to
+//! [`MokaTreeReader::invalidate_stale_nodes`], e.g. each batch of
+//! [`Pruner::stale_node_batches`](crate::pruner::Pruner::stale_node_batches), so that reads of
+//! pruned versions fail as they would without the cache.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use moka::future::Cache;
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{AsyncTreeReader, AsyncTreeWriter, NodeBatch, StaleNodeIndex},
+    KeyHash, OwnedValue, RootHash, Version,
+};
+
+/// An [`AsyncTreeReader`] caching the nodes read from another [`AsyncTreeReader`] in a
+/// [`moka::future::Cache`].
+///
+/// When the underlying storage is also an [`AsyncTreeWriter`], nodes written through the cache are
+/// cached as well, since the nodes of the latest version are the most likely to be read next.
+pub struct MokaTreeReader<R> {
+    reader: R,
+    cache: Cache<NodeKey, Node>,
+}
+
+impl<R> MokaTreeReader<R> {
+    /// Creates a cache of up to `max_capacity` nodes in front of `reader`.
+    pub fn new(reader: R, max_capacity: u64) -> Self {
+        Self::from_cache(reader, Cache::new(max_capacity))
+    }
+
+    /// Creates a cache of up to `max_capacity` nodes in front of `reader`, evicting every node
+    /// `time_to_live` after it was cached.
+    pub fn with_time_to_live(reader: R, max_capacity: u64, time_to_live: Duration) -> Self {
+        Self::from_cache(
+            reader,
+            Cache::builder()
+                .max_capacity(max_capacity)
+                .time_to_live(time_to_live)
+                .build(),
+        )
+    }
+
+    /// Creates a cache in front of `reader` from a `cache` configured with
+    /// [`Cache::builder`], e.g. to evict idle nodes or to weigh nodes by their size.
+    pub fn from_cache(reader: R, cache: Cache<NodeKey, Node>) -> Self {
+        Self { reader, cache }
+    }
+
+    /// Returns the underlying reader.
+    pub fn inner(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the underlying reader, dropping the cache.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the underlying cache.
+    pub fn cache(&self) -> &Cache<NodeKey, Node> {
+        &self.cache
+    }
+
+    /// Evicts the node at `node_key`, if cached.
+    pub async fn invalidate(&self, node_key: &NodeKey) {
+        self.cache.invalidate(node_key).await;
+    }
+
+    /// Evicts the nodes referred to by `stale_node_indices`, typically once they were pruned from
+    /// the underlying storage.
+    pub async fn invalidate_stale_nodes(&self, stale_node_indices: &[StaleNodeIndex]) {
+        for index in stale_node_indices {
+            self.cache.invalidate(&index.node_key).await;
+        }
+    }
+
+    /// Evicts all the cached nodes.
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: AsyncTreeReader> AsyncTreeReader for MokaTreeReader<R> {
+    async fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        if let Some(node) = self.cache.get(node_key).await {
+            return Ok(Some(node));
+        }
+        let node = self.reader.get_node_option(node_key).await?;
+        if let Some(node) = &node {
+            self.cache.insert(node_key.clone(), node.clone()).await;
+        }
+        Ok(node)
+    }
+
+    async fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.reader.get_value_option(max_version, key_hash).await
+    }
+
+    async fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf().await
+    }
+
+    async fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.reader.get_indexed_root_hash(version).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: AsyncTreeWriter> AsyncTreeWriter for MokaTreeReader<R> {
+    async fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.reader.write_node_batch(node_batch).await?;
+        for (node_key, node) in node_batch.nodes() {
+            self.cache.insert(node_key.clone(), node.clone()).await;
+        }
+        Ok(())
+    }
+}
@@ -26,19 +26,17 @@ use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+#[cfg(feature = "metrics")]
+use crate::metrics::{DIEM_JELLYFISH_INTERNAL_ENCODED_BYTES, DIEM_JELLYFISH_LEAF_ENCODED_BYTES};
 use crate::{
-    metrics::{DIEM_JELLYFISH_INTERNAL_ENCODED_BYTES, DIEM_JELLYFISH_LEAF_ENCODED_BYTES},
     types::{
         nibble::{nibble_path::NibblePath, Nibble, ROOT_NIBBLE_HEIGHT},
         proof::{SparseMerkleInternalNode, SparseMerkleLeafNode},
         Version,
     },
-    KeyHash, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    KeyHash, SimpleHasher, ValueHash,
 };
 
-#[cfg(any(test, feature = "fuzzing"))]
-use crate::SimpleHasher;
-
 /// The unique key of each node.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
@@ -60,7 +58,7 @@ impl NodeKey {
 
     /// A shortcut to generate a node key consisting of a version and an empty nibble path.
     pub(crate) fn new_empty_path(version: Version) -> Self {
-        Self::new(version, NibblePath::new(vec![]))
+        Self::new(version, NibblePath::from_bytes(&[]))
     }
 
     /// Gets the version.
@@ -109,20 +107,25 @@ impl NodeKey {
         let mut reader = Cursor::new(val);
         let version = reader.read_u64::<BigEndian>()?;
         let num_nibbles = reader.read_u8()? as usize;
+        let mut nibble_bytes = Vec::with_capacity(num_nibbles.div_ceil(2));
+        reader.read_to_end(&mut nibble_bytes)?;
+        Self::from_parts(version, num_nibbles, nibble_bytes)
+    }
+
+    /// Recovers a node key from its version, and the number of nibbles and bytes of its path.
+    fn from_parts(version: Version, num_nibbles: usize, nibble_bytes: Vec<u8>) -> Result<NodeKey> {
         ensure!(
             num_nibbles <= ROOT_NIBBLE_HEIGHT,
             "Invalid number of nibbles: {}",
             num_nibbles,
         );
-        let mut nibble_bytes = Vec::with_capacity((num_nibbles + 1) / 2);
-        reader.read_to_end(&mut nibble_bytes)?;
         ensure!(
-            (num_nibbles + 1) / 2 == nibble_bytes.len(),
+            num_nibbles.div_ceil(2) == nibble_bytes.len(),
             "encoded num_nibbles {} mismatches nibble path bytes {:?}",
             num_nibbles,
             nibble_bytes
         );
-        let nibble_path = if num_nibbles % 2 == 0 {
+        let nibble_path = if num_nibbles.is_multiple_of(2) {
             NibblePath::new(nibble_bytes)
         } else {
             let padding = nibble_bytes.last().unwrap() & 0x0f;
@@ -138,6 +141,10 @@ impl NodeKey {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub enum NodeType {
     Leaf,
     /// A internal node that haven't been finished the leaf count migration, i.e. None or not all
@@ -166,6 +173,10 @@ impl Arbitrary for NodeType {
 /// Each child of [`InternalNode`] encapsulates a nibble forking at this node.
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub struct Child {
     /// The hash value of this child node.
     pub hash: [u8; 32],
@@ -277,11 +288,9 @@ impl Children {
             .iter_mut()
             .enumerate()
             .filter_map(|(nibble, child)| {
-                if let Some(child) = child {
-                    Some((Nibble::from(nibble as u8), child))
-                } else {
-                    None
-                }
+                child
+                    .as_mut()
+                    .map(|child| (Nibble::from(nibble as u8), child))
             })
     }
 
@@ -296,11 +305,9 @@ impl Children {
             .iter()
             .enumerate()
             .filter_map(|(nibble, child)| {
-                if let Some(child) = child {
-                    Some((Nibble::from(nibble as u8), child))
-                } else {
-                    None
-                }
+                child
+                    .as_ref()
+                    .map(|child| (Nibble::from(nibble as u8), child))
             })
     }
 }
@@ -346,11 +353,11 @@ pub struct InternalNode {
 /// However, if an internal node doesn't have all 16 chidren exist at height 0 but just a few of
 /// them, we have a modified hashing rule on top of what is stated above:
 /// 1. From top to bottom, a node will be replaced by a leaf child if the subtree rooted at this
-/// node has only one child at height 0 and it is a leaf child.
+///    node has only one child at height 0 and it is a leaf child.
 /// 2. From top to bottom, a node will be replaced by the placeholder node if the subtree rooted at
-/// this node doesn't have any child at height 0. For example, if an internal node has 3 leaf
-/// children at index 0, 3, 8, respectively, and 1 internal node at index C, then the computation
-/// graph will be like:
+///    this node doesn't have any child at height 0. For example, if an internal node has 3 leaf
+///    children at index 0, 3, 8, respectively, and 1 internal node at index C, then the
+///    computation graph will be like:
 ///
 /// ```text
 ///   4 ->              +------ root hash ------+
@@ -440,12 +447,26 @@ impl InternalNode {
         }
     }
 
-    pub fn hash(&self) -> [u8; 32] {
-        self.merkle_hash(
-            0,  /* start index */
-            16, /* the number of leaves in the subtree of which we want the hash of root */
-            self.generate_bitmaps(),
-        )
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        self.to_ref().hash::<H>()
+    }
+
+    /// Returns a borrowed view of this node, which shares the read path of nodes decoded with
+    /// [`Node::decode_ref`].
+    pub fn to_ref(&self) -> InternalNodeRef<'_> {
+        let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
+        let mut children = [None; 16];
+        for (nibble, child) in self.children.iter() {
+            children[nibble.as_usize()] = Some(ChildRef {
+                hash: &child.hash,
+                version: child.version,
+            });
+        }
+        InternalNodeRef {
+            children,
+            existence_bitmap,
+            leaf_bitmap,
+        }
     }
 
     pub fn children_sorted(&self) -> impl Iterator<Item = (Nibble, &Child)> {
@@ -577,9 +598,154 @@ impl InternalNode {
         (existence_bitmap, leaf_bitmap)
     }
 
+    /// Gets the child without its corresponding siblings (like using
+    /// [`get_child_with_siblings`](InternalNode::get_child_with_siblings) and dropping the
+    /// siblings, but more efficient).
+    pub fn get_child_without_siblings(&self, node_key: &NodeKey, n: Nibble) -> Option<NodeKey> {
+        self.to_ref().get_child_without_siblings(node_key, n)
+    }
+
+    /// Gets the child and its corresponding siblings that are necessary to generate the proof for
+    /// the `n`-th child. If it is an existence proof, the returned child must be the `n`-th
+    /// child; otherwise, the returned child may be another child. See inline explanation for
+    /// details. When calling this function with n = 11 (node `b` in the following graph), the
+    /// range at each level is illustrated as a pair of square brackets:
+    ///
+    /// ```text
+    ///     4      [f   e   d   c   b   a   9   8   7   6   5   4   3   2   1   0] -> root level
+    ///            ---------------------------------------------------------------
+    ///     3      [f   e   d   c   b   a   9   8] [7   6   5   4   3   2   1   0] width = 8
+    ///                                  chs <--┘                        shs <--┘
+    ///     2      [f   e   d   c] [b   a   9   8] [7   6   5   4] [3   2   1   0] width = 4
+    ///                  shs <--┘               └--> chs
+    ///     1      [f   e] [d   c] [b   a] [9   8] [7   6] [5   4] [3   2] [1   0] width = 2
+    ///                          chs <--┘       └--> shs
+    ///     0      [f] [e] [d] [c] [b] [a] [9] [8] [7] [6] [5] [4] [3] [2] [1] [0] width = 1
+    ///     ^                chs <--┘   └--> shs
+    ///     |   MSB|<---------------------- uint 16 ---------------------------->|LSB
+    ///  height    chs: `child_half_start`         shs: `sibling_half_start`
+    /// ```
+    pub fn get_child_with_siblings<H: SimpleHasher>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+    ) -> (Option<NodeKey>, Vec<[u8; 32]>) {
+        self.to_ref().get_child_with_siblings::<H>(node_key, n)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn into_legacy_internal(self) -> InternalNode {
+        let mut children = self.children;
+        children.iter_mut().for_each(|(_, child)| {
+            if matches!(child.node_type, NodeType::Internal { .. }) {
+                child.node_type = NodeType::InternalLegacy
+            }
+        });
+
+        InternalNode::new_migration(children, false /* leaf_count_migration */)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn children(&self) -> &Children {
+        &self.children
+    }
+}
+
+/// A borrowed view of an `InternalNode`, as returned by [`Node::decode_ref`]. The hashes of the
+/// children are borrowed from the encoded node rather than copied into a heap-allocated
+/// `Children`, so that walking through nodes on the read path does not allocate.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InternalNodeRef<'a> {
+    /// Up to 16 children.
+    children: [Option<ChildRef<'a>>; 16],
+    existence_bitmap: u16,
+    leaf_bitmap: u16,
+}
+
+/// A borrowed view of a `Child`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChildRef<'a> {
+    /// The hash value of this child node.
+    pub hash: &'a [u8; 32],
+    /// The version of this child node.
+    pub version: Version,
+}
+
+impl<'a> InternalNodeRef<'a> {
+    /// Decodes an internal node serialized by [`InternalNode::serialize`], borrowing the hashes of
+    /// its children from `data`. The leaf counts, if present, are skipped.
+    pub(crate) fn decode(data: &'a [u8], read_leaf_counts: bool) -> Result<Self> {
+        let mut reader = data;
+
+        // Read and validate existence and leaf bitmaps
+        let existence_bitmap = reader.read_u16::<LittleEndian>()?;
+        let leaf_bitmap = reader.read_u16::<LittleEndian>()?;
+        match existence_bitmap {
+            0 => return Err(NodeDecodeError::NoChildren.into()),
+            _ if (existence_bitmap & leaf_bitmap) != leaf_bitmap => {
+                return Err(NodeDecodeError::ExtraLeaves {
+                    existing: existence_bitmap,
+                    leaves: leaf_bitmap,
+                }
+                .into())
+            }
+            _ => (),
+        }
+        ensure!(
+            existence_bitmap.count_ones() > 1 || leaf_bitmap == 0,
+            "If there's only one child, it must not be a leaf."
+        );
+
+        let mut children = [None; 16];
+        let mut remaining_bitmap = existence_bitmap;
+        while remaining_bitmap != 0 {
+            let next_child = remaining_bitmap.trailing_zeros() as usize;
+            let version = deserialize_u64_varint(&mut reader)?;
+            ensure!(
+                reader.len() >= size_of::<[u8; 32]>(),
+                "not enough bytes left, children: {}, bytes: {}",
+                existence_bitmap.count_ones(),
+                reader.len()
+            );
+            let (hash, rest) = reader.split_at(size_of::<[u8; 32]>());
+            reader = rest;
+
+            let child_bit = 1 << next_child;
+            if (leaf_bitmap & child_bit) == 0 && read_leaf_counts {
+                deserialize_u64_varint(&mut reader)?;
+            }
+
+            children[next_child] = Some(ChildRef {
+                hash: hash.try_into()?,
+                version,
+            });
+            remaining_bitmap &= !child_bit;
+        }
+
+        Ok(Self {
+            children,
+            existence_bitmap,
+            leaf_bitmap,
+        })
+    }
+
+    /// Gets the `n`-th child.
+    pub fn child(&self, n: Nibble) -> Option<ChildRef<'a>> {
+        self.children[n.as_usize()]
+    }
+
+    /// Computes the hash of the node, like `InternalNode::hash`.
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        self.merkle_hash::<H>(
+            0,  /* start index */
+            16, /* the number of leaves in the subtree of which we want the hash of root */
+            (self.existence_bitmap, self.leaf_bitmap),
+        )
+    }
+
     /// Given a range [start, start + width), returns the sub-bitmap of that range.
     fn range_bitmaps(start: u8, width: u8, bitmaps: (u16, u16)) -> (u16, u16) {
-        assert!(start < 16 && width.count_ones() == 1 && start % width == 0);
+        assert!(start < 16 && width.count_ones() == 1 && start.is_multiple_of(width));
         assert!(width <= 16 && (start + width) <= 16);
         // A range with `start == 8` and `width == 4` will generate a mask 0b0000111100000000.
         // use as converting to smaller integer types when 'width == 16'
@@ -587,7 +753,7 @@ impl InternalNode {
         (bitmaps.0 & mask, bitmaps.1 & mask)
     }
 
-    fn merkle_hash(
+    fn merkle_hash<H: SimpleHasher>(
         &self,
         start: u8,
         width: u8,
@@ -598,12 +764,13 @@ impl InternalNode {
             Self::range_bitmaps(start, width, (existence_bitmap, leaf_bitmap));
         if range_existence_bitmap == 0 {
             // No child under this subtree
-            SPARSE_MERKLE_PLACEHOLDER_HASH
+            H::PLACEHOLDER_HASH
         } else if width == 1 || (range_existence_bitmap.count_ones() == 1 && range_leaf_bitmap != 0)
         {
             // Only 1 leaf child under this subtree or reach the lowest level
             let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
-            self.child(only_child_index)
+            *self
+                .child(only_child_index)
                 .with_context(|| {
                     format!(
                         "Corrupted internal node: existence_bitmap indicates \
@@ -614,25 +781,24 @@ impl InternalNode {
                 .unwrap()
                 .hash
         } else {
-            let left_child = self.merkle_hash(
+            let left_child = self.merkle_hash::<H>(
                 start,
                 width / 2,
                 (range_existence_bitmap, range_leaf_bitmap),
             );
-            let right_child = self.merkle_hash(
+            let right_child = self.merkle_hash::<H>(
                 start + width / 2,
                 width / 2,
                 (range_existence_bitmap, range_leaf_bitmap),
             );
-            SparseMerkleInternalNode::new(left_child, right_child).hash()
+            SparseMerkleInternalNode::new(left_child, right_child).hash::<H>()
         }
     }
 
-    /// Gets the child without its corresponding siblings (like using
-    /// [`get_child_with_siblings`](InternalNode::get_child_with_siblings) and dropping the
-    /// siblings, but more efficient).
+    /// Gets the child without its corresponding siblings, like
+    /// `InternalNode::get_child_without_siblings`.
     pub fn get_child_without_siblings(&self, node_key: &NodeKey, n: Nibble) -> Option<NodeKey> {
-        let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
+        let (existence_bitmap, leaf_bitmap) = (self.existence_bitmap, self.leaf_bitmap);
 
         // Nibble height from 3 to 0.
         for h in (0..4).rev() {
@@ -676,32 +842,56 @@ impl InternalNode {
     }
 
     /// Gets the child and its corresponding siblings that are necessary to generate the proof for
-    /// the `n`-th child. If it is an existence proof, the returned child must be the `n`-th
-    /// child; otherwise, the returned child may be another child. See inline explanation for
-    /// details. When calling this function with n = 11 (node `b` in the following graph), the
-    /// range at each level is illustrated as a pair of square brackets:
-    ///
-    /// ```text
-    ///     4      [f   e   d   c   b   a   9   8   7   6   5   4   3   2   1   0] -> root level
-    ///            ---------------------------------------------------------------
-    ///     3      [f   e   d   c   b   a   9   8] [7   6   5   4   3   2   1   0] width = 8
-    ///                                  chs <--┘                        shs <--┘
-    ///     2      [f   e   d   c] [b   a   9   8] [7   6   5   4] [3   2   1   0] width = 4
-    ///                  shs <--┘               └--> chs
-    ///     1      [f   e] [d   c] [b   a] [9   8] [7   6] [5   4] [3   2] [1   0] width = 2
-    ///                          chs <--┘       └--> shs
-    ///     0      [f] [e] [d] [c] [b] [a] [9] [8] [7] [6] [5] [4] [3] [2] [1] [0] width = 1
-    ///     ^                chs <--┘   └--> shs
-    ///     |   MSB|<---------------------- uint 16 ---------------------------->|LSB
-    ///  height    chs: `child_half_start`         shs: `sibling_half_start`
-    /// ```
-    pub fn get_child_with_siblings(
+    /// the `n`-th child, like `InternalNode::get_child_with_siblings`.
+    pub fn get_child_with_siblings<H: SimpleHasher>(
         &self,
         node_key: &NodeKey,
         n: Nibble,
     ) -> (Option<NodeKey>, Vec<[u8; 32]>) {
         let mut siblings = vec![];
-        let (existence_bitmap, leaf_bitmap) = self.generate_bitmaps();
+        let child = self.push_child_with_siblings::<H>(node_key, n, &mut siblings);
+        (child, siblings)
+    }
+
+    /// Like [`get_child_with_siblings`](InternalNodeRef::get_child_with_siblings), but appends the
+    /// siblings to `siblings` instead of allocating a new vector for them.
+    pub(crate) fn push_child_with_siblings<H: SimpleHasher>(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        siblings: &mut Vec<[u8; 32]>,
+    ) -> Option<NodeKey> {
+        let bitmaps = (self.existence_bitmap, self.leaf_bitmap);
+        self.visit_child_siblings(node_key, n, |start, width| {
+            siblings.push(self.merkle_hash::<H>(start, width, bitmaps))
+        })
+    }
+
+    /// Like [`push_child_with_siblings`](InternalNodeRef::push_child_with_siblings), but only
+    /// appends whether each sibling is a non-placeholder, which is known without hashing.
+    pub(crate) fn push_child_with_sibling_presence(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        sibling_presence: &mut Vec<bool>,
+    ) -> Option<NodeKey> {
+        let bitmaps = (self.existence_bitmap, self.leaf_bitmap);
+        self.visit_child_siblings(node_key, n, |start, width| {
+            let (range_existence_bitmap, _) = Self::range_bitmaps(start, width, bitmaps);
+            sibling_presence.push(range_existence_bitmap != 0)
+        })
+    }
+
+    /// Walks down from this node towards its `n`-th child, calling `visit_sibling` with the start
+    /// and width of the range of children under each sibling met, from the top. Returns the key of
+    /// the child the walk ends at, if any.
+    fn visit_child_siblings(
+        &self,
+        node_key: &NodeKey,
+        n: Nibble,
+        mut visit_sibling: impl FnMut(u8, u8),
+    ) -> Option<NodeKey> {
+        let (existence_bitmap, leaf_bitmap) = (self.existence_bitmap, self.leaf_bitmap);
 
         // Nibble height from 3 to 0.
         for h in (0..4).rev() {
@@ -709,19 +899,15 @@ impl InternalNode {
             // covers.
             let width = 1 << h;
             let (child_half_start, sibling_half_start) = get_child_and_sibling_half_start(n, h);
-            // Compute the root hash of the subtree rooted at the sibling of `r`.
-            siblings.push(self.merkle_hash(
-                sibling_half_start,
-                width,
-                (existence_bitmap, leaf_bitmap),
-            ));
+            // Visit the subtree rooted at the sibling of `r`.
+            visit_sibling(sibling_half_start, width);
 
             let (range_existence_bitmap, range_leaf_bitmap) =
                 Self::range_bitmaps(child_half_start, width, (existence_bitmap, leaf_bitmap));
 
             if range_existence_bitmap == 0 {
                 // No child in this range.
-                return (None, siblings);
+                return None;
             } else if width == 1
                 || (range_existence_bitmap.count_ones() == 1 && range_leaf_bitmap != 0)
             {
@@ -730,45 +916,23 @@ impl InternalNode {
                 // `None` because it's existence indirectly proves the n-th child doesn't exist.
                 // Please read proof format for details.
                 let only_child_index = Nibble::from(range_existence_bitmap.trailing_zeros() as u8);
-                return (
-                    {
-                        let only_child_version = self
-                            .child(only_child_index)
-                            // Should be guaranteed by the self invariants, but these are not easy to express at the moment
-                            .with_context(|| {
-                                format!(
-                                    "Corrupted internal node: child_bitmap indicates \
-                                     the existence of a non-exist child at index {:x}",
-                                    only_child_index
-                                )
-                            })
-                            .unwrap()
-                            .version;
-                        Some(node_key.gen_child_node_key(only_child_version, only_child_index))
-                    },
-                    siblings,
-                );
+                let only_child_version = self
+                    .child(only_child_index)
+                    // Should be guaranteed by the self invariants, but these are not easy to express at the moment
+                    .with_context(|| {
+                        format!(
+                            "Corrupted internal node: child_bitmap indicates \
+                             the existence of a non-exist child at index {:x}",
+                            only_child_index
+                        )
+                    })
+                    .unwrap()
+                    .version;
+                return Some(node_key.gen_child_node_key(only_child_version, only_child_index));
             }
         }
         unreachable!("Impossible to get here without returning even at the lowest level.")
     }
-
-    #[cfg(test)]
-    pub(crate) fn into_legacy_internal(self) -> InternalNode {
-        let mut children = self.children;
-        children.iter_mut().for_each(|(_, mut child)| {
-            if matches!(child.node_type, NodeType::Internal { .. }) {
-                child.node_type = NodeType::InternalLegacy
-            }
-        });
-
-        InternalNode::new_migration(children, false /* leaf_count_migration */)
-    }
-
-    #[cfg(test)]
-    pub(crate) fn children(&self) -> &Children {
-        &self.children
-    }
 }
 
 /// Given a nibble, computes the start position of its `child_half_start` and `sibling_half_start`
@@ -796,8 +960,15 @@ pub(crate) fn get_child_half_start(n: Nibble, height: u8) -> u8 {
 
 /// Represents a key-value pair in the map.
 ///
-/// Note: this does not store the key itself.
+/// Note: this does not store the key itself, nor the value: a leaf only commits to the hash of
+/// the value, and the value is stored separately under its key hash and version, written through
+/// [`NodeBatch::values`](crate::storage::NodeBatch::values) and read back through
+/// [`TreeReader::get_value_option`](crate::storage::TreeReader::get_value_option).
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub struct LeafNode {
     /// The hash of the key for this entry.
     key_hash: KeyHash,
@@ -824,8 +995,8 @@ impl LeafNode {
         self.value_hash
     }
 
-    pub fn hash(&self) -> [u8; 32] {
-        SparseMerkleLeafNode::new(self.key_hash, self.value_hash).hash()
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        SparseMerkleLeafNode::new(self.key_hash, self.value_hash).hash::<H>()
     }
 }
 
@@ -844,8 +1015,67 @@ enum NodeTag {
     Internal = 3,
 }
 
+/// The formats of encoded nodes, all of which [`Node::decode`] understands.
+///
+/// Versioned formats start with a format byte, whose high bit is set so that it cannot be
+/// mistaken for the node tag starting unversioned encodings. A change to the encoding of nodes
+/// comes with a new format, so that nodes written in earlier formats can still be read, and
+/// re-encoded in the new one one at a time, e.g. when they are next read.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum NodeFormat {
+    /// The node tag, then the node: the encoding of earlier releases, and of Diem and Aptos
+    /// internal nodes.
+    Unversioned,
+    /// The format byte `0x81`, then the node as in the unversioned format.
+    V1,
+    /// The format byte `0x82`, then the Borsh encoding of the [`Node`], for storage inspected with
+    /// Borsh tooling.
+    #[cfg(feature = "borsh")]
+    Borsh,
+}
+
+impl NodeFormat {
+    /// The format [`Node::encode`] writes.
+    pub const LATEST: NodeFormat = NodeFormat::V1;
+
+    /// The bit set in format bytes, and never in node tags.
+    const VERSIONED_BIT: u8 = 0x80;
+
+    /// Returns the format byte of a versioned format.
+    fn byte(self) -> Option<u8> {
+        match self {
+            NodeFormat::Unversioned => None,
+            NodeFormat::V1 => Some(Self::VERSIONED_BIT | 1),
+            #[cfg(feature = "borsh")]
+            NodeFormat::Borsh => Some(Self::VERSIONED_BIT | 2),
+        }
+    }
+
+    /// Returns the format of an encoded node, and the encoding of the node in that format without
+    /// its format byte, which starts with the node tag.
+    fn split(val: &[u8]) -> Result<(NodeFormat, &[u8])> {
+        match val.first() {
+            None => Err(NodeDecodeError::EmptyInput.into()),
+            Some(byte) if byte & Self::VERSIONED_BIT == 0 => Ok((NodeFormat::Unversioned, val)),
+            Some(byte) if Some(*byte) == NodeFormat::V1.byte() => Ok((NodeFormat::V1, &val[1..])),
+            #[cfg(feature = "borsh")]
+            Some(byte) if Some(*byte) == NodeFormat::Borsh.byte() => {
+                Ok((NodeFormat::Borsh, &val[1..]))
+            }
+            Some(byte) => Err(NodeDecodeError::UnknownFormat {
+                unknown_format: *byte,
+            }
+            .into()),
+        }
+    }
+}
+
 /// The concrete node type of [`JellyfishMerkleTree`](crate::JellyfishMerkleTree).
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub enum Node {
     /// Represents `null`.
     Null,
@@ -880,7 +1110,7 @@ impl Node {
     }
 
     /// Creates the [`Internal`](Node::Internal) variant.
-    #[cfg(any(test, feature = "fuzzing"))]
+    #[cfg(test)]
     pub(crate) fn new_internal(children: Children) -> Self {
         Node::Internal(InternalNode::new(children))
     }
@@ -891,7 +1121,7 @@ impl Node {
     }
 
     /// Creates the [`Leaf`](Node::Leaf) variant by hashing a raw value.
-    #[cfg(any(test, feature = "fuzzing"))]
+    #[cfg(test)]
     pub(crate) fn leaf_from_value<H: SimpleHasher>(
         key_hash: KeyHash,
         value: impl AsRef<[u8]>,
@@ -924,9 +1154,20 @@ impl Node {
         }
     }
 
-    /// Serializes to bytes for physical storage.
+    /// Serializes to bytes for physical storage, in the [latest format](NodeFormat::LATEST).
     pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_with_format(NodeFormat::LATEST)
+    }
+
+    /// Serializes to bytes in the given format, e.g. for storage still read by earlier releases.
+    pub fn encode_with_format(&self, format: NodeFormat) -> Result<Vec<u8>> {
         let mut out = vec![];
+        out.extend(format.byte());
+        #[cfg(feature = "borsh")]
+        if format == NodeFormat::Borsh {
+            borsh::to_writer(&mut out, self)?;
+            return Ok(out);
+        }
 
         match self {
             Node::Null => {
@@ -941,11 +1182,13 @@ impl Node {
                 };
                 out.push(tag as u8);
                 internal_node.serialize(&mut out, persist_leaf_count)?;
+                #[cfg(feature = "metrics")]
                 DIEM_JELLYFISH_INTERNAL_ENCODED_BYTES.inc_by(out.len() as u64);
             }
             Node::Leaf(leaf_node) => {
                 out.push(NodeTag::Leaf as u8);
                 out.extend(bcs::to_bytes(&leaf_node)?);
+                #[cfg(feature = "metrics")]
                 DIEM_JELLYFISH_LEAF_ENCODED_BYTES.inc_by(out.len() as u64);
             }
         }
@@ -953,16 +1196,26 @@ impl Node {
     }
 
     /// Computes the hash of nodes.
-    pub(crate) fn hash(&self) -> [u8; 32] {
+    pub(crate) fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
         match self {
-            Node::Null => SPARSE_MERKLE_PLACEHOLDER_HASH,
-            Node::Internal(internal_node) => internal_node.hash(),
-            Node::Leaf(leaf_node) => leaf_node.hash(),
+            Node::Null => H::PLACEHOLDER_HASH,
+            Node::Internal(internal_node) => internal_node.hash::<H>(),
+            Node::Leaf(leaf_node) => leaf_node.hash::<H>(),
         }
     }
 
-    /// Recovers from serialized bytes in physical storage.
+    /// Returns the format in which a node was serialized.
+    pub fn format(val: &[u8]) -> Result<NodeFormat> {
+        Ok(NodeFormat::split(val)?.0)
+    }
+
+    /// Recovers from serialized bytes in physical storage, in any [`NodeFormat`].
     pub fn decode(val: &[u8]) -> Result<Node> {
+        let val = match NodeFormat::split(val)? {
+            #[cfg(feature = "borsh")]
+            (NodeFormat::Borsh, val) => return Ok(borsh::from_slice(val)?),
+            (_, val) => val,
+        };
         if val.is_empty() {
             return Err(NodeDecodeError::EmptyInput.into());
         }
@@ -980,6 +1233,65 @@ impl Node {
             None => Err(NodeDecodeError::UnknownTag { unknown_tag: tag }.into()),
         }
     }
+
+    /// Recovers a borrowed view of the node from serialized bytes in physical storage. Unlike
+    /// [`decode`](Node::decode), this does not allocate: the hashes of the children of an internal
+    /// node are borrowed from `val`.
+    pub fn decode_ref(val: &[u8]) -> Result<NodeRef<'_>> {
+        let val = match NodeFormat::split(val)? {
+            #[cfg(feature = "borsh")]
+            (NodeFormat::Borsh, val) => return NodeRef::decode_borsh(val),
+            (_, val) => val,
+        };
+        if val.is_empty() {
+            return Err(NodeDecodeError::EmptyInput.into());
+        }
+        let tag = val[0];
+        let node_tag = NodeTag::from_u8(tag);
+        match node_tag {
+            Some(NodeTag::Null) => Ok(NodeRef::Null),
+            Some(NodeTag::InternalLegacy) => Ok(NodeRef::Internal(InternalNodeRef::decode(
+                &val[1..],
+                false,
+            )?)),
+            Some(NodeTag::Internal) => {
+                Ok(NodeRef::Internal(InternalNodeRef::decode(&val[1..], true)?))
+            }
+            Some(NodeTag::Leaf) => Ok(NodeRef::Leaf(bcs::from_bytes(&val[1..])?)),
+            None => Err(NodeDecodeError::UnknownTag { unknown_tag: tag }.into()),
+        }
+    }
+
+    /// Returns a borrowed view of the node.
+    pub fn to_ref(&self) -> NodeRef<'_> {
+        match self {
+            Node::Null => NodeRef::Null,
+            Node::Internal(internal_node) => NodeRef::Internal(internal_node.to_ref()),
+            Node::Leaf(leaf_node) => NodeRef::Leaf(leaf_node.clone()),
+        }
+    }
+}
+
+/// A borrowed view of a [`Node`], as returned by [`Node::decode_ref`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NodeRef<'a> {
+    /// Represents `null`.
+    Null,
+    /// A wrapper of [`InternalNodeRef`].
+    Internal(InternalNodeRef<'a>),
+    /// A wrapper of [`LeafNode`], which is small enough to be decoded without allocating.
+    Leaf(LeafNode),
+}
+
+impl NodeRef<'_> {
+    /// Computes the hash of nodes.
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        match self {
+            NodeRef::Null => H::PLACEHOLDER_HASH,
+            NodeRef::Internal(internal_node) => internal_node.hash::<H>(),
+            NodeRef::Leaf(leaf_node) => leaf_node.hash::<H>(),
+        }
+    }
 }
 
 /// Error thrown when a [`Node`] fails to be deserialized out of a byte sequence stored in physical
@@ -994,6 +1306,11 @@ pub enum NodeDecodeError {
     #[error("lead tag byte is unknown: {}", unknown_tag)]
     UnknownTag { unknown_tag: u8 },
 
+    /// The first byte of the input is the format byte of an unknown format, e.g. one written by a
+    /// later release.
+    #[error("format byte is unknown: {:#x}", unknown_format)]
+    UnknownFormat { unknown_format: u8 },
+
     /// No children found in internal node
     #[error("No children found in internal node")]
     NoChildren,
@@ -1046,3 +1363,118 @@ where
     num |= u64::from(byte) << 56;
     Ok(num)
 }
+
+#[cfg(feature = "borsh")]
+mod borsh_impls {
+    //! The Borsh encodings of node keys and nodes.
+    //!
+    //! A [`NodeKey`] is encoded as its version, the number of nibbles of its path as a `u8`, then
+    //! the bytes of its path as a `Vec<u8>`. Unlike [`NodeKey::encode`], this does not sort like
+    //! node keys. An [`InternalNode`] is encoded as its children, as a `Vec<(u8, Child)>` sorted by
+    //! nibble, then whether it persists leaf counts as a `bool`.
+
+    use borsh::{
+        io::{Error, ErrorKind, Read, Result, Write},
+        BorshDeserialize, BorshSerialize,
+    };
+
+    use super::{
+        Child, ChildRef, Children, InternalNode, InternalNodeRef, Nibble, NodeDecodeError, NodeKey,
+        NodeRef, Version,
+    };
+
+    fn invalid_data(error: impl ToString) -> Error {
+        Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+
+    impl BorshSerialize for NodeKey {
+        fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+            BorshSerialize::serialize(&self.version, writer)?;
+            BorshSerialize::serialize(&(self.nibble_path.num_nibbles() as u8), writer)?;
+            BorshSerialize::serialize(self.nibble_path.bytes(), writer)
+        }
+    }
+
+    impl BorshDeserialize for NodeKey {
+        fn deserialize_reader<R: Read>(reader: &mut R) -> Result<Self> {
+            let version = Version::deserialize_reader(reader)?;
+            let num_nibbles = u8::deserialize_reader(reader)?;
+            let nibble_bytes = Vec::<u8>::deserialize_reader(reader)?;
+            NodeKey::from_parts(version, num_nibbles.into(), nibble_bytes).map_err(invalid_data)
+        }
+    }
+
+    impl BorshSerialize for InternalNode {
+        fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+            BorshSerialize::serialize(&(self.children.num_children() as u32), writer)?;
+            for (nibble, child) in self.children_sorted() {
+                BorshSerialize::serialize(&u8::from(nibble), writer)?;
+                BorshSerialize::serialize(child, writer)?;
+            }
+            BorshSerialize::serialize(&self.leaf_count_migration, writer)
+        }
+    }
+
+    impl BorshDeserialize for InternalNode {
+        fn deserialize_reader<R: Read>(reader: &mut R) -> Result<Self> {
+            let num_children = u32::deserialize_reader(reader)?;
+            let mut children = Children::new();
+            let mut next_nibble = 0;
+            for _ in 0..num_children {
+                let nibble = u8::deserialize_reader(reader)?;
+                if nibble < next_nibble || nibble >= 16 {
+                    return Err(invalid_data(
+                        "Children must have increasing nibbles below 16.",
+                    ));
+                }
+                next_nibble = nibble + 1;
+                children.insert(Nibble::from(nibble), Child::deserialize_reader(reader)?);
+            }
+            let leaf_count_migration = bool::deserialize_reader(reader)?;
+            InternalNode::new_impl(children, leaf_count_migration).map_err(invalid_data)
+        }
+    }
+
+    impl<'a> NodeRef<'a> {
+        /// Decodes a borrowed view of a Borsh-encoded node.
+        pub(super) fn decode_borsh(val: &'a [u8]) -> anyhow::Result<Self> {
+            match val.split_first() {
+                Some((0, [])) => Ok(NodeRef::Null),
+                Some((1, rest)) => Ok(NodeRef::Internal(InternalNodeRef::decode_borsh(rest)?)),
+                Some((2, rest)) => Ok(NodeRef::Leaf(borsh::from_slice(rest)?)),
+                Some((tag, _)) => Err(NodeDecodeError::UnknownTag { unknown_tag: *tag }.into()),
+                None => Err(NodeDecodeError::EmptyInput.into()),
+            }
+        }
+    }
+
+    impl<'a> InternalNodeRef<'a> {
+        /// Decodes a Borsh-encoded internal node, borrowing the hashes of its children from `val`.
+        fn decode_borsh(val: &'a [u8]) -> anyhow::Result<Self> {
+            // The view is checked against the owned node, which validates the children.
+            InternalNode::try_from_slice(val)?;
+            let (num_children, mut rest) = val.split_at(4);
+            let num_children = u32::from_le_bytes(num_children.try_into()?);
+            let mut children = [None; 16];
+            let (mut existence_bitmap, mut leaf_bitmap) = (0u16, 0u16);
+            for _ in 0..num_children {
+                let nibble = rest[0] as usize;
+                let hash: &'a [u8; 32] = rest[1..33].try_into()?;
+                let version = u64::from_le_bytes(rest[33..41].try_into()?);
+                let node_type = rest[41];
+                existence_bitmap |= 1 << nibble;
+                if node_type == 0 {
+                    leaf_bitmap |= 1 << nibble;
+                }
+                // Internal children are followed by their leaf count.
+                rest = &rest[if node_type == 2 { 50 } else { 42 }..];
+                children[nibble] = Some(ChildRef { hash, version });
+            }
+            Ok(Self {
+                children,
+                existence_bitmap,
+                leaf_bitmap,
+            })
+        }
+    }
+}
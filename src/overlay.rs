@@ -0,0 +1,200 @@
+//! Speculative updates of a tree, kept in memory on top of its storage.
+//!
+//! An [`OverlayTree`] applies value sets to a tree without writing anything to the underlying
+//! storage: the resulting nodes and values are kept in memory, and reads go to them first and to
+//! the storage second. This makes it possible to compute the root hash a block would produce, or
+//! to simulate transactions against uncommitted state, and then either commit the accumulated
+//! [`TreeUpdateBatch`] or drop it.
+
+use std::{collections::BTreeMap, marker::PhantomData, ops::Range};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    reader::read_missing_nodes,
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeUpdateBatch},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// A tree whose pending updates are held in memory on top of a [`TreeReader`].
+///
+/// `OverlayTree` is itself a [`TreeReader`] over the pending updates and the underlying storage,
+/// so [`tree`](OverlayTree::tree) gives a [`JellyfishMerkleTree`] reading the speculative state,
+/// including proofs against pending root hashes.
+pub struct OverlayTree<R, H: SimpleHasher> {
+    reader: R,
+    pending: TreeUpdateBatch,
+    root_hashes: Vec<(Version, RootHash)>,
+    _phantom_hasher: PhantomData<H>,
+}
+
+impl<R, H> OverlayTree<R, H>
+where
+    R: TreeReader,
+    H: SimpleHasher,
+{
+    /// Creates an overlay without any pending update on top of `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: TreeUpdateBatch::default(),
+            root_hashes: vec![],
+            _phantom_hasher: PhantomData,
+        }
+    }
+
+    /// Returns a tree reading the pending updates on top of the underlying storage.
+    pub fn tree(&self) -> JellyfishMerkleTree<'_, Self, H> {
+        JellyfishMerkleTree::new(self)
+    }
+
+    /// Applies `value_set` at `version` in memory, and returns the would-be root hash of the tree
+    /// at `version`. As with [`JellyfishMerkleTree::put_value_set`], `version` must follow the
+    /// latest version, whether it is pending or in the underlying storage.
+    pub fn put_value_set(
+        &mut self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<RootHash> {
+        let (root_hash, batch) = self.tree().put_value_set(value_set, version)?;
+        self.pending.node_batch.merge(batch.node_batch);
+        self.pending
+            .stale_node_index_batch
+            .extend(batch.stale_node_index_batch);
+        self.pending.node_stats.extend(batch.node_stats);
+        self.root_hashes.push((version, root_hash));
+        Ok(root_hash)
+    }
+
+    /// Returns the versions applied since the last commit or reset, with their root hashes.
+    pub fn pending_root_hashes(&self) -> &[(Version, RootHash)] {
+        &self.root_hashes
+    }
+
+    /// Returns the updates applied since the last commit or reset, as a single batch.
+    pub fn pending(&self) -> &TreeUpdateBatch {
+        &self.pending
+    }
+
+    /// Takes the pending updates, to be written to the underlying storage, along with the root
+    /// hash of each pending version. The overlay is left without any pending update, so it must
+    /// not be read again before the returned batch is written.
+    pub fn take_pending(&mut self) -> (Vec<(Version, RootHash)>, TreeUpdateBatch) {
+        (
+            std::mem::take(&mut self.root_hashes),
+            std::mem::take(&mut self.pending),
+        )
+    }
+
+    /// Drops all the pending updates.
+    pub fn reset(&mut self) {
+        self.root_hashes.clear();
+        self.pending = TreeUpdateBatch::default();
+    }
+
+    /// Returns the underlying reader.
+    pub fn inner(&self) -> &R {
+        &self.reader
+    }
+}
+
+impl<R: TreeReader, H: SimpleHasher> TreeReader for OverlayTree<R, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.pending.node_batch.get_node(node_key) {
+            Some(node) => Ok(Some(node.clone())),
+            None => self.reader.get_node_option(node_key),
+        }
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let mut nodes: Vec<Option<Node>> = node_keys
+            .iter()
+            .map(|node_key| self.pending.node_batch.get_node(node_key).cloned())
+            .collect();
+        read_missing_nodes(&self.reader, node_keys, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        // Pending nodes are not kept encoded: returning `None` makes the read path fall back to
+        // `get_node_option`.
+        if self.pending.node_batch.get_node(node_key).is_some() {
+            return Ok(None);
+        }
+        self.reader.get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        match pending_value(&self.pending.node_batch, max_version, key_hash) {
+            Some(value) => Ok(value.as_deref().map(<[u8]>::to_vec)),
+            None => self.reader.get_value_option(max_version, key_hash),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        match self
+            .root_hashes
+            .iter()
+            .find(|(pending_version, _)| *pending_version == version)
+        {
+            Some((_, root_hash)) => Ok(Some(*root_hash)),
+            None => self.reader.get_indexed_root_hash(version),
+        }
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        // Pending root hashes take precedence over those of the reader for the same version.
+        let mut root_hashes: BTreeMap<Version, RootHash> = self
+            .reader
+            .get_indexed_root_hashes(versions.clone(), limit)?
+            .into_iter()
+            .collect();
+        root_hashes.extend(
+            self.root_hashes
+                .iter()
+                .filter(|(version, _)| versions.contains(version))
+                .copied(),
+        );
+        Ok(root_hashes.into_iter().take(limit).collect())
+    }
+}
+
+/// Returns the newest value of `key_hash` in `node_batch` at or before `max_version`, if any.
+pub(crate) fn pending_value(
+    node_batch: &NodeBatch,
+    max_version: Version,
+    key_hash: KeyHash,
+) -> Option<&Option<Bytes>> {
+    // Values are ordered by version first, so look for the newest value of the key by scanning
+    // backwards from `max_version`.
+    node_batch
+        .values()
+        .range(..=(max_version, KeyHash([u8::MAX; 32])))
+        .rev()
+        .find(|((_, pending_key_hash), _)| *pending_key_hash == key_hash)
+        .map(|(_, value)| value)
+}
+
+impl<R: HasPreimage, H: SimpleHasher> HasPreimage for OverlayTree<R, H> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.reader.preimage(key_hash)
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.reader.preimages(key_hashes)
+    }
+}
@@ -0,0 +1,124 @@
+//! A [`SimpleHasher`] built on a Poseidon permutation, for trees whose proofs are verified in
+//! SNARK circuits.
+//!
+//! Poseidon operates on elements of a prime field rather than on bytes, and each proof system
+//! fixes its own field, state width, round numbers and constants. This crate does not implement
+//! the permutation: it is supplied through [`PoseidonPermutation`], typically by wrapping the
+//! implementation of the proof system the circuit is written with, so that the hashes computed
+//! by the tree are exactly those the circuit recomputes. [`PoseidonHasher`] turns the permutation
+//! into a hash of byte strings with a sponge:
+//!
+//! 1. The input is padded with a `0x01` byte, then with `0x00` bytes up to a multiple of
+//!    [`PoseidonPermutation::BYTES_PER_ELEMENT`].
+//! 2. Each chunk of that many bytes is read as a little-endian integer, which is a field element.
+//! 3. The elements are added to the first `WIDTH - CAPACITY` elements of a state starting at
+//!    zero, that many at a time, the state being permuted after each group of elements.
+//! 4. The digest is made of the `BYTES_PER_ELEMENT` low bytes, in little-endian order, of the
+//!    first `WIDTH - CAPACITY` elements of the state, the state being permuted again whenever
+//!    more bytes are needed, up to 32 bytes.
+//!
+//! With the default domain separators, node hashes only ever hash at most 80 bytes, so with a
+//! 254-bit field and 31 bytes per element, a leaf or an internal node is hashed with a single
+//! permutation of a state of width 4.
+
+use alloc::vec::Vec;
+
+use crate::SimpleHasher;
+
+/// A Poseidon permutation over a prime field, with its parameters.
+pub trait PoseidonPermutation {
+    /// An element of the field.
+    type Element: Copy;
+
+    /// The number of elements of the state.
+    const WIDTH: usize;
+    /// The number of elements of the state which are never absorbed into nor squeezed from.
+    const CAPACITY: usize;
+    /// The number of bytes held by each absorbed element. Any integer of that many bytes must be
+    /// smaller than the modulus of the field, e.g. 31 for a 254-bit field.
+    const BYTES_PER_ELEMENT: usize;
+
+    /// Returns the zero of the field.
+    fn zero() -> Self::Element;
+
+    /// Returns the field element whose value is the little-endian integer `bytes`, of at most
+    /// [`BYTES_PER_ELEMENT`](PoseidonPermutation::BYTES_PER_ELEMENT) bytes.
+    fn from_le_bytes(bytes: &[u8]) -> Self::Element;
+
+    /// Writes the low `out.len()` bytes of the canonical little-endian representation of
+    /// `element` to `out`.
+    fn to_le_bytes(element: &Self::Element, out: &mut [u8]);
+
+    /// Returns the sum of two field elements.
+    fn add(a: &Self::Element, b: &Self::Element) -> Self::Element;
+
+    /// Applies the permutation to `state`, which has [`WIDTH`](PoseidonPermutation::WIDTH)
+    /// elements.
+    fn permute(state: &mut [Self::Element]);
+}
+
+/// A [`SimpleHasher`] hashing byte strings with a sponge over the Poseidon permutation `P`.
+pub struct PoseidonHasher<P> {
+    buffer: Vec<u8>,
+    _permutation: core::marker::PhantomData<P>,
+}
+
+impl<P: PoseidonPermutation> PoseidonHasher<P> {
+    const RATE: usize = P::WIDTH - P::CAPACITY;
+}
+
+impl<P: PoseidonPermutation> SimpleHasher for PoseidonHasher<P> {
+    fn new() -> Self {
+        assert!(
+            P::CAPACITY > 0 && P::CAPACITY < P::WIDTH,
+            "The capacity of a sponge must leave it a positive rate."
+        );
+        assert!(
+            P::BYTES_PER_ELEMENT > 0 && P::BYTES_PER_ELEMENT <= 32,
+            "Field elements must hold between 1 and 32 bytes."
+        );
+        Self {
+            buffer: Vec::new(),
+            _permutation: core::marker::PhantomData,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        // Bytes are only absorbed once the input is complete, since its padding depends on its
+        // length.
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn finalize(mut self) -> [u8; 32] {
+        self.buffer.push(0x01);
+        let padded_len = self.buffer.len().div_ceil(P::BYTES_PER_ELEMENT) * P::BYTES_PER_ELEMENT;
+        self.buffer.resize(padded_len, 0x00);
+
+        let mut state: Vec<P::Element> = (0..P::WIDTH).map(|_| P::zero()).collect();
+        let elements: Vec<P::Element> = self
+            .buffer
+            .chunks(P::BYTES_PER_ELEMENT)
+            .map(P::from_le_bytes)
+            .collect();
+        for group in elements.chunks(Self::RATE) {
+            for (state_element, element) in state.iter_mut().zip(group) {
+                *state_element = P::add(state_element, element);
+            }
+            P::permute(&mut state);
+        }
+
+        let mut digest = [0u8; 32];
+        let mut filled = 0;
+        loop {
+            for element in &state[..Self::RATE] {
+                let len = P::BYTES_PER_ELEMENT.min(32 - filled);
+                P::to_le_bytes(element, &mut digest[filled..filled + len]);
+                filled += len;
+                if filled == 32 {
+                    return digest;
+                }
+            }
+            P::permute(&mut state);
+        }
+    }
+}
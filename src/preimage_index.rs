@@ -0,0 +1,186 @@
+//! An in-memory index of key preimages, sorted lexicographically.
+//!
+//! Keys are placed in a tree by their hashes, so the keys sharing a prefix, such as all the
+//! `validators/` or all the balances of an account, are scattered across the whole tree. A
+//! [`PreimageIndex`] keeps the preimages of the live keys of a tree in lexicographic order, so that
+//! they can be scanned by prefix with
+//! [`JellyfishMerkleTree::iter_preimage_prefix`](crate::JellyfishMerkleTree::iter_preimage_prefix).
+//!
+//! The index follows the tree as it is updated: each [`TreeUpdateBatch`], or each [`Commit`], is
+//! applied to it along with the preimages of the keys it writes. It can also be rebuilt from the
+//! tree at a version and a [`HasPreimage`] store, e.g. on startup.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use anyhow::{format_err, Result};
+
+use crate::{
+    commit::{Commit, CommitTarget},
+    storage::{HasPreimage, HasPreimageIndex, PreimageIterator, TreeReader, TreeUpdateBatch},
+    JellyfishMerkleTree, KeyHash, SimpleHasher, Version,
+};
+
+/// The number of key hashes whose preimages are looked up at once when rebuilding an index.
+const REBUILD_CHUNK_SIZE: usize = 1000;
+
+/// A sorted index of the preimages of the keys of a tree.
+///
+/// The index lists the preimages of the keys which have a value at the latest version applied to
+/// it: a key deleted by a batch leaves the sorted index, and comes back if a later batch writes it
+/// again. Scans at earlier versions therefore miss the keys deleted since. Preimages of keys
+/// never applied in a batch are indexed as soon as they are inserted.
+#[derive(Default)]
+pub struct PreimageIndex {
+    inner: RwLock<IndexInner>,
+}
+
+#[derive(Default)]
+struct IndexInner {
+    /// The preimages of all the key hashes seen, live or not.
+    preimages: HashMap<KeyHash, Vec<u8>>,
+    /// The preimages of the live keys, sorted.
+    sorted: BTreeMap<Vec<u8>, KeyHash>,
+}
+
+impl PreimageIndex {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index of the keys of `tree` at `version`, whose preimages are looked up in
+    /// `preimages`. Keys whose preimage is unknown are not indexed.
+    pub fn build<R, H>(
+        tree: &JellyfishMerkleTree<R, H>,
+        version: Version,
+        preimages: &impl HasPreimage,
+    ) -> Result<Self>
+    where
+        R: TreeReader,
+        H: SimpleHasher,
+    {
+        let index = Self::new();
+        let mut leaves = tree.iter(version)?.peekable();
+        while leaves.peek().is_some() {
+            let key_hashes = leaves
+                .by_ref()
+                .take(REBUILD_CHUNK_SIZE)
+                .map(|leaf| leaf.map(|(key_hash, _)| key_hash))
+                .collect::<Result<Vec<_>>>()?;
+            let chunk_preimages = preimages.preimages(&key_hashes)?;
+            index.insert_preimages(
+                key_hashes
+                    .into_iter()
+                    .zip(chunk_preimages)
+                    .filter_map(|(key_hash, preimage)| Some((key_hash, preimage?))),
+            )?;
+        }
+        Ok(index)
+    }
+
+    /// Records the preimages of live key hashes.
+    pub fn insert_preimages(
+        &self,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Result<()> {
+        let mut inner = self.write()?;
+        for (key_hash, preimage) in preimages {
+            inner.insert(key_hash, preimage);
+        }
+        Ok(())
+    }
+
+    /// Updates the index with the keys written by `batch`, given the preimages of the keys it
+    /// writes. Keys deleted by the batch leave the sorted index, and keys written with a value
+    /// join it if their preimage is known, whether it was given now or earlier.
+    pub fn apply(
+        &self,
+        batch: &TreeUpdateBatch,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Result<()> {
+        let mut inner = self.write()?;
+        for (key_hash, preimage) in preimages {
+            inner.insert(key_hash, preimage);
+        }
+        // Values are sorted by version, so the last write of each key decides whether it is live.
+        let mut is_live = HashMap::new();
+        for ((_, key_hash), value) in batch.node_batch.values() {
+            is_live.insert(*key_hash, value.is_some());
+        }
+        for (key_hash, is_live) in is_live {
+            let preimage = match inner.preimages.get(&key_hash) {
+                Some(preimage) => preimage.clone(),
+                None => continue,
+            };
+            if is_live {
+                inner.sorted.insert(preimage, key_hash);
+            } else {
+                inner.sorted.remove(&preimage);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of live keys in the sorted index.
+    pub fn len(&self) -> usize {
+        self.read().map_or(0, |inner| inner.sorted.len())
+    }
+
+    /// Returns true if the sorted index holds no key.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, IndexInner>> {
+        self.inner
+            .read()
+            .map_err(|_| format_err!("The preimage index lock is poisoned."))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, IndexInner>> {
+        self.inner
+            .write()
+            .map_err(|_| format_err!("The preimage index lock is poisoned."))
+    }
+}
+
+impl IndexInner {
+    fn insert(&mut self, key_hash: KeyHash, preimage: Vec<u8>) {
+        if let Some(previous) = self.preimages.insert(key_hash, preimage.clone()) {
+            if previous != preimage && self.sorted.get(&previous) == Some(&key_hash) {
+                self.sorted.remove(&previous);
+            }
+        }
+        self.sorted.insert(preimage, key_hash);
+    }
+}
+
+impl HasPreimage for PreimageIndex {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        Ok(self.read()?.preimages.get(&key_hash).cloned())
+    }
+}
+
+impl HasPreimageIndex for PreimageIndex {
+    fn preimages_with_prefix(&self, prefix: &[u8]) -> Result<PreimageIterator<'_>> {
+        let inner = self.read()?;
+        // The lock is not held while the caller iterates, so the matching entries are copied.
+        let preimages: Vec<(Vec<u8>, KeyHash)> = inner
+            .sorted
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(preimage, _)| preimage.starts_with(prefix))
+            .map(|(preimage, key_hash)| (preimage.clone(), *key_hash))
+            .collect();
+        Ok(Box::new(preimages.into_iter().map(Ok)))
+    }
+}
+
+impl CommitTarget for PreimageIndex {
+    fn apply_commit(&self, commit: &Commit) -> Result<()> {
+        self.apply(&commit.batch, commit.preimages.iter().cloned())
+    }
+}
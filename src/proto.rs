@@ -0,0 +1,163 @@
+//! Protobuf messages for the native proofs of the tree, so that services written in other
+//! languages can decode and verify them without going through ics23.
+//!
+//! The messages are defined by [`PROOFS_PROTO`], the `jmt.v1` package published in
+//! `proto/jmt/v1/proofs.proto`, from which other languages can generate their own types. They are
+//! converted from the proofs of [`proof`](crate::proof) with [`From`], and back with [`TryFrom`],
+//! which checks that every hash has 32 bytes and that a proof has at most 256 siblings. A decoded
+//! proof must still be verified against a trusted root hash.
+
+use alloc::vec::Vec;
+
+use anyhow::{ensure, format_err, Error, Result};
+
+use crate::{
+    proof::{ExclusionProof, SparseMerkleLeafNode, SparseMerkleProof},
+    KeyHash, SimpleHasher, ValueHash,
+};
+
+/// The Protobuf definition of the messages of this module.
+pub const PROOFS_PROTO: &str = include_str!("../proto/jmt/v1/proofs.proto");
+
+/// The maximal number of siblings of a proof, one per bit of a key hash.
+const MAX_SIBLINGS: usize = 256;
+
+/// A leaf of the tree, as the message `jmt.v1.SparseMerkleLeafNode`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoSparseMerkleLeafNode {
+    #[prost(bytes = "vec", tag = "1")]
+    pub key_hash: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub value_hash: Vec<u8>,
+}
+
+/// A [`SparseMerkleProof`], as the message `jmt.v1.SparseMerkleProof`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoSparseMerkleProof {
+    #[prost(message, optional, tag = "1")]
+    pub leaf: Option<ProtoSparseMerkleLeafNode>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub siblings: Vec<Vec<u8>>,
+}
+
+/// An [`ExclusionProof`], as the message `jmt.v1.ExclusionProof`. Its variant follows from which
+/// of its neighbor proofs are present.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoExclusionProof {
+    #[prost(message, optional, tag = "1")]
+    pub leftmost_right_proof: Option<ProtoSparseMerkleProof>,
+    #[prost(message, optional, tag = "2")]
+    pub rightmost_left_proof: Option<ProtoSparseMerkleProof>,
+}
+
+fn decode_hash(bytes: &[u8], name: &str) -> Result<[u8; 32]> {
+    bytes
+        .try_into()
+        .map_err(|_| format_err!("Expected a 32-byte {}, got {} bytes.", name, bytes.len()))
+}
+
+impl From<SparseMerkleLeafNode> for ProtoSparseMerkleLeafNode {
+    fn from(leaf: SparseMerkleLeafNode) -> Self {
+        Self {
+            key_hash: leaf.key_hash().0.to_vec(),
+            value_hash: leaf.value_hash().0.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<ProtoSparseMerkleLeafNode> for SparseMerkleLeafNode {
+    type Error = Error;
+
+    fn try_from(leaf: ProtoSparseMerkleLeafNode) -> Result<Self> {
+        Ok(SparseMerkleLeafNode::new(
+            KeyHash(decode_hash(&leaf.key_hash, "key hash")?),
+            ValueHash(decode_hash(&leaf.value_hash, "value hash")?),
+        ))
+    }
+}
+
+impl<H: SimpleHasher> From<SparseMerkleProof<H>> for ProtoSparseMerkleProof {
+    fn from(proof: SparseMerkleProof<H>) -> Self {
+        Self {
+            leaf: proof.leaf().map(Into::into),
+            siblings: proof
+                .siblings()
+                .iter()
+                .map(|sibling| sibling.to_vec())
+                .collect(),
+        }
+    }
+}
+
+impl<H: SimpleHasher> TryFrom<ProtoSparseMerkleProof> for SparseMerkleProof<H> {
+    type Error = Error;
+
+    fn try_from(proof: ProtoSparseMerkleProof) -> Result<Self> {
+        ensure!(
+            proof.siblings.len() <= MAX_SIBLINGS,
+            "A proof has at most {} siblings, got {}.",
+            MAX_SIBLINGS,
+            proof.siblings.len()
+        );
+        let leaf = proof.leaf.map(TryInto::try_into).transpose()?;
+        let siblings = proof
+            .siblings
+            .iter()
+            .map(|sibling| decode_hash(sibling, "sibling"))
+            .collect::<Result<_>>()?;
+        Ok(SparseMerkleProof::new(leaf, siblings))
+    }
+}
+
+impl<H: SimpleHasher> From<ExclusionProof<H>> for ProtoExclusionProof {
+    fn from(proof: ExclusionProof<H>) -> Self {
+        let (leftmost_right_proof, rightmost_left_proof) = match proof {
+            ExclusionProof::Leftmost {
+                leftmost_right_proof,
+            } => (Some(leftmost_right_proof), None),
+            ExclusionProof::Middle {
+                leftmost_right_proof,
+                rightmost_left_proof,
+            } => (Some(leftmost_right_proof), Some(rightmost_left_proof)),
+            ExclusionProof::Rightmost {
+                rightmost_left_proof,
+            } => (None, Some(rightmost_left_proof)),
+        };
+        Self {
+            leftmost_right_proof: leftmost_right_proof.map(Into::into),
+            rightmost_left_proof: rightmost_left_proof.map(Into::into),
+        }
+    }
+}
+
+impl<H: SimpleHasher> TryFrom<ProtoExclusionProof> for ExclusionProof<H> {
+    type Error = Error;
+
+    fn try_from(proof: ProtoExclusionProof) -> Result<Self> {
+        let leftmost_right_proof = proof
+            .leftmost_right_proof
+            .map(TryInto::try_into)
+            .transpose()?;
+        let rightmost_left_proof = proof
+            .rightmost_left_proof
+            .map(TryInto::try_into)
+            .transpose()?;
+        match (leftmost_right_proof, rightmost_left_proof) {
+            (Some(leftmost_right_proof), None) => Ok(ExclusionProof::Leftmost {
+                leftmost_right_proof,
+            }),
+            (Some(leftmost_right_proof), Some(rightmost_left_proof)) => {
+                Ok(ExclusionProof::Middle {
+                    leftmost_right_proof,
+                    rightmost_left_proof,
+                })
+            }
+            (None, Some(rightmost_left_proof)) => Ok(ExclusionProof::Rightmost {
+                rightmost_left_proof,
+            }),
+            (None, None) => Err(format_err!(
+                "An exclusion proof has at least one neighbor proof."
+            )),
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! This module implements [`Pruner`], which deletes the nodes of a
+//! [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) that are no longer needed to read the tree
+//! at recent versions.
+//!
+//! Every update to the tree records a [`StaleNodeIndex`] for each node it replaces, in the
+//! `stale_node_index_batch` of its [`TreeUpdateBatch`](crate::storage::TreeUpdateBatch). Once those
+//! indices have been persisted through a [`StaleNodeIndexStore`], a [`Pruner`] can delete all the
+//! nodes which became stale at or before a version, keeping the tree readable at that version and
+//! every later one.
+//!
+//! [`Pruner::stale_node_batches`] instead yields the stale node indices lazily, one bounded batch
+//! at a time, for callers which delete the nodes themselves. Each batch is a resumable position,
+//! so pruning a large store can be interrupted and resumed without holding all its indices in
+//! memory.
+//!
+//! Readers which hold on to a historical version, such as long-running iterators or proof
+//! generation, can protect it from pruning with a [`VersionGuard`], returned by
+//! [`JellyfishMerkleTree::pin_version`](crate::JellyfishMerkleTree::pin_version) or
+//! [`VersionPins::pin`]. A [`Pruner`] given the [`VersionPins`] of its store with
+//! [`Pruner::with_version_pins`] never prunes past the oldest pinned version, and versions which
+//! were already pruned can no longer be pinned.
+//!
+//! Rather than pruning up to a given version, [`Pruner::prune_with_policy`] prunes the versions
+//! which a [`RetentionPolicy`] does not keep, e.g. all but the latest 1000 versions and every
+//! 10000th version as an archive checkpoint.
+
+use std::{
+    collections::BTreeMap,
+    ops::AddAssign,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+
+use crate::{
+    node_type::Node,
+    storage::{StaleNodeIndex, TreeReader},
+    types::Version,
+    JmtError,
+};
+
+/// The default number of stale nodes deleted at once by a [`Pruner`].
+pub const DEFAULT_PRUNING_BATCH_SIZE: usize = 10_000;
+
+/// Defines the interface to the storage of the [`StaleNodeIndex`] entries of a tree, as needed by
+/// a [`Pruner`].
+pub trait StaleNodeIndexStore {
+    /// Gets up to `limit` stale node indices whose `stale_since_version` is at most
+    /// `max_stale_since_version`, in ascending order.
+    fn get_stale_node_indices(
+        &self,
+        max_stale_since_version: Version,
+        limit: usize,
+    ) -> Result<Vec<StaleNodeIndex>> {
+        self.get_stale_node_indices_after(None, max_stale_since_version, limit)
+    }
+
+    /// Same as [`get_stale_node_indices`](StaleNodeIndexStore::get_stale_node_indices), but only
+    /// gets the indices strictly greater than `after`, if given.
+    fn get_stale_node_indices_after(
+        &self,
+        after: Option<&StaleNodeIndex>,
+        max_stale_since_version: Version,
+        limit: usize,
+    ) -> Result<Vec<StaleNodeIndex>>;
+
+    /// Deletes the nodes referred to by `stale_node_indices`, together with the indices
+    /// themselves.
+    fn delete_stale_nodes(&self, stale_node_indices: &[StaleNodeIndex]) -> Result<()>;
+}
+
+/// The versions pinned against pruning, shared between the readers of a store and its [`Pruner`].
+///
+/// Cloning `VersionPins` returns another handle to the same pins.
+#[derive(Clone, Debug, Default)]
+pub struct VersionPins {
+    state: Arc<Mutex<PinState>>,
+}
+
+#[derive(Debug, Default)]
+struct PinState {
+    /// The number of guards of each pinned version.
+    pins: BTreeMap<Version, usize>,
+    /// The least version still readable after the pruning so far, if any.
+    least_readable_version: Option<Version>,
+}
+
+impl VersionPins {
+    /// Creates an empty set of pins.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pins `version` until the returned guard is dropped. Fails if `version` was already pruned.
+    pub fn pin(&self, version: Version) -> Result<VersionGuard> {
+        let mut state = self.lock();
+        if let Some(least_readable_version) = state.least_readable_version {
+            if version < least_readable_version {
+                return Err(JmtError::VersionPruned {
+                    version,
+                    least_readable_version,
+                }
+                .into());
+            }
+        }
+        *state.pins.entry(version).or_default() += 1;
+        Ok(VersionGuard {
+            pins: self.clone(),
+            version,
+        })
+    }
+
+    /// Returns the oldest pinned version, if any.
+    pub fn min_pinned_version(&self) -> Option<Version> {
+        self.lock().pins.keys().next().copied()
+    }
+
+    /// Returns true if `version` is pinned by at least one guard.
+    pub fn is_pinned(&self, version: Version) -> bool {
+        self.lock().pins.contains_key(&version)
+    }
+
+    /// Returns the least version a pruning up to `least_readable_version` may keep readable
+    /// without deleting the nodes of a pinned version, and records it so that no older version
+    /// can be pinned from now on.
+    fn reserve_pruning(&self, least_readable_version: Version) -> Version {
+        let mut state = self.lock();
+        let least_readable_version = state
+            .pins
+            .keys()
+            .next()
+            .map_or(least_readable_version, |pinned| {
+                least_readable_version.min(*pinned)
+            });
+        state.least_readable_version = state
+            .least_readable_version
+            .max(Some(least_readable_version));
+        least_readable_version
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PinState> {
+        // The state is consistent after every operation, so a poisoned lock can be recovered.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Keeps a version pinned against pruning until dropped, see [`VersionPins::pin`].
+#[derive(Debug)]
+pub struct VersionGuard {
+    pins: VersionPins,
+    version: Version,
+}
+
+impl VersionGuard {
+    /// Returns the pinned version.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+}
+
+impl Clone for VersionGuard {
+    fn clone(&self) -> Self {
+        *self.pins.lock().pins.entry(self.version).or_default() += 1;
+        Self {
+            pins: self.pins.clone(),
+            version: self.version,
+        }
+    }
+}
+
+impl Drop for VersionGuard {
+    fn drop(&mut self) {
+        let mut state = self.pins.lock();
+        if let Some(count) = state.pins.get_mut(&self.version) {
+            *count -= 1;
+            if *count == 0 {
+                state.pins.remove(&self.version);
+            }
+        }
+    }
+}
+
+/// Defines access to the [`VersionPins`] of a store, through which
+/// [`JellyfishMerkleTree::pin_version`](crate::JellyfishMerkleTree::pin_version) pins versions.
+pub trait HasVersionPins {
+    /// Returns the pins of the store.
+    fn version_pins(&self) -> &VersionPins;
+}
+
+impl<T: HasVersionPins> HasVersionPins for &T {
+    fn version_pins(&self) -> &VersionPins {
+        (**self).version_pins()
+    }
+}
+
+/// Which versions of a tree to keep readable when pruning it with [`Pruner::prune_with_policy`].
+///
+/// A policy keeps the latest versions of the tree, and optionally every K-th older version, from
+/// version 0, as an archive checkpoint. Versions pinned through the [`VersionPins`] given to the
+/// pruner are never pruned either.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetentionPolicy {
+    keep_latest: u64,
+    checkpoint_interval: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Creates a policy keeping the latest `keep_latest` versions, the latest one included.
+    pub fn keep_latest(keep_latest: u64) -> Self {
+        assert!(keep_latest > 0, "At least one version must be kept.");
+        Self {
+            keep_latest,
+            checkpoint_interval: None,
+        }
+    }
+
+    /// Also keeps every version which is a multiple of `checkpoint_interval`.
+    pub fn with_checkpoint_interval(mut self, checkpoint_interval: u64) -> Self {
+        assert!(
+            checkpoint_interval > 0,
+            "The checkpoint interval must be positive."
+        );
+        self.checkpoint_interval = Some(checkpoint_interval);
+        self
+    }
+
+    /// Returns the oldest of the latest versions kept when `latest_version` is the latest version
+    /// of the tree.
+    pub fn least_readable_version(&self, latest_version: Version) -> Version {
+        latest_version.saturating_sub(self.keep_latest - 1)
+    }
+
+    /// Returns true if `version` is kept when `latest_version` is the latest version of the tree.
+    pub fn retains_version(&self, version: Version, latest_version: Version) -> bool {
+        version >= self.least_readable_version(latest_version)
+            || self
+                .checkpoint_interval
+                .is_some_and(|interval| version.is_multiple_of(interval))
+    }
+
+    /// Returns true if the node of `index` is needed to read a checkpoint, that is if a
+    /// checkpoint lies between the version of the node and the version it became stale at.
+    pub fn retains_stale_node(&self, index: &StaleNodeIndex) -> bool {
+        let (Some(interval), Some(last_version)) = (
+            self.checkpoint_interval,
+            index.stale_since_version.checked_sub(1),
+        ) else {
+            return false;
+        };
+        last_version / interval * interval >= index.node_key.version()
+    }
+}
+
+/// What a call to [`Pruner::prune`] reclaimed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PruningStats {
+    /// The number of nodes deleted, leaves included.
+    pub pruned_nodes: usize,
+    /// The number of leaves deleted.
+    pub pruned_leaves: usize,
+    /// The total size of the encoded keys and encoded nodes deleted.
+    pub reclaimed_bytes: usize,
+}
+
+impl AddAssign for PruningStats {
+    fn add_assign(&mut self, other: Self) {
+        self.pruned_nodes += other.pruned_nodes;
+        self.pruned_leaves += other.pruned_leaves;
+        self.reclaimed_bytes += other.reclaimed_bytes;
+    }
+}
+
+/// Deletes stale nodes from a store, in batches.
+pub struct Pruner<'a, S> {
+    store: &'a S,
+    batch_size: usize,
+    version_pins: Option<VersionPins>,
+}
+
+impl<'a, S> Pruner<'a, S>
+where
+    S: 'a + TreeReader + StaleNodeIndexStore,
+{
+    /// Creates a `Pruner` which deletes stale nodes from `store`, [`DEFAULT_PRUNING_BATCH_SIZE`]
+    /// nodes at a time.
+    pub fn new(store: &'a S) -> Self {
+        Self {
+            store,
+            batch_size: DEFAULT_PRUNING_BATCH_SIZE,
+            version_pins: None,
+        }
+    }
+
+    /// Sets the number of stale nodes deleted at once.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "The batch size must be positive.");
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Protects the versions pinned in `version_pins` from pruning.
+    pub fn with_version_pins(mut self, version_pins: &VersionPins) -> Self {
+        self.version_pins = Some(version_pins.clone());
+        self
+    }
+
+    /// Lowers `least_readable_version` to the oldest pinned version, if any is older.
+    fn reserve_pruning(&self, least_readable_version: Version) -> Version {
+        match &self.version_pins {
+            Some(version_pins) => version_pins.reserve_pruning(least_readable_version),
+            None => least_readable_version,
+        }
+    }
+
+    /// Deletes all the nodes which became stale at or before `least_readable_version`. The tree
+    /// remains readable at `least_readable_version` and every later version.
+    ///
+    /// With [`with_version_pins`](Pruner::with_version_pins), nothing is pruned past the oldest
+    /// pinned version, which remains readable as well.
+    pub fn prune(&self, least_readable_version: Version) -> Result<PruningStats> {
+        self.prune_retaining(least_readable_version, |_| false)
+    }
+
+    /// Prunes the versions which `policy` does not retain, `latest_version` being the latest
+    /// version of the tree. As for [`prune`](Pruner::prune), nothing is pruned past the oldest
+    /// pinned version.
+    ///
+    /// The stale nodes of the retained checkpoints are kept, together with their indices, which
+    /// are then skipped again by every later pruning.
+    pub fn prune_with_policy(
+        &self,
+        policy: &RetentionPolicy,
+        latest_version: Version,
+    ) -> Result<PruningStats> {
+        self.prune_retaining(policy.least_readable_version(latest_version), |index| {
+            policy.retains_stale_node(index)
+        })
+    }
+
+    /// Deletes the nodes which became stale at or before `least_readable_version`, except those
+    /// for which `retain` is true.
+    fn prune_retaining(
+        &self,
+        least_readable_version: Version,
+        retain: impl Fn(&StaleNodeIndex) -> bool,
+    ) -> Result<PruningStats> {
+        let least_readable_version = self.reserve_pruning(least_readable_version);
+        let mut stats = PruningStats::default();
+        let mut cursor = None;
+        loop {
+            let (batch_stats, next_cursor) =
+                self.prune_batch(cursor.as_ref(), least_readable_version, &retain)?;
+            stats += batch_stats;
+            match next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => return Ok(stats),
+            }
+        }
+    }
+
+    /// Deletes the nodes of the next batch of at most the batch size of this pruner, which became
+    /// stale at or before `least_readable_version`, up to the oldest pinned version. Returns what
+    /// was reclaimed, and whether stale nodes may be left.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn prune_next_batch(
+        &self,
+        least_readable_version: Version,
+    ) -> Result<(PruningStats, bool)> {
+        let least_readable_version = self.reserve_pruning(least_readable_version);
+        let (stats, cursor) = self.prune_batch(None, least_readable_version, |_| false)?;
+        Ok((stats, cursor.is_some()))
+    }
+
+    /// Deletes the nodes of the batch of stale node indices after `cursor`, except those for
+    /// which `retain` is true. Returns what was reclaimed, and the cursor of the next batch if
+    /// this one was full.
+    fn prune_batch(
+        &self,
+        cursor: Option<&StaleNodeIndex>,
+        least_readable_version: Version,
+        retain: impl Fn(&StaleNodeIndex) -> bool,
+    ) -> Result<(PruningStats, Option<StaleNodeIndex>)> {
+        let mut stats = PruningStats::default();
+        let stale_node_indices = self.store.get_stale_node_indices_after(
+            cursor,
+            least_readable_version,
+            self.batch_size,
+        )?;
+        let pruned_indices: Vec<StaleNodeIndex> = stale_node_indices
+            .iter()
+            .filter(|index| !retain(index))
+            .cloned()
+            .collect();
+        for stale_node_index in &pruned_indices {
+            // The node may already have been deleted by other means.
+            if let Some(node) = self.store.get_node_option(&stale_node_index.node_key)? {
+                stats.pruned_nodes += 1;
+                if let Node::Leaf(_) = node {
+                    stats.pruned_leaves += 1;
+                }
+                stats.reclaimed_bytes +=
+                    stale_node_index.node_key.encode()?.len() + node.encode()?.len();
+            }
+        }
+        if !pruned_indices.is_empty() {
+            self.store.delete_stale_nodes(&pruned_indices)?;
+        }
+
+        let next_cursor = if stale_node_indices.len() < self.batch_size {
+            None
+        } else {
+            stale_node_indices.last().cloned()
+        };
+        Ok((stats, next_cursor))
+    }
+
+    /// Returns an iterator over the indices of the nodes which became stale at or before
+    /// `least_readable_version`, in ascending order and in batches of at most the batch size of
+    /// this pruner, starting strictly after `resume_after` if given.
+    ///
+    /// Nothing is deleted: the nodes of each batch can be deleted with
+    /// [`StaleNodeIndexStore::delete_stale_nodes`], or by other means. The last index of the
+    /// latest batch can be persisted, and passed as `resume_after` to resume the iteration after
+    /// an interruption. As for [`prune`](Pruner::prune), the batches stop at the oldest pinned
+    /// version.
+    pub fn stale_node_batches(
+        &self,
+        least_readable_version: Version,
+        resume_after: Option<StaleNodeIndex>,
+    ) -> StaleNodeBatches<'a, S> {
+        StaleNodeBatches {
+            store: self.store,
+            least_readable_version: self.reserve_pruning(least_readable_version),
+            batch_size: self.batch_size,
+            cursor: resume_after,
+            done: false,
+        }
+    }
+}
+
+/// An iterator over batches of stale node indices, returned by [`Pruner::stale_node_batches`].
+pub struct StaleNodeBatches<'a, S> {
+    store: &'a S,
+    least_readable_version: Version,
+    batch_size: usize,
+    cursor: Option<StaleNodeIndex>,
+    done: bool,
+}
+
+impl<S> StaleNodeBatches<'_, S> {
+    /// Returns the last index yielded so far, or the index the iteration was resumed after.
+    pub fn cursor(&self) -> Option<&StaleNodeIndex> {
+        self.cursor.as_ref()
+    }
+}
+
+impl<S: StaleNodeIndexStore> Iterator for StaleNodeBatches<'_, S> {
+    type Item = Result<Vec<StaleNodeIndex>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let batch = match self.store.get_stale_node_indices_after(
+            self.cursor.as_ref(),
+            self.least_readable_version,
+            self.batch_size,
+        ) {
+            Ok(batch) => batch,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        };
+        if batch.len() < self.batch_size {
+            self.done = true;
+        }
+        match batch.last() {
+            Some(last) => {
+                self.cursor = Some(last.clone());
+                Some(Ok(batch))
+            }
+            None => None,
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use anyhow::{format_err, Result};
+use core::ops::Range;
+use std::sync::Arc;
+
+use anyhow::Result;
 
 use crate::node_type::{LeafNode, Node, NodeKey};
-use crate::{KeyHash, OwnedValue, Version};
+use crate::{JmtError, KeyHash, OwnedValue, RootHash, Version};
 
 /// Defines the interface between a
 /// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
@@ -9,21 +12,51 @@ use crate::{KeyHash, OwnedValue, Version};
 pub trait TreeReader {
     /// Gets node given a node key. Returns error if the node does not exist.
     fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
-        self.get_node_option(node_key)?
-            .ok_or_else(|| format_err!("Missing node at {:?}.", node_key))
+        self.get_node_option(node_key)?.ok_or_else(|| {
+            JmtError::MissingNode {
+                node_key: node_key.clone(),
+            }
+            .into()
+        })
     }
 
     /// Gets node given a node key. Returns `None` if the node does not exist.
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>>;
 
+    /// Gets the nodes under several node keys at once, in the same order as `node_keys`. Returns
+    /// `None` for each node that does not exist.
+    ///
+    /// The tree calls this when it needs several nodes at the same time, such as the children of
+    /// an internal node on the paths of a batch of keys. The default implementation reads them one
+    /// by one: storage which can serve several reads in a single round trip should override it.
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        node_keys
+            .iter()
+            .map(|node_key| self.get_node_option(node_key))
+            .collect()
+    }
+
+    /// Gets the node given a node key in its encoded form, as produced by [`Node::encode`].
+    /// Returns `None` if the node does not exist, or if the storage does not keep nodes encoded.
+    ///
+    /// The read path decodes the nodes returned here with [`Node::decode_ref`], which does not
+    /// allocate, and falls back to [`get_node_option`](TreeReader::get_node_option) when this
+    /// returns `None`. Storage keeping nodes encoded should override this to return them as
+    /// stored.
+    fn get_encoded_node_option(&self, _node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
     /// Gets a value by identifier, returning the newest value whose version is *less than or
     /// equal to* the specified version. Returns an error if the value does not exist.
     fn get_value(&self, max_version: Version, key_hash: KeyHash) -> Result<OwnedValue> {
         self.get_value_option(max_version, key_hash)?
             .ok_or_else(|| {
-                format_err!(
-                    "Missing value with max_version {max_version:} and key hash {key_hash:?}."
-                )
+                JmtError::MissingValue {
+                    key_hash,
+                    max_version,
+                }
+                .into()
             })
     }
 
@@ -38,4 +71,265 @@ pub trait TreeReader {
     /// Gets the rightmost leaf. Note that this assumes we are in the process of restoring the tree
     /// and all nodes are at the same version.
     fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>>;
+
+    /// Gets the root hash recorded for a version, if the storage keeps an index of root hashes.
+    /// Returns `None` if the version has no recorded root hash, or if the storage does not keep
+    /// such an index.
+    ///
+    /// [`get_root_hash`](crate::JellyfishMerkleTree::get_root_hash) consults this first, and
+    /// falls back to reading and hashing the root node when this returns `None`. Storage
+    /// recording the root hash of each version it writes should override this, so that root
+    /// hash queries do not read any node. The index may keep the root hashes of versions whose
+    /// nodes were pruned.
+    fn get_indexed_root_hash(&self, _version: Version) -> Result<Option<RootHash>> {
+        Ok(None)
+    }
+
+    /// Gets up to `limit` of the root hashes recorded for the versions in `versions`, in
+    /// ascending order of version. See [`get_indexed_root_hash`](TreeReader::get_indexed_root_hash).
+    ///
+    /// The default implementation looks each version up in turn, which takes time proportional to
+    /// the width of `versions` when few of them have a recorded root hash. Storage keeping an index
+    /// of root hashes should override it with a scan of the index.
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        let mut root_hashes = vec![];
+        for version in versions {
+            if root_hashes.len() >= limit {
+                break;
+            }
+            if let Some(root_hash) = self.get_indexed_root_hash(version)? {
+                root_hashes.push((version, root_hash));
+            }
+        }
+        Ok(root_hashes)
+    }
+}
+
+impl<R: TreeReader + ?Sized> TreeReader for &R {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        (**self).get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        (**self).get_nodes(node_keys)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        (**self).get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        (**self).get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        (**self).get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        (**self).get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        (**self).get_indexed_root_hashes(versions, limit)
+    }
+}
+
+impl<R: TreeReader + ?Sized> TreeReader for Arc<R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        (**self).get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        (**self).get_nodes(node_keys)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        (**self).get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        (**self).get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        (**self).get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        (**self).get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        (**self).get_indexed_root_hashes(versions, limit)
+    }
+}
+
+/// Reads from `reader`, with a single call to [`TreeReader::get_nodes`], the nodes under
+/// `node_keys` which are `None` in `nodes`, e.g. because they were not found in a cache or a
+/// pending batch in front of `reader`. Returns the indices of the nodes it read.
+pub(crate) fn read_missing_nodes<R: TreeReader + ?Sized>(
+    reader: &R,
+    node_keys: &[NodeKey],
+    nodes: &mut [Option<Node>],
+) -> Result<Vec<usize>> {
+    let missing_indices: Vec<usize> = (0..nodes.len()).filter(|i| nodes[*i].is_none()).collect();
+    if !missing_indices.is_empty() {
+        let missing_node_keys: Vec<NodeKey> = missing_indices
+            .iter()
+            .map(|i| node_keys[*i].clone())
+            .collect();
+        for (i, node) in missing_indices
+            .iter()
+            .zip(reader.get_nodes(&missing_node_keys)?)
+        {
+            nodes[*i] = node;
+        }
+    }
+    Ok(missing_indices)
+}
+
+/// Defines the ability of the storage holding a
+/// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) to look up the preimage of a key hash, that
+/// is, the original key it was computed from.
+pub trait HasPreimage {
+    /// Gets the preimage of a key hash, if it is known to the storage.
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>>;
+
+    /// Gets the preimages of several key hashes at once, in the same order as `key_hashes`.
+    ///
+    /// The default implementation looks them up one by one: storage which can serve several
+    /// lookups in a single round trip should override it.
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        key_hashes
+            .iter()
+            .map(|key_hash| self.preimage(*key_hash))
+            .collect()
+    }
+}
+
+impl<R: HasPreimage + ?Sized> HasPreimage for &R {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        (**self).preimage(key_hash)
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        (**self).preimages(key_hashes)
+    }
+}
+
+/// An iterator over preimages and their key hashes, as returned by
+/// [`HasPreimageIndex::preimages_with_prefix`].
+pub type PreimageIterator<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, KeyHash)>> + 'a>;
+
+/// Defines the ability of the storage to list the preimages it knows in lexicographic order, so
+/// that ranges of original keys can be scanned even though the tree is ordered by key hash.
+pub trait HasPreimageIndex: HasPreimage {
+    /// Returns the known preimages starting with `prefix`, in lexicographic order, along with
+    /// their key hashes.
+    fn preimages_with_prefix(&self, prefix: &[u8]) -> Result<PreimageIterator<'_>>;
+}
+
+impl<R: HasPreimageIndex + ?Sized> HasPreimageIndex for &R {
+    fn preimages_with_prefix(&self, prefix: &[u8]) -> Result<PreimageIterator<'_>> {
+        (**self).preimages_with_prefix(prefix)
+    }
+}
+
+/// The asynchronous counterpart of [`TreeReader`], for storage that can only be read
+/// asynchronously. It is used by
+/// [`AsyncJellyfishMerkleTree`](crate::AsyncJellyfishMerkleTree).
+///
+/// Every [`TreeReader`] which is `Sync` is also an `AsyncTreeReader`.
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+pub trait AsyncTreeReader: Sync {
+    /// Gets node given a node key. Returns error if the node does not exist.
+    async fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
+        self.get_node_option(node_key).await?.ok_or_else(|| {
+            JmtError::MissingNode {
+                node_key: node_key.clone(),
+            }
+            .into()
+        })
+    }
+
+    /// Gets node given a node key. Returns `None` if the node does not exist.
+    async fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>>;
+
+    /// Gets a value by identifier, returning the newest value whose version is *less than or
+    /// equal to* the specified version. Returns an error if the value does not exist.
+    async fn get_value(&self, max_version: Version, key_hash: KeyHash) -> Result<OwnedValue> {
+        self.get_value_option(max_version, key_hash)
+            .await?
+            .ok_or_else(|| {
+                JmtError::MissingValue {
+                    key_hash,
+                    max_version,
+                }
+                .into()
+            })
+    }
+
+    /// Gets a value by identifier, returning the newest value whose version is *less than or
+    /// equal to* the specified version.  Returns None if the value does not exist.
+    async fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>>;
+
+    /// Gets the rightmost leaf. Note that this assumes we are in the process of restoring the tree
+    /// and all nodes are at the same version.
+    async fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>>;
+
+    /// Gets the root hash recorded for a version, if the storage keeps an index of root hashes.
+    /// See [`TreeReader::get_indexed_root_hash`].
+    async fn get_indexed_root_hash(&self, _version: Version) -> Result<Option<RootHash>> {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl<R: TreeReader + Sync> AsyncTreeReader for R {
+    async fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        TreeReader::get_node_option(self, node_key)
+    }
+
+    async fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        TreeReader::get_value_option(self, max_version, key_hash)
+    }
+
+    async fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        TreeReader::get_rightmost_leaf(self)
+    }
+
+    async fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        TreeReader::get_indexed_root_hash(self, version)
+    }
 }
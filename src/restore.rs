@@ -7,10 +7,11 @@
 
 use std::sync::Arc;
 
-use anyhow::{bail, ensure, Result};
+use anyhow::Result;
 use mirai_annotations::*;
 
 use crate::{
+    error::{bail_corrupted, ensure_not_corrupted, ensure_valid_proof},
     node_type::{
         get_child_and_sibling_half_start, Child, Children, InternalNode, LeafNode, Node, NodeKey,
         NodeType,
@@ -25,7 +26,7 @@ use crate::{
         Version,
     },
     Bytes32Ext, KeyHash, OwnedValue, PhantomHasher, RootHash, SimpleHasher, ValueHash,
-    ROOT_NIBBLE_HEIGHT, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    ROOT_NIBBLE_HEIGHT,
 };
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -44,7 +45,7 @@ enum ChildInfo {
 
 impl ChildInfo {
     /// Converts `self` to a child, assuming the hash is known if it's an internal node.
-    fn into_child(self, version: Version) -> Child {
+    fn into_child<H: SimpleHasher>(self, version: Version) -> Child {
         match self {
             Self::Internal { hash, leaf_count } => Child::new(
                 hash.expect("Must have been initialized."),
@@ -53,7 +54,7 @@ impl ChildInfo {
                     .map(|n| NodeType::Internal { leaf_count: n })
                     .unwrap_or(NodeType::InternalLegacy),
             ),
-            Self::Leaf { node } => Child::new(node.hash(), version, NodeType::Leaf),
+            Self::Leaf { node } => Child::new(node.hash::<H>(), version, NodeType::Leaf),
         }
     }
 }
@@ -84,7 +85,7 @@ impl InternalInfo {
 
     /// Converts `self` to an internal node, assuming all of its children are already known and
     /// fully initialized.
-    fn into_internal_node(
+    fn into_internal_node<H: SimpleHasher>(
         mut self,
         version: Version,
         leaf_count_migration: bool,
@@ -95,7 +96,7 @@ impl InternalInfo {
         // https://github.com/rust-lang/rust/issues/25725. So we use `iter_mut` and `take`.
         for (index, child_info_option) in self.children.iter_mut().enumerate() {
             if let Some(child_info) = child_info_option.take() {
-                children.insert((index as u8).into(), child_info.into_child(version));
+                children.insert((index as u8).into(), child_info.into_child::<H>(version));
             }
         }
 
@@ -168,6 +169,9 @@ pub struct JellyfishMerkleRestore<H: SimpleHasher> {
 }
 
 impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
+    /// Starts restoring the tree at `version` into `store`, which must end up with the root hash
+    /// `expected_root_hash`. If `store` already holds the leaves of an interrupted restoration, it
+    /// is resumed after the rightmost of them.
     pub fn new<D: 'static + TreeReader + TreeWriter>(
         store: Arc<D>,
         version: Version,
@@ -204,6 +208,8 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         })
     }
 
+    /// Starts restoring the tree at `version` into `store` from scratch, overwriting whatever an
+    /// earlier restoration may have written.
     pub fn new_overwrite<D: 'static + TreeWriter>(
         store: Arc<D>,
         version: Version,
@@ -230,7 +236,7 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         version: Version,
         rightmost_leaf_node_key: NodeKey,
     ) -> Result<Vec<InternalInfo>> {
-        ensure!(
+        ensure_not_corrupted!(
             !rightmost_leaf_node_key.nibble_path().is_empty(),
             "Root node would not be written until entire restoration process has completed \
              successfully.",
@@ -261,11 +267,11 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
                 if let Some(node) = store.get_node_option(&child_node_key)? {
                     let child_info = match node {
                         Node::Internal(internal_node) => ChildInfo::Internal {
-                            hash: Some(internal_node.hash()),
+                            hash: Some(internal_node.hash::<H>()),
                             leaf_count: internal_node.leaf_count(),
                         },
                         Node::Leaf(leaf_node) => ChildInfo::Leaf { node: leaf_node },
-                        Node::Null => bail!("Null node should not appear in storage."),
+                        Node::Null => bail_corrupted!("Null node should not appear in storage."),
                     };
                     internal_info.set_child(i, child_info);
                 }
@@ -303,13 +309,13 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
     fn add_chunk_impl(
         &mut self,
         chunk: Vec<(KeyHash, OwnedValue)>,
-        proof: SparseMerkleRangeProof,
+        proof: SparseMerkleRangeProof<H>,
     ) -> Result<()> {
-        ensure!(!chunk.is_empty(), "Should not add empty chunks.");
+        ensure_valid_proof!(!chunk.is_empty(), "Should not add empty chunks.");
 
         for (key, value) in chunk {
             if let Some(ref prev_leaf) = self.previous_leaf {
-                ensure!(
+                ensure_valid_proof!(
                     key > prev_leaf.key_hash(),
                     "Account keys must come in increasing order.",
                 );
@@ -334,7 +340,7 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
 
     /// Restores one account.
     fn add_one(&mut self, new_key: KeyHash, value_hash: ValueHash) {
-        let nibble_path = NibblePath::new(new_key.0.to_vec());
+        let nibble_path = NibblePath::from_bytes(&new_key.0);
         let mut nibbles = nibble_path.nibbles();
 
         for i in 0..ROOT_NIBBLE_HEIGHT {
@@ -509,10 +515,10 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         while self.partial_nodes.len() > num_remaining_nodes {
             let last_node = self.partial_nodes.pop().expect("This node must exist.");
             let (node_key, internal_node) =
-                last_node.into_internal_node(self.version, self.leaf_count_migration);
+                last_node.into_internal_node::<H>(self.version, self.leaf_count_migration);
             // Keep the hash of this node before moving it into `frozen_nodes`, so we can update
             // its parent later.
-            let node_hash = internal_node.hash();
+            let node_hash = internal_node.hash::<H>();
             let node_leaf_count = internal_node.leaf_count();
             self.frozen_nodes
                 .insert_node(node_key, internal_node.into());
@@ -548,7 +554,7 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
     /// `self.previous_leaf`) are correct, i.e., we are able to construct `self.expected_root_hash`
     /// by combining all existing accounts and `proof`.
     #[allow(clippy::collapsible_if)]
-    fn verify(&self, proof: SparseMerkleRangeProof) -> Result<()> {
+    fn verify(&self, proof: SparseMerkleRangeProof<H>) -> Result<()> {
         let previous_leaf = self
             .previous_leaf
             .as_ref()
@@ -569,7 +575,7 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
             if bit {
                 // This node is a right child and there should be a sibling on the left.
                 let sibling = if i >= self.partial_nodes.len() * 4 {
-                    SPARSE_MERKLE_PLACEHOLDER_HASH
+                    H::PLACEHOLDER_HASH
                 } else {
                     Self::compute_left_sibling(
                         &self.partial_nodes[i / 4],
@@ -583,7 +589,7 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
                 num_visited_right_siblings += 1;
             }
         }
-        ensure!(
+        ensure_valid_proof!(
             num_visited_right_siblings >= proof.right_siblings().len(),
             "Too many right siblings in the proof.",
         );
@@ -592,9 +598,7 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
         // sibling if 1) it's a placeholder 2) it's a sibling on the left.
         for bit in previous_key.0.iter_bits().rev() {
             if bit {
-                if *left_siblings.last().expect("This sibling must exist.")
-                    == SPARSE_MERKLE_PLACEHOLDER_HASH
-                {
+                if *left_siblings.last().expect("This sibling must exist.") == H::PLACEHOLDER_HASH {
                     left_siblings.pop();
                 } else {
                     break;
@@ -637,8 +641,8 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
                 Some(ChildInfo::Internal { hash, .. }) => {
                     (*hash.as_ref().expect("The hash must be known."), false)
                 }
-                Some(ChildInfo::Leaf { node }) => (node.hash(), true),
-                None => (SPARSE_MERKLE_PLACEHOLDER_HASH, true),
+                Some(ChildInfo::Leaf { node }) => (node.hash::<H>(), true),
+                None => (H::PLACEHOLDER_HASH, true),
             }
         } else {
             let (left_hash, left_is_leaf) =
@@ -646,13 +650,13 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
             let (right_hash, right_is_leaf) =
                 Self::compute_left_sibling_impl(&children[num_children / 2..]);
 
-            if left_hash == SPARSE_MERKLE_PLACEHOLDER_HASH && right_is_leaf {
+            if left_hash == H::PLACEHOLDER_HASH && right_is_leaf {
                 (right_hash, true)
-            } else if left_is_leaf && right_hash == SPARSE_MERKLE_PLACEHOLDER_HASH {
+            } else if left_is_leaf && right_hash == H::PLACEHOLDER_HASH {
                 (left_hash, true)
             } else {
                 (
-                    SparseMerkleInternalNode::new(left_hash, right_hash).hash(),
+                    SparseMerkleInternalNode::new(left_hash, right_hash).hash::<H>(),
                     false,
                 )
             }
@@ -692,23 +696,28 @@ impl<H: SimpleHasher> JellyfishMerkleRestore<H> {
 }
 
 /// The interface used with [`JellyfishMerkleRestore`], taken from the Diem `storage-interface` crate.
-pub trait StateSnapshotReceiver {
+pub trait StateSnapshotReceiver<H: SimpleHasher> {
+    /// Adds a chunk of key-value pairs, sorted by key hash and following all the pairs added so
+    /// far. `proof` proves the chunk, together with the pairs added before it, against the
+    /// expected root hash.
     fn add_chunk(
         &mut self,
         chunk: Vec<(KeyHash, OwnedValue)>,
-        proof: SparseMerkleRangeProof,
+        proof: SparseMerkleRangeProof<H>,
     ) -> Result<()>;
 
+    /// Writes the remaining nodes, including the root, once all the chunks have been added.
     fn finish(self) -> Result<()>;
 
+    /// Same as [`finish`](StateSnapshotReceiver::finish), for boxed receivers.
     fn finish_box(self: Box<Self>) -> Result<()>;
 }
 
-impl<H: SimpleHasher> StateSnapshotReceiver for JellyfishMerkleRestore<H> {
+impl<H: SimpleHasher> StateSnapshotReceiver<H> for JellyfishMerkleRestore<H> {
     fn add_chunk(
         &mut self,
         chunk: Vec<(KeyHash, OwnedValue)>,
-        proof: SparseMerkleRangeProof,
+        proof: SparseMerkleRangeProof<H>,
     ) -> Result<()> {
         self.add_chunk_impl(chunk, proof)
     }
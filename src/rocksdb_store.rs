@@ -0,0 +1,443 @@
+//! A [RocksDB](https://rocksdb.org)-backed tree store, enabled by the `rocksdb` feature.
+//!
+//! [`RocksDbTreeStore`] implements [`TreeReader`], [`TreeWriter`], [`HasPreimage`] and
+//! [`CommitTarget`], and keeps each kind of record in its own column family, so that each can be
+//! tuned for the way it is accessed:
+//!
+//! - [`NODES_CF`]: encoded [`NodeKey`] => encoded [`Node`]. Keys start with the big-endian version,
+//!   so all nodes created at one version are stored contiguously.
+//! - [`VALUES_CF`]: key hash ‖ big-endian version => value, or a tombstone if the key was deleted
+//!   at that version. A reverse seek finds the newest value at or before a version.
+//! - [`STALE_NODE_INDICES_CF`]: big-endian stale-since version ‖ encoded [`NodeKey`] => the key
+//!   hash of the node if it is a leaf, or nothing,
+//!   so that the nodes which became stale up to a version can be found with a range scan, as done
+//!   by the [`Pruner`](crate::pruner::Pruner).
+//! - [`PREIMAGES_CF`]: key hash => key.
+//! - [`ROOTS_CF`]: big-endian version => root hash.
+//!
+//! Failures of RocksDB are reported as [`JmtError::Storage`].
+
+use std::{ops::Range, path::Path};
+
+use anyhow::{ensure, format_err, Result};
+use rocksdb::{
+    BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, Options,
+    SliceTransform, WriteBatch, DB,
+};
+
+use crate::{
+    commit::{Commit, CommitTarget},
+    kv_encoding::{decode_root_hash, decode_value, decode_version, encode_value, encode_value_key},
+    node_type::{LeafNode, Node, NodeKey},
+    pruner::StaleNodeIndexStore,
+    storage::{
+        HasPreimage, NodeBatch, StaleNodeIndex, StaleNodeKind, TreeReader, TreeUpdateBatch,
+        TreeWriter,
+    },
+    types::Version,
+    JmtError, KeyHash, OwnedValue, RootHash,
+};
+
+/// The column family holding the nodes of the tree.
+pub const NODES_CF: &str = "jmt_nodes";
+/// The column family holding the values of the tree, indexed by key hash and version.
+pub const VALUES_CF: &str = "jmt_values";
+/// The column family holding the indices of stale nodes.
+pub const STALE_NODE_INDICES_CF: &str = "jmt_stale_node_indices";
+/// The column family holding the preimages of the key hashes.
+pub const PREIMAGES_CF: &str = "jmt_preimages";
+/// The column family holding the root hash of each version.
+pub const ROOTS_CF: &str = "jmt_roots";
+
+/// The length of the prefix shared by all the entries of one key in [`VALUES_CF`].
+const KEY_HASH_LENGTH: usize = 32;
+
+/// A [`TreeReader`], [`TreeWriter`] and [`StaleNodeIndexStore`] backed by a RocksDB database.
+pub struct RocksDbTreeStore {
+    db: DB,
+}
+
+impl RocksDbTreeStore {
+    /// Opens the database at `path`, creating it and any missing column family if necessary.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut db_options = Options::default();
+        db_options.create_if_missing(true);
+        db_options.create_missing_column_families(true);
+        Self::open_with_options(path, db_options)
+    }
+
+    /// Opens the database at `path` with the given database-wide options. The options of each
+    /// column family are set by this store.
+    pub fn open_with_options(path: impl AsRef<Path>, db_options: Options) -> Result<Self> {
+        let column_families = [
+            NODES_CF,
+            VALUES_CF,
+            STALE_NODE_INDICES_CF,
+            PREIMAGES_CF,
+            ROOTS_CF,
+        ]
+        .into_iter()
+        .map(|name| ColumnFamilyDescriptor::new(name, Self::column_family_options(name)));
+        let db = DB::open_cf_descriptors(&db_options, path, column_families)
+            .map_err(JmtError::storage)?;
+        Ok(Self { db })
+    }
+
+    fn column_family_options(name: &str) -> Options {
+        let mut options = Options::default();
+        match name {
+            // Nodes and preimages are only ever read by point lookups.
+            NODES_CF | PREIMAGES_CF => {
+                let mut table_options = BlockBasedOptions::default();
+                table_options.set_bloom_filter(10.0, false);
+                options.set_block_based_table_factory(&table_options);
+            }
+            // Values are looked up by seeking within the entries of a single key hash.
+            VALUES_CF => {
+                let mut table_options = BlockBasedOptions::default();
+                table_options.set_bloom_filter(10.0, false);
+                options.set_block_based_table_factory(&table_options);
+                options.set_prefix_extractor(SliceTransform::create_fixed_prefix(KEY_HASH_LENGTH));
+            }
+            _ => {}
+        }
+        options
+    }
+
+    fn cf(&self, name: &str) -> Result<&ColumnFamily> {
+        self.db
+            .cf_handle(name)
+            .ok_or_else(|| format_err!("Missing column family {}.", name))
+    }
+
+    /// Writes all the nodes, values, stale node indices and root hashes of `batch`, and the given
+    /// root hashes, atomically.
+    pub fn write_tree_update_batch(
+        &self,
+        batch: TreeUpdateBatch,
+        root_hashes: impl IntoIterator<Item = (Version, RootHash)>,
+    ) -> Result<()> {
+        let mut write_batch = WriteBatch::default();
+        self.add_tree_update_batch(&mut write_batch, &batch, root_hashes)?;
+        self.db.write(write_batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+
+    fn add_tree_update_batch(
+        &self,
+        write_batch: &mut WriteBatch,
+        batch: &TreeUpdateBatch,
+        root_hashes: impl IntoIterator<Item = (Version, RootHash)>,
+    ) -> Result<()> {
+        self.add_node_batch(write_batch, &batch.node_batch)?;
+
+        let stale_node_indices_cf = self.cf(STALE_NODE_INDICES_CF)?;
+        for stale_node_index in &batch.stale_node_index_batch {
+            write_batch.put_cf(
+                stale_node_indices_cf,
+                encode_stale_node_index(stale_node_index)?,
+                encode_stale_node_kind(&stale_node_index.node_kind),
+            );
+        }
+
+        let roots_cf = self.cf(ROOTS_CF)?;
+        for (version, root_hash) in root_hashes {
+            write_batch.put_cf(roots_cf, version.to_be_bytes(), root_hash.0);
+        }
+        Ok(())
+    }
+
+    fn add_node_batch(&self, write_batch: &mut WriteBatch, node_batch: &NodeBatch) -> Result<()> {
+        let nodes_cf = self.cf(NODES_CF)?;
+        for (node_key, node) in node_batch.nodes() {
+            write_batch.put_cf(nodes_cf, node_key.encode()?, node.encode()?);
+        }
+
+        let values_cf = self.cf(VALUES_CF)?;
+        for ((version, key_hash), value) in node_batch.values() {
+            write_batch.put_cf(
+                values_cf,
+                encode_value_key(*version, *key_hash),
+                encode_value(value.as_deref()),
+            );
+        }
+
+        let roots_cf = self.cf(ROOTS_CF)?;
+        for (version, root_hash) in node_batch.root_hashes() {
+            write_batch.put_cf(roots_cf, version.to_be_bytes(), root_hash.0);
+        }
+        Ok(())
+    }
+
+    /// Records the preimages of key hashes, so that they can be looked up through
+    /// [`HasPreimage`].
+    pub fn put_key_preimages(
+        &self,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Result<()> {
+        let mut write_batch = WriteBatch::default();
+        self.add_key_preimages(&mut write_batch, preimages)?;
+        self.db.write(write_batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+
+    fn add_key_preimages(
+        &self,
+        write_batch: &mut WriteBatch,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Result<()> {
+        let preimages_cf = self.cf(PREIMAGES_CF)?;
+        for (key_hash, preimage) in preimages {
+            write_batch.put_cf(preimages_cf, key_hash.0, preimage);
+        }
+        Ok(())
+    }
+
+    /// Returns the root hash recorded for `version`, if any.
+    pub fn get_root_hash_option(&self, version: Version) -> Result<Option<RootHash>> {
+        self.db
+            .get_cf(self.cf(ROOTS_CF)?, version.to_be_bytes())
+            .map_err(JmtError::storage)?
+            .map(|bytes| decode_root_hash(&bytes))
+            .transpose()
+    }
+
+    /// Returns the latest version for which a root hash was recorded, if any.
+    pub fn get_latest_version(&self) -> Result<Option<Version>> {
+        self.db
+            .iterator_cf(self.cf(ROOTS_CF)?, IteratorMode::End)
+            .next()
+            .transpose()
+            .map_err(JmtError::storage)?
+            .map(|(key, _)| decode_version(&key))
+            .transpose()
+    }
+}
+
+impl TreeReader for RocksDbTreeStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.db
+            .get_cf(self.cf(NODES_CF)?, node_key.encode()?)
+            .map_err(JmtError::storage)?
+            .map(|bytes| Node::decode(&bytes))
+            .transpose()
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .db
+            .get_cf(self.cf(NODES_CF)?, node_key.encode()?)
+            .map_err(JmtError::storage)?)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let nodes_cf = self.cf(NODES_CF)?;
+        let encoded_node_keys = node_keys
+            .iter()
+            .map(NodeKey::encode)
+            .collect::<Result<Vec<_>>>()?;
+        self.db
+            .multi_get_cf(encoded_node_keys.iter().map(|key| (nodes_cf, key)))
+            .into_iter()
+            .map(|bytes| {
+                bytes
+                    .map_err(JmtError::storage)?
+                    .map(|bytes| Node::decode(&bytes))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let seek_key = encode_value_key(max_version, key_hash);
+        let newest = self
+            .db
+            .iterator_cf(
+                self.cf(VALUES_CF)?,
+                IteratorMode::From(&seek_key, Direction::Reverse),
+            )
+            .next()
+            .transpose()
+            .map_err(JmtError::storage)?;
+        match newest {
+            Some((key, value)) if key.starts_with(&key_hash.0) => decode_value(&value),
+            _ => Ok(None),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        // Node keys start with their version, so the nodes of the latest version come last.
+        let mut rightmost: Option<(NodeKey, LeafNode)> = None;
+        let mut latest_version = None;
+        for item in self.db.iterator_cf(self.cf(NODES_CF)?, IteratorMode::End) {
+            let (key, value) = item.map_err(JmtError::storage)?;
+            let node_key = NodeKey::decode(&key)?;
+            if *latest_version.get_or_insert(node_key.version()) != node_key.version() {
+                break;
+            }
+            if let Node::Leaf(leaf_node) = Node::decode(&value)? {
+                if rightmost.as_ref().is_none_or(|(_, rightmost_leaf)| {
+                    leaf_node.key_hash() > rightmost_leaf.key_hash()
+                }) {
+                    rightmost = Some((node_key, leaf_node));
+                }
+            }
+        }
+        Ok(rightmost)
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.get_root_hash_option(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        let start = versions.start.to_be_bytes();
+        let mut root_hashes = vec![];
+        for item in self.db.iterator_cf(
+            self.cf(ROOTS_CF)?,
+            IteratorMode::From(&start, Direction::Forward),
+        ) {
+            if root_hashes.len() >= limit {
+                break;
+            }
+            let (key, value) = item.map_err(JmtError::storage)?;
+            let version = decode_version(&key)?;
+            if version >= versions.end {
+                break;
+            }
+            root_hashes.push((version, decode_root_hash(&value)?));
+        }
+        Ok(root_hashes)
+    }
+}
+
+impl StaleNodeIndexStore for RocksDbTreeStore {
+    fn get_stale_node_indices_after(
+        &self,
+        after: Option<&StaleNodeIndex>,
+        max_stale_since_version: Version,
+        limit: usize,
+    ) -> Result<Vec<StaleNodeIndex>> {
+        let after = after.map(encode_stale_node_index).transpose()?;
+        let mode = match &after {
+            Some(after) => IteratorMode::From(after, Direction::Forward),
+            None => IteratorMode::Start,
+        };
+        let mut stale_node_indices = vec![];
+        for item in self.db.iterator_cf(self.cf(STALE_NODE_INDICES_CF)?, mode) {
+            if stale_node_indices.len() >= limit {
+                break;
+            }
+            let (key, value) = item.map_err(JmtError::storage)?;
+            // Seeking to `after` positions the iterator on it, if it still exists.
+            if after.as_deref() == Some(&*key) {
+                continue;
+            }
+            let stale_node_index = decode_stale_node_index(&key, &value)?;
+            if stale_node_index.stale_since_version > max_stale_since_version {
+                break;
+            }
+            stale_node_indices.push(stale_node_index);
+        }
+        Ok(stale_node_indices)
+    }
+
+    fn delete_stale_nodes(&self, stale_node_indices: &[StaleNodeIndex]) -> Result<()> {
+        let nodes_cf = self.cf(NODES_CF)?;
+        let stale_node_indices_cf = self.cf(STALE_NODE_INDICES_CF)?;
+        let mut write_batch = WriteBatch::default();
+        for stale_node_index in stale_node_indices {
+            write_batch.delete_cf(nodes_cf, stale_node_index.node_key.encode()?);
+            write_batch.delete_cf(
+                stale_node_indices_cf,
+                encode_stale_node_index(stale_node_index)?,
+            );
+        }
+        self.db.write(write_batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+}
+
+impl TreeWriter for RocksDbTreeStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut write_batch = WriteBatch::default();
+        self.add_node_batch(&mut write_batch, node_batch)?;
+        self.db.write(write_batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+}
+
+impl CommitTarget for RocksDbTreeStore {
+    /// Writes the whole commit in a single write batch. Writing the same commit again stores the
+    /// same records, so this is idempotent.
+    fn apply_commit(&self, commit: &Commit) -> Result<()> {
+        let mut write_batch = WriteBatch::default();
+        self.add_tree_update_batch(
+            &mut write_batch,
+            &commit.batch,
+            [(commit.version, commit.root_hash)],
+        )?;
+        self.add_key_preimages(&mut write_batch, commit.preimages.iter().cloned())?;
+        self.db.write(write_batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+}
+
+impl HasPreimage for RocksDbTreeStore {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .db
+            .get_cf(self.cf(PREIMAGES_CF)?, key_hash.0)
+            .map_err(JmtError::storage)?)
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        let preimages_cf = self.cf(PREIMAGES_CF)?;
+        self.db
+            .multi_get_cf(key_hashes.iter().map(|key_hash| (preimages_cf, key_hash.0)))
+            .into_iter()
+            .map(|preimage| Ok(preimage.map_err(JmtError::storage)?))
+            .collect()
+    }
+}
+
+fn encode_stale_node_index(stale_node_index: &StaleNodeIndex) -> Result<Vec<u8>> {
+    let mut key = stale_node_index.stale_since_version.to_be_bytes().to_vec();
+    key.extend(stale_node_index.node_key.encode()?);
+    Ok(key)
+}
+
+fn encode_stale_node_kind(node_kind: &StaleNodeKind) -> Vec<u8> {
+    match node_kind {
+        StaleNodeKind::Internal => vec![],
+        StaleNodeKind::Leaf { key_hash } => key_hash.0.to_vec(),
+    }
+}
+
+fn decode_stale_node_index(key: &[u8], value: &[u8]) -> Result<StaleNodeIndex> {
+    ensure!(key.len() > 8, "Invalid encoded stale node index {:?}.", key);
+    let (version, node_key) = key.split_at(8);
+    let node_kind = match value {
+        [] => StaleNodeKind::Internal,
+        key_hash => StaleNodeKind::Leaf {
+            key_hash: KeyHash(
+                key_hash
+                    .try_into()
+                    .map_err(|_| format_err!("Invalid encoded stale node kind {:?}.", key_hash))?,
+            ),
+        },
+    };
+    Ok(StaleNodeIndex {
+        stale_since_version: decode_version(version)?,
+        node_key: NodeKey::decode(node_key)?,
+        node_kind,
+    })
+}
@@ -0,0 +1,214 @@
+//! A [sled](https://sled.rs)-backed tree store, enabled by the `sled` feature.
+//!
+//! [`SledTreeStore`] implements [`TreeReader`], [`TreeWriter`] and [`HasPreimage`] on top of a
+//! single sled tree, so that every batch is written atomically. Records are told apart by a one
+//! byte prefix:
+//!
+//! - `n` ‖ encoded [`NodeKey`] => encoded [`Node`].
+//! - `v` ‖ key hash ‖ big-endian version => value, or a tombstone if the key was deleted at that
+//!   version.
+//! - `p` ‖ key hash => key.
+//! - `r` ‖ big-endian version => root hash.
+//!
+//! Failures of sled are reported as [`JmtError::Storage`].
+//!
+//! This is intended for small deployments and tests; larger ones should prefer the `rocksdb`
+//! feature.
+
+use std::{ops::Range, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    kv_encoding::{decode_root_hash, decode_value, decode_version, encode_value, encode_value_key},
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeWriter},
+    types::Version,
+    JmtError, KeyHash, OwnedValue, RootHash,
+};
+
+const NODE_PREFIX: u8 = b'n';
+const VALUE_PREFIX: u8 = b'v';
+const PREIMAGE_PREFIX: u8 = b'p';
+const ROOT_PREFIX: u8 = b'r';
+
+/// A [`TreeReader`] and [`TreeWriter`] backed by a sled tree.
+pub struct SledTreeStore {
+    tree: sled::Tree,
+}
+
+impl SledTreeStore {
+    /// Opens the sled database at `path`, creating it if necessary, and stores the tree in its
+    /// default tree.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path).map_err(JmtError::storage)?;
+        Ok(Self::new((*db).clone()))
+    }
+
+    /// Creates a store which keeps the tree in the given sled tree. The tree should not be used for
+    /// anything else.
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Records the preimages of key hashes, so that they can be looked up through
+    /// [`HasPreimage`].
+    pub fn put_key_preimages(
+        &self,
+        preimages: impl IntoIterator<Item = (KeyHash, Vec<u8>)>,
+    ) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for (key_hash, preimage) in preimages {
+            batch.insert(prefixed(PREIMAGE_PREFIX, &key_hash.0), preimage);
+        }
+        self.tree.apply_batch(batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+
+    /// Records the root hash of each of the given versions, so that
+    /// [`get_root_hash`](crate::JellyfishMerkleTree::get_root_hash) does not have to read the
+    /// root node.
+    pub fn put_root_hashes(
+        &self,
+        root_hashes: impl IntoIterator<Item = (Version, RootHash)>,
+    ) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for (version, root_hash) in root_hashes {
+            batch.insert(
+                prefixed(ROOT_PREFIX, &version.to_be_bytes()),
+                root_hash.0.to_vec(),
+            );
+        }
+        self.tree.apply_batch(batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+
+    /// Flushes all the writes to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.tree.flush().map_err(JmtError::storage)?;
+        Ok(())
+    }
+}
+
+impl TreeReader for SledTreeStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.tree
+            .get(prefixed(NODE_PREFIX, &node_key.encode()?))
+            .map_err(JmtError::storage)?
+            .map(|bytes| Node::decode(&bytes))
+            .transpose()
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .tree
+            .get(prefixed(NODE_PREFIX, &node_key.encode()?))
+            .map_err(JmtError::storage)?
+            .map(|bytes| bytes.to_vec()))
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let first = prefixed(VALUE_PREFIX, &key_hash.0);
+        let last = prefixed(VALUE_PREFIX, &encode_value_key(max_version, key_hash));
+        match self
+            .tree
+            .range(first..=last)
+            .next_back()
+            .transpose()
+            .map_err(JmtError::storage)?
+        {
+            Some((_, value)) => decode_value(&value),
+            None => Ok(None),
+        }
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        // Node keys start with their version, so the nodes of the latest version come last.
+        let mut rightmost: Option<(NodeKey, LeafNode)> = None;
+        let mut latest_version = None;
+        for item in self.tree.scan_prefix([NODE_PREFIX]).rev() {
+            let (key, value) = item.map_err(JmtError::storage)?;
+            let node_key = NodeKey::decode(&key[1..])?;
+            if *latest_version.get_or_insert(node_key.version()) != node_key.version() {
+                break;
+            }
+            if let Node::Leaf(leaf_node) = Node::decode(&value)? {
+                if rightmost.as_ref().is_none_or(|(_, rightmost_leaf)| {
+                    leaf_node.key_hash() > rightmost_leaf.key_hash()
+                }) {
+                    rightmost = Some((node_key, leaf_node));
+                }
+            }
+        }
+        Ok(rightmost)
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.tree
+            .get(prefixed(ROOT_PREFIX, &version.to_be_bytes()))
+            .map_err(JmtError::storage)?
+            .map(|bytes| decode_root_hash(&bytes))
+            .transpose()
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        let start = prefixed(ROOT_PREFIX, &versions.start.to_be_bytes());
+        let end = prefixed(ROOT_PREFIX, &versions.end.to_be_bytes());
+        self.tree
+            .range(start..end)
+            .take(limit)
+            .map(|item| {
+                let (key, value) = item.map_err(JmtError::storage)?;
+                Ok((decode_version(&key[1..])?, decode_root_hash(&value)?))
+            })
+            .collect()
+    }
+}
+
+impl TreeWriter for SledTreeStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for (node_key, node) in node_batch.nodes() {
+            batch.insert(prefixed(NODE_PREFIX, &node_key.encode()?), node.encode()?);
+        }
+        for ((version, key_hash), value) in node_batch.values() {
+            batch.insert(
+                prefixed(VALUE_PREFIX, &encode_value_key(*version, *key_hash)),
+                encode_value(value.as_deref()),
+            );
+        }
+        for (version, root_hash) in node_batch.root_hashes() {
+            batch.insert(
+                prefixed(ROOT_PREFIX, &version.to_be_bytes()),
+                root_hash.0.to_vec(),
+            );
+        }
+        self.tree.apply_batch(batch).map_err(JmtError::storage)?;
+        Ok(())
+    }
+}
+
+impl HasPreimage for SledTreeStore {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .tree
+            .get(prefixed(PREIMAGE_PREFIX, &key_hash.0))
+            .map_err(JmtError::storage)?
+            .map(|bytes| bytes.to_vec()))
+    }
+}
+
+fn prefixed(prefix: u8, key: &[u8]) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(key.len() + 1);
+    prefixed.push(prefix);
+    prefixed.extend_from_slice(key);
+    prefixed
+}
@@ -0,0 +1,238 @@
+//! Full snapshots of a tree at a version.
+//!
+//! [`JellyfishMerkleTree::export_snapshot`] streams all the leaves of a tree at a version, in key
+//! hash order, and [`import_snapshot`] rebuilds the tree from them in another store. The import
+//! only writes nodes at the snapshot version, so the resulting tree is compact: it holds none of
+//! the historical versions, nor any stale node, of the tree it was exported from.
+//!
+//! A snapshot is made of a header and of chunks of leaves, each followed by a
+//! [`SparseMerkleRangeProof`] of the leaves so far, so that [`import_snapshot`] checks every chunk
+//! against the root hash of the header before writing it, with [`JellyfishMerkleRestore`]. All
+//! integers are big-endian:
+//!
+//! - Header: the magic bytes `JMTSNAP`, the format version `1`, the version of the tree (`u64`),
+//!   its root hash (32 bytes) and a flag byte telling whether the leaves carry preimages.
+//! - Chunk: the number of leaves in the chunk (`u32`), then for each leaf its key hash (32 bytes),
+//!   the length of its value (`u32`) and the value, and if the snapshot carries preimages, a
+//!   presence byte followed by the length of the preimage (`u32`) and the preimage. The chunk ends
+//!   with the number of right siblings of its range proof (`u32`) and the siblings (32 bytes
+//!   each).
+//! - Trailer: a chunk length of 0.
+
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    node_type::{Node, NodeKey},
+    proof::SparseMerkleRangeProof,
+    restore::{JellyfishMerkleRestore, StateSnapshotReceiver},
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeWriter},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+const MAGIC: &[u8; 7] = b"JMTSNAP";
+const FORMAT_VERSION: u8 = 1;
+/// The number of leaves in each chunk of an exported snapshot.
+const CHUNK_SIZE: usize = 1000;
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Writes a snapshot of all the leaves of the tree at `version` to `writer`, to be imported
+    /// with [`import_snapshot`].
+    pub fn export_snapshot(&self, version: Version, writer: impl Write) -> Result<()> {
+        self.write_snapshot(version, None, writer)
+    }
+
+    /// Same as [`export_snapshot`](JellyfishMerkleTree::export_snapshot), but also writes the
+    /// preimage of each key hash known to `preimages`.
+    pub fn export_snapshot_with_preimages(
+        &self,
+        version: Version,
+        preimages: &dyn HasPreimage,
+        writer: impl Write,
+    ) -> Result<()> {
+        self.write_snapshot(version, Some(preimages), writer)
+    }
+
+    fn write_snapshot(
+        &self,
+        version: Version,
+        preimages: Option<&dyn HasPreimage>,
+        mut writer: impl Write,
+    ) -> Result<()> {
+        let root_hash = self.get_root_hash(version)?;
+        writer.write_all(MAGIC)?;
+        writer.write_u8(FORMAT_VERSION)?;
+        writer.write_u64::<BigEndian>(version)?;
+        writer.write_all(&root_hash.0)?;
+        writer.write_u8(preimages.is_some().into())?;
+
+        let mut leaves = self.iter(version)?.peekable();
+        while leaves.peek().is_some() {
+            let chunk = leaves
+                .by_ref()
+                .take(CHUNK_SIZE)
+                .collect::<Result<Vec<(KeyHash, OwnedValue)>>>()?;
+            writer.write_u32::<BigEndian>(u32::try_from(chunk.len())?)?;
+            let chunk_preimages = match preimages {
+                Some(preimages) => Some(
+                    preimages.preimages(&chunk.iter().map(|(key, _)| *key).collect::<Vec<_>>())?,
+                ),
+                None => None,
+            };
+            for (i, (key_hash, value)) in chunk.iter().enumerate() {
+                writer.write_all(&key_hash.0)?;
+                write_bytes(&mut writer, value)?;
+                if let Some(chunk_preimages) = &chunk_preimages {
+                    match &chunk_preimages[i] {
+                        Some(preimage) => {
+                            writer.write_u8(1)?;
+                            write_bytes(&mut writer, preimage)?;
+                        }
+                        None => writer.write_u8(0)?,
+                    }
+                }
+            }
+
+            let (rightmost_key, _) = chunk.last().expect("Chunks are not empty.");
+            let proof = self.get_range_proof(*rightmost_key, version)?;
+            writer.write_u32::<BigEndian>(u32::try_from(proof.right_siblings().len())?)?;
+            for sibling in proof.right_siblings() {
+                writer.write_all(sibling)?;
+            }
+        }
+        writer.write_u32::<BigEndian>(0)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Rebuilds the tree of a snapshot written by
+/// [`JellyfishMerkleTree::export_snapshot`] into `store`, and returns its version and root hash.
+///
+/// Every chunk of the snapshot is checked against the root hash of its header before being
+/// written, and the root hash of the rebuilt tree is checked once all the chunks are written.
+/// `store` should not hold any node at the version of the snapshot. The preimages carried by
+/// the snapshot, if any, are passed to `put_preimages` one chunk at a time.
+pub fn import_snapshot<H, D>(
+    store: Arc<D>,
+    mut reader: impl Read,
+    mut put_preimages: impl FnMut(Vec<(KeyHash, Vec<u8>)>) -> Result<()>,
+) -> Result<(Version, RootHash)>
+where
+    H: SimpleHasher,
+    D: 'static + TreeReader + TreeWriter,
+{
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    ensure!(&magic == MAGIC, "Not a tree snapshot.");
+    let format_version = reader.read_u8()?;
+    ensure!(
+        format_version == FORMAT_VERSION,
+        "Unsupported snapshot format version {}.",
+        format_version
+    );
+    let version = reader.read_u64::<BigEndian>()?;
+    let root_hash = RootHash(read_hash(&mut reader)?);
+    let has_preimages = match reader.read_u8()? {
+        0 => false,
+        1 => true,
+        flag => bail!("Invalid preimage flag {}.", flag),
+    };
+
+    let mut restore =
+        JellyfishMerkleRestore::<H>::new_overwrite(Arc::clone(&store), version, root_hash, true)?;
+    let mut is_empty = true;
+    loop {
+        let chunk_len = reader.read_u32::<BigEndian>()? as usize;
+        if chunk_len == 0 {
+            break;
+        }
+        is_empty = false;
+        let mut chunk = Vec::with_capacity(chunk_len.min(CHUNK_SIZE));
+        let mut preimages = vec![];
+        for _ in 0..chunk_len {
+            let key_hash = KeyHash(read_hash(&mut reader)?);
+            chunk.push((key_hash, read_bytes(&mut reader)?));
+            if has_preimages {
+                match reader.read_u8()? {
+                    0 => {}
+                    1 => preimages.push((key_hash, read_bytes(&mut reader)?)),
+                    flag => bail!("Invalid preimage presence flag {}.", flag),
+                }
+            }
+        }
+        let num_siblings = reader.read_u32::<BigEndian>()? as usize;
+        ensure!(
+            num_siblings <= 256,
+            "Invalid number of range proof siblings {}.",
+            num_siblings
+        );
+        let right_siblings = (0..num_siblings)
+            .map(|_| read_hash(&mut reader))
+            .collect::<Result<_>>()?;
+        restore.add_chunk(chunk, SparseMerkleRangeProof::new(right_siblings))?;
+        if !preimages.is_empty() {
+            put_preimages(preimages)?;
+        }
+    }
+    ensure!(
+        reader.read(&mut [0])? == 0,
+        "Unexpected bytes after the end of the snapshot."
+    );
+
+    if is_empty {
+        ensure!(
+            root_hash.0 == H::PLACEHOLDER_HASH,
+            "An empty snapshot must have the placeholder root hash."
+        );
+        let mut node_batch = NodeBatch::default();
+        node_batch.insert_node(NodeKey::new_empty_path(version), Node::Null);
+        store.write_node_batch(&node_batch)?;
+    } else {
+        restore.finish()?;
+    }
+    let imported_root_hash =
+        JellyfishMerkleTree::<_, H>::new(store.as_ref()).get_root_hash(version)?;
+    ensure!(
+        imported_root_hash == root_hash,
+        "The imported tree has root hash {:?} instead of {:?}.",
+        imported_root_hash,
+        root_hash
+    );
+    Ok((version, root_hash))
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    writer.write_u32::<BigEndian>(u32::try_from(bytes.len())?)?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = reader.read_u32::<BigEndian>()? as u64;
+    let mut bytes = vec![];
+    // Reading through `take` does not allocate more than the bytes actually available.
+    let num_read = reader.take(len).read_to_end(&mut bytes)?;
+    ensure!(
+        num_read as u64 == len,
+        "Not enough bytes left: expected {}, found {}.",
+        len,
+        num_read
+    );
+    Ok(bytes)
+}
+
+fn read_hash(reader: &mut impl Read) -> Result<[u8; 32]> {
+    let mut hash = [0; 32];
+    reader.read_exact(&mut hash)?;
+    Ok(hash)
+}
@@ -0,0 +1,279 @@
+//! Notifications of the changes to chosen keys, as they are committed.
+//!
+//! Indexers and push services usually care about a small set of keys, and would otherwise have to
+//! [`diff`](crate::JellyfishMerkleTree::diff) every new version against the previous one to learn
+//! what changed. A [`SubscribedStore`] wraps the storage of a tree, and whenever a batch is written
+//! through it, tells every subscriber which of its keys changed at each version of the batch, with
+//! their old and new values.
+//!
+//! Subscribers are either callbacks, called on the writing thread once the batch is written, or
+//! channels, whose subscriptions end when their receiver is dropped.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use anyhow::{format_err, Result};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{HasPreimage, NodeBatch, TreeReader, TreeWriter},
+    KeyHash, OwnedValue, RootHash, ValueChange, Version,
+};
+
+/// The changes to the keys of a subscription at one version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyChanges {
+    /// The version at which the keys changed.
+    pub version: Version,
+    /// The keys which changed, in key hash order, with their values before and at `version`.
+    pub changes: Vec<ValueChange>,
+}
+
+/// Identifies a subscription, to end it with [`SubscribedStore::unsubscribe`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SubscriptionId(u64);
+
+type Callback = Arc<dyn Fn(&KeyChanges) + Send + Sync>;
+
+#[derive(Clone)]
+enum Sink {
+    Callback(Callback),
+    Channel(Sender<KeyChanges>),
+}
+
+struct Subscription {
+    keys: HashSet<KeyHash>,
+    sink: Sink,
+}
+
+/// A store notifying subscribers of the changes to their keys written through it.
+///
+/// `SubscribedStore` is a [`TreeReader`] and a [`TreeWriter`] forwarding to the underlying store.
+/// Before writing a batch, it reads the previous value of each subscribed key written by the
+/// batch; once the batch is written, it notifies the subscribers of the keys whose value changed,
+/// once per version and in version order. Writing a key with its current value is not a change.
+pub struct SubscribedStore<S> {
+    store: S,
+    next_id: AtomicU64,
+    subscriptions: RwLock<HashMap<SubscriptionId, Subscription>>,
+}
+
+impl<S> SubscribedStore<S> {
+    /// Wraps `store`, without any subscription.
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            next_id: AtomicU64::new(0),
+            subscriptions: RwLock::default(),
+        }
+    }
+
+    /// Returns the underlying store.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the underlying store, ending all the subscriptions.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    /// Calls `callback` with the changes to `keys` at each version written from now on.
+    ///
+    /// The callback runs on the thread writing the batch, after the batch is written, so it should
+    /// return quickly. It may subscribe or unsubscribe.
+    pub fn subscribe(
+        &self,
+        keys: impl IntoIterator<Item = KeyHash>,
+        callback: impl Fn(&KeyChanges) + Send + Sync + 'static,
+    ) -> Result<SubscriptionId> {
+        self.insert(keys, Sink::Callback(Arc::new(callback)))
+    }
+
+    /// Sends the changes to `keys` at each version written from now on to the returned receiver.
+    /// The subscription ends when the receiver is dropped.
+    pub fn subscribe_channel(
+        &self,
+        keys: impl IntoIterator<Item = KeyHash>,
+    ) -> Result<(SubscriptionId, Receiver<KeyChanges>)> {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.insert(keys, Sink::Channel(sender))?;
+        Ok((id, receiver))
+    }
+
+    /// Ends a subscription. Returns false if it had already ended.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        Ok(self.write()?.remove(&id).is_some())
+    }
+
+    fn insert(
+        &self,
+        keys: impl IntoIterator<Item = KeyHash>,
+        sink: Sink,
+    ) -> Result<SubscriptionId> {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let subscription = Subscription {
+            keys: keys.into_iter().collect(),
+            sink,
+        };
+        self.write()?.insert(id, subscription);
+        Ok(id)
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, HashMap<SubscriptionId, Subscription>>> {
+        self.subscriptions
+            .read()
+            .map_err(|_| format_err!("The subscriptions lock is poisoned."))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, HashMap<SubscriptionId, Subscription>>> {
+        self.subscriptions
+            .write()
+            .map_err(|_| format_err!("The subscriptions lock is poisoned."))
+    }
+}
+
+impl<S: TreeReader> SubscribedStore<S> {
+    /// Returns the changes to subscribed keys made by `node_batch`, by version.
+    fn changes(&self, node_batch: &NodeBatch) -> Result<Vec<(Version, Vec<ValueChange>)>> {
+        let subscribed_keys: HashSet<KeyHash> = self
+            .read()?
+            .values()
+            .flat_map(|subscription| subscription.keys.iter().copied())
+            .collect();
+        if subscribed_keys.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // The values of a batch are sorted by version, so the latest value of a key written at an
+        // earlier version of the batch is known when reaching a later version.
+        let mut latest_values: HashMap<KeyHash, Option<OwnedValue>> = HashMap::new();
+        let mut changes: Vec<(Version, Vec<ValueChange>)> = vec![];
+        for ((version, key_hash), new_value) in node_batch.values() {
+            if !subscribed_keys.contains(key_hash) {
+                continue;
+            }
+            let old_value = match latest_values.remove(key_hash) {
+                Some(old_value) => old_value,
+                None => match version.checked_sub(1) {
+                    Some(max_version) => self.store.get_value_option(max_version, *key_hash)?,
+                    None => None,
+                },
+            };
+            let new_value = new_value.as_deref().map(<[u8]>::to_vec);
+            latest_values.insert(*key_hash, new_value.clone());
+            if old_value == new_value {
+                continue;
+            }
+            let change = (*key_hash, old_value, new_value);
+            match changes.last_mut() {
+                Some((last_version, version_changes)) if last_version == version => {
+                    version_changes.push(change)
+                }
+                _ => changes.push((*version, vec![change])),
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Notifies each subscriber of the changes to its keys.
+    fn notify(&self, changes: &[(Version, Vec<ValueChange>)]) -> Result<()> {
+        let mut notifications = vec![];
+        for (id, subscription) in self.read()?.iter() {
+            for (version, version_changes) in changes {
+                let changes: Vec<ValueChange> = version_changes
+                    .iter()
+                    .filter(|(key_hash, _, _)| subscription.keys.contains(key_hash))
+                    .cloned()
+                    .collect();
+                if !changes.is_empty() {
+                    let key_changes = KeyChanges {
+                        version: *version,
+                        changes,
+                    };
+                    notifications.push((*id, subscription.sink.clone(), key_changes));
+                }
+            }
+        }
+
+        // The lock is released before notifying, so that callbacks can change the subscriptions.
+        let mut disconnected = HashSet::new();
+        for (id, sink, key_changes) in notifications {
+            match sink {
+                Sink::Callback(callback) => callback(&key_changes),
+                Sink::Channel(sender) => {
+                    if sender.send(key_changes).is_err() {
+                        disconnected.insert(id);
+                    }
+                }
+            }
+        }
+        if !disconnected.is_empty() {
+            self.write()?.retain(|id, _| !disconnected.contains(id));
+        }
+        Ok(())
+    }
+}
+
+impl<S: TreeReader> TreeReader for SubscribedStore<S> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.store.get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        self.store.get_nodes(node_keys)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        self.store.get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.store.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.store.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.store.get_indexed_root_hashes(versions, limit)
+    }
+}
+
+impl<S: TreeReader + TreeWriter> TreeWriter for SubscribedStore<S> {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let changes = self.changes(node_batch)?;
+        self.store.write_node_batch(node_batch)?;
+        self.notify(&changes)
+    }
+}
+
+impl<S: HasPreimage> HasPreimage for SubscribedStore<S> {
+    fn preimage(&self, key_hash: KeyHash) -> Result<Option<Vec<u8>>> {
+        self.store.preimage(key_hash)
+    }
+
+    fn preimages(&self, key_hashes: &[KeyHash]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.store.preimages(key_hashes)
+    }
+}
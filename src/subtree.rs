@@ -0,0 +1,161 @@
+//! Export and import of the subtrees of a tree.
+//!
+//! [`JellyfishMerkleTree::export_subtree`](crate::JellyfishMerkleTree::export_subtree) collects
+//! all the nodes and values below a [`NibblePath`] prefix at a version, along with the hash of
+//! the root of that subtree. [`import_subtree`] checks such an export against an expected
+//! subtree root hash and writes it to another store, so that the state of a tree can be
+//! distributed shard by shard, or partially replicated.
+//!
+//! Nodes keep their [`NodeKey`]s across an export, so an imported subtree can be read by a
+//! [`JellyfishMerkleTree`](crate::JellyfishMerkleTree) once the nodes above it are present too.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    error::{bail_invalid_proof, ensure_valid_proof, invalid_proof},
+    node_type::{Node, NodeKey},
+    storage::{NodeBatch, TreeWriter},
+    types::nibble::nibble_path::NibblePath,
+    KeyHash, OwnedValue, SimpleHasher, ValueHash, Version,
+};
+
+/// All the nodes and values below a nibble path prefix of a tree at a version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubtreeExport {
+    /// The version of the tree the subtree was exported from.
+    pub version: Version,
+    /// The nibble path of the root of the subtree.
+    pub prefix: NibblePath,
+    /// The hash of the root of the subtree, or the placeholder hash if it is empty.
+    pub root_hash: [u8; 32],
+    /// The nodes of the subtree, root first. Empty if the subtree is empty.
+    ///
+    /// If the only key below the prefix is stored in a leaf above it, that leaf is the root of the
+    /// subtree, so its nibble path is shorter than the prefix.
+    pub nodes: Vec<(NodeKey, Node)>,
+    /// The value of each leaf of the subtree, at the version of the leaf.
+    pub values: Vec<(Version, KeyHash, OwnedValue)>,
+}
+
+impl SubtreeExport {
+    /// Checks that the nodes and values of the export form a subtree whose root hash is
+    /// `expected_root_hash`: every node must be reachable from the root, the children of every
+    /// internal node must be present with the hashes recorded by their parent, and every leaf
+    /// must come with a value matching its value hash.
+    pub fn verify<H: SimpleHasher>(&self, expected_root_hash: [u8; 32]) -> Result<()> {
+        ensure_valid_proof!(
+            self.root_hash == expected_root_hash,
+            "Subtree root hash {:?} does not match the expected root hash {:?}.",
+            self.root_hash,
+            expected_root_hash
+        );
+        let Some((root_key, root_node)) = self.nodes.first() else {
+            ensure_valid_proof!(
+                self.root_hash == H::PLACEHOLDER_HASH && self.values.is_empty(),
+                "An empty subtree must have the placeholder hash and no values."
+            );
+            return Ok(());
+        };
+        ensure_valid_proof!(
+            root_node.hash::<H>() == self.root_hash,
+            "The root node of the subtree does not match its root hash."
+        );
+        match root_node {
+            Node::Leaf(leaf) => ensure_valid_proof!(
+                starts_with(&NibblePath::from_bytes(&leaf.key_hash().0), &self.prefix)
+                    && starts_with(&self.prefix, root_key.nibble_path()),
+                "The root leaf of the subtree is not on the path of its prefix."
+            ),
+            _ => ensure_valid_proof!(
+                *root_key.nibble_path() == self.prefix,
+                "The root node of the subtree is not at its prefix."
+            ),
+        }
+
+        let nodes: BTreeMap<&NodeKey, &Node> = self.nodes.iter().map(|(k, n)| (k, n)).collect();
+        let values: BTreeMap<(Version, KeyHash), &OwnedValue> = self
+            .values
+            .iter()
+            .map(|(version, key_hash, value)| ((*version, *key_hash), value))
+            .collect();
+        ensure_valid_proof!(
+            nodes.len() == self.nodes.len() && values.len() == self.values.len(),
+            "The subtree holds duplicate nodes or values."
+        );
+
+        let mut visited = BTreeSet::new();
+        let mut num_leaves = 0;
+        let mut stack = vec![root_key.clone()];
+        while let Some(node_key) = stack.pop() {
+            let node = nodes
+                .get(&node_key)
+                .ok_or_else(|| invalid_proof!("Missing node {:?} in the subtree.", node_key))?;
+            match node {
+                Node::Internal(internal) => {
+                    for (nibble, child) in internal.children_sorted() {
+                        let child_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = nodes.get(&child_key).ok_or_else(|| {
+                            invalid_proof!("Missing node {:?} in the subtree.", child_key)
+                        })?;
+                        ensure_valid_proof!(
+                            child_node.hash::<H>() == child.hash,
+                            "Node {:?} does not match the hash recorded by its parent.",
+                            child_key
+                        );
+                        stack.push(child_key);
+                    }
+                }
+                Node::Leaf(leaf) => {
+                    let value = values
+                        .get(&(node_key.version(), leaf.key_hash()))
+                        .ok_or_else(|| invalid_proof!("Missing value of leaf {:?}.", node_key))?;
+                    ensure_valid_proof!(
+                        ValueHash::with::<H>(value) == leaf.value_hash(),
+                        "The value of leaf {:?} does not match its value hash.",
+                        node_key
+                    );
+                    num_leaves += 1;
+                }
+                Node::Null => {
+                    bail_invalid_proof!("Unexpected null node {:?}.", node_key);
+                }
+            }
+            visited.insert(node_key);
+        }
+        ensure_valid_proof!(
+            visited.len() == nodes.len() && num_leaves == values.len(),
+            "The subtree holds nodes or values which are not reachable from its root."
+        );
+        Ok(())
+    }
+}
+
+/// Checks `subtree` against `expected_root_hash` with [`SubtreeExport::verify`], then writes its
+/// nodes and values with `writer`.
+pub fn import_subtree<H: SimpleHasher>(
+    writer: &impl TreeWriter,
+    subtree: &SubtreeExport,
+    expected_root_hash: [u8; 32],
+) -> Result<()> {
+    subtree.verify::<H>(expected_root_hash)?;
+    let mut node_batch = NodeBatch::default();
+    node_batch.extend(
+        subtree.nodes.iter().cloned(),
+        subtree.values.iter().map(|(version, key_hash, value)| {
+            ((*version, *key_hash), Some(Bytes::copy_from_slice(value)))
+        }),
+    );
+    writer.write_node_batch(&node_batch)
+}
+
+/// Returns true if the first nibbles of `path` are those of `prefix`.
+pub(crate) fn starts_with(path: &NibblePath, prefix: &NibblePath) -> bool {
+    path.num_nibbles() >= prefix.num_nibbles()
+        && prefix
+            .nibbles()
+            .zip(path.nibbles())
+            .all(|(prefix_nibble, nibble)| prefix_nibble == nibble)
+}
@@ -0,0 +1,260 @@
+// Copyright (c) The Diem Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Test utilities, enabled by the `testing` feature.
+//!
+//! This module exposes the [`proptest`](mod@proptest) strategies and tree-building helpers used
+//! by this crate's own tests, along with [`MockTreeStore`], so that downstream crates can
+//! property-test their storage integrations against the same generators. For example,
+//! [`write_value_sets`] builds a tree in any [`TreeReader`] and [`TreeWriter`], whose root hashes
+//! can be compared with those of the same value sets written to a [`MockTreeStore`].
+
+use std::{collections::HashMap, fmt::Debug};
+
+use anyhow::Result;
+use proptest::{
+    collection::{hash_map, vec},
+    prelude::*,
+    sample,
+};
+use sha2::Sha256;
+
+pub use crate::mock::MockTreeStore;
+use crate::{
+    storage::{TreeReader, TreeWriter},
+    types::Version,
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, Sha256JMT,
+};
+
+/// Puts each of `value_sets` into a tree backed by `store`, at successive versions starting
+/// from 0, writes the resulting nodes and values to `store`, and returns the root hash of each
+/// version.
+pub fn write_value_sets<S: TreeReader + TreeWriter>(
+    store: &S,
+    value_sets: impl IntoIterator<Item = Vec<(KeyHash, Option<OwnedValue>)>>,
+) -> Result<Vec<RootHash>> {
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(store);
+    value_sets
+        .into_iter()
+        .enumerate()
+        .map(|(version, value_set)| {
+            let (root_hash, batch) = tree.put_value_set(value_set, version as Version)?;
+            store.write_node_batch(&batch.node_batch)?;
+            Ok(root_hash)
+        })
+        .collect()
+}
+
+/// Computes the key immediately after `key`.
+pub fn plus_one(key: KeyHash) -> KeyHash {
+    assert_ne!(key, KeyHash([0xff; 32]));
+
+    let mut buf = key.0;
+    for i in (0..32).rev() {
+        if buf[i] == 255 {
+            buf[i] = 0;
+        } else {
+            buf[i] += 1;
+            break;
+        }
+    }
+    KeyHash(buf)
+}
+
+/// Initializes a DB with a set of key-value pairs by inserting one key at each version.
+pub fn init_mock_db(kvs: &HashMap<KeyHash, OwnedValue>) -> (MockTreeStore, Version) {
+    assert!(!kvs.is_empty());
+
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+
+    for (i, (key, value)) in kvs.clone().into_iter().enumerate() {
+        let (_root_hash, write_batch) = tree
+            .put_value_set(vec![(key, Some(value))], i as Version)
+            .unwrap();
+        db.write_tree_update_batch(write_batch).unwrap();
+    }
+
+    (db, (kvs.len() - 1) as Version)
+}
+
+/// Initializes a DB with a set of key-value pairs by inserting one key at each version, then
+/// deleting the specified keys afterwards.
+pub fn init_mock_db_with_deletions_afterwards(
+    kvs: &HashMap<KeyHash, OwnedValue>,
+    deletions: Vec<KeyHash>,
+) -> (MockTreeStore, Version) {
+    assert!(!kvs.is_empty());
+
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+
+    for (i, (key, value)) in kvs.clone().into_iter().enumerate() {
+        let (_root_hash, write_batch) = tree
+            .put_value_set(vec![(key, Some(value))], i as Version)
+            .unwrap();
+        db.write_tree_update_batch(write_batch).unwrap();
+    }
+
+    let after_insertions_version = kvs.len();
+
+    for (i, key) in deletions.iter().enumerate() {
+        let (_root_hash, write_batch) = tree
+            .put_value_set(
+                vec![(*key, None)],
+                (after_insertions_version + i) as Version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(write_batch).unwrap();
+    }
+    (db, (kvs.len() + deletions.len() - 1) as Version)
+}
+
+/// Generates between 1 and `num_kvs` key-value pairs, and `num_non_existing_keys` keys which are
+/// not among them.
+pub fn arb_existent_kvs_and_nonexistent_keys(
+    num_kvs: usize,
+    num_non_existing_keys: usize,
+) -> impl Strategy<Value = (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>)> {
+    hash_map(any::<KeyHash>(), any::<OwnedValue>(), 1..num_kvs).prop_flat_map(move |kvs| {
+        let kvs_clone = kvs.clone();
+        (
+            Just(kvs),
+            vec(
+                any::<KeyHash>().prop_filter(
+                    "Make sure these keys do not exist in the tree.",
+                    move |key| !kvs_clone.contains_key(key),
+                ),
+                num_non_existing_keys,
+            ),
+        )
+    })
+}
+
+/// Same as [`arb_existent_kvs_and_nonexistent_keys`], but also generates a subset of the keys of
+/// the pairs, to be deleted after they are inserted.
+pub fn arb_existent_kvs_and_deletions_and_nonexistent_keys(
+    num_kvs: usize,
+    num_non_existing_keys: usize,
+) -> impl Strategy<Value = (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>, Vec<KeyHash>)> {
+    hash_map(any::<KeyHash>(), any::<OwnedValue>(), 1..num_kvs).prop_flat_map(move |kvs| {
+        let kvs_clone = kvs.clone();
+        let keys: Vec<_> = kvs.keys().cloned().collect();
+        let keys_count = keys.len();
+        (
+            Just(kvs),
+            sample::subsequence(keys, 0..keys_count),
+            vec(
+                any::<KeyHash>().prop_filter(
+                    "Make sure these keys do not exist in the tree.",
+                    move |key| !kvs_clone.contains_key(key),
+                ),
+                num_non_existing_keys,
+            ),
+        )
+    })
+}
+
+/// Generates a shuffled sequence of insertions and deletions among `num_keys` keys and
+/// `num_values` values, in which only keys inserted at some point are deleted.
+pub fn arb_interleaved_insertions_and_deletions(
+    num_keys: usize,
+    num_values: usize,
+    num_insertions: usize,
+    num_deletions: usize,
+) -> impl Strategy<Value = Vec<(KeyHash, Option<OwnedValue>)>> {
+    // Make a hash set of all the keys and a vector of all the values we'll use in this test
+    (
+        // Key hashes are the sequential set of keys up to num_keys, but shuffled so that we don't
+        // use them in order
+        Just(
+            (0..num_keys)
+                .map(|n| KeyHash::with::<Sha256>(n.to_le_bytes()))
+                .collect::<Vec<_>>(),
+        )
+        .prop_shuffle(),
+        // Values are sequential little-endian byte sequences starting from 0, with trailing zeroes
+        // trimmed -- it doesn't really matter what they are for these tests, so we just use the
+        // smallest distinct sequences we can
+        (1..=num_values).prop_map(|end| {
+            (0..end)
+                .map(|i| {
+                    let mut value = i.to_le_bytes().to_vec();
+                    while let Some(byte) = value.last() {
+                        if *byte != 0 {
+                            break;
+                        }
+                        value.pop();
+                    }
+                    value
+                })
+                .collect::<Vec<_>>()
+        }),
+    )
+        .prop_flat_map(move |(keys, values)| {
+            // Create a random sequence of insertions using only the keys and values in the sets
+            // (this permits keys to be inserted more than once, and with different values)
+            vec(
+                (sample::select(keys), sample::select(values).prop_map(Some)),
+                1..num_insertions,
+            )
+            .prop_flat_map(move |insertions| {
+                // Create a random sequence of deletions using only the keys that were actually inserted
+                // (this permits keys to be deleted more than once, but not more times than they will
+                // ever be inserted, though they may be deleted before they are inserted, in the end)
+                let deletions = sample::subsequence(
+                    insertions
+                        .iter()
+                        .map(|(key, _)| (*key, None))
+                        .collect::<Vec<_>>(),
+                    0..num_deletions.min(insertions.len()),
+                );
+                (Just(insertions), deletions)
+            })
+            .prop_flat_map(move |(insertions, deletions)| {
+                // Shuffle together the insertions and the deletions into a single sequence
+                let mut insertions_and_deletions = insertions;
+                insertions_and_deletions.extend(deletions);
+                Just(insertions_and_deletions).prop_shuffle()
+            })
+        })
+}
+
+/// Divide a vector into arbitrary partitions of size >= 1. If the number of partitions exceeds the
+/// length of the vector, it is divided into size 1 partitions.
+pub fn arb_partitions<T>(
+    num_partitions: usize,
+    values: Vec<T>,
+) -> impl Strategy<Value = Vec<Vec<T>>>
+where
+    T: Debug + Clone,
+{
+    assert_ne!(
+        num_partitions, 0,
+        "cannot partition a vector into 0 partitions"
+    );
+
+    let indices = sample::subsequence(
+        (0..=values.len()).collect::<Vec<_>>(),
+        num_partitions.min(values.len()) - 1,
+    );
+
+    indices.prop_map(move |indices| {
+        let mut partitions = Vec::with_capacity(num_partitions);
+        let mut start = 0;
+        for end in indices {
+            if end - start > 0 {
+                partitions.push(values[start..end].to_vec());
+            } else {
+                partitions.push(vec![]);
+            }
+            start = end;
+        }
+
+        // Anything that hasn't yet been put into the partitions, put it in the last chunk
+        let remainder = values[start..].to_vec();
+        partitions.push(remainder);
+
+        partitions
+    })
+}
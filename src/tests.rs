@@ -1,7 +1,57 @@
+mod aptos_migration;
+#[cfg(feature = "async")]
+mod async_tree;
+#[cfg(feature = "tokio")]
+mod background_pruner;
+mod batched_writer;
+#[cfg(feature = "blake3")]
+mod blake3;
+#[cfg(feature = "borsh")]
+mod borsh;
+mod cache;
+mod chunked;
+mod commit;
+#[cfg(feature = "compat")]
+mod compat;
+mod cost;
+mod default_value;
+mod error;
+#[cfg(feature = "keccak")]
+mod evm;
+mod fork;
 mod helper;
 mod iterator;
 mod jellyfish_merkle;
+#[cfg(feature = "leaf-count-commitment")]
+mod leaf_count;
+mod light;
+mod memory_store;
+#[cfg(feature = "moka")]
+mod moka_cache;
 mod nibble_path;
 mod node_type;
+mod overlay;
+#[cfg(feature = "poseidon")]
+mod poseidon;
+mod preimage_index;
+mod proof_serde;
+#[cfg(feature = "proto")]
+mod proto;
+mod pruner;
 mod restore;
+#[cfg(feature = "rocksdb")]
+mod rocksdb_store;
+#[cfg(feature = "sled")]
+mod sled_store;
+mod snapshot;
+mod subscription;
+mod subtree;
+mod trace;
 mod tree_cache;
+mod tree_snapshot;
+mod typed;
+// The rendered hashes of internal nodes commit to leaf counts with `leaf-count-commitment`.
+#[cfg(not(feature = "leaf-count-commitment"))]
+mod visualize;
+mod witness;
+mod write_behind;
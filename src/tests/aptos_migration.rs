@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sha2::Sha256;
+
+use crate::{
+    aptos_migration::{AptosMigration, AptosTreeReader},
+    mock::MockTreeStore,
+    node_type::{Node, NodeFormat},
+    storage::TreeUpdateBatch,
+    JellyfishMerkleTree, KeyHash, OwnedValue, Version,
+};
+
+/// The storage of an Aptos tree, whose state keys are BCS-encoded byte strings.
+#[derive(Default)]
+struct AptosStore {
+    nodes: HashMap<Vec<u8>, Vec<u8>>,
+    values: HashMap<(Vec<u8>, Version), OwnedValue>,
+    /// The version of the latest value of each key, which is that of its leaf even if the leaf
+    /// moved since.
+    value_versions: HashMap<KeyHash, Version>,
+}
+
+fn encode_state_key(key: &[u8]) -> Vec<u8> {
+    assert!(key.len() < 0x80);
+    let mut encoded = vec![key.len() as u8];
+    encoded.extend_from_slice(key);
+    encoded
+}
+
+impl AptosStore {
+    /// Writes `batch` in the Aptos layout, given the keys of its key hashes.
+    fn write(&mut self, batch: &TreeUpdateBatch, keys: &HashMap<KeyHash, Vec<u8>>) {
+        for ((version, key_hash), value) in batch.node_batch.values() {
+            if let Some(value) = value {
+                let state_key = encode_state_key(&keys[key_hash]);
+                self.values.insert((state_key, *version), value.to_vec());
+                self.value_versions.insert(*key_hash, *version);
+            }
+        }
+        for (node_key, node) in batch.node_batch.nodes() {
+            let encoded_node = match node {
+                Node::Leaf(leaf) => {
+                    let mut encoded = vec![2, 32];
+                    encoded.extend_from_slice(&leaf.key_hash().0);
+                    encoded.push(32);
+                    encoded.extend_from_slice(&leaf.value_hash().0);
+                    encoded.extend(encode_state_key(&keys[&leaf.key_hash()]));
+                    encoded.extend_from_slice(&self.value_versions[&leaf.key_hash()].to_le_bytes());
+                    encoded
+                }
+                _ => node.encode_with_format(NodeFormat::Unversioned).unwrap(),
+            };
+            self.nodes.insert(node_key.encode().unwrap(), encoded_node);
+        }
+    }
+}
+
+impl AptosTreeReader for AptosStore {
+    fn get_aptos_node_option(&self, encoded_node_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.nodes.get(encoded_node_key).cloned())
+    }
+
+    fn get_aptos_value_option(
+        &self,
+        encoded_state_key: &[u8],
+        version: Version,
+    ) -> Result<Option<OwnedValue>> {
+        Ok(self
+            .values
+            .get(&(encoded_state_key.to_vec(), version))
+            .cloned())
+    }
+}
+
+#[test]
+fn test_aptos_migration() {
+    // The upstream tree is built by this crate, and written in the Aptos layout, so this checks
+    // the migration of every shape of tree; `test_aptos_migration_fixture` checks Aptos hashes.
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: HashMap<KeyHash, Vec<u8>> = (0..40u8)
+        .map(|i| {
+            let key = vec![b'k', i];
+            (KeyHash::with::<Sha256>(&key), key)
+        })
+        .collect();
+    let key_hashes: Vec<KeyHash> = keys.keys().copied().collect();
+    let mut aptos_store = AptosStore::default();
+    let mut root_hashes = vec![];
+    for version in 0..4u8 {
+        let value_set = key_hashes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| version == 0 || *i % 4 == version as usize)
+            .map(|(i, key_hash)| {
+                let value = (version == 0 || i % 8 != 2).then(|| vec![version, i as u8]);
+                (*key_hash, value)
+            });
+        let (root_hash, batch) = tree.put_value_set(value_set, version.into()).unwrap();
+        aptos_store.write(&batch, &keys);
+        db.write_tree_update_batch(batch).unwrap();
+        root_hashes.push(root_hash);
+    }
+
+    let target = MockTreeStore::default();
+    let migration = AptosMigration::<_, _, Sha256>::new(&aptos_store, &target);
+    for version in 0..4u8 {
+        let root_hash = migration.migrate_version(version.into()).unwrap();
+        assert_eq!(root_hash, root_hashes[version as usize]);
+    }
+    let migrated_tree = JellyfishMerkleTree::<_, Sha256>::new(&target);
+    for key_hash in &key_hashes {
+        for version in 0..4 {
+            let (value, proof) = migrated_tree.get_with_proof(*key_hash, version).unwrap();
+            assert_eq!(value, tree.get(*key_hash, version).unwrap());
+            proof
+                .verify(root_hashes[version as usize], *key_hash, value)
+                .unwrap();
+        }
+    }
+
+    let snapshot_target = MockTreeStore::default();
+    let snapshot_migration = AptosMigration::<_, _, Sha256>::new(&aptos_store, &snapshot_target);
+    assert_eq!(
+        snapshot_migration.migrate_snapshot(3).unwrap(),
+        root_hashes[3]
+    );
+    let snapshot_tree = JellyfishMerkleTree::<_, Sha256>::new(&snapshot_target);
+    for key_hash in &key_hashes {
+        assert_eq!(
+            snapshot_tree.get(*key_hash, 3).unwrap(),
+            tree.get(*key_hash, 3).unwrap()
+        );
+    }
+
+    // A value which does not match its leaf fails the migration.
+    for value in aptos_store.values.values_mut() {
+        value.push(0);
+    }
+    let failed_target = MockTreeStore::default();
+    assert!(
+        AptosMigration::<_, _, Sha256>::new(&aptos_store, &failed_target)
+            .migrate_snapshot(3)
+            .is_err()
+    );
+}
+
+/// The nodes of an Aptos tree, as encoded node key and Aptos-encoded node, keyed by the
+/// [`NodeKey`](crate::storage::NodeKey) encoding. Version 0 holds the leaves of alice (first
+/// nibble 0), bob (1) and carol (f) under an internal node with leaf counts (tag 3); version 1
+/// adds dave (1), which moves bob down under a new internal node at nibble 1. Leaves are the
+/// tag 2, the BCS-encoded key hash and value hash, the BCS-encoded `StateKey::Raw` and the
+/// little-endian version of the value.
+#[cfg(feature = "compat")]
+const APTOS_NODES: [(&str, &str); 8] = [
+    (
+        "000000000000000000",
+        "03038003800091dbc73dbbfd23c03153b95697b841916754c72fbf72b5f232bc60a31565bf5700fabd59aed9b1\
+         45c95fecf7615345b8787f29a810bfe0b23733b9fb5c885633f300ec059bee8a2951ed8d4ef335921c6fdc2e78\
+         0117f3023b386ead01b23311eb76",
+    ),
+    (
+        "00000000000000000100",
+        "0220001111111111111111111111111111111111111111111111111111111111111120134fddcfb40fb785e845\
+         1ec37cf82dc096db8b3d85e0ce37385a1394c46ecbf10205616c6963650000000000000000",
+    ),
+    (
+        "00000000000000000110",
+        "02201a2222222222222222222222222222222222222222222222222222222222222220072cab082a7593c525c2\
+         91e820bac771ac47436e9af1a5aead810fcdd1cceb150203626f620000000000000000",
+    ),
+    (
+        "000000000000000001f0",
+        "0220f0333333333333333333333333333333333333333333333333333333333333332045d7f52134f95833c693\
+         91e1a1cc96a62d48535cdc25e8d178ba77060948340902056361726f6c0000000000000000",
+    ),
+    (
+        "000000000000000100",
+        "03038001800091dbc73dbbfd23c03153b95697b841916754c72fbf72b5f232bc60a31565bf5701c0ba076651c1\
+         0395f31f294ebe7e8a713234cac0dd0e35d43011a89e418d82580200ec059bee8a2951ed8d4ef335921c6fdc2e\
+         780117f3023b386ead01b23311eb76",
+    ),
+    (
+        "00000000000000010110",
+        "03000c000c01fabd59aed9b145c95fecf7615345b8787f29a810bfe0b23733b9fb5c885633f301b6b153ec2c5d\
+         b3d71139d55285ce37cf3e69cd5bdf6b4acc62c24c2eddf01eb1",
+    ),
+    (
+        "0000000000000001021a",
+        "02201a2222222222222222222222222222222222222222222222222222222222222220072cab082a7593c525c2\
+         91e820bac771ac47436e9af1a5aead810fcdd1cceb150203626f620000000000000000",
+    ),
+    (
+        "0000000000000001021b",
+        "02201b4444444444444444444444444444444444444444444444444444444444444420904fc0ea5e2a672101ae\
+         b11c6aebcdd7e8f2eb2d5848f11930f6ff5dcd50c5ce0204646176650100000000000000",
+    ),
+];
+
+/// The values of the Aptos tree, as raw state key, version and value.
+#[cfg(feature = "compat")]
+const APTOS_VALUES: [(&[u8], Version, &[u8]); 4] = [
+    (b"alice", 0, b"alice's balance"),
+    (b"bob", 0, b"bob's balance"),
+    (b"carol", 0, b"carol's balance"),
+    (b"dave", 1, b"dave's balance"),
+];
+
+/// The Aptos root hashes of the tree at versions 0 and 1.
+#[cfg(feature = "compat")]
+const APTOS_ROOT_HASHES: [&str; 2] = [
+    "ca8ca1f0b10764223acca8c20b9be2ec47c1139552f98c1e3cc6c27f5d95ca6c",
+    "1245c0654da5da77fb231e26f1ae552ee4127c17ab05c6c326c149157ac3eaec",
+];
+
+#[cfg(feature = "compat")]
+#[test]
+fn test_aptos_migration_fixture() {
+    use crate::{compat::AptosHasher, RootHash};
+
+    let mut aptos_store = AptosStore::default();
+    for (node_key, node) in APTOS_NODES {
+        aptos_store
+            .nodes
+            .insert(hex::decode(node_key).unwrap(), hex::decode(node).unwrap());
+    }
+    for (raw_key, version, value) in APTOS_VALUES {
+        // `StateKey::Raw` is the third variant of the state key enum.
+        let mut state_key = vec![2];
+        state_key.extend(encode_state_key(raw_key));
+        aptos_store
+            .values
+            .insert((state_key, version), value.to_vec());
+    }
+    let root_hashes = APTOS_ROOT_HASHES
+        .map(|root_hash| RootHash(hex::decode(root_hash).unwrap().try_into().unwrap()));
+
+    let target = MockTreeStore::default();
+    let migration = AptosMigration::<_, _, AptosHasher>::new(&aptos_store, &target);
+    assert_eq!(migration.migrate_version(0).unwrap(), root_hashes[0]);
+    assert_eq!(migration.migrate_version(1).unwrap(), root_hashes[1]);
+
+    // Every key and the absent ones in between are proven against the Aptos root hashes, through
+    // the placeholder siblings of nibbles 2 to e.
+    let tree = JellyfishMerkleTree::<_, AptosHasher>::new(&target);
+    let key_hash = |first: u8, fill: u8| {
+        let mut key_hash = KeyHash([fill; 32]);
+        key_hash.0[0] = first;
+        key_hash
+    };
+    let keys = [
+        (key_hash(0x00, 0x11), Some(&b"alice's balance"[..]), 0),
+        (key_hash(0x1a, 0x22), Some(b"bob's balance"), 0),
+        (key_hash(0xf0, 0x33), Some(b"carol's balance"), 0),
+        (key_hash(0x1b, 0x44), Some(b"dave's balance"), 1),
+        (key_hash(0x80, 0x55), None, 0),
+    ];
+    for version in 0..2 {
+        for (key_hash, value, since) in keys {
+            let expected = value.filter(|_| version >= since);
+            let (value, proof) = tree.get_with_proof(key_hash, version).unwrap();
+            assert_eq!(value.as_deref(), expected);
+            proof
+                .verify(root_hashes[version as usize], key_hash, value)
+                .unwrap();
+        }
+    }
+
+    let snapshot_target = MockTreeStore::default();
+    let snapshot_migration =
+        AptosMigration::<_, _, AptosHasher>::new(&aptos_store, &snapshot_target);
+    assert_eq!(
+        snapshot_migration.migrate_snapshot(1).unwrap(),
+        root_hashes[1]
+    );
+
+    // The hashes of the default hasher differ from the Aptos ones, so the migration fails.
+    let sha256_target = MockTreeStore::default();
+    assert!(
+        AptosMigration::<_, _, Sha256>::new(&aptos_store, &sha256_target)
+            .migrate_version(0)
+            .is_err()
+    );
+}
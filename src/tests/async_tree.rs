@@ -0,0 +1,166 @@
+use anyhow::Result;
+use futures::executor::block_on;
+use proptest::prelude::*;
+use sha2::Sha256;
+
+use super::helper::{arb_interleaved_insertions_and_deletions, arb_partitions};
+use crate::{
+    mock::MockTreeStore,
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{AsyncTreeReader, AsyncTreeWriter, TreeReader},
+    types::Version,
+    AsyncJellyfishMerkleTree, KeyHash, OwnedValue, Sha256JMT,
+};
+
+/// A store which can only be read through [`AsyncTreeReader`], and which yields to the executor
+/// before every read.
+#[derive(Default)]
+struct AsyncOnlyStore(MockTreeStore);
+
+#[async_trait::async_trait]
+impl AsyncTreeReader for AsyncOnlyStore {
+    async fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        yield_now().await;
+        TreeReader::get_node_option(&self.0, node_key)
+    }
+
+    async fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        yield_now().await;
+        TreeReader::get_value_option(&self.0, max_version, key_hash)
+    }
+
+    async fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        yield_now().await;
+        TreeReader::get_rightmost_leaf(&self.0)
+    }
+}
+
+/// Returns `Pending` once, so that the caller is actually suspended.
+async fn yield_now() {
+    let mut yielded = false;
+    futures::future::poll_fn(|cx| {
+        if yielded {
+            std::task::Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        }
+    })
+    .await
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[test]
+fn test_async_futures_are_send() {
+    let db = AsyncOnlyStore::default();
+    let tree = AsyncJellyfishMerkleTree::<_, Sha256>::new(&db);
+    assert_send(&tree.get_with_proof(KeyHash([0; 32]), 0));
+    assert_send(&tree.put_value_set(vec![(KeyHash([0; 32]), Some(vec![]))], 0));
+}
+
+#[test]
+fn test_async_missing_root() {
+    let db = AsyncOnlyStore::default();
+    let tree = AsyncJellyfishMerkleTree::<_, Sha256>::new(&db);
+    assert!(block_on(tree.get_with_proof(KeyHash([0; 32]), 0)).is_err());
+    assert!(block_on(tree.get_root_hash_option(0)).unwrap().is_none());
+}
+
+/// Applies the same operations through the synchronous and the asynchronous tree, and checks that
+/// they produce the same batches, and then the same proofs.
+fn test_async_tree_matches_sync_tree(
+    operations_by_version: Vec<Vec<(KeyHash, Option<OwnedValue>)>>,
+) {
+    let sync_db = MockTreeStore::default();
+    let sync_tree = Sha256JMT::new(&sync_db);
+    let async_db = AsyncOnlyStore::default();
+    let async_tree = AsyncJellyfishMerkleTree::<_, Sha256>::new(&async_db);
+
+    for (version, operations) in operations_by_version.iter().enumerate() {
+        let version = version as Version;
+        let (sync_root_hash, sync_batch) = sync_tree
+            .put_value_set(operations.clone(), version)
+            .unwrap();
+        let (async_root_hash, async_batch) =
+            block_on(async_tree.put_value_set(operations.clone(), version)).unwrap();
+        assert_eq!(sync_root_hash, async_root_hash);
+        assert_eq!(sync_batch, async_batch);
+        sync_db.write_tree_update_batch(sync_batch).unwrap();
+        async_db.0.write_tree_update_batch(async_batch).unwrap();
+    }
+
+    let Some(latest_version) = operations_by_version.len().checked_sub(1) else {
+        return;
+    };
+    let latest_version = latest_version as Version;
+    let keys: Vec<KeyHash> = operations_by_version
+        .iter()
+        .flatten()
+        .map(|(key, _)| *key)
+        .collect();
+    let async_results = block_on(async_tree.multi_get_with_proof(&keys, latest_version)).unwrap();
+    for (key, (value, proof)) in keys.iter().zip(async_results) {
+        let (sync_value, sync_proof) = sync_tree.get_with_proof(*key, latest_version).unwrap();
+        assert_eq!(sync_value, value);
+        assert_eq!(sync_proof.leaf(), proof.leaf());
+        assert_eq!(sync_proof.siblings(), proof.siblings());
+    }
+    for key in &keys {
+        let (sync_value, sync_proof) = sync_tree.get_with_proof(*key, latest_version).unwrap();
+        let (async_value, async_proof) =
+            block_on(async_tree.get_with_proof(*key, latest_version)).unwrap();
+        assert_eq!(sync_value, async_value);
+        assert_eq!(sync_proof.leaf(), async_proof.leaf());
+        assert_eq!(sync_proof.siblings(), async_proof.siblings());
+        let async_proof = block_on(async_tree.get_proof(*key, latest_version)).unwrap();
+        assert_eq!(sync_proof.leaf(), async_proof.leaf());
+        assert_eq!(sync_proof.siblings(), async_proof.siblings());
+    }
+    let (sync_values, sync_proof) = sync_tree
+        .get_with_multiproof(&keys, latest_version)
+        .unwrap();
+    let (async_values, async_proof) =
+        block_on(async_tree.get_with_multiproof(&keys, latest_version)).unwrap();
+    assert_eq!(sync_values, async_values);
+    assert_eq!(
+        block_on(async_tree.multi_get(&keys, latest_version)).unwrap(),
+        sync_values
+    );
+    assert_eq!(sync_proof.leaves(), async_proof.leaves());
+    assert_eq!(sync_proof.siblings(), async_proof.siblings());
+    assert_eq!(
+        sync_tree.get_root_hash(latest_version).unwrap(),
+        block_on(async_tree.get_root_hash(latest_version)).unwrap()
+    );
+}
+
+#[test]
+fn test_async_write_node_batch() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let (_, batch) = tree
+        .put_value_set(vec![(KeyHash([1; 32]), Some(vec![1]))], 0)
+        .unwrap();
+    block_on(AsyncTreeWriter::write_node_batch(&db, &batch.node_batch)).unwrap();
+    assert_eq!(tree.get(KeyHash([1; 32]), 0).unwrap(), Some(vec![1]));
+}
+
+proptest! {
+    #[test]
+    fn proptest_async_tree_matches_sync_tree(
+        operations_by_version in
+            (1usize..10) // possible numbers of versions
+                .prop_flat_map(|versions| {
+                    arb_interleaved_insertions_and_deletions(50, 50, 200, 200) // (distinct keys, distinct values, insertions, deletions)
+                        .prop_flat_map(move |ops| arb_partitions(versions, ops))
+            })
+    ) {
+        test_async_tree_matches_sync_tree(operations_by_version)
+    }
+}
@@ -0,0 +1,60 @@
+use std::{sync::Arc, time::Duration};
+
+use sha2::Sha256;
+use tokio::time::Instant;
+
+use crate::{
+    background_pruner::{BackgroundPruner, BackgroundPrunerConfig},
+    mock::MockTreeStore,
+    pruner::{HasVersionPins, StaleNodeIndexStore},
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[tokio::test(start_paused = true)]
+async fn test_background_pruner() {
+    let db = Arc::new(MockTreeStore::default());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&*db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    for version in 0..4 {
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter().map(|key| (*key, Some(vec![version as u8]))),
+                version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    let stale_nodes = db.get_stale_node_indices(3, usize::MAX).unwrap().len();
+    let num_nodes = db.num_nodes();
+
+    let config = BackgroundPrunerConfig {
+        batch_size: 5,
+        max_deletes_per_second: Some(10),
+    };
+    let pruner = BackgroundPruner::spawn(db.clone(), config, Some(db.version_pins().clone()));
+    let guard = tree.pin_version(2).unwrap();
+    let start = Instant::now();
+    pruner.set_least_readable_version(3);
+    while !db.get_stale_node_indices(2, 1).unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    // The pinned version is still readable.
+    tree.get_with_proof(keys[0], 2).unwrap();
+    assert!(!db.get_stale_node_indices(3, 1).unwrap().is_empty());
+
+    // Once unpinned, pruning resumes up to the current version, lower versions being ignored.
+    drop(guard);
+    pruner.set_least_readable_version(3);
+    pruner.set_least_readable_version(1);
+    while !db.get_stale_node_indices(3, 1).unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    // All but the last batch are followed by a pause within the budget.
+    assert!(start.elapsed() >= Duration::from_secs_f64((stale_nodes - 5) as f64 / 10.0));
+    assert!(tree.get_with_proof(keys[0], 2).is_err());
+    tree.get_with_proof(keys[0], 3).unwrap();
+
+    let stats = pruner.shutdown().await.unwrap();
+    assert_eq!(stats.pruned_nodes, stale_nodes);
+    assert_eq!(db.num_nodes(), num_nodes - stale_nodes);
+}
@@ -0,0 +1,65 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use sha2::Sha256;
+
+use crate::{
+    batched_writer::BatchedWriter,
+    mock::MockTreeStore,
+    storage::{NodeBatch, TreeWriter},
+    JellyfishMerkleTree, KeyHash,
+};
+
+/// A store recording the size of every batch written to it.
+#[derive(Default)]
+struct RecordingStore {
+    store: MockTreeStore,
+    batch_sizes: Mutex<Vec<usize>>,
+}
+
+impl TreeWriter for RecordingStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        self.batch_sizes
+            .lock()
+            .unwrap()
+            .push(node_batch.nodes().len() + node_batch.values().len());
+        self.store.write_node_batch(node_batch)
+    }
+}
+
+#[test]
+fn test_batched_writer() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..100).map(|i| KeyHash::with::<Sha256>([i])).collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1; 100]))), 0)
+        .unwrap();
+    let num_items = batch.node_batch.nodes().len() + batch.node_batch.values().len();
+
+    let writer = BatchedWriter::new(RecordingStore::default()).with_max_items(32);
+    writer.write_node_batch(&batch.node_batch).unwrap();
+    assert!(writer.inner().batch_sizes.lock().unwrap().len() < num_items.div_ceil(32));
+    let store = writer.into_inner().unwrap();
+    let batch_sizes = store.batch_sizes.into_inner().unwrap();
+    assert_eq!(batch_sizes.len(), num_items.div_ceil(32));
+    assert!(batch_sizes.iter().all(|size| *size <= 32));
+    assert_eq!(batch_sizes.iter().sum::<usize>(), num_items);
+    let written_tree = JellyfishMerkleTree::<_, Sha256>::new(&store.store);
+    assert_eq!(written_tree.get_root_hash(0).unwrap(), root_hash);
+    assert_eq!(written_tree.get(keys[0], 0).unwrap(), Some(vec![1; 100]));
+
+    // Values of 100 bytes, with their keys of 40 bytes, fit twice in 300 bytes.
+    let writer = BatchedWriter::new(RecordingStore::default()).with_max_bytes(300);
+    let mut values = NodeBatch::default();
+    values.extend(
+        [],
+        keys.iter()
+            .map(|key| ((0, *key), Some(vec![1; 100].into()))),
+    );
+    writer.write_node_batch(&values).unwrap();
+    writer.flush().unwrap();
+    let batch_sizes = writer.inner().batch_sizes.lock().unwrap();
+    assert_eq!(batch_sizes.len(), keys.len().div_ceil(2));
+    assert!(batch_sizes.iter().all(|size| *size <= 2));
+}
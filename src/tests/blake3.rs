@@ -0,0 +1,25 @@
+use crate::{mock::MockTreeStore, Blake3, JellyfishMerkleTree, KeyHash, SimpleHasher};
+
+#[test]
+fn test_blake3_hasher() {
+    assert_eq!(
+        hex::encode(Blake3::hash(b"")),
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+    );
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Blake3>::new(&db);
+    let keys: Vec<KeyHash> = (0u32..100)
+        .map(|i| KeyHash::with::<Blake3>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(value, Some(key.0.to_vec()));
+        proof.verify(root_hash, *key, value).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use proptest::prelude::*;
+use sha2::Sha256;
+
+use super::helper::init_mock_db;
+use crate::{
+    node_type::{InternalNode, Node, NodeFormat, NodeKey},
+    proof::{SparseMerkleProof, SparseMerkleRangeProof},
+    KeyHash, RootHash, Sha256JMT, ValueHash,
+};
+
+/// Checks that `value` decodes back from its encoding. Proofs are compared through their
+/// encodings, since they are only `PartialEq` if their hasher is.
+fn assert_round_trip<T: BorshSerialize + BorshDeserialize>(value: &T) {
+    let bytes = borsh::to_vec(value).unwrap();
+    let decoded = T::try_from_slice(&bytes).unwrap();
+    assert_eq!(borsh::to_vec(&decoded).unwrap(), bytes);
+}
+
+proptest! {
+    #[test]
+    fn proptest_borsh_round_trip_sparse_merkle_proof(proof in any::<SparseMerkleProof<Sha256>>()) {
+        assert_round_trip(&proof);
+        if let Some(leaf) = proof.leaf() {
+            assert_round_trip(&leaf);
+        }
+    }
+
+    #[test]
+    fn proptest_borsh_round_trip_sparse_merkle_range_proof(
+        proof in any::<SparseMerkleRangeProof<Sha256>>()
+    ) {
+        assert_round_trip(&proof);
+    }
+
+    #[test]
+    fn proptest_borsh_round_trip_node_key(node_key in any::<NodeKey>()) {
+        assert_round_trip(&node_key);
+        prop_assert_eq!(borsh::from_slice::<NodeKey>(&borsh::to_vec(&node_key).unwrap()).unwrap(), node_key);
+    }
+
+    #[test]
+    fn proptest_borsh_node_format(
+        internal_node in any::<InternalNode>(),
+        key_hash in any::<KeyHash>(),
+        value_hash in any::<ValueHash>(),
+    ) {
+        let nodes = [
+            Node::Null,
+            Node::Internal(internal_node.clone().into_legacy_internal()),
+            Node::Internal(internal_node),
+            Node::new_leaf(key_hash, value_hash),
+        ];
+        for node in nodes {
+            let encoded = node.encode_with_format(NodeFormat::Borsh).unwrap();
+            prop_assert_eq!(Node::format(&encoded).unwrap(), NodeFormat::Borsh);
+            // The format byte is followed by the plain Borsh encoding of the node.
+            prop_assert_eq!(&encoded[1..], &borsh::to_vec(&node).unwrap()[..]);
+            prop_assert_eq!(&Node::decode(&encoded).unwrap(), &node);
+            let node_ref = Node::decode_ref(&encoded).unwrap();
+            prop_assert_eq!(&node_ref, &node.to_ref());
+            prop_assert_eq!(node_ref.hash::<Sha256>(), node.hash::<Sha256>());
+        }
+    }
+
+    #[test]
+    fn proptest_borsh_round_trip_hashes(root_hash in any::<RootHash>(), key_hash in any::<KeyHash>()) {
+        assert_round_trip(&root_hash);
+        assert_round_trip(&key_hash);
+    }
+}
+
+#[test]
+fn test_borsh_round_trip_multiproof_and_interval_proof() {
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash::with::<Sha256>([i])).collect();
+    let (db, version) = init_mock_db(&keys.iter().map(|key| (*key, key.0.to_vec())).collect());
+    let tree = Sha256JMT::new(&db);
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    let (_, multiproof) = tree.get_with_multiproof(&keys[..5], version).unwrap();
+    assert_round_trip(&multiproof);
+
+    let (leaves, interval_proof) = tree
+        .get_interval_proof(KeyHash([0; 32]), KeyHash([0x80; 32]), version)
+        .unwrap();
+    assert_round_trip(&interval_proof);
+    let decoded = borsh::from_slice::<crate::proof::SparseMerkleIntervalProof<Sha256>>(
+        &borsh::to_vec(&interval_proof).unwrap(),
+    )
+    .unwrap();
+    decoded
+        .verify(root_hash, KeyHash([0; 32]), KeyHash([0x80; 32]), &leaves)
+        .unwrap();
+}
@@ -0,0 +1,129 @@
+use std::{num::NonZeroUsize, sync::atomic::AtomicUsize, sync::atomic::Ordering};
+
+use anyhow::Result;
+use sha2::Sha256;
+
+use crate::{
+    cache::CachedTreeReader,
+    mock::MockTreeStore,
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{TreeReader, TreeWriter},
+    JellyfishMerkleTree, KeyHash, OwnedValue, Version,
+};
+
+/// A store counting the nodes read from it, one by one or in batches.
+#[derive(Default)]
+struct CountingStore {
+    store: MockTreeStore,
+    node_reads: AtomicUsize,
+    batch_reads: AtomicUsize,
+}
+
+impl TreeReader for CountingStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.node_reads.fetch_add(1, Ordering::SeqCst);
+        self.store.get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        self.batch_reads.fetch_add(1, Ordering::SeqCst);
+        self.store.get_nodes(node_keys)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.store.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+}
+
+#[test]
+fn test_cached_tree_reader() {
+    let db = CountingStore::default();
+    let keys: Vec<KeyHash> = (0u8..50).map(|i| KeyHash([i; 32])).collect();
+    let (root, batch) = JellyfishMerkleTree::<_, Sha256>::new(&db)
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.store.write_tree_update_batch(batch).unwrap();
+
+    let cached = CachedTreeReader::new(&db, NonZeroUsize::new(1000).unwrap());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&cached);
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(value, Some(key.0.to_vec()));
+        proof.verify(root, *key, value).unwrap();
+    }
+    let node_reads = db.node_reads.load(Ordering::SeqCst);
+    assert!(!cached.is_empty());
+
+    // All the nodes on the paths to the keys are now cached.
+    for key in &keys {
+        tree.get_with_proof(*key, 0).unwrap();
+    }
+    assert_eq!(db.node_reads.load(Ordering::SeqCst), node_reads);
+
+    cached.clear().unwrap();
+    assert!(cached.is_empty());
+    tree.get_with_proof(keys[0], 0).unwrap();
+    assert!(db.node_reads.load(Ordering::SeqCst) > node_reads);
+}
+
+#[test]
+fn test_cached_tree_reader_capacity() {
+    let db = MockTreeStore::default();
+    let cached = CachedTreeReader::new(&db, NonZeroUsize::new(4).unwrap());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&cached);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 0)
+        .unwrap();
+    // Nodes written through the cache are cached, up to its capacity.
+    cached.write_node_batch(&batch.node_batch).unwrap();
+    assert_eq!(cached.len(), 4);
+
+    for key in &keys {
+        assert_eq!(tree.get_with_proof(*key, 0).unwrap().0, Some(vec![1]));
+    }
+    assert_eq!(cached.len(), 4);
+}
+
+#[test]
+fn test_cached_tree_reader_batches() {
+    let db = CountingStore::default();
+    let keys: Vec<KeyHash> = (0u32..200)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (root, batch) = JellyfishMerkleTree::<_, Sha256>::new(&db)
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.store.write_tree_update_batch(batch).unwrap();
+    db.node_reads.store(0, Ordering::SeqCst);
+
+    // Only the root is read on its own, and the children of each internal node in one batch.
+    let cached = CachedTreeReader::new(&db, NonZeroUsize::new(1000).unwrap());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&cached);
+    let results = tree.multi_get_with_proof(&keys, 0).unwrap();
+    for (key, (value, proof)) in keys.iter().zip(results) {
+        assert_eq!(value, Some(key.0.to_vec()));
+        proof.verify(root, *key, value).unwrap();
+    }
+    assert_eq!(db.node_reads.load(Ordering::SeqCst), 1);
+    let batch_reads = db.batch_reads.load(Ordering::SeqCst);
+    assert!(batch_reads > 0);
+
+    // The second time, every node is served by the cache.
+    let values = tree.multi_get(&keys, 0).unwrap();
+    assert!(values
+        .iter()
+        .zip(&keys)
+        .all(|(value, key)| *value == Some(key.0.to_vec())));
+    assert_eq!(db.node_reads.load(Ordering::SeqCst), 1);
+    assert_eq!(db.batch_reads.load(Ordering::SeqCst), batch_reads);
+}
@@ -0,0 +1,50 @@
+use sha2::Sha256;
+
+use crate::{
+    chunked::{leaf_value, ChunkedValueTree},
+    mock::MockTreeStore,
+    KeyHash,
+};
+
+#[test]
+fn test_chunked_value_tree() {
+    let db = MockTreeStore::default();
+    let tree = ChunkedValueTree::<_, Sha256>::new(&db, 4);
+    let small = KeyHash::with::<Sha256>(b"small");
+    let large = KeyHash::with::<Sha256>(b"large");
+    let small_value = b"abc".to_vec();
+    let large_value = b"0123456789".to_vec();
+
+    let (root_hash, batch) = tree
+        .put_value_set(
+            [
+                (small, Some(small_value.clone())),
+                (large, Some(large_value.clone())),
+            ],
+            0,
+        )
+        .unwrap();
+    // The large value is stored as 3 chunks, besides the leaf values of both keys.
+    assert_eq!(batch.node_batch.values().len(), 5);
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert_eq!(tree.get(small, 0).unwrap(), Some(small_value.clone()));
+    assert_eq!(tree.get(large, 0).unwrap(), Some(large_value.clone()));
+    for (key, value) in [(small, small_value), (large, large_value.clone())] {
+        let (read, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(read.as_ref(), Some(&value));
+        proof
+            .verify(root_hash, key, Some(leaf_value::<Sha256>(&value, 4)))
+            .unwrap();
+    }
+    assert_eq!(
+        tree.inner().get(large, 0).unwrap(),
+        Some(leaf_value::<Sha256>(&large_value, 4))
+    );
+
+    // Chunks written at earlier versions are found by later reads.
+    let (_, batch) = tree.put_value_set([(small, None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(small, 1).unwrap(), None);
+    assert_eq!(tree.get(large, 1).unwrap(), Some(large_value));
+}
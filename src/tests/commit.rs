@@ -0,0 +1,141 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+};
+
+use anyhow::{bail, Result};
+use sha2::Sha256;
+
+use crate::{
+    commit::{Commit, CommitTarget, FileWriteAheadLog, WriteAheadCommitter, WriteAheadLog},
+    mock::MockTreeStore,
+    storage::TreeWriter,
+    types::Version,
+    JellyfishMerkleTree, KeyHash, RootHash,
+};
+
+/// A target which can be made to crash once, after writing the nodes of a commit but before
+/// recording its root hash.
+struct CrashingTarget {
+    store: MockTreeStore,
+    root_hashes: RefCell<BTreeMap<Version, RootHash>>,
+    crash: Cell<bool>,
+}
+
+impl Default for CrashingTarget {
+    fn default() -> Self {
+        Self {
+            // Applying a commit again rewrites the same nodes.
+            store: MockTreeStore::new(true),
+            root_hashes: Default::default(),
+            crash: Cell::new(false),
+        }
+    }
+}
+
+impl CommitTarget for CrashingTarget {
+    fn apply_commit(&self, commit: &Commit) -> Result<()> {
+        self.store.write_node_batch(&commit.batch.node_batch)?;
+        if self.crash.replace(false) {
+            bail!("Crashed while applying version {}.", commit.version);
+        }
+        self.root_hashes
+            .borrow_mut()
+            .insert(commit.version, commit.root_hash);
+        Ok(())
+    }
+}
+
+fn commit_at(target: &CrashingTarget, version: Version) -> Commit {
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&target.store);
+    let key = KeyHash::with::<Sha256>(format!("key{}", version));
+    let (root_hash, batch) = tree
+        .put_value_set(vec![(key, Some(vec![version as u8]))], version)
+        .unwrap();
+    Commit::new(version, root_hash, batch).with_preimages([(key, b"key".to_vec())])
+}
+
+#[test]
+fn test_commit_encode_decode() {
+    let target = CrashingTarget::default();
+    let commit = commit_at(&target, 0);
+    target.apply_commit(&commit).unwrap();
+    let commit = commit_at(&target, 1);
+    assert!(!commit.batch.stale_node_index_batch.is_empty());
+
+    let encoded = commit.encode().unwrap();
+    assert_eq!(Commit::decode(&encoded).unwrap(), commit);
+    assert!(Commit::decode(&encoded[..encoded.len() - 1]).is_err());
+    assert!(Commit::decode(&[encoded.as_slice(), &[0]].concat()).is_err());
+}
+
+#[test]
+fn test_commit_encode_decode_multi_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let log_path = dir.path().join("commit.wal");
+    let target = CrashingTarget::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&target.store);
+    let value_sets =
+        (0..3u8).map(|version| vec![(KeyHash::with::<Sha256>([version]), Some(vec![version]))]);
+    let (root_hashes, batch) = tree.put_value_sets(value_sets, 0).unwrap();
+    let commit = Commit::new(2, root_hashes[2], batch);
+    assert_eq!(commit.batch.node_batch.root_hashes().len(), 3);
+
+    let decoded = Commit::decode(&commit.encode().unwrap()).unwrap();
+    assert_eq!(decoded, commit);
+    assert_eq!(
+        decoded.batch.node_batch.root_hashes(),
+        &BTreeMap::from([
+            (0, root_hashes[0]),
+            (1, root_hashes[1]),
+            (2, root_hashes[2])
+        ])
+    );
+
+    // Recovering the commit records the root hashes of all its versions.
+    target.crash.set(true);
+    let committer = WriteAheadCommitter::new(FileWriteAheadLog::new(&log_path), &target);
+    assert!(committer.commit(&commit).is_err());
+    assert_eq!(committer.recover().unwrap(), Some(2));
+    for (version, root_hash) in root_hashes.into_iter().enumerate() {
+        assert_eq!(tree.get_root_hash(version as Version).unwrap(), root_hash);
+    }
+}
+
+#[test]
+fn test_write_ahead_commit_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let log_path = dir.path().join("commit.wal");
+    let target = CrashingTarget::default();
+
+    let committer = WriteAheadCommitter::new(FileWriteAheadLog::new(&log_path), &target);
+    assert_eq!(committer.recover().unwrap(), None);
+    let commit0 = commit_at(&target, 0);
+    committer.commit(&commit0).unwrap();
+    assert!(!log_path.exists());
+
+    let commit1 = commit_at(&target, 1);
+    target.crash.set(true);
+    assert!(committer.commit(&commit1).is_err());
+    assert!(!target.root_hashes.borrow().contains_key(&1));
+    // Nothing can be committed on top of a torn commit before it is recovered.
+    assert!(committer.commit(&commit1).is_err());
+
+    // On restart, the staged commit is applied again.
+    let committer = WriteAheadCommitter::new(FileWriteAheadLog::new(&log_path), &target);
+    assert_eq!(committer.recover().unwrap(), Some(1));
+    assert_eq!(committer.recover().unwrap(), None);
+    assert_eq!(
+        *target.root_hashes.borrow(),
+        BTreeMap::from([(0, commit0.root_hash), (1, commit1.root_hash)])
+    );
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&target.store);
+    assert_eq!(tree.get_root_hash(1).unwrap(), commit1.root_hash);
+
+    let log = FileWriteAheadLog::new(&log_path);
+    log.stage(b"commit").unwrap();
+    assert_eq!(log.staged().unwrap(), Some(b"commit".to_vec()));
+    log.clear().unwrap();
+    log.clear().unwrap();
+    assert_eq!(log.staged().unwrap(), None);
+}
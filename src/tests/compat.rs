@@ -0,0 +1,167 @@
+use sha3::{Digest, Sha3_256};
+
+use crate::{
+    compat::{
+        seed, Aptos, AptosHasher, Diem, DiemHasher, APTOS_HASH_PREFIX, DIEM_HASH_PREFIX,
+        UPSTREAM_PLACEHOLDER_HASH,
+    },
+    mock::MockTreeStore,
+    DomainSeparators, JellyfishMerkleTree, KeyHash, RootHash,
+};
+
+/// `SPARSE_MERKLE_PLACEHOLDER_HASH` of `aptos_crypto::hash` and `diem_crypto::hash`, which is
+/// also the root hash of their empty trees.
+const UPSTREAM_PLACEHOLDER_HEX: &str =
+    "5350415253455f4d45524b4c455f504c414345484f4c4445525f484153480000";
+
+#[test]
+fn test_compat_seeds() {
+    assert_eq!(
+        Diem::INTERNAL,
+        seed(DIEM_HASH_PREFIX, b"SparseMerkleInternal")
+    );
+    assert_eq!(Diem::LEAF, seed(DIEM_HASH_PREFIX, b"SparseMerkleLeafNode"));
+    assert_eq!(Diem::VALUE, seed(DIEM_HASH_PREFIX, b"AccountStateBlob"));
+    assert_eq!(
+        Aptos::INTERNAL,
+        seed(APTOS_HASH_PREFIX, b"SparseMerkleInternal")
+    );
+    assert_eq!(
+        Aptos::LEAF,
+        seed(APTOS_HASH_PREFIX, b"SparseMerkleLeafNode")
+    );
+    assert!(Aptos::VALUE.is_empty());
+}
+
+/// Hashes the concatenation of `parts` as upstream hashers do, the first part being the seed.
+fn upstream_hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha3_256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+#[test]
+fn test_compat_node_hashes() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, DiemHasher>::new(&db);
+
+    // Two keys on each side of the root, so that the root hashes their two leaves.
+    let left = KeyHash([0x00; 32]);
+    let right = KeyHash([0xff; 32]);
+    let (root_hash, batch) = tree
+        .put_value_set(
+            vec![
+                (left, Some(b"left".to_vec())),
+                (right, Some(b"right".to_vec())),
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let leaf_hash = |key: KeyHash, value: &[u8]| {
+        let value_hash = upstream_hash(&[Diem::VALUE, value]);
+        upstream_hash(&[Diem::LEAF, &key.0, &value_hash])
+    };
+    let left_hash = leaf_hash(left, b"left");
+    let right_hash = leaf_hash(right, b"right");
+    assert_eq!(
+        root_hash.0,
+        upstream_hash(&[Diem::INTERNAL, &left_hash, &right_hash])
+    );
+
+    let (value, proof) = tree.get_with_proof(left, 0).unwrap();
+    assert_eq!(proof.siblings().len(), 1);
+    proof.verify(root_hash, left, value).unwrap();
+
+    let aptos_db = MockTreeStore::default();
+    let aptos_tree = JellyfishMerkleTree::<_, AptosHasher>::new(&aptos_db);
+    let (aptos_root_hash, _) = aptos_tree
+        .put_value_set(vec![(left, Some(b"left".to_vec()))], 0)
+        .unwrap();
+    let value_hash = upstream_hash(&[b"left"]);
+    assert_eq!(
+        aptos_root_hash.0,
+        upstream_hash(&[Aptos::LEAF, &left.0, &value_hash])
+    );
+}
+
+#[test]
+fn test_compat_empty_tree() {
+    assert_eq!(
+        hex::encode(UPSTREAM_PLACEHOLDER_HASH),
+        UPSTREAM_PLACEHOLDER_HEX
+    );
+    assert_eq!(Diem::PLACEHOLDER, UPSTREAM_PLACEHOLDER_HASH);
+    assert_eq!(Aptos::PLACEHOLDER, UPSTREAM_PLACEHOLDER_HASH);
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, DiemHasher>::new(&db);
+    let (root_hash, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(hex::encode(root_hash.0), UPSTREAM_PLACEHOLDER_HEX);
+
+    let key = KeyHash([0x42; 32]);
+    let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+    assert!(value.is_none());
+    proof.verify_nonexistence(root_hash, key).unwrap();
+}
+
+#[test]
+fn test_compat_placeholder_siblings() {
+    // The first nibbles of the keys are 0 and 1, so their leaves only meet at the fourth bit of
+    // the root, whose three upper binary nodes each have an empty subtree as their right child.
+    let left = KeyHash([0x00; 32]);
+    let mut right = KeyHash([0x00; 32]);
+    right.0[0] = 0x10;
+    let absent = KeyHash([0x80; 32]);
+    let values = [(left, b"left".to_vec()), (right, b"right".to_vec())];
+
+    let placeholder = UPSTREAM_PLACEHOLDER_HASH;
+    let expected_root_hash = |internal: &[u8], leaf: &[u8], value_seed: &[u8]| {
+        let leaf_hash = |key: KeyHash, value: &[u8]| {
+            let value_hash = upstream_hash(&[value_seed, value]);
+            upstream_hash(&[leaf, &key.0, &value_hash])
+        };
+        let mut hash = upstream_hash(&[
+            internal,
+            &leaf_hash(left, b"left"),
+            &leaf_hash(right, b"right"),
+        ]);
+        for _ in 0..3 {
+            hash = upstream_hash(&[internal, &hash, &placeholder]);
+        }
+        RootHash(hash)
+    };
+
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, DiemHasher>::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(values.iter().map(|(k, v)| (*k, Some(v.clone()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        root_hash,
+        expected_root_hash(Diem::INTERNAL, Diem::LEAF, Diem::VALUE)
+    );
+    let (value, proof) = tree.get_with_proof(left, 0).unwrap();
+    assert_eq!(proof.siblings()[1..], [placeholder; 3]);
+    proof.verify(root_hash, left, value).unwrap();
+    let (value, proof) = tree.get_with_proof(absent, 0).unwrap();
+    proof.verify(root_hash, absent, value).unwrap();
+
+    let aptos_db = MockTreeStore::default();
+    let aptos_tree = JellyfishMerkleTree::<_, AptosHasher>::new(&aptos_db);
+    let (aptos_root_hash, batch) = aptos_tree
+        .put_value_set(values.iter().map(|(k, v)| (*k, Some(v.clone()))), 0)
+        .unwrap();
+    aptos_db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        aptos_root_hash,
+        expected_root_hash(Aptos::INTERNAL, Aptos::LEAF, Aptos::VALUE)
+    );
+    let (value, proof) = aptos_tree.get_with_proof(right, 0).unwrap();
+    proof.verify(aptos_root_hash, right, value).unwrap();
+}
@@ -0,0 +1,57 @@
+use sha2::Sha256;
+
+use crate::{
+    cost::{measure, Cost, CostCounter, CountingHasher},
+    mock::MockTreeStore,
+    node_type::NodeKey,
+    storage::TreeReader,
+    JellyfishMerkleTree, KeyHash,
+};
+
+type Hasher = CountingHasher<Sha256>;
+
+#[test]
+fn test_cost() {
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    let value_set = || keys.iter().map(|key| (*key, Some(vec![1])));
+
+    let db = CostCounter::new(MockTreeStore::default());
+    let (result, put_cost) =
+        measure(|| JellyfishMerkleTree::<_, Hasher>::new(&db).put_value_set(value_set(), 0));
+    let (root, batch) = result.unwrap();
+    db.inner().write_tree_update_batch(batch).unwrap();
+    assert!(put_cost.hashes >= keys.len() as u64);
+    assert_eq!(put_cost.value_reads, 0);
+
+    // Counting does not change the tree.
+    let (expected_root, _) = JellyfishMerkleTree::<_, Sha256>::new(&MockTreeStore::default())
+        .put_value_set(value_set(), 0)
+        .unwrap();
+    assert_eq!(root, expected_root);
+
+    let tree = JellyfishMerkleTree::<_, Hasher>::new(&db);
+    let ((result, read_cost), outer_cost) = measure(|| measure(|| tree.get_with_proof(keys[3], 0)));
+    let (value, proof) = result.unwrap();
+    assert_eq!(read_cost, outer_cost);
+    assert!(read_cost.node_reads >= 2);
+    assert_eq!(read_cost.value_reads, 1);
+
+    // Verifying hashes the value, the leaf, and one internal node per sibling.
+    let (result, verify_cost) = measure(|| proof.verify(root, keys[3], value));
+    result.unwrap();
+    assert_eq!(
+        verify_cost,
+        Cost {
+            hashes: 2 + proof.siblings().len() as u64,
+            node_reads: 0,
+            value_reads: 0,
+        }
+    );
+
+    let (nodes, batch_cost) = measure(|| {
+        db.get_nodes(&[NodeKey::new_empty_path(0), NodeKey::new_empty_path(1)])
+            .unwrap()
+    });
+    assert_eq!(nodes.iter().filter(|node| node.is_some()).count(), 1);
+    assert_eq!(batch_cost.node_reads, 2);
+}
@@ -0,0 +1,49 @@
+use sha2::Sha256;
+
+use crate::{default_value::DefaultValueTree, mock::MockTreeStore, JellyfishMerkleTree, KeyHash};
+
+#[test]
+fn test_default_value_tree() {
+    let db = MockTreeStore::default();
+    let zero = 0u64.to_be_bytes().to_vec();
+    let tree = DefaultValueTree::<_, Sha256>::new(&db, zero.clone());
+    let alice = KeyHash::with::<Sha256>(b"alice");
+    let bob = KeyHash::with::<Sha256>(b"bob");
+    let carol = KeyHash::with::<Sha256>(b"carol");
+
+    let (root_hash, batch) = tree
+        .put_value_set(
+            [(alice, 10u64.to_be_bytes().to_vec()), (bob, zero.clone())],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(alice, 0).unwrap(), 10u64.to_be_bytes().to_vec());
+    assert_eq!(tree.get(bob, 0).unwrap(), zero);
+    assert_eq!(tree.get(carol, 0).unwrap(), zero);
+    // Default values are not stored.
+    assert_eq!(tree.inner().get(bob, 0).unwrap(), None);
+
+    for key in [alice, bob, carol] {
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        proof
+            .verify_with_default(root_hash, key, &value, &zero)
+            .unwrap();
+        assert!(proof
+            .verify_with_default(root_hash, key, 20u64.to_be_bytes(), &zero)
+            .is_err());
+    }
+    let (_, proof) = tree.get_with_proof(alice, 0).unwrap();
+    assert!(proof
+        .verify_with_default(root_hash, alice, &zero, &zero)
+        .is_err());
+
+    // Putting the default value removes the key, so the root only depends on other values.
+    let (root_hash, batch) = tree.put_value_set([(alice, zero.clone())], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(alice, 1).unwrap(), zero);
+    let (empty_root_hash, _) = JellyfishMerkleTree::<_, Sha256>::new(&MockTreeStore::default())
+        .put_value_set([], 0)
+        .unwrap();
+    assert_eq!(root_hash, empty_root_hash);
+}
@@ -0,0 +1,73 @@
+use anyhow::Context;
+use sha2::Sha256;
+
+use crate::{
+    light::RootTracker,
+    mock::MockTreeStore,
+    pruner::{HasVersionPins, Pruner},
+    storage::{NodeKey, TreeReader},
+    JellyfishMerkleTree, JmtError, KeyHash, RootHash,
+};
+
+#[test]
+fn test_jmt_error() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    for version in 0..3 {
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter().map(|key| (*key, Some(vec![version as u8]))),
+                version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    Pruner::new(&db)
+        .with_version_pins(db.version_pins())
+        .prune(2)
+        .unwrap();
+
+    let error = tree.pin_version(1).err().unwrap();
+    assert!(matches!(
+        JmtError::find(&error),
+        Some(JmtError::VersionPruned {
+            version: 1,
+            least_readable_version: 2,
+        })
+    ));
+
+    // The nodes of a pruned version are missing.
+    let root_key = NodeKey::new_empty_path(1);
+    let error = db.get_node(&root_key).err().unwrap();
+    assert!(matches!(
+        JmtError::find(&error),
+        Some(JmtError::MissingNode { node_key }) if *node_key == root_key
+    ));
+
+    // The error is still found once context is added to it.
+    let (value, proof) = tree.get_with_proof(keys[0], 2).unwrap();
+    let error = proof
+        .verify(RootHash([0; 32]), keys[0], value.as_ref())
+        .context("Verifying the proof of key 0")
+        .err()
+        .unwrap();
+    assert!(matches!(
+        JmtError::find(&error),
+        Some(JmtError::InvalidProof(_))
+    ));
+    assert!(JmtError::find(&anyhow::anyhow!("Other error.")).is_none());
+
+    // Verifiers other than proofs report their failures the same way.
+    let mut root_tracker = RootTracker::new(2, tree.get_root_hash(2).unwrap());
+    let error = root_tracker.checkpoint(2, RootHash([0; 32])).err().unwrap();
+    assert!(matches!(
+        JmtError::find(&error),
+        Some(JmtError::InvalidProof(_))
+    ));
+
+    // The error of a storage stays reachable as the source of the `JmtError`.
+    let error = anyhow::Error::from(JmtError::storage(std::io::Error::other("Disk failure.")));
+    let storage_error = std::error::Error::source(JmtError::find(&error).unwrap()).unwrap();
+    assert_eq!(storage_error.to_string(), "Disk failure.");
+}
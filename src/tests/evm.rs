@@ -0,0 +1,59 @@
+use crate::{
+    evm::{decode_proof, encode_proof, encoded_len, verify_encoded_proof},
+    mock::MockTreeStore,
+    Keccak256, Keccak256JMT, KeyHash, RootHash,
+};
+
+#[test]
+fn test_evm_proof_encoding() {
+    let db = MockTreeStore::default();
+    let tree = Keccak256JMT::new(&db);
+    let (root_hash, batch) = tree
+        .put_value_set(
+            (0..50u8).map(|i| (KeyHash::with::<Keccak256>([i]), Some(vec![i; 4]))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for i in 0..60u8 {
+        let key = KeyHash::with::<Keccak256>([i]);
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(value.is_some(), i < 50);
+        let bytes = encode_proof(&proof);
+        assert_eq!(bytes.len(), encoded_len(&proof));
+        let decoded = decode_proof(&bytes).unwrap();
+        assert_eq!(
+            (decoded.leaf(), decoded.siblings()),
+            (proof.leaf(), proof.siblings())
+        );
+
+        // The reference verifier agrees with the native one.
+        verify_encoded_proof(&bytes, root_hash, key, value.as_ref()).unwrap();
+        let other_value = Some(vec![i; 3]);
+        assert!(verify_encoded_proof(&bytes, root_hash, key, other_value.as_ref()).is_err());
+        assert!(proof.verify(root_hash, key, other_value.as_ref()).is_err());
+        let other_key = KeyHash::with::<Keccak256>([i, i]);
+        assert_eq!(
+            verify_encoded_proof(&bytes, root_hash, other_key, None::<&[u8]>).is_ok(),
+            proof.verify_nonexistence(root_hash, other_key).is_ok(),
+        );
+        assert!(verify_encoded_proof(&bytes, RootHash([0; 32]), key, value.as_ref()).is_err());
+    }
+
+    // Malformed encodings are rejected.
+    let key = KeyHash::with::<Keccak256>([0]);
+    let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+    let bytes = encode_proof(&proof);
+    for malformed in [
+        bytes[..bytes.len() - 32].to_vec(),
+        bytes[..bytes.len() - 1].to_vec(),
+        [&bytes[..], &[0; 32]].concat(),
+        [&[2], &bytes[1..]].concat(),
+        [&bytes[..1], &[1], &bytes[2..]].concat(),
+        [&bytes[..32], &[0x80], &bytes[33..]].concat(),
+    ] {
+        assert!(decode_proof(&malformed).is_err());
+        assert!(verify_encoded_proof(&malformed, root_hash, key, value.as_ref()).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use sha2::Sha256;
+
+use crate::{
+    fork::ForkedTree, mock::MockTreeStore, JellyfishMerkleTree, KeyHash, OwnedValue, RootHash,
+    Version,
+};
+
+fn value_set(branch: u8) -> Vec<(KeyHash, Option<OwnedValue>)> {
+    (0u8..8)
+        .map(|i| (KeyHash::with::<Sha256>([i]), Some(vec![branch, i])))
+        .collect()
+}
+
+/// Returns the root hashes of the tree after applying `value_sets` as consecutive versions.
+fn linear_root_hashes(value_sets: &[Vec<(KeyHash, Option<OwnedValue>)>]) -> Vec<RootHash> {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let mut root_hashes = vec![];
+    for (version, value_set) in value_sets.iter().enumerate() {
+        let (root_hash, batch) = tree
+            .put_value_set(value_set.clone(), version as Version)
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        root_hashes.push(root_hash);
+    }
+    root_hashes
+}
+
+#[test]
+fn test_forked_tree() {
+    let db = MockTreeStore::default();
+    let (_, batch) = JellyfishMerkleTree::<_, Sha256>::new(&db)
+        .put_value_set(value_set(0), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let mut forks = ForkedTree::<_, Sha256>::new(&db);
+    let (a, root_a) = forks.branch(None, value_set(1), 1).unwrap();
+    let (b, root_b) = forks.branch(None, value_set(2), 1).unwrap();
+    let (a1, root_a1) = forks.branch(Some(a), value_set(3), 2).unwrap();
+    let (b1, _) = forks.branch(Some(b), value_set(4), 2).unwrap();
+    assert!(forks.branch(Some(b1), value_set(5), 4).is_err());
+
+    let expected_a = linear_root_hashes(&[value_set(0), value_set(1), value_set(3)]);
+    let expected_b = linear_root_hashes(&[value_set(0), value_set(2)]);
+    assert_eq!(root_a, expected_a[1]);
+    assert_eq!(root_a1, expected_a[2]);
+    assert_eq!(root_b, expected_b[1]);
+
+    // Each branch reads its own state.
+    let key = KeyHash::with::<Sha256>([5]);
+    for (branch, root_hash, value) in [(a1, root_a1, vec![3, 5]), (b, root_b, vec![2, 5])] {
+        let reader = forks.reader_at(Some(branch)).unwrap();
+        let tree = JellyfishMerkleTree::<_, Sha256>::new(&reader);
+        let version = forks.version(branch).unwrap();
+        let (value_read, proof) = tree.get_with_proof(key, version).unwrap();
+        assert_eq!(value_read, Some(value));
+        proof.verify(root_hash, key, value_read).unwrap();
+    }
+
+    let finalized = forks.finalize(a).unwrap();
+    assert_eq!(finalized.len(), 1);
+    assert_eq!((finalized[0].0, finalized[0].1), (1, root_a));
+    assert_eq!(forks.branches().collect::<Vec<_>>(), vec![a1]);
+    assert_eq!(forks.parent(a1).unwrap(), None);
+    assert!(forks.root_hash(b).is_err());
+    for (_, _, batch) in finalized {
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    let (a2, _) = forks.branch(Some(a1), value_set(6), 3).unwrap();
+    forks.discard(a2).unwrap();
+    let (_, _, batch) = forks.finalize(a1).unwrap().pop().unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(forks.branches().next().is_none());
+    assert_eq!(
+        JellyfishMerkleTree::<_, Sha256>::new(&db)
+            .get_root_hash(2)
+            .unwrap(),
+        root_a1
+    );
+}
@@ -3,95 +3,34 @@
 
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
-    fmt::Debug,
     ops::Bound,
     sync::Arc,
 };
 
 use proptest::{
-    collection::{btree_map, hash_map, vec},
+    collection::{btree_map, vec},
     prelude::*,
     sample,
 };
 use sha2::Sha256;
 
+pub use crate::testing::{
+    arb_existent_kvs_and_deletions_and_nonexistent_keys, arb_existent_kvs_and_nonexistent_keys,
+    arb_interleaved_insertions_and_deletions, arb_partitions, init_mock_db,
+    init_mock_db_with_deletions_afterwards, plus_one,
+};
 use crate::{
     mock::MockTreeStore,
     node_type::LeafNode,
     storage::Node,
     types::{
-        proof::{SparseMerkleInternalNode, SparseMerkleRangeProof},
+        proof::{SparseMerkleInternalNode, SparseMerkleProof, SparseMerkleRangeProof},
         Version, PRE_GENESIS_VERSION,
     },
     Bytes32Ext, JellyfishMerkleIterator, JellyfishMerkleTree, KeyHash, OwnedValue, RootHash,
     Sha256JMT, ValueHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
 
-/// Computes the key immediately after `key`.
-pub fn plus_one(key: KeyHash) -> KeyHash {
-    assert_ne!(key, KeyHash([0xff; 32]));
-
-    let mut buf = key.0;
-    for i in (0..32).rev() {
-        if buf[i] == 255 {
-            buf[i] = 0;
-        } else {
-            buf[i] += 1;
-            break;
-        }
-    }
-    KeyHash(buf)
-}
-
-/// Initializes a DB with a set of key-value pairs by inserting one key at each version.
-pub fn init_mock_db(kvs: &HashMap<KeyHash, OwnedValue>) -> (MockTreeStore, Version) {
-    assert!(!kvs.is_empty());
-
-    let db = MockTreeStore::default();
-    let tree = Sha256JMT::new(&db);
-
-    for (i, (key, value)) in kvs.clone().into_iter().enumerate() {
-        let (_root_hash, write_batch) = tree
-            .put_value_set(vec![(key, Some(value))], i as Version)
-            .unwrap();
-        db.write_tree_update_batch(write_batch).unwrap();
-    }
-
-    (db, (kvs.len() - 1) as Version)
-}
-
-/// Initializes a DB with a set of key-value pairs by inserting one key at each version, then
-/// deleting the specified keys afterwards.
-pub fn init_mock_db_with_deletions_afterwards(
-    kvs: &HashMap<KeyHash, OwnedValue>,
-    deletions: Vec<KeyHash>,
-) -> (MockTreeStore, Version) {
-    assert!(!kvs.is_empty());
-
-    let db = MockTreeStore::default();
-    let tree = Sha256JMT::new(&db);
-
-    for (i, (key, value)) in kvs.clone().into_iter().enumerate() {
-        let (_root_hash, write_batch) = tree
-            .put_value_set(vec![(key, Some(value))], i as Version)
-            .unwrap();
-        db.write_tree_update_batch(write_batch).unwrap();
-    }
-
-    let after_insertions_version = kvs.len();
-
-    for (i, key) in deletions.iter().enumerate() {
-        let (_root_hash, write_batch) = tree
-            .put_value_set(
-                vec![(*key, None)],
-                (after_insertions_version + i) as Version,
-            )
-            .unwrap();
-        db.write_tree_update_batch(write_batch).unwrap();
-    }
-    (db, (kvs.len() + deletions.len() - 1) as Version)
-}
-
 fn init_mock_db_versioned(
     operations_by_version: Vec<Vec<(KeyHash, Vec<u8>)>>,
 ) -> (MockTreeStore, Version) {
@@ -158,149 +97,6 @@ fn init_mock_db_versioned_with_deletions(
     }
 }
 
-pub fn arb_existent_kvs_and_nonexistent_keys(
-    num_kvs: usize,
-    num_non_existing_keys: usize,
-) -> impl Strategy<Value = (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>)> {
-    hash_map(any::<KeyHash>(), any::<OwnedValue>(), 1..num_kvs).prop_flat_map(move |kvs| {
-        let kvs_clone = kvs.clone();
-        (
-            Just(kvs),
-            vec(
-                any::<KeyHash>().prop_filter(
-                    "Make sure these keys do not exist in the tree.",
-                    move |key| !kvs_clone.contains_key(key),
-                ),
-                num_non_existing_keys,
-            ),
-        )
-    })
-}
-
-pub fn arb_existent_kvs_and_deletions_and_nonexistent_keys(
-    num_kvs: usize,
-    num_non_existing_keys: usize,
-) -> impl Strategy<Value = (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>, Vec<KeyHash>)> {
-    hash_map(any::<KeyHash>(), any::<OwnedValue>(), 1..num_kvs).prop_flat_map(move |kvs| {
-        let kvs_clone = kvs.clone();
-        let keys: Vec<_> = kvs.keys().cloned().collect();
-        let keys_count = keys.len();
-        (
-            Just(kvs),
-            sample::subsequence(keys, 0..keys_count),
-            vec(
-                any::<KeyHash>().prop_filter(
-                    "Make sure these keys do not exist in the tree.",
-                    move |key| !kvs_clone.contains_key(key),
-                ),
-                num_non_existing_keys,
-            ),
-        )
-    })
-}
-
-pub fn arb_interleaved_insertions_and_deletions(
-    num_keys: usize,
-    num_values: usize,
-    num_insertions: usize,
-    num_deletions: usize,
-) -> impl Strategy<Value = Vec<(KeyHash, Option<OwnedValue>)>> {
-    // Make a hash set of all the keys and a vector of all the values we'll use in this test
-    (
-        // Key hashes are the sequential set of keys up to num_keys, but shuffled so that we don't
-        // use them in order
-        Just(
-            (0..num_keys)
-                .map(|n| KeyHash::with::<Sha256>(n.to_le_bytes()))
-                .collect::<Vec<_>>(),
-        )
-        .prop_shuffle(),
-        // Values are sequential little-endian byte sequences starting from 0, with trailing zeroes
-        // trimmed -- it doesn't really matter what they are for these tests, so we just use the
-        // smallest distinct sequences we can
-        (1..=num_values).prop_map(|end| {
-            (0..end)
-                .map(|i| {
-                    let mut value = i.to_le_bytes().to_vec();
-                    while let Some(byte) = value.last() {
-                        if *byte != 0 {
-                            break;
-                        }
-                        value.pop();
-                    }
-                    value
-                })
-                .collect::<Vec<_>>()
-        }),
-    )
-        .prop_flat_map(move |(keys, values)| {
-            // Create a random sequence of insertions using only the keys and values in the sets
-            // (this permits keys to be inserted more than once, and with different values)
-            vec(
-                (sample::select(keys), sample::select(values).prop_map(Some)),
-                1..num_insertions,
-            )
-            .prop_flat_map(move |insertions| {
-                // Create a random sequence of deletions using only the keys that were actually inserted
-                // (this permits keys to be deleted more than once, but not more times than they will
-                // ever be inserted, though they may be deleted before they are inserted, in the end)
-                let deletions = sample::subsequence(
-                    insertions
-                        .iter()
-                        .map(|(key, _)| (*key, None))
-                        .collect::<Vec<_>>(),
-                    0..num_deletions.min(insertions.len()),
-                );
-                (Just(insertions), deletions)
-            })
-            .prop_flat_map(move |(insertions, deletions)| {
-                // Shuffle together the insertions and the deletions into a single sequence
-                let mut insertions_and_deletions = insertions;
-                insertions_and_deletions.extend(deletions);
-                Just(insertions_and_deletions).prop_shuffle()
-            })
-        })
-}
-
-/// Divide a vector into arbitrary partitions of size >= 1. If the number of partitions exceeds the
-/// length of the vector, it is divided into size 1 partitions.
-pub fn arb_partitions<T>(
-    num_partitions: usize,
-    values: Vec<T>,
-) -> impl Strategy<Value = Vec<Vec<T>>>
-where
-    T: Debug + Clone,
-{
-    assert_ne!(
-        num_partitions, 0,
-        "cannot partition a vector into 0 partitions"
-    );
-
-    let indices = sample::subsequence(
-        (0..=values.len()).collect::<Vec<_>>(),
-        num_partitions.min(values.len()) - 1,
-    );
-
-    indices.prop_map(move |indices| {
-        let mut partitions = Vec::with_capacity(num_partitions);
-        let mut start = 0;
-        for end in indices {
-            if end - start > 0 {
-                partitions.push(values[start..end].to_vec());
-            } else {
-                partitions.push(vec![]);
-            }
-            start = end;
-        }
-
-        // Anything that hasn't yet been put into the partitions, put it in the last chunk
-        let remainder = values[start..].to_vec();
-        partitions.push(remainder);
-
-        partitions
-    })
-}
-
 pub fn test_get_with_proof(
     (existent_kvs, nonexistent_keys): (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>),
 ) {
@@ -311,6 +107,50 @@ pub fn test_get_with_proof(
     test_nonexistent_keys_impl(&tree, version, &nonexistent_keys);
 }
 
+pub fn test_compact_proof_encoding(
+    (existent_kvs, nonexistent_keys): (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>),
+) {
+    let (db, version) = init_mock_db(&existent_kvs);
+    let tree = Sha256JMT::new(&db);
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    for key in existent_kvs.keys().chain(&nonexistent_keys) {
+        let (value, proof) = tree.get_with_proof(*key, version).unwrap();
+        let bytes = proof.to_compact_bytes();
+        let decoded = SparseMerkleProof::<Sha256>::from_compact_bytes(&bytes).unwrap();
+        assert_eq!(decoded.leaf(), proof.leaf());
+        assert_eq!(decoded.siblings(), proof.siblings());
+        decoded.verify(root_hash, *key, value.as_ref()).unwrap();
+        SparseMerkleProof::<Sha256>::verify_compact_bytes(&bytes, root_hash, *key, value.as_ref())
+            .unwrap();
+        // Placeholder siblings only take a bit of the bitmap.
+        let non_placeholder_siblings = proof
+            .siblings()
+            .iter()
+            .filter(|sibling| **sibling != SPARSE_MERKLE_PLACEHOLDER_HASH)
+            .count();
+        assert_eq!(
+            bytes.len(),
+            1 + proof.leaf().map_or(0, |_| 64)
+                + 2
+                + proof.siblings().len().div_ceil(8)
+                + non_placeholder_siblings * 32
+        );
+
+        // Any truncation must be detected.
+        assert!(
+            SparseMerkleProof::<Sha256>::from_compact_bytes(&bytes[..bytes.len() - 1]).is_err()
+        );
+        assert!(SparseMerkleProof::<Sha256>::verify_compact_bytes(
+            &bytes[..bytes.len() - 1],
+            root_hash,
+            *key,
+            value.as_ref()
+        )
+        .is_err());
+    }
+}
+
 pub fn test_get_with_proof_with_deletions(
     (mut existent_kvs, deletions, mut nonexistent_keys): (
         HashMap<KeyHash, OwnedValue>,
@@ -331,6 +171,48 @@ pub fn test_get_with_proof_with_deletions(
     test_nonexistent_keys_impl(&tree, version, &nonexistent_keys);
 }
 
+pub fn test_get_with_multiproof(
+    (existent_kvs, nonexistent_keys): (HashMap<KeyHash, OwnedValue>, Vec<KeyHash>),
+) {
+    let (db, version) = init_mock_db(&existent_kvs);
+    let tree: JellyfishMerkleTree<MockTreeStore, Sha256> = JellyfishMerkleTree::new(&db);
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    let keys: Vec<KeyHash> = existent_kvs
+        .keys()
+        .chain(nonexistent_keys.iter())
+        .copied()
+        .collect();
+    let (values, proof) = tree.get_with_multiproof(&keys, version).unwrap();
+    for (key, value) in keys.iter().zip(values.iter()) {
+        assert_eq!(value.as_ref(), existent_kvs.get(key));
+    }
+    proof.verify(root_hash, &keys, &values).unwrap();
+
+    // The multiproof never carries more siblings than the individual proofs combined.
+    let individual_siblings: usize = keys
+        .iter()
+        .map(|key| {
+            tree.get_with_proof(*key, version)
+                .unwrap()
+                .1
+                .siblings()
+                .len()
+        })
+        .sum();
+    assert!(proof.siblings().len() <= individual_siblings);
+
+    // Claiming a wrong value for any key must fail verification.
+    if let Some(first) = values.first() {
+        let mut wrong_values = values.clone();
+        wrong_values[0] = match first {
+            Some(_) => None,
+            None => Some(vec![]),
+        };
+        assert!(proof.verify(root_hash, &keys, &wrong_values).is_err());
+    }
+}
+
 /// A very general test that demonstrates that given a sequence of insertions and deletions, batched
 /// by version, the end result of having performed those operations is identical to having *already
 /// known* what the end result would be, and only performing the insertions necessary to get there,
@@ -419,7 +301,7 @@ pub fn test_clairvoyant_construction_matches_interleaved_construction(
             // the null node, since it should contain nothing
             assert_eq!(
                 root_hash_with_deletions.unwrap(),
-                RootHash(Node::Null.hash())
+                RootHash(Node::Null.hash::<Sha256>())
             );
         }
         (false, true) => {
@@ -431,7 +313,7 @@ pub fn test_clairvoyant_construction_matches_interleaved_construction(
             // the null node, since it should contain nothing
             assert_eq!(
                 root_hash_without_deletions.unwrap(),
-                RootHash(Node::Null.hash())
+                RootHash(Node::Null.hash::<Sha256>())
             );
         }
     }
@@ -520,6 +402,71 @@ pub fn arb_tree_with_index(
     })
 }
 
+pub fn arb_tree_with_interval(
+    tree_size: usize,
+) -> impl Strategy<Value = (BTreeMap<KeyHash, OwnedValue>, KeyHash, KeyHash)> {
+    btree_map(any::<KeyHash>(), any::<OwnedValue>(), 1..tree_size).prop_flat_map(|btree| {
+        // Bounds are either existing keys or arbitrary ones, which almost never exist.
+        let keys: Vec<KeyHash> = btree.keys().copied().collect();
+        let arb_bound = prop_oneof![sample::select(keys), any::<KeyHash>()];
+        (Just(btree), arb_bound.clone(), arb_bound).prop_map(|(btree, x, y)| {
+            let (start, end) = if x <= y { (x, y) } else { (y, x) };
+            (btree, start, end)
+        })
+    })
+}
+
+pub fn test_get_interval_proof(
+    (btree, start, end): (BTreeMap<KeyHash, OwnedValue>, KeyHash, KeyHash),
+) {
+    let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+    let tree = Sha256JMT::new(&db);
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    let (leaves, proof) = tree.get_interval_proof(start, end, version).unwrap();
+    let expected: Vec<(KeyHash, OwnedValue)> = btree
+        .range(start..=end)
+        .map(|(key, value)| (*key, value.clone()))
+        .collect();
+    assert_eq!(leaves, expected);
+    proof.verify(root_hash, start, end, &leaves).unwrap();
+
+    // Leaving out any leaf must fail verification.
+    for i in 0..leaves.len() {
+        let mut incomplete = leaves.clone();
+        incomplete.remove(i);
+        assert!(proof.verify(root_hash, start, end, &incomplete).is_err());
+    }
+}
+
+pub fn test_get_range_with_proof(
+    (btree, start, limit): (BTreeMap<KeyHash, OwnedValue>, KeyHash, usize),
+) {
+    let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
+    let tree = Sha256JMT::new(&db);
+    let root_hash = tree.get_root_hash(version).unwrap();
+
+    let (leaves, proof) = tree.get_range_with_proof(start, limit, version).unwrap();
+    let expected: Vec<(KeyHash, OwnedValue)> = btree
+        .range(start..)
+        .take(limit)
+        .map(|(key, value)| (*key, value.clone()))
+        .collect();
+    assert_eq!(leaves, expected);
+    proof
+        .verify_range(root_hash, start, limit, &leaves)
+        .unwrap();
+
+    // Leaving out any leaf must fail verification, including the last one of a full page.
+    for i in 0..leaves.len() {
+        let mut incomplete = leaves.clone();
+        incomplete.remove(i);
+        assert!(proof
+            .verify_range(root_hash, start, limit, &incomplete)
+            .is_err());
+    }
+}
+
 pub fn test_get_range_proof((btree, n): (BTreeMap<KeyHash, OwnedValue>, usize)) {
     let (db, version) = init_mock_db(&btree.clone().into_iter().collect());
     let tree = Sha256JMT::new(&db);
@@ -566,7 +513,7 @@ fn test_nonexistent_keys_impl<'a>(
 fn verify_range_proof(
     expected_root_hash: RootHash,
     btree: BTreeMap<KeyHash, OwnedValue>,
-    proof: SparseMerkleRangeProof,
+    proof: SparseMerkleRangeProof<Sha256>,
 ) {
     // For example, given the following sparse Merkle tree:
     //
@@ -603,7 +550,7 @@ fn verify_range_proof(
     let mut btree1 = BTreeMap::new();
     for (key, value) in &btree {
         let leaf = LeafNode::new(*key, ValueHash::with::<Sha256>(value.as_slice()));
-        btree1.insert(*key, leaf.hash());
+        btree1.insert(*key, leaf.hash::<Sha256>());
     }
     // Using the above example, `last_proven_key` is `e`. We look at the path from root to `e`.
     // For each 0-bit, there should be a sibling in the proof. And we use the path from root to
@@ -722,7 +669,7 @@ fn compute_root_hash_impl(kvs: Vec<(&[bool], [u8; 32])>) -> [u8; 32] {
         }
     }
 
-    SparseMerkleInternalNode::new(left_hash, right_hash).hash()
+    SparseMerkleInternalNode::new(left_hash, right_hash).hash::<Sha256>()
 }
 
 pub fn test_get_leaf_count(keys: HashSet<KeyHash>) {
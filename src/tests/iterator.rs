@@ -106,6 +106,17 @@ fn run_tests(db: Arc<MockTreeStore>, btree: &BTreeMap<KeyHash, OwnedValue>, vers
         );
     }
 
+    {
+        let tree = Sha256JMT::new(&*db);
+        assert_eq!(
+            tree.iter(version)
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            btree.clone().into_iter().collect::<Vec<_>>(),
+        );
+    }
+
     for i in 0..btree.len() {
         {
             let iter = JellyfishMerkleIterator::new_by_index(Arc::clone(&db), version, i).unwrap();
@@ -125,6 +136,17 @@ fn run_tests(db: Arc<MockTreeStore>, btree: &BTreeMap<KeyHash, OwnedValue>, vers
             );
         }
 
+        {
+            let tree = Sha256JMT::new(&*db);
+            assert_eq!(
+                tree.iter_from(ith_key, version)
+                    .unwrap()
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap(),
+                btree.clone().into_iter().skip(i).collect::<Vec<_>>(),
+            );
+        }
+
         {
             let ith_key_plus_one = plus_one(ith_key);
             let iter =
@@ -148,3 +170,38 @@ fn run_tests(db: Arc<MockTreeStore>, btree: &BTreeMap<KeyHash, OwnedValue>, vers
         assert_eq!(iter.collect::<Result<Vec<_>>>().unwrap(), vec![]);
     }
 }
+
+#[test]
+fn test_iter_from_pagination() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let btree: BTreeMap<KeyHash, OwnedValue> = (0..100u32)
+        .map(|i| {
+            (
+                KeyHash::with::<Sha256>(i.to_be_bytes()),
+                i.to_be_bytes().to_vec(),
+            )
+        })
+        .collect();
+    let (_root_hash, batch) = tree
+        .put_value_set(btree.iter().map(|(k, v)| (*k, Some(v.clone()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let mut pages = vec![];
+    let mut next_key = Some(KeyHash([0; 32]));
+    while let Some(starting_key) = next_key {
+        let page = tree
+            .iter_from(starting_key, 0)
+            .unwrap()
+            .take(7)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        next_key = match page.last() {
+            Some((last_key, _)) if *last_key != KeyHash([0xFF; 32]) => Some(plus_one(*last_key)),
+            _ => None,
+        };
+        pages.extend(page);
+    }
+    assert_eq!(pages, btree.into_iter().collect::<Vec<_>>());
+}
@@ -8,26 +8,32 @@ use sha2::Sha256;
 use crate::{
     mock::MockTreeStore,
     node_type::{Child, Children, Node, NodeKey, NodeType},
-    storage::{TreeReader, TreeUpdateBatch},
+    storage::{
+        NodeBatch, NodeBatchEntry, NodeKind, StaleNodeKind, TreeReader, TreeUpdateBatch, TreeWriter,
+    },
     tests::helper::{
         arb_existent_kvs_and_deletions_and_nonexistent_keys, arb_existent_kvs_and_nonexistent_keys,
         arb_interleaved_insertions_and_deletions, arb_kv_pair_with_distinct_last_nibble,
-        arb_partitions, arb_tree_with_index,
-        test_clairvoyant_construction_matches_interleaved_construction, test_get_leaf_count,
-        test_get_range_proof, test_get_with_proof, test_get_with_proof_with_deletions,
+        arb_partitions, arb_tree_with_index, arb_tree_with_interval,
+        test_clairvoyant_construction_matches_interleaved_construction,
+        test_compact_proof_encoding, test_get_interval_proof, test_get_leaf_count,
+        test_get_range_proof, test_get_range_with_proof, test_get_with_multiproof,
+        test_get_with_proof, test_get_with_proof_with_deletions,
         test_get_with_proof_with_distinct_last_nibble,
     },
     types::{
         nibble::{nibble_path::NibblePath, Nibble},
+        proof::{ExclusionProof, SparseMerkleProof},
         Version,
     },
-    JellyfishMerkleTree, KeyHash, MissingRootError, Sha256JMT, SPARSE_MERKLE_PLACEHOLDER_HASH,
+    JellyfishMerkleTree, KeyHash, MissingRootError, ProofSizeEstimate, RootHash, Sha256JMT,
+    TreeStats, SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
 
 fn update_nibble(original_key: &KeyHash, n: usize, nibble: u8) -> KeyHash {
     assert!(nibble < 16);
     let mut key = original_key.0;
-    key[n / 2] = if n % 2 == 0 {
+    key[n / 2] = if n.is_multiple_of(2) {
         key[n / 2] & 0x0f | nibble << 4
     } else {
         key[n / 2] & 0xf0 | nibble
@@ -96,6 +102,11 @@ fn test_insert_at_leaf_with_internal_created() {
         )
         .unwrap();
     assert_eq!(batch.stale_node_index_batch.len(), 1);
+    // The stale index of the replaced leaf tells which key it held.
+    assert_eq!(
+        batch.stale_node_index_batch.first().unwrap().node_kind,
+        StaleNodeKind::Leaf { key_hash: key1 }
+    );
     db.write_tree_update_batch(batch).unwrap();
 
     assert_eq!(tree.get(key1, 0).unwrap().unwrap(), value1);
@@ -112,11 +123,11 @@ fn test_insert_at_leaf_with_internal_created() {
     let mut children = Children::new();
     children.insert(
         Nibble::from(0),
-        Child::new(leaf1.hash(), 1 /* version */, NodeType::Leaf),
+        Child::new(leaf1.hash::<Sha256>(), 1 /* version */, NodeType::Leaf),
     );
     children.insert(
         Nibble::from(15),
-        Child::new(leaf2.hash(), 1 /* version */, NodeType::Leaf),
+        Child::new(leaf2.hash::<Sha256>(), 1 /* version */, NodeType::Leaf),
     );
     let internal = Node::new_internal(children);
     assert_eq!(db.get_node(&NodeKey::new_empty_path(0)).unwrap(), leaf1);
@@ -179,11 +190,11 @@ fn test_insert_at_leaf_with_multiple_internals_created() {
         let mut children = Children::new();
         children.insert(
             Nibble::from(0),
-            Child::new(leaf1.hash(), 1 /* version */, NodeType::Leaf),
+            Child::new(leaf1.hash::<Sha256>(), 1 /* version */, NodeType::Leaf),
         );
         children.insert(
             Nibble::from(1),
-            Child::new(leaf2.hash(), 1 /* version */, NodeType::Leaf),
+            Child::new(leaf2.hash::<Sha256>(), 1 /* version */, NodeType::Leaf),
         );
         Node::new_internal(children)
     };
@@ -193,7 +204,7 @@ fn test_insert_at_leaf_with_multiple_internals_created() {
         children.insert(
             Nibble::from(0),
             Child::new(
-                internal.hash(),
+                internal.hash::<Sha256>(),
                 1, /* version */
                 NodeType::Internal { leaf_count: 2 },
             ),
@@ -489,6 +500,694 @@ fn test_non_existence() {
     }
 }
 
+#[test]
+fn test_get_with_multiproof_shares_siblings() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+
+    // Two keys that only differ in their last nibble share all the siblings above their parent.
+    let key1 = KeyHash([0u8; 32]);
+    let key2 = update_nibble(&key1, 63, 1);
+    let key3 = update_nibble(&key1, 0, 15);
+    let (root_hash, batch) = tree
+        .put_value_set(
+            vec![(key1, Some(vec![1u8])), (key2, Some(vec![2u8]))],
+            0, /* version */
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let keys = [key1, key2, key3, key1];
+    let (values, proof) = tree.get_with_multiproof(&keys, 0).unwrap();
+    assert_eq!(
+        values,
+        vec![Some(vec![1u8]), Some(vec![2u8]), None, Some(vec![1u8])]
+    );
+    proof.verify(root_hash, &keys, &values).unwrap();
+
+    let (_, proof1) = tree.get_with_proof(key1, 0).unwrap();
+    assert_eq!(proof1.siblings().len(), 256);
+    // `key3` is the sibling of `key1` and `key2` at the top level and its path ends right below,
+    // while `key1` and `key2` are each other's sibling at the bottom level. Only the 254 levels
+    // in between need a sibling, shared by both keys.
+    assert_eq!(proof.siblings().len(), 254);
+
+    // Keys must be given in the same order as when the proof was generated.
+    assert!(proof
+        .verify(root_hash, &[key2, key1, key3, key1], &values)
+        .is_err());
+}
+
+#[test]
+fn test_get_deletion_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let keys: Vec<KeyHash> = (0u8..10).map(|i| KeyHash::with::<Sha256>([i])).collect();
+
+    let (root0, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root1, batch) = tree.put_value_set(vec![(keys[3], None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (deleted_value, proof) = tree.get_deletion_proof(keys[3], 1).unwrap();
+    assert_eq!(deleted_value, keys[3].0.to_vec());
+    assert_eq!(
+        (proof.old_root_hash(), proof.new_root_hash()),
+        (root0, root1)
+    );
+    proof.verify(root0, root1, keys[3], &deleted_value).unwrap();
+    assert!(proof.verify(root0, root1, keys[3], b"other").is_err());
+    assert!(proof.verify(root1, root0, keys[3], &deleted_value).is_err());
+    assert!(proof.verify(root0, root1, keys[4], &deleted_value).is_err());
+
+    // Keys which were not deleted at the version have no deletion proof.
+    assert!(tree.get_deletion_proof(keys[4], 1).is_err());
+    assert!(tree
+        .get_deletion_proof(KeyHash::with::<Sha256>([10]), 1)
+        .is_err());
+    assert!(tree.get_deletion_proof(keys[3], 0).is_err());
+}
+
+#[test]
+fn test_diff() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([7; 32]);
+    let keys: Vec<KeyHash> = (0..200).map(|_| KeyHash(rng.gen())).collect();
+
+    let mut expected_state = std::collections::BTreeMap::new();
+    let mut states = vec![];
+    for version in 0..4 {
+        let value_set: Vec<_> = (0..50)
+            .map(|_| {
+                let key = keys[rng.gen_range(0..keys.len())];
+                let value = rng.gen_bool(0.7).then(|| vec![version as u8, rng.gen()]);
+                (key, value)
+            })
+            .collect();
+        let (_, batch) = tree.put_value_set(value_set.clone(), version).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+        for (key, value) in value_set
+            .into_iter()
+            .collect::<std::collections::BTreeMap<_, _>>()
+        {
+            match value {
+                Some(value) => expected_state.insert(key, value),
+                None => expected_state.remove(&key),
+            };
+        }
+        states.push(expected_state.clone());
+    }
+
+    for (old_version, new_version) in [(0, 1), (1, 3), (3, 0), (2, 2)] {
+        let (old_state, new_state) = (&states[old_version], &states[new_version]);
+        let expected_diff: Vec<_> = keys
+            .iter()
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .filter(|key| old_state.get(key) != new_state.get(key))
+            .map(|key| {
+                (
+                    *key,
+                    old_state.get(key).cloned(),
+                    new_state.get(key).cloned(),
+                )
+            })
+            .collect();
+        let diff = tree
+            .diff(old_version as Version, new_version as Version)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(diff, expected_diff);
+    }
+}
+
+#[test]
+fn test_put_value_set_with_update_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([9; 32]);
+    let keys: Vec<KeyHash> = (0..100).map(|_| KeyHash(rng.gen())).collect();
+
+    let mut old_root_hash = RootHash(SPARSE_MERKLE_PLACEHOLDER_HASH);
+    for version in 0..10 {
+        let value_set: Vec<_> = (0..rng.gen_range(1..40))
+            .map(|_| {
+                let key = keys[rng.gen_range(0..keys.len())];
+                let value = rng.gen_bool(0.6).then(|| vec![version as u8, rng.gen()]);
+                (key, value)
+            })
+            .collect();
+        let (root_hash, proof, batch) = tree
+            .put_value_set_with_update_proof(value_set.clone(), version)
+            .unwrap();
+        assert_eq!(
+            root_hash,
+            tree.put_value_set(value_set.clone(), version).unwrap().0
+        );
+        db.write_tree_update_batch(batch).unwrap();
+
+        proof
+            .verify_update(old_root_hash, root_hash, value_set.clone())
+            .unwrap();
+        assert!(proof
+            .verify_update(root_hash, root_hash, value_set.clone())
+            .is_err());
+        let mut tampered = value_set.clone();
+        tampered.last_mut().unwrap().1 = Some(b"tampered".to_vec());
+        assert!(proof
+            .verify_update(old_root_hash, root_hash, tampered)
+            .is_err());
+        old_root_hash = root_hash;
+    }
+
+    // Deleting every key empties the tree.
+    let value_set: Vec<_> = keys.iter().map(|key| (*key, None::<Vec<u8>>)).collect();
+    let (root_hash, proof, _) = tree
+        .put_value_set_with_update_proof(value_set.clone(), 10)
+        .unwrap();
+    assert_eq!(root_hash, RootHash(SPARSE_MERKLE_PLACEHOLDER_HASH));
+    proof
+        .verify_update(old_root_hash, root_hash, value_set)
+        .unwrap();
+}
+
+#[test]
+fn test_get_with_exclusion_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([11; 32]);
+    let mut keys: Vec<KeyHash> = (0..100).map(|_| KeyHash(rng.gen())).collect();
+    keys.sort();
+
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (value, proof) = tree.get_with_exclusion_proof(keys[5], 0).unwrap().unwrap();
+    assert_eq!(value, keys[5].0.to_vec());
+    proof.verify(root, keys[5], Some(value)).unwrap();
+
+    let missing_keys = (0..100)
+        .map(|_| KeyHash(rng.gen()))
+        .chain([KeyHash([0; 32]), KeyHash([0xff; 32])]);
+    for missing_key in missing_keys {
+        let proof = tree
+            .get_with_exclusion_proof(missing_key, 0)
+            .unwrap()
+            .unwrap_err();
+        proof.verify(root, missing_key).unwrap();
+        let position = keys.partition_point(|key| *key < missing_key);
+        match (&proof, position) {
+            (ExclusionProof::Leftmost { .. }, 0) | (ExclusionProof::Rightmost { .. }, 100) => {}
+            (ExclusionProof::Middle { .. }, 1..=99) => {}
+            _ => panic!("Unexpected exclusion proof {:?}", proof),
+        }
+        // The proof only holds for keys in the gap between the neighbors.
+        for key in [
+            keys[position.saturating_sub(2)],
+            keys[(position + 1).min(99)],
+        ] {
+            assert!(proof.verify(root, key).is_err());
+        }
+    }
+
+    // Neighbors which are not adjacent do not prove anything.
+    let proof_of = |key: KeyHash| tree.get_with_proof(key, 0).unwrap().1;
+    let between = KeyHash({
+        let mut key = keys[10].0;
+        key[31] = key[31].wrapping_add(1);
+        key
+    });
+    let proof = ExclusionProof::Middle {
+        leftmost_right_proof: proof_of(keys[12]),
+        rightmost_left_proof: proof_of(keys[10]),
+    };
+    assert!(proof.verify(root, between).is_err());
+    let proof = ExclusionProof::Leftmost {
+        leftmost_right_proof: proof_of(keys[1]),
+    };
+    assert!(proof.verify(root, KeyHash([0; 32])).is_err());
+
+    let empty_db = MockTreeStore::default();
+    let (_, batch) = Sha256JMT::new(&empty_db)
+        .put_value_set(vec![(keys[0], None)], 0)
+        .unwrap();
+    empty_db.write_tree_update_batch(batch).unwrap();
+    assert!(Sha256JMT::new(&empty_db)
+        .get_with_exclusion_proof(keys[0], 0)
+        .is_err());
+}
+
+#[test]
+fn test_get_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([13; 32]);
+    let keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+
+    // A store without values still serves proofs.
+    let nodes_only_db = MockTreeStore::default();
+    let mut node_batch = NodeBatch::default();
+    for (node_key, node) in batch.node_batch.nodes() {
+        node_batch.insert_node(node_key.clone(), node.clone());
+    }
+    nodes_only_db.write_node_batch(&node_batch).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let nodes_only_tree = Sha256JMT::new(&nodes_only_db);
+
+    for key in keys.iter().copied().chain([KeyHash(rng.gen())]) {
+        let (value, expected_proof) = tree.get_with_proof(key, 0).unwrap();
+        let proof = nodes_only_tree.get_proof(key, 0).unwrap();
+        assert_eq!(proof.leaf(), expected_proof.leaf());
+        assert_eq!(proof.siblings(), expected_proof.siblings());
+        proof.verify(root, key, value).unwrap();
+    }
+    assert!(nodes_only_tree.get_with_proof(keys[0], 0).is_err());
+}
+
+#[test]
+fn test_contains_key() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([14; 32]);
+    let keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    let missing_key = KeyHash(rng.gen());
+    assert!(tree.contains_key(keys[0], 0).is_err());
+
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(keys[..25].iter().map(|key| (*key, None)), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for (i, key) in keys.iter().enumerate() {
+        assert!(tree.contains_key(*key, 0).unwrap());
+        assert_eq!(tree.contains_key(*key, 1).unwrap(), i >= 25);
+    }
+    assert!(!tree.contains_key(missing_key, 0).unwrap());
+    assert!(!tree.contains_key(missing_key, 1).unwrap());
+}
+
+#[test]
+fn test_multi_get() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([15; 32]);
+    let keys: Vec<KeyHash> = (0..200).map(|_| KeyHash(rng.gen())).collect();
+    assert!(tree.multi_get(&keys, 0).is_err());
+    assert_eq!(tree.multi_get(&[], 0).unwrap(), vec![]);
+
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(keys[..100].iter().map(|key| (*key, None)), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Existing, deleted, missing and repeated keys, in no particular order.
+    let mut queried_keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    queried_keys.extend(keys.iter().step_by(3));
+    queried_keys.extend(keys.iter().step_by(7));
+    queried_keys.reverse();
+    for version in [0, 1] {
+        let expected: Vec<_> = queried_keys
+            .iter()
+            .map(|key| tree.get(*key, version).unwrap())
+            .collect();
+        assert_eq!(tree.multi_get(&queried_keys, version).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_multi_get_with_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([16; 32]);
+    let keys: Vec<KeyHash> = (0..200).map(|_| KeyHash(rng.gen())).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(keys[..100].iter().map(|key| (*key, None)), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Existing, deleted, missing and repeated keys, in no particular order.
+    let mut queried_keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    queried_keys.extend(keys.iter().step_by(3));
+    queried_keys.extend(keys.iter().step_by(7));
+    queried_keys.reverse();
+    for version in [0, 1] {
+        let root_hash = tree.get_root_hash(version).unwrap();
+        let results = tree.multi_get_with_proof(&queried_keys, version).unwrap();
+        assert_eq!(results.len(), queried_keys.len());
+        for (key, (value, proof)) in queried_keys.iter().zip(results) {
+            let (expected_value, expected_proof) = tree.get_with_proof(*key, version).unwrap();
+            assert_eq!(value, expected_value);
+            assert_eq!(proof.leaf(), expected_proof.leaf());
+            assert_eq!(proof.siblings(), expected_proof.siblings());
+            proof.verify(root_hash, *key, value).unwrap();
+        }
+    }
+
+    // A tree with a single leaf, and the empty tree.
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let (_, batch) = tree.put_value_set([(keys[0], Some(vec![1]))], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree.put_value_set([(keys[0], None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    for version in [0, 1] {
+        let results = tree.multi_get_with_proof(&keys[..3], version).unwrap();
+        for (key, (value, proof)) in keys.iter().zip(results) {
+            let (expected_value, expected_proof) = tree.get_with_proof(*key, version).unwrap();
+            assert_eq!(value, expected_value);
+            assert_eq!(proof.leaf(), expected_proof.leaf());
+            assert_eq!(proof.siblings(), expected_proof.siblings());
+        }
+    }
+}
+
+#[test]
+fn test_put_value_set_with_proofs() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([17; 32]);
+    let keys: Vec<KeyHash> = (0..100).map(|_| KeyHash(rng.gen())).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Updated, inserted and deleted keys.
+    let mut value_set: Vec<(KeyHash, Option<Vec<u8>>)> = keys
+        .iter()
+        .step_by(3)
+        .map(|key| (*key, Some(vec![1])))
+        .collect();
+    value_set.extend((0..20).map(|_| (KeyHash(rng.gen()), Some(vec![2]))));
+    value_set.extend(keys.iter().skip(1).step_by(3).map(|key| (*key, None)));
+    let (root_hash, batch, proofs) = tree
+        .put_value_set_with_proofs(value_set.clone(), 1)
+        .unwrap();
+    assert_eq!(proofs.len(), value_set.len());
+    // The proofs verify before the batch is written.
+    for ((key, value), proof) in value_set.iter().zip(&proofs) {
+        proof.verify(root_hash, *key, value.as_ref()).unwrap();
+    }
+
+    db.write_tree_update_batch(batch).unwrap();
+    for ((key, _), proof) in value_set.iter().zip(proofs) {
+        let expected_proof = tree.get_proof(*key, 1).unwrap();
+        assert_eq!(proof.leaf(), expected_proof.leaf());
+        assert_eq!(proof.siblings(), expected_proof.siblings());
+    }
+}
+
+#[test]
+fn test_delete_with_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([18; 32]);
+    let keys: Vec<KeyHash> = (0..20).map(|_| KeyHash(rng.gen())).collect();
+    assert!(tree.delete_with_proof(keys[0], 0).is_err());
+    let (old_root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (root_hash, batch, value, proof) = tree.delete_with_proof(keys[3], 1).unwrap();
+    assert_eq!(value, keys[3].0.to_vec());
+    // The proof verifies before the batch is written.
+    proof
+        .verify(old_root_hash, root_hash, keys[3], &value)
+        .unwrap();
+    assert!(proof
+        .verify(old_root_hash, root_hash, keys[3], [0; 32])
+        .is_err());
+
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, expected_proof) = tree.get_deletion_proof(keys[3], 1).unwrap();
+    assert_eq!(proof.new_root_hash(), expected_proof.new_root_hash());
+    assert_eq!(
+        proof.exclusion_proof().leaf(),
+        expected_proof.exclusion_proof().leaf()
+    );
+    assert_eq!(
+        proof.exclusion_proof().siblings(),
+        expected_proof.exclusion_proof().siblings()
+    );
+    // Keys which do not exist cannot be deleted.
+    assert!(tree.delete_with_proof(keys[3], 2).is_err());
+    assert!(tree.delete_with_proof(KeyHash(rng.gen()), 2).is_err());
+}
+
+#[test]
+fn test_get_value_history() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([19; 32]);
+    let key = KeyHash(rng.gen());
+    let updates: Vec<(Version, Option<Vec<u8>>)> = vec![
+        (2, Some(b"a".to_vec())),
+        (5, Some(b"b".to_vec())),
+        (7, None),
+        (9, Some(b"a".to_vec())),
+    ];
+    for version in 0..12 {
+        // Other keys are written at every version, moving the leaf of `key` around.
+        let mut value_set: Vec<_> = (0..8)
+            .map(|_| (KeyHash(rng.gen()), Some(vec![version as u8])))
+            .collect();
+        if let Some((_, value)) = updates.iter().find(|(v, _)| *v == version) {
+            value_set.push((key, value.clone()));
+        }
+        let (_, batch) = tree.put_value_set(value_set, version).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    let history = |versions| tree.get_value_history(key, versions).unwrap();
+    assert_eq!(history(0..12), updates);
+    assert_eq!(history(3..8), vec![(5, Some(b"b".to_vec())), (7, None)]);
+    assert_eq!(history(2..3), vec![(2, Some(b"a".to_vec()))]);
+    assert_eq!(history(6..7), vec![]);
+    assert_eq!(history(7..7), vec![]);
+    assert_eq!(history(8..12), vec![(9, Some(b"a".to_vec()))]);
+    // The walk starts from the root of the last version.
+    assert_eq!(history(8..20), vec![]);
+}
+
+#[test]
+fn test_get_unchanged_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([20; 32]);
+    let key = KeyHash(rng.gen());
+    for version in 0..8 {
+        // Other keys are written at every version, moving the leaf of `key` around.
+        let mut value_set: Vec<_> = (0..8)
+            .map(|_| (KeyHash(rng.gen()), Some(vec![version as u8])))
+            .collect();
+        match version {
+            1 => value_set.push((key, Some(b"a".to_vec()))),
+            6 => value_set.push((key, Some(b"b".to_vec()))),
+            _ => {}
+        }
+        let (_, batch) = tree.put_value_set(value_set, version).unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    let root_hash = |version| tree.get_root_hash(version).unwrap();
+
+    let (value, proof) = tree.get_unchanged_proof(key, 2, 5).unwrap();
+    assert_eq!(value, b"a".to_vec());
+    assert_eq!(proof.since_version(), 1);
+    proof
+        .verify(2, root_hash(2), 5, root_hash(5), key, &value)
+        .unwrap();
+    assert!(proof
+        .verify(2, root_hash(2), 6, root_hash(6), key, &value)
+        .is_err());
+    assert!(proof
+        .verify(2, root_hash(2), 5, root_hash(5), key, b"b")
+        .is_err());
+    assert!(proof
+        .verify(2, root_hash(3), 5, root_hash(5), key, &value)
+        .is_err());
+
+    assert!(tree.get_unchanged_proof(key, 1, 1).is_ok());
+    assert!(tree.get_unchanged_proof(key, 0, 5).is_err());
+    assert!(tree.get_unchanged_proof(key, 5, 6).is_err());
+    assert!(tree.get_unchanged_proof(key, 5, 2).is_err());
+}
+
+#[test]
+fn test_concurrent_reads_during_commits() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Sha256JMT<'static, MockTreeStore>>();
+    assert_send_sync::<crate::memory_store::MemoryTreeStore>();
+    assert_send_sync::<crate::cache::CachedTreeReader<MockTreeStore>>();
+
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let keys: Vec<KeyHash> = (0..16u8).map(|i| KeyHash([i; 32])).collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Readers share the tree with the writer, and keep reading version 0 while it commits.
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for key in &keys {
+                    let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+                    assert_eq!(value, Some(vec![0]));
+                    proof.verify(root_hash, *key, value).unwrap();
+                }
+            });
+        }
+        scope.spawn(|| {
+            for version in 1..16 {
+                let (_, batch) = tree
+                    .put_value_set(
+                        [(keys[version as usize], Some(vec![version as u8]))],
+                        version,
+                    )
+                    .unwrap();
+                db.write_tree_update_batch(batch).unwrap();
+            }
+        });
+    });
+    assert_eq!(tree.get(keys[15], 15).unwrap(), Some(vec![15]));
+}
+
+#[test]
+fn test_iter_root_hashes() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    // Only the index is read, so the root nodes need not exist.
+    let root_hashes: Vec<(Version, RootHash)> = (0..2500)
+        .filter(|version| version % 7 != 3)
+        .map(|version: Version| {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&version.to_be_bytes());
+            (version, RootHash(hash))
+        })
+        .collect();
+    db.put_root_hashes(root_hashes.clone());
+
+    let all: Vec<_> = tree
+        .iter_root_hashes(..)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(all, root_hashes);
+    let some: Vec<_> = tree
+        .iter_root_hashes(10..=2010)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    let expected: Vec<_> = root_hashes
+        .iter()
+        .filter(|(version, _)| (10..=2010).contains(version))
+        .copied()
+        .collect();
+    assert_eq!(some, expected);
+    assert_eq!(tree.iter_root_hashes(3..4).count(), 0);
+    assert_eq!(tree.iter_root_hashes(5000..).count(), 0);
+}
+
+#[test]
+fn test_node_batch_entries() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([19; 32]);
+    let keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().take(5).map(|key| (*key, Some(vec![1]))), 1)
+        .unwrap();
+
+    let entries: Vec<NodeBatchEntry> = batch.node_batch.entries::<Sha256>().collect();
+    assert_eq!(entries.len(), batch.node_batch.nodes().len());
+    let root = entries
+        .iter()
+        .find(|entry| entry.nibble_path.num_nibbles() == 0)
+        .unwrap();
+    assert_eq!(root.hash, root_hash.0);
+    for entry in &entries {
+        assert_eq!(entry.nibble_path, entry.node_key.nibble_path());
+        match entry.kind {
+            NodeKind::Leaf { key_hash, .. } => {
+                assert!(keys[..5].contains(&key_hash));
+                assert!(entry.children.is_empty());
+            }
+            NodeKind::Internal => {
+                // Children are either new, or read from the store.
+                for child in &entry.children {
+                    assert!(
+                        batch.node_batch.get_node(child).is_some()
+                            || db.get_node_option(child).unwrap().is_some()
+                    );
+                }
+            }
+            NodeKind::Null => panic!("Unexpected null node."),
+        }
+    }
+}
+
+#[test]
+fn test_get_non_inclusion_proof() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([12; 32]);
+    let mut keys: Vec<KeyHash> = (0..50).map(|_| KeyHash(rng.gen())).collect();
+    keys.sort();
+
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(tree.get_non_inclusion_proof(keys[5], 0).is_err());
+
+    let missing_keys: Vec<KeyHash> = (0..50)
+        .map(|_| KeyHash(rng.gen()))
+        .chain([KeyHash([0; 32]), KeyHash([0xff; 32])])
+        .collect();
+    for missing_key in missing_keys {
+        let proof = tree.get_non_inclusion_proof(missing_key, 0).unwrap();
+        assert_eq!(proof.key_hash(), missing_key);
+        proof.verify(root, missing_key).unwrap();
+        let position = keys.partition_point(|key| *key < missing_key);
+        assert_eq!(
+            proof.left_neighbor().map(|leaf| leaf.key_hash()),
+            position.checked_sub(1).map(|i| keys[i])
+        );
+        assert_eq!(
+            proof.right_neighbor().map(|leaf| leaf.key_hash()),
+            keys.get(position).copied()
+        );
+        // The proof is bound to its key, even if it also holds for other keys of the same gap.
+        let other_key = KeyHash(rng.gen());
+        assert!(proof.verify(root, other_key).is_err());
+    }
+}
+
 #[test]
 fn test_missing_root() {
     let db = MockTreeStore::default();
@@ -502,6 +1201,72 @@ fn test_missing_root() {
     assert_eq!(err.version, 0);
 }
 
+/// Serves the nodes of a [`MockTreeStore`] encoded, as storage backed by a database would.
+struct EncodedNodeStore(MockTreeStore);
+
+impl TreeReader for EncodedNodeStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> anyhow::Result<Option<Node>> {
+        self.0.get_node_option(node_key)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> anyhow::Result<Option<Vec<u8>>> {
+        self.0
+            .get_node_option(node_key)?
+            .map(|node| node.encode())
+            .transpose()
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> anyhow::Result<Option<crate::OwnedValue>> {
+        self.0.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> anyhow::Result<Option<(NodeKey, crate::storage::LeafNode)>> {
+        self.0.get_rightmost_leaf()
+    }
+}
+
+#[test]
+fn test_get_with_proof_from_encoded_nodes() {
+    let db = EncodedNodeStore(MockTreeStore::default());
+    let tree = Sha256JMT::new(&db);
+
+    let kvs: Vec<_> = (0..100)
+        .map(|i| {
+            (
+                KeyHash::with::<Sha256>(format!("key{}", i)),
+                Some(format!("value{}", i).into_bytes()),
+            )
+        })
+        .collect();
+    let (root, batch) = tree.put_value_set(kvs.clone(), 0 /* version */).unwrap();
+    db.0.write_tree_update_batch(batch).unwrap();
+
+    let decoded_tree = Sha256JMT::new(&db.0);
+    for (key, value) in kvs {
+        let (actual_value, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(actual_value, value);
+        assert!(proof.verify(root, key, value.as_ref()).is_ok());
+        let (_, decoded_proof) = decoded_tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(proof.leaf(), decoded_proof.leaf());
+        assert_eq!(proof.siblings(), decoded_proof.siblings());
+    }
+    let missing_key = KeyHash::with::<Sha256>(b"missing");
+    let (value, proof) = tree.get_with_proof(missing_key, 0).unwrap();
+    assert!(value.is_none());
+    assert!(proof.verify_nonexistence(root, missing_key).is_ok());
+
+    let err = tree
+        .get_with_proof(missing_key, 1)
+        .unwrap_err()
+        .downcast::<MissingRootError>()
+        .unwrap();
+    assert_eq!(err.version, 1);
+}
+
 #[test]
 fn test_non_batch_empty_write_set() {
     let db = MockTreeStore::default();
@@ -549,7 +1314,7 @@ fn test_put_value_sets() {
         }
     }
     {
-        let mut iter = keys.into_iter().zip(values.into_iter());
+        let mut iter = keys.into_iter().zip(values);
         let db = MockTreeStore::default();
         let tree = Sha256JMT::new(&db);
         let mut value_sets = vec![];
@@ -568,6 +1333,52 @@ fn test_put_value_sets() {
     }
 }
 
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_put_value_set() {
+    let sequential_db = MockTreeStore::default();
+    let sequential_tree = Sha256JMT::new(&sequential_db);
+    let parallel_db = MockTreeStore::default();
+    let parallel_tree = Sha256JMT::new(&parallel_db);
+
+    let mut rng: StdRng = StdRng::from_seed([0; 32]);
+    let keys: Vec<_> = (0..1000).map(|_| KeyHash(rng.gen())).collect();
+    let value_sets = vec![
+        // A single key at genesis, making the root a leaf.
+        vec![(keys[0], vec![0])],
+        // Many keys below a leaf root.
+        keys[..500].iter().map(|key| (*key, vec![1])).collect(),
+        // New and updated keys below an internal root.
+        keys[250..].iter().map(|key| (*key, vec![2])).collect(),
+    ];
+    for (version, value_set) in value_sets.into_iter().enumerate() {
+        let (root_hashes, sequential_batch) = sequential_tree
+            .batch_put_value_sets(vec![value_set.clone()], None, version as Version)
+            .unwrap();
+        let (root_hash, parallel_batch) = parallel_tree
+            .par_put_value_set(value_set, version as Version)
+            .unwrap();
+        assert_eq!(root_hash, root_hashes[0]);
+        assert_eq!(parallel_batch, sequential_batch);
+        sequential_db
+            .write_tree_update_batch(sequential_batch)
+            .unwrap();
+        parallel_db.write_tree_update_batch(parallel_batch).unwrap();
+    }
+
+    // Many keys at genesis, below a null root.
+    let db = MockTreeStore::default();
+    let value_set: Vec<_> = keys.iter().map(|key| (*key, vec![3])).collect();
+    let (root_hashes, sequential_batch) = Sha256JMT::new(&db)
+        .batch_put_value_sets(vec![value_set.clone()], None, 0 /* version */)
+        .unwrap();
+    let (root_hash, parallel_batch) = Sha256JMT::new(&db)
+        .par_put_value_set(value_set, 0 /* version */)
+        .unwrap();
+    assert_eq!(root_hash, root_hashes[0]);
+    assert_eq!(parallel_batch, sequential_batch);
+}
+
 fn many_keys_get_proof_and_verify_tree_root(seed: &[u8], num_keys: usize) {
     assert!(seed.len() < 32);
     let mut actual_seed = [0u8; 32];
@@ -708,6 +1519,25 @@ proptest! {
         test_get_with_proof((existent_kvs, nonexistent_keys))
     }
 
+    #[test]
+    fn proptest_compact_proof_encoding((existent_kvs, nonexistent_keys) in arb_existent_kvs_and_nonexistent_keys(1000, 100)) {
+        test_compact_proof_encoding((existent_kvs, nonexistent_keys))
+    }
+
+    #[test]
+    fn proptest_compact_proof_round_trip(proof in any::<SparseMerkleProof<Sha256>>()) {
+        let bytes = proof.to_compact_bytes();
+        let decoded = SparseMerkleProof::<Sha256>::from_compact_bytes(&bytes).unwrap();
+        prop_assert_eq!(decoded.leaf(), proof.leaf());
+        prop_assert_eq!(decoded.siblings(), proof.siblings());
+        prop_assert_eq!(decoded.to_compact_bytes(), bytes);
+    }
+
+    #[test]
+    fn proptest_get_with_multiproof((existent_kvs, nonexistent_keys) in arb_existent_kvs_and_nonexistent_keys(1000, 100)) {
+        test_get_with_multiproof((existent_kvs, nonexistent_keys))
+    }
+
     #[test]
     fn proptest_get_with_proof_with_deletions((existent_kvs, deletions, nonexistent_keys) in arb_existent_kvs_and_deletions_and_nonexistent_keys(1000, 100)) {
         test_get_with_proof_with_deletions((existent_kvs, deletions, nonexistent_keys))
@@ -754,8 +1584,275 @@ proptest! {
         test_get_range_proof((btree, n))
     }
 
+    #[test]
+    fn proptest_get_interval_proof((btree, start, end) in arb_tree_with_interval(1000)) {
+        test_get_interval_proof((btree, start, end))
+    }
+
+    #[test]
+    fn proptest_get_range_with_proof(
+        (btree, start, _) in arb_tree_with_interval(1000),
+        limit in 1usize..50,
+    ) {
+        test_get_range_with_proof((btree, start, limit))
+    }
+
     #[test]
     fn proptest_get_leaf_count(keys in hash_set(any::<KeyHash>(), 1..1000)) {
         test_get_leaf_count(keys)
     }
 }
+
+#[test]
+fn test_get_root_hash_from_index() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let key = KeyHash::with::<Sha256>(b"key");
+    let (root_hash, batch) = tree.put_value_set(vec![(key, Some(vec![1]))], 0).unwrap();
+    assert_eq!(batch.root_hashes().get(&0), Some(&root_hash));
+    // Writing the batch records its root hash.
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(db.get_indexed_root_hash(0).unwrap(), Some(root_hash));
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+    assert!(tree.get_root_hash(1).is_err());
+
+    // Indexed root hashes are returned without reading the root node.
+    let indexed_root_hash = RootHash([1; 32]);
+    db.put_root_hashes([(0, root_hash), (1, indexed_root_hash)]);
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+    assert_eq!(tree.get_root_hash(1).unwrap(), indexed_root_hash);
+    assert_eq!(
+        tree.get_root_hash_option(1).unwrap(),
+        Some(indexed_root_hash)
+    );
+    assert_eq!(tree.get_root_hash_option(2).unwrap(), None);
+}
+
+#[test]
+fn test_stats() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (_, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.stats(0).unwrap(), TreeStats::default());
+
+    let (_, batch) = tree
+        .put_value_set(vec![(KeyHash([0x00; 32]), Some(vec![0]))], 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        tree.stats(1).unwrap(),
+        TreeStats {
+            leaf_count: 1,
+            internal_node_count: 0,
+            max_depth: 0,
+            value_bytes: 1,
+        }
+    );
+
+    let (_, batch) = tree
+        .put_value_set(
+            vec![
+                (KeyHash([0x11; 32]), Some(vec![1; 2])),
+                (KeyHash([0x12; 32]), Some(vec![2; 3])),
+            ],
+            2,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(
+        tree.stats(2).unwrap(),
+        TreeStats {
+            leaf_count: 3,
+            internal_node_count: 2,
+            max_depth: 2,
+            value_bytes: 6,
+        }
+    );
+    assert_eq!(tree.stats(1).unwrap().leaf_count, 1);
+    assert!(tree.stats(3).is_err());
+}
+
+#[test]
+fn test_put_value_set_with_prior() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let key1 = KeyHash([0x11; 32]);
+    let key2 = KeyHash([0x12; 32]);
+    let key3 = KeyHash([0x20; 32]);
+
+    let (_, batch, prior) = tree
+        .put_value_set_with_prior(vec![(key1, Some(vec![1])), (key2, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(prior, vec![(key1, None), (key2, None)]);
+
+    let value_set = vec![
+        (key1, Some(vec![3])),
+        (key2, None),
+        (key3, None),
+        (key1, Some(vec![4])),
+    ];
+    let (root_hash, batch, prior) = tree.put_value_set_with_prior(value_set.clone(), 1).unwrap();
+    assert_eq!(
+        prior,
+        vec![
+            (key1, Some(vec![1])),
+            (key2, Some(vec![2])),
+            (key3, None),
+            (key1, Some(vec![1])),
+        ]
+    );
+    let (expected_root_hash, expected_batch) = tree.put_value_set(value_set, 1).unwrap();
+    assert_eq!(root_hash, expected_root_hash);
+    assert_eq!(batch, expected_batch);
+    db.write_tree_update_batch(batch).unwrap();
+
+    let (_, _, prior) = tree
+        .put_value_set_with_prior(vec![(key2, Some(vec![5])), (key1, None)], 2)
+        .unwrap();
+    assert_eq!(prior, vec![(key2, None), (key1, Some(vec![4]))]);
+}
+
+#[test]
+fn test_put_value_set_with_prior_duplicate_keys() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let key1 = KeyHash([0x11; 32]);
+    let key2 = KeyHash([0x12; 32]);
+
+    let (_, batch, prior) = tree
+        .put_value_set_with_prior(vec![(key1, Some(vec![1])), (key1, Some(vec![2]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(prior, vec![(key1, None), (key1, None)]);
+
+    let (_, _, prior) = tree
+        .put_value_set_with_prior(
+            vec![
+                (key1, None),
+                (key2, Some(vec![3])),
+                (key1, Some(vec![4])),
+                (key2, Some(vec![5])),
+                (key1, Some(vec![6])),
+            ],
+            1,
+        )
+        .unwrap();
+    assert_eq!(
+        prior,
+        vec![
+            (key1, Some(vec![2])),
+            (key2, None),
+            (key1, Some(vec![2])),
+            (key2, None),
+            (key1, Some(vec![2])),
+        ]
+    );
+}
+
+#[test]
+fn test_iter_preimage_prefix() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<&[u8]> = vec![
+        b"accounts/bob",
+        b"validators/1",
+        b"accounts/alice",
+        b"accountz",
+    ];
+    db.put_key_preimages(
+        keys.iter()
+            .map(|key| (KeyHash::with::<Sha256>(key), key.to_vec())),
+    );
+    let (_, batch) = tree
+        .put_value_set(
+            keys.iter()
+                .map(|key| (KeyHash::with::<Sha256>(key), Some(key.to_vec()))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(vec![(KeyHash::with::<Sha256>(b"accounts/bob"), None)], 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let scan = |prefix: &[u8], version| {
+        tree.iter_preimage_prefix(&db, prefix, version)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        scan(b"accounts/", 0),
+        vec![b"accounts/alice".to_vec(), b"accounts/bob".to_vec()]
+    );
+    assert_eq!(scan(b"accounts/", 1), vec![b"accounts/alice".to_vec()]);
+    assert_eq!(scan(b"", 1).len(), 3);
+    assert!(scan(b"balances/", 1).is_empty());
+    let (preimage, value) = tree
+        .iter_preimage_prefix(&db, b"validators/", 1)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(preimage, value);
+}
+
+#[test]
+fn test_estimate_proof_size() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (_, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let key = KeyHash::with::<Sha256>(b"missing");
+    let estimate = tree.estimate_proof_size(key, 0).unwrap();
+    assert_eq!(estimate, ProofSizeEstimate::default());
+    let (_, proof) = tree.get_with_proof(key, 0).unwrap();
+    assert_eq!(estimate.compact_len(), proof.to_compact_bytes().len());
+
+    let keys: Vec<KeyHash> = (0u32..300)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    for key in keys.iter().take(100).chain([&key]) {
+        let estimate = tree.estimate_proof_size(*key, 1).unwrap();
+        let (_, proof) = tree.get_with_proof(*key, 1).unwrap();
+        assert_eq!(estimate.has_leaf, proof.leaf().is_some());
+        assert_eq!(estimate.num_siblings, proof.siblings().len());
+        assert_eq!(estimate.compact_len(), proof.to_compact_bytes().len());
+    }
+    assert!(tree.estimate_proof_size(key, 2).is_err());
+}
+
+#[test]
+fn test_padded_key_hashes() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let mut rng: StdRng = StdRng::from_seed([5; 32]);
+    let addresses: Vec<[u8; 20]> = (0..1000).map(|_| rng.gen()).collect();
+    let (root_hash, batch) = tree
+        .put_value_set(
+            addresses
+                .iter()
+                .map(|address| (KeyHash::from_padded(*address), Some(address.to_vec()))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for address in addresses.iter().take(50) {
+        let key = KeyHash::from_padded(*address);
+        assert_eq!(key.truncated::<20>(), *address);
+        assert_eq!(key.0[20..], [0; 12]);
+        let (value, proof) = tree.get_with_proof(key, 0).unwrap();
+        assert_eq!(value.as_deref(), Some(&address[..]));
+        proof.verify(root_hash, key, value).unwrap();
+        // About log2(1000) siblings, far below the 160 bits of the addresses.
+        assert!(proof.siblings().len() < 32);
+    }
+}
@@ -0,0 +1,51 @@
+use sha2::Sha256;
+
+use crate::{
+    leaf_count::leaf_count, mock::MockTreeStore, JellyfishMerkleTree, KeyHash, SimpleHasher,
+};
+
+#[test]
+fn test_leaf_count_commitment() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (empty_root_hash, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(empty_root_hash.0, Sha256::PLACEHOLDER_HASH);
+    assert_eq!(leaf_count::<Sha256>(empty_root_hash), 0);
+    assert!(tree.get_with_rank_proof(0, 0).is_err());
+
+    let mut keys: Vec<KeyHash> = (0u32..300)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0[..4].to_vec()))), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    keys.sort();
+    assert_eq!(leaf_count::<Sha256>(root_hash), 300);
+
+    for (rank, key) in keys.iter().enumerate() {
+        let rank = rank as u64;
+        let (key_hash, value, proof) = tree.get_with_rank_proof(rank, 1).unwrap();
+        assert_eq!(key_hash, *key);
+        assert_eq!(value, key.0[..4].to_vec());
+        proof.verify(root_hash, rank, key_hash, &value).unwrap();
+        // The rank proof is the usual inclusion proof.
+        let (_, inclusion_proof) = tree.get_with_proof(key_hash, 1).unwrap();
+        assert_eq!(proof.proof().siblings(), inclusion_proof.siblings());
+        assert!(proof.verify(root_hash, rank + 1, key_hash, &value).is_err());
+        assert!(proof.verify(root_hash, rank, key_hash, [0]).is_err());
+        assert!(proof
+            .verify(empty_root_hash, rank, key_hash, &value)
+            .is_err());
+    }
+    assert!(tree.get_with_rank_proof(300, 1).is_err());
+
+    // Deleting a key updates the count committed to by the root hash.
+    let (root_hash, batch) = tree.put_value_set(vec![(keys[0], None)], 2).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(leaf_count::<Sha256>(root_hash), 299);
+    let (key_hash, value, proof) = tree.get_with_rank_proof(0, 2).unwrap();
+    assert_eq!(key_hash, keys[1]);
+    proof.verify(root_hash, 0, key_hash, value).unwrap();
+}
@@ -0,0 +1,51 @@
+use sha2::Sha256;
+
+use crate::{light::RootTracker, mock::MockTreeStore, KeyHash, RootHash, Sha256JMT};
+
+#[test]
+fn test_root_tracker() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let alice = KeyHash::with::<Sha256>(b"alice");
+    let bob = KeyHash::with::<Sha256>(b"bob");
+
+    let (root_hash, batch) = tree
+        .put_value_set([(alice, Some(b"1".to_vec()))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let mut tracker = RootTracker::new(0, root_hash);
+    let (_, proof) = tree.get_with_proof(alice, 0).unwrap();
+    tracker.verify_membership(alice, b"1", &proof).unwrap();
+    assert!(tracker.verify_membership(alice, b"2", &proof).is_err());
+    let (_, proof) = tree.get_with_proof(bob, 0).unwrap();
+    tracker.verify_non_membership(bob, &proof).unwrap();
+
+    // The trusted root advances with verified updates.
+    let updates = vec![(bob, Some(b"2".to_vec()))];
+    let (root_hash, update_proof, batch) = tree
+        .put_value_set_with_update_proof(updates.clone(), 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(tracker
+        .apply_update(1, RootHash([0; 32]), updates.clone(), &update_proof)
+        .is_err());
+    assert!(tracker
+        .apply_update(0, root_hash, updates.clone(), &update_proof)
+        .is_err());
+    tracker
+        .apply_update(1, root_hash, updates, &update_proof)
+        .unwrap();
+    assert_eq!((tracker.version(), tracker.root_hash()), (1, root_hash));
+    let (_, proof) = tree.get_with_proof(bob, 1).unwrap();
+    tracker.verify_membership(bob, b"2", &proof).unwrap();
+
+    // Stale checkpoints are ignored, and conflicting ones rejected.
+    assert!(!tracker.checkpoint(0, RootHash([0; 32])).unwrap());
+    assert!(!tracker.checkpoint(1, root_hash).unwrap());
+    assert!(tracker.checkpoint(1, RootHash([0; 32])).is_err());
+    let (root_hash, batch) = tree.put_value_set([(alice, None)], 2).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(tracker.checkpoint(2, root_hash).unwrap());
+    let (_, proof) = tree.get_with_proof(alice, 2).unwrap();
+    tracker.verify_non_membership(alice, &proof).unwrap();
+}
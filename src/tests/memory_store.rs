@@ -0,0 +1,106 @@
+use std::thread;
+
+use proptest::prelude::*;
+use sha2::Sha256;
+
+use crate::{
+    memory_store::MemoryTreeStore,
+    pruner::Pruner,
+    storage::HasPreimage,
+    testing::{
+        arb_interleaved_insertions_and_deletions, arb_partitions, write_value_sets, MockTreeStore,
+    },
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[test]
+fn test_memory_store() {
+    let db = MemoryTreeStore::new().with_num_shards(4);
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..50).map(|i| KeyHash([i; 32])).collect();
+
+    for version in 0..3 {
+        let (root_hash, batch) = tree
+            .put_value_set(
+                keys.iter().map(|key| (*key, Some(vec![version as u8; 10]))),
+                version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch, [(version, root_hash)])
+            .unwrap();
+    }
+    db.put_key_preimages(keys.iter().map(|key| (*key, key.0[..4].to_vec())))
+        .unwrap();
+    assert_eq!(db.get_latest_version().unwrap(), Some(2));
+    assert_eq!(
+        db.preimages(&[keys[3], KeyHash([0xff; 32])]).unwrap(),
+        vec![Some(vec![3; 4]), None]
+    );
+
+    // Readers on several threads see the same values and proofs.
+    let root_hash = tree.get_root_hash(2).unwrap();
+    thread::scope(|scope| {
+        for chunk in keys.chunks(10) {
+            let tree = &tree;
+            scope.spawn(move || {
+                for key in chunk {
+                    let (value, proof) = tree.get_with_proof(*key, 2).unwrap();
+                    assert_eq!(value, Some(vec![2; 10]));
+                    proof.verify(root_hash, *key, value).unwrap();
+                }
+            });
+        }
+    });
+
+    // Pruning releases the memory of the stale nodes.
+    let num_nodes = db.num_nodes().unwrap();
+    let memory_usage = db.memory_usage();
+    let stats = Pruner::new(&db).prune(2).unwrap();
+    assert_eq!(db.num_nodes().unwrap(), num_nodes - stats.pruned_nodes);
+    assert!(db.memory_usage() < memory_usage);
+    assert_eq!(tree.get(keys[7], 2).unwrap(), Some(vec![2; 10]));
+    assert!(tree.get_with_proof(keys[7], 0).is_err());
+}
+
+#[test]
+fn test_memory_store_limit() {
+    let db = MemoryTreeStore::new().with_memory_limit(20_000);
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+
+    let (root_hash, batch) = tree
+        .put_value_set([(KeyHash([1; 32]), Some(vec![1; 100]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch, [(0, root_hash)]).unwrap();
+    let memory_usage = db.memory_usage();
+    assert!(memory_usage > 100);
+
+    // A write exceeding the limit is rejected as a whole.
+    let (_, batch) = tree
+        .put_value_set(
+            (0u8..100).map(|i| (KeyHash([i; 32]), Some(vec![i; 1000]))),
+            1,
+        )
+        .unwrap();
+    assert!(db.write_tree_update_batch(batch, []).is_err());
+    assert_eq!(db.memory_usage(), memory_usage);
+    assert_eq!(db.num_nodes().unwrap(), 1);
+    assert!(db
+        .put_key_preimages([(KeyHash([2; 32]), vec![0; 30_000])])
+        .is_err());
+    assert_eq!(db.memory_usage(), memory_usage);
+}
+
+proptest! {
+    #[test]
+    fn test_memory_store_matches_mock(
+        value_sets in arb_interleaved_insertions_and_deletions(20, 5, 40, 10)
+            .prop_flat_map(|operations| arb_partitions(4, operations))
+    ) {
+        let memory_store = MemoryTreeStore::new();
+        let mock_store = MockTreeStore::default();
+        prop_assert_eq!(
+            write_value_sets(&memory_store, value_sets.clone()).unwrap(),
+            write_value_sets(&mock_store, value_sets).unwrap()
+        );
+    }
+}
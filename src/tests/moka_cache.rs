@@ -0,0 +1,49 @@
+use futures::executor::block_on;
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore, moka_cache::MokaTreeReader, storage::StaleNodeIndex,
+    AsyncJellyfishMerkleTree, JellyfishMerkleTree, KeyHash,
+};
+
+#[test]
+fn test_moka_tree_reader() {
+    let db = MockTreeStore::default();
+    let keys: Vec<KeyHash> = (0u8..50).map(|i| KeyHash([i; 32])).collect();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 1)
+        .unwrap();
+    let stale_node_indices: Vec<StaleNodeIndex> =
+        batch.stale_node_index_batch.iter().cloned().collect();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let cached = MokaTreeReader::new(&db, 1000);
+    let cached_tree = AsyncJellyfishMerkleTree::<_, Sha256>::new(&cached);
+    block_on(async {
+        for key in &keys {
+            let (value, proof) = cached_tree.get_with_proof(*key, 0).await.unwrap();
+            assert_eq!(value, Some(vec![0]));
+            proof.verify(root, *key, value).unwrap();
+        }
+        cached.cache().run_pending_tasks().await;
+        assert!(cached.cache().entry_count() > 0);
+
+        // Pruned nodes are still served by the cache until they are invalidated.
+        db.purge_stale_nodes(1).unwrap();
+        let (value, proof) = cached_tree.get_with_proof(keys[0], 0).await.unwrap();
+        proof.verify(root, keys[0], value).unwrap();
+        cached.invalidate_stale_nodes(&stale_node_indices).await;
+        assert!(cached_tree.get_with_proof(keys[0], 0).await.is_err());
+        let (value, _) = cached_tree.get_with_proof(keys[0], 1).await.unwrap();
+        assert_eq!(value, Some(vec![1]));
+
+        cached.invalidate_all();
+        cached.cache().run_pending_tasks().await;
+        assert_eq!(cached.cache().entry_count(), 0);
+    });
+}
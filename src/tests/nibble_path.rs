@@ -242,4 +242,20 @@ proptest! {
         let bit_iter = nibble_iter.bits();
         prop_assert_eq!(remaining_bit_iter.collect::<Vec<bool>>(), bit_iter.collect::<Vec<_>>());
     }
+
+    #[test]
+    fn test_serialize_as_vec(nibble_path in any::<NibblePath>()) {
+        let bytes = nibble_path.bytes().to_vec();
+        prop_assert_eq!(
+            bcs::to_bytes(&nibble_path).unwrap(),
+            bcs::to_bytes(&(nibble_path.num_nibbles(), &bytes)).unwrap()
+        );
+        prop_assert_eq!(
+            bcs::from_bytes::<NibblePath>(&bcs::to_bytes(&nibble_path).unwrap()).unwrap(),
+            nibble_path.clone()
+        );
+        if nibble_path.num_nibbles().is_multiple_of(2) {
+            prop_assert_eq!(NibblePath::from_bytes(&bytes), nibble_path);
+        }
+    }
 }
@@ -4,13 +4,13 @@
 use std::{convert::TryInto, io::Cursor, panic, rc::Rc};
 
 use proptest::prelude::*;
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, Rng};
 use sha2::Sha256;
 
 use crate::{
     node_type::{
         deserialize_u64_varint, serialize_u64_varint, Child, Children, InternalNode, Node,
-        NodeDecodeError, NodeKey, NodeType,
+        NodeDecodeError, NodeFormat, NodeKey, NodeType,
     },
     types::{
         nibble::{nibble_path::NibblePath, Nibble},
@@ -21,11 +21,11 @@ use crate::{
 };
 
 fn hash_internal(left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
-    SparseMerkleInternalNode::new(left, right).hash()
+    SparseMerkleInternalNode::new(left, right).hash::<Sha256>()
 }
 
 fn hash_leaf(key: KeyHash, value_hash: ValueHash) -> [u8; 32] {
-    SparseMerkleLeafNode::new(key, value_hash).hash()
+    SparseMerkleLeafNode::new(key, value_hash).hash::<Sha256>()
 }
 
 // Generate a random node key with 63 nibbles.
@@ -57,11 +57,19 @@ fn test_encode_decode() {
     let mut children = Children::default();
     children.insert(
         Nibble::from(1),
-        Child::new(leaf1_node.hash(), 0 /* version */, NodeType::Leaf),
+        Child::new(
+            leaf1_node.hash::<Sha256>(),
+            0, /* version */
+            NodeType::Leaf,
+        ),
     );
     children.insert(
         Nibble::from(2),
-        Child::new(leaf2_node.hash(), 0 /* version */, NodeType::Leaf),
+        Child::new(
+            leaf2_node.hash::<Sha256>(),
+            0, /* version */
+            NodeType::Leaf,
+        ),
     );
 
     let account_key = KeyHash(OsRng.gen());
@@ -72,7 +80,18 @@ fn test_encode_decode() {
     for n in &nodes {
         let v = n.encode().unwrap();
         assert_eq!(*n, Node::decode(&v).unwrap());
+        assert_eq!(n.to_ref(), Node::decode_ref(&v).unwrap());
+        assert_eq!(Node::format(&v).unwrap(), NodeFormat::LATEST);
+        for format in [NodeFormat::Unversioned, NodeFormat::V1] {
+            let v = n.encode_with_format(format).unwrap();
+            assert_eq!(Node::format(&v).unwrap(), format);
+            assert_eq!(*n, Node::decode(&v).unwrap());
+            assert_eq!(n.to_ref(), Node::decode_ref(&v).unwrap());
+        }
     }
+    // Nodes written before formats were versioned start with their tag.
+    assert_eq!(Node::decode(&[0]).unwrap(), Node::Null);
+    assert_eq!(Node::decode(&[0x81, 0]).unwrap(), Node::Null);
     // Error cases
     if let Err(e) = Node::decode(&[]) {
         assert_eq!(
@@ -86,6 +105,36 @@ fn test_encode_decode() {
             NodeDecodeError::UnknownTag { unknown_tag: 100 }
         );
     }
+    assert_eq!(
+        Node::decode(&[0xff, 0])
+            .unwrap_err()
+            .downcast::<NodeDecodeError>()
+            .unwrap(),
+        NodeDecodeError::UnknownFormat {
+            unknown_format: 0xff
+        }
+    );
+    assert_eq!(
+        Node::decode(&[0x81])
+            .unwrap_err()
+            .downcast::<NodeDecodeError>()
+            .unwrap(),
+        NodeDecodeError::EmptyInput
+    );
+    assert_eq!(
+        Node::decode_ref(&[])
+            .unwrap_err()
+            .downcast::<NodeDecodeError>()
+            .unwrap(),
+        NodeDecodeError::EmptyInput
+    );
+    assert_eq!(
+        Node::decode_ref(&[100])
+            .unwrap_err()
+            .downcast::<NodeDecodeError>()
+            .unwrap(),
+        NodeDecodeError::UnknownTag { unknown_tag: 100 }
+    );
 }
 
 proptest! {
@@ -108,6 +157,16 @@ proptest! {
         let deserialized = InternalNode::deserialize(&vec, true /* read_leaf_count */).unwrap();
         assert_eq!(deserialized, input);
     }
+
+    #[test]
+    fn test_internal_node_decode_ref(input in any::<InternalNode>()) {
+        for node in [Node::Internal(input.clone().into_legacy_internal()), Node::Internal(input)] {
+            let encoded = node.encode().unwrap();
+            let node_ref = Node::decode_ref(&encoded).unwrap();
+            prop_assert_eq!(&node_ref, &node.to_ref());
+            prop_assert_eq!(node_ref.hash::<Sha256>(), node.hash::<Sha256>());
+        }
+    }
 }
 
 #[test]
@@ -137,7 +196,7 @@ fn test_leaf_hash() {
         let value_hash = ValueHash::with::<Sha256>(blob.as_slice());
         let hash = hash_leaf(address, value_hash);
         let leaf_node = Node::leaf_from_value::<Sha256>(address, blob);
-        assert_eq!(leaf_node.hash(), hash);
+        assert_eq!(leaf_node.hash::<Sha256>(), hash);
     }
 }
 
@@ -164,17 +223,17 @@ proptest! {
         //        leaf1     leaf2
         //
         let root_hash = hash_internal(hash1, hash2);
-        prop_assert_eq!(internal_node.hash(), root_hash);
+        prop_assert_eq!(internal_node.hash::<Sha256>(), root_hash);
 
         for i in 0..8 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (Some(leaf1_node_key.clone()), vec![hash2])
             );
         }
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (Some(leaf2_node_key.clone()), vec![hash1])
             );
         }
@@ -211,18 +270,18 @@ proptest! {
         let hash_x2 = hash_internal(SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x1);
 
         let root_hash = hash_internal(hash_x2, SPARSE_MERKLE_PLACEHOLDER_HASH);
-        assert_eq!(internal_node.hash(), root_hash);
+        assert_eq!(internal_node.hash::<Sha256>(), root_hash);
 
         for i in 0..4 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x1])
             );
         }
 
         for i in 4..6 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (
                     Some(leaf1_node_key.clone()),
                     vec![
@@ -236,7 +295,7 @@ proptest! {
 
         for i in 6..8 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (
                     Some(leaf2_node_key.clone()),
                     vec![
@@ -250,7 +309,7 @@ proptest! {
 
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![hash_x2])
             );
         }
@@ -285,25 +344,25 @@ proptest! {
         //      leaf1     leaf2
         let hash_x = hash_internal(hash1, hash2);
         let root_hash = hash_internal(hash_x, hash3);
-        prop_assert_eq!(internal_node.hash(), root_hash);
+        prop_assert_eq!(internal_node.hash::<Sha256>(), root_hash);
 
         for i in 0..4 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (Some(leaf1_node_key.clone()),vec![hash3, hash2])
             );
         }
 
         for i in 4..8 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (Some(leaf2_node_key.clone()),vec![hash3, hash1])
             );
         }
 
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (Some(leaf3_node_key.clone()),vec![hash_x])
             );
         }
@@ -350,11 +409,11 @@ proptest! {
         let hash_x4 = hash_internal(SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x3);
         let hash_x5 = hash_internal(hash_x2, hash_x4);
         let root_hash = hash_internal(hash_x5, hash4);
-        assert_eq!(internal_node.hash(), root_hash);
+        assert_eq!(internal_node.hash::<Sha256>(), root_hash);
 
         for i in 0..2 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (
                     Some(leaf1_node_key.clone()),
                     vec![hash4, hash_x4, hash_x1]
@@ -363,7 +422,7 @@ proptest! {
         }
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 2.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 2.into()),
             (
                 Some(internal2_node_key),
                 vec![
@@ -376,7 +435,7 @@ proptest! {
         );
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 3.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 3.into()),
 
             (
                 None,
@@ -386,7 +445,7 @@ proptest! {
 
         for i in 4..6 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (
                     None,
                     vec![hash4, hash_x2, hash_x3]
@@ -395,7 +454,7 @@ proptest! {
         }
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 6.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 6.into()),
             (
                 None,
                 vec![
@@ -408,7 +467,7 @@ proptest! {
         );
 
         prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, 7.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 7.into()),
             (
                 Some(internal3_node_key),
                 vec![
@@ -422,7 +481,7 @@ proptest! {
 
         for i in 8..16 {
             prop_assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (Some(leaf4_node_key.clone()), vec![hash_x5])
             );
         }
@@ -484,17 +543,17 @@ fn test_internal_hash_and_proof() {
         let hash_x5 = hash_internal(SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x4);
         let hash_x6 = hash_internal(SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x5);
         let root_hash = hash_internal(hash_x3, hash_x6);
-        assert_eq!(internal_node.hash(), root_hash);
+        assert_eq!(internal_node.hash::<Sha256>(), root_hash);
 
         for i in 0..4 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![hash_x6, hash_x2])
             );
         }
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, index1),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, index1),
             (
                 Some(child1_node_key),
                 vec![
@@ -507,7 +566,7 @@ fn test_internal_hash_and_proof() {
         );
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 5.into()),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 5.into()),
             (
                 None,
                 vec![
@@ -520,26 +579,26 @@ fn test_internal_hash_and_proof() {
         );
         for i in 6..8 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![hash_x6, SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x1])
             );
         }
 
         for i in 8..12 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![hash_x3, hash_x5])
             );
         }
 
         for i in 12..14 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![hash_x3, SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x4])
             );
         }
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 14.into()),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 14.into()),
             (
                 None,
                 vec![
@@ -551,7 +610,7 @@ fn test_internal_hash_and_proof() {
             )
         );
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, index2),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, index2),
             (
                 Some(child2_node_key),
                 vec![
@@ -617,10 +676,10 @@ fn test_internal_hash_and_proof() {
         let hash_x4 = hash_internal(SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x3);
         let hash_x5 = hash_internal(hash_x2, hash_x4);
         let root_hash = hash_internal(hash_x5, SPARSE_MERKLE_PLACEHOLDER_HASH);
-        assert_eq!(internal_node.hash(), root_hash);
+        assert_eq!(internal_node.hash::<Sha256>(), root_hash);
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 0.into()),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 0.into()),
             (
                 Some(child1_node_key),
                 vec![
@@ -633,7 +692,7 @@ fn test_internal_hash_and_proof() {
         );
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 1.into()),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 1.into()),
             (
                 None,
                 vec![
@@ -647,20 +706,20 @@ fn test_internal_hash_and_proof() {
 
         for i in 2..4 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x4, hash_x1])
             );
         }
 
         for i in 4..6 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![SPARSE_MERKLE_PLACEHOLDER_HASH, hash_x2, hash_x3])
             );
         }
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 6.into()),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 6.into()),
             (
                 None,
                 vec![
@@ -673,7 +732,7 @@ fn test_internal_hash_and_proof() {
         );
 
         assert_eq!(
-            internal_node.get_child_with_siblings(&internal_node_key, 7.into()),
+            internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, 7.into()),
             (
                 Some(child2_node_key),
                 vec![
@@ -687,7 +746,7 @@ fn test_internal_hash_and_proof() {
 
         for i in 8..16 {
             assert_eq!(
-                internal_node.get_child_with_siblings(&internal_node_key, i.into()),
+                internal_node.get_child_with_siblings::<Sha256>(&internal_node_key, i.into()),
                 (None, vec![hash_x5])
             );
         }
@@ -716,7 +775,7 @@ impl BinaryTreeNode {
         left: BinaryTreeNode,
         right: BinaryTreeNode,
     ) -> Self {
-        let hash = SparseMerkleInternalNode::new(left.hash(), right.hash()).hash();
+        let hash = SparseMerkleInternalNode::new(left.hash(), right.hash()).hash::<Sha256>();
 
         Self::Internal(BinaryTreeInternalNode {
             begin: first_child_index,
@@ -810,10 +869,10 @@ impl NaiveInternalNode {
                 return BinaryTreeNode::Null;
             }
             (BinaryTreeNode::Null, BinaryTreeNode::Child(node))
-            | (BinaryTreeNode::Child(node), BinaryTreeNode::Null) => {
-                if node.is_leaf {
-                    return BinaryTreeNode::Child(*node);
-                }
+            | (BinaryTreeNode::Child(node), BinaryTreeNode::Null)
+                if node.is_leaf =>
+            {
+                return BinaryTreeNode::Child(*node);
             }
             _ => (),
         };
@@ -864,7 +923,7 @@ proptest! {
     ) {
         for n in 0..16u8 {
             prop_assert_eq!(
-                node.get_child_with_siblings(&node_key, n.into()),
+                node.get_child_with_siblings::<Sha256>(&node_key, n.into()),
                 NaiveInternalNode::from_clever_node(&node).get_child_with_siblings(&node_key, n)
             )
         }
@@ -0,0 +1,87 @@
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore, overlay::OverlayTree, storage::HasPreimage, JellyfishMerkleTree, KeyHash,
+    OwnedValue, Version,
+};
+
+fn value_set(version: Version) -> Vec<(KeyHash, Option<OwnedValue>)> {
+    (0u8..10)
+        .map(|i| {
+            let value = (i as Version + version).is_multiple_of(3).then(|| vec![i]);
+            (KeyHash::with::<Sha256>([i]), value)
+        })
+        .collect()
+}
+
+#[test]
+fn test_overlay_tree() {
+    let db = MockTreeStore::default();
+    let expected_db = MockTreeStore::default();
+    let expected_tree = JellyfishMerkleTree::<_, Sha256>::new(&expected_db);
+    let mut expected_roots = vec![];
+    for version in 0..3 {
+        let (root, batch) = expected_tree
+            .put_value_set(value_set(version), version)
+            .unwrap();
+        expected_db.write_tree_update_batch(batch).unwrap();
+        expected_roots.push(root);
+    }
+
+    let (_, batch) = JellyfishMerkleTree::<_, Sha256>::new(&db)
+        .put_value_set(value_set(0), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let num_nodes = db.num_nodes();
+
+    let mut overlay = OverlayTree::<_, Sha256>::new(&db);
+    assert_eq!(
+        overlay.put_value_set(value_set(1), 1).unwrap(),
+        expected_roots[1]
+    );
+    assert_eq!(
+        overlay.put_value_set(value_set(2), 2).unwrap(),
+        expected_roots[2]
+    );
+    // Nothing is written until the pending updates are committed.
+    assert_eq!(db.num_nodes(), num_nodes);
+
+    for version in 0..3 {
+        for (key, _) in value_set(version) {
+            let (value, proof) = overlay.tree().get_with_proof(key, version).unwrap();
+            assert_eq!(value, expected_tree.get(key, version).unwrap());
+            proof
+                .verify(expected_roots[version as usize], key, value)
+                .unwrap();
+        }
+    }
+
+    let (root_hashes, batch) = overlay.take_pending();
+    assert_eq!(
+        root_hashes,
+        vec![(1, expected_roots[1]), (2, expected_roots[2])]
+    );
+    db.write_tree_update_batch(batch).unwrap();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    assert_eq!(tree.get_root_hash(2).unwrap(), expected_roots[2]);
+
+    // Dropped updates leave the tree as committed.
+    assert!(overlay.put_value_set(value_set(4), 3).is_ok());
+    overlay.reset();
+    assert!(overlay.pending().node_batch.is_empty());
+    assert!(overlay.tree().get_root_hash_option(3).unwrap().is_none());
+}
+
+#[test]
+fn test_overlay_tree_preimages() {
+    let db = MockTreeStore::default();
+    let key_hashes: Vec<KeyHash> = (0u8..3).map(|i| KeyHash::with::<Sha256>([i])).collect();
+    db.put_key_preimages([(key_hashes[0], vec![0]), (key_hashes[2], vec![2])]);
+
+    let overlay = OverlayTree::<_, Sha256>::new(&db);
+    assert_eq!(overlay.preimage(key_hashes[1]).unwrap(), None);
+    assert_eq!(
+        overlay.preimages(&key_hashes).unwrap(),
+        vec![Some(vec![0]), None, Some(vec![2])]
+    );
+}
@@ -0,0 +1,107 @@
+use crate::{
+    mock::MockTreeStore,
+    poseidon::{PoseidonHasher, PoseidonPermutation},
+    JellyfishMerkleTree, KeyHash, SimpleHasher,
+};
+
+/// A toy Poseidon-like permutation over the Goldilocks field, for testing only: its round
+/// constants and number of rounds are not secure.
+struct ToyPermutation;
+
+const MODULUS: u128 = 0xffff_ffff_0000_0001;
+
+fn mul(a: u64, b: u64) -> u64 {
+    (a as u128 * b as u128 % MODULUS) as u64
+}
+
+impl PoseidonPermutation for ToyPermutation {
+    type Element = u64;
+
+    const WIDTH: usize = 3;
+    const CAPACITY: usize = 1;
+    const BYTES_PER_ELEMENT: usize = 7;
+
+    fn zero() -> u64 {
+        0
+    }
+
+    fn from_le_bytes(bytes: &[u8]) -> u64 {
+        let mut buf = [0u8; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    fn to_le_bytes(element: &u64, out: &mut [u8]) {
+        out.copy_from_slice(&element.to_le_bytes()[..out.len()]);
+    }
+
+    fn add(a: &u64, b: &u64) -> u64 {
+        ((*a as u128 + *b as u128) % MODULUS) as u64
+    }
+
+    fn permute(state: &mut [u64]) {
+        let mut constant: u64 = 0x243f_6a88_85a3_08d3;
+        for _ in 0..8 {
+            for element in state.iter_mut() {
+                constant = constant
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let x = Self::add(element, &(constant % MODULUS as u64));
+                let x2 = mul(x, x);
+                let x4 = mul(x2, x2);
+                *element = mul(mul(x4, x2), x);
+            }
+            let sum = state
+                .iter()
+                .fold(0, |sum, element| Self::add(&sum, element));
+            for element in state.iter_mut() {
+                *element = Self::add(element, &sum);
+            }
+        }
+    }
+}
+
+type Hasher = PoseidonHasher<ToyPermutation>;
+
+#[test]
+fn test_poseidon_hasher_padding() {
+    assert_ne!(Hasher::hash(b""), Hasher::hash(b"\0"));
+    assert_ne!(Hasher::hash(b"a"), Hasher::hash(b"a\0"));
+    // Inputs spanning several permutations.
+    let long = [7u8; 100];
+    assert_ne!(Hasher::hash(&long[..99]), Hasher::hash(&long[..]));
+
+    let mut hasher = Hasher::new();
+    hasher.update(&long[..40]);
+    hasher.update(&long[40..]);
+    assert_eq!(hasher.finalize(), Hasher::hash(&long[..]));
+}
+
+#[test]
+fn test_poseidon_tree_proofs() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Hasher>::new(&db);
+
+    let keys: Vec<KeyHash> = (0u32..50)
+        .map(|i| KeyHash::with::<Hasher>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0[..4].to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+        assert_eq!(value, Some(key.0[..4].to_vec()));
+        proof.verify(root_hash, *key, value).unwrap();
+    }
+
+    let absent = KeyHash::with::<Hasher>(b"absent");
+    let (value, proof) = tree.get_with_proof(absent, 0).unwrap();
+    assert_eq!(value, None);
+    proof.verify_nonexistence(root_hash, absent).unwrap();
+    assert!(proof
+        .verify_existence(root_hash, absent, &keys[0].0[..4])
+        .is_err());
+}
@@ -0,0 +1,92 @@
+use sha2::Sha256;
+
+use crate::{
+    commit::{Commit, CommitTarget},
+    mock::MockTreeStore,
+    preimage_index::PreimageIndex,
+    storage::{HasPreimage, HasPreimageIndex},
+    JellyfishMerkleTree, KeyHash,
+};
+
+fn key(preimage: &[u8]) -> (KeyHash, Vec<u8>) {
+    (KeyHash::with::<Sha256>(preimage), preimage.to_vec())
+}
+
+fn indexed(index: &PreimageIndex, prefix: &[u8]) -> Vec<Vec<u8>> {
+    index
+        .preimages_with_prefix(prefix)
+        .unwrap()
+        .map(|entry| entry.unwrap().0)
+        .collect()
+}
+
+#[test]
+fn test_preimage_index() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let index = PreimageIndex::new();
+    let keys = [
+        key(b"balances/bob"),
+        key(b"validators/2"),
+        key(b"balances/alice"),
+        key(b"validators/1"),
+    ];
+
+    let (root_hash, batch) = tree
+        .put_value_set(
+            keys.iter().map(|(key_hash, _)| (*key_hash, Some(vec![1]))),
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch.clone()).unwrap();
+    index
+        .apply_commit(&Commit::new(0, root_hash, batch).with_preimages(keys.iter().cloned()))
+        .unwrap();
+    assert_eq!(
+        indexed(&index, b"validators/"),
+        vec![b"validators/1".to_vec(), b"validators/2".to_vec()]
+    );
+    assert_eq!(index.len(), 4);
+
+    // A deleted key leaves the index, and comes back without its preimage being given again.
+    let (_, batch) = tree.put_value_set(vec![(keys[1].0, None)], 1).unwrap();
+    db.write_tree_update_batch(batch.clone()).unwrap();
+    index.apply(&batch, vec![]).unwrap();
+    assert_eq!(
+        indexed(&index, b"validators/"),
+        vec![b"validators/1".to_vec()]
+    );
+    assert_eq!(index.preimage(keys[1].0).unwrap(), Some(keys[1].1.clone()));
+
+    let new_key = key(b"balances/carol");
+    let (_, batch) = tree
+        .put_value_set(
+            vec![(keys[1].0, Some(vec![2])), (new_key.0, Some(vec![3]))],
+            2,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch.clone()).unwrap();
+    index.apply(&batch, vec![new_key.clone()]).unwrap();
+    assert_eq!(
+        indexed(&index, b"balances/"),
+        vec![
+            b"balances/alice".to_vec(),
+            b"balances/bob".to_vec(),
+            b"balances/carol".to_vec(),
+        ]
+    );
+    assert_eq!(indexed(&index, b"").len(), 5);
+    assert!(indexed(&index, b"validators/3").is_empty());
+
+    // The index rebuilt from the tree matches the maintained one.
+    db.put_key_preimages(keys.iter().cloned().chain([new_key]));
+    let rebuilt = PreimageIndex::build(&tree, 2, &db).unwrap();
+    assert_eq!(indexed(&rebuilt, b""), indexed(&index, b""));
+    assert_eq!(
+        tree.iter_preimage_prefix(&rebuilt, b"balances/c", 2)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>(),
+        vec![(b"balances/carol".to_vec(), vec![3])]
+    );
+}
@@ -0,0 +1,123 @@
+use serde::{
+    de::value::{Error, SeqDeserializer, StrDeserializer},
+    de::DeserializeOwned,
+    Serialize,
+};
+use sha2::Sha256;
+
+use crate::{
+    hex_serde,
+    mock::MockTreeStore,
+    proof::{
+        ExclusionProof, NonInclusionProof, SparseMerkleDeletionProof, SparseMerkleMultiProof,
+        SparseMerkleRangeProof, SparseMerkleUpdateProof,
+    },
+    KeyHash, Sha256JMT,
+};
+
+/// Decodes the BCS encoding of `proof`, and checks that the decoded proof encodes the same way.
+fn bcs_round_trip<T: Serialize + DeserializeOwned>(proof: &T) -> T {
+    let bytes = bcs::to_bytes(proof).unwrap();
+    let decoded: T = bcs::from_bytes(&bytes).unwrap();
+    assert_eq!(bcs::to_bytes(&decoded).unwrap(), bytes);
+    decoded
+}
+
+#[test]
+fn test_proof_serde_binary() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let mut keys: Vec<KeyHash> = (0u32..30)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    keys.sort();
+    let value = |key: &KeyHash| Some(key.0.to_vec());
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, value(key))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Binary formats lay hashes out as raw bytes, and leave the hasher out.
+    let (_, proof) = tree.get_with_proof(keys[0], 0).unwrap();
+    let leaf = proof.leaf().unwrap();
+    let mut expected = vec![1];
+    expected.extend_from_slice(&leaf.key_hash().0);
+    expected.extend_from_slice(&leaf.value_hash().0);
+    expected.push(proof.siblings().len() as u8);
+    expected.extend(proof.siblings().iter().flatten());
+    assert_eq!(bcs::to_bytes(&proof).unwrap(), expected);
+    bcs_round_trip(&proof)
+        .verify(root, keys[0], value(&keys[0]))
+        .unwrap();
+
+    let missing_key = KeyHash::with::<Sha256>(100u32.to_be_bytes());
+    let exclusion_proof: ExclusionProof<Sha256> = tree
+        .get_with_exclusion_proof(missing_key, 0)
+        .unwrap()
+        .unwrap_err();
+    bcs_round_trip(&exclusion_proof)
+        .verify(root, missing_key)
+        .unwrap();
+    let non_inclusion_proof: NonInclusionProof<Sha256> =
+        tree.get_non_inclusion_proof(missing_key, 0).unwrap();
+    bcs_round_trip(&non_inclusion_proof)
+        .verify(root, missing_key)
+        .unwrap();
+
+    let multiproof_keys = [keys[3], keys[17], missing_key];
+    let (values, multiproof): (_, SparseMerkleMultiProof<Sha256>) =
+        tree.get_with_multiproof(&multiproof_keys, 0).unwrap();
+    bcs_round_trip(&multiproof)
+        .verify(root, &multiproof_keys, &values)
+        .unwrap();
+
+    let range_proof: SparseMerkleRangeProof<Sha256> = tree.get_range_proof(keys[10], 0).unwrap();
+    assert_eq!(
+        bcs_round_trip(&range_proof).right_siblings(),
+        range_proof.right_siblings()
+    );
+
+    let (new_root, update_proof, batch): (_, SparseMerkleUpdateProof<Sha256>, _) = tree
+        .put_value_set_with_update_proof([(keys[5], None), (missing_key, Some(vec![1]))], 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    bcs_round_trip(&update_proof)
+        .verify_update(
+            root,
+            new_root,
+            [(keys[5], None), (missing_key, Some(vec![1]))],
+        )
+        .unwrap();
+
+    let (deleted_value, deletion_proof): (_, SparseMerkleDeletionProof<Sha256>) =
+        tree.get_deletion_proof(keys[5], 1).unwrap();
+    bcs_round_trip(&deletion_proof)
+        .verify(root, new_root, keys[5], deleted_value)
+        .unwrap();
+}
+
+#[test]
+fn test_proof_serde_hex() {
+    let hash: [u8; 32] = core::array::from_fn(|i| i as u8 * 8);
+    let hex = hex::encode(hash);
+    assert_eq!(
+        hex_serde::hash::deserialize(StrDeserializer::<Error>::new(&hex)).unwrap(),
+        hash
+    );
+    assert_eq!(
+        hex_serde::hash::deserialize(StrDeserializer::<Error>::new(&hex.to_uppercase())).unwrap(),
+        hash
+    );
+    for invalid in [&hex[2..], &hex[..62], "not a hash"] {
+        assert!(hex_serde::hash::deserialize(StrDeserializer::<Error>::new(invalid)).is_err());
+    }
+
+    let hashes = vec![hash, [0xff; 32]];
+    let deserializer = SeqDeserializer::<_, Error>::new(hashes.iter().map(hex::encode));
+    assert_eq!(
+        hex_serde::hashes::deserialize(deserializer).unwrap(),
+        hashes
+    );
+    let deserializer = SeqDeserializer::<_, Error>::new([hex, "00".to_string()].into_iter());
+    assert!(hex_serde::hashes::deserialize(deserializer).is_err());
+}
@@ -0,0 +1,92 @@
+use prost::Message;
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    proof::{ExclusionProof, SparseMerkleProof},
+    proto::{ProtoExclusionProof, ProtoSparseMerkleLeafNode, ProtoSparseMerkleProof},
+    KeyHash, Sha256JMT,
+};
+
+#[test]
+fn test_proto_proofs() {
+    let db = MockTreeStore::default();
+    let tree = Sha256JMT::new(&db);
+    let keys: Vec<KeyHash> = (0u32..50)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (root, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(key.0.to_vec()))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    for key in &keys {
+        let (value, proof) = tree.get_with_proof(*key, 0).unwrap();
+        let message = ProtoSparseMerkleProof::from(proof);
+        let bytes = message.encode_to_vec();
+        let decoded =
+            SparseMerkleProof::<Sha256>::try_from(ProtoSparseMerkleProof::decode(&*bytes).unwrap())
+                .unwrap();
+        assert_eq!(ProtoSparseMerkleProof::from(decoded.clone()), message);
+        decoded.verify(root, *key, value).unwrap();
+    }
+
+    for i in 50u32..100 {
+        let missing_key = KeyHash::with::<Sha256>(i.to_be_bytes());
+        let (_, proof) = tree.get_with_proof(missing_key, 0).unwrap();
+        let decoded =
+            SparseMerkleProof::<Sha256>::try_from(ProtoSparseMerkleProof::from(proof.clone()))
+                .unwrap();
+        assert_eq!(decoded.leaf(), proof.leaf());
+        assert_eq!(decoded.siblings(), proof.siblings());
+        decoded.verify_nonexistence(root, missing_key).unwrap();
+
+        let proof = tree
+            .get_with_exclusion_proof(missing_key, 0)
+            .unwrap()
+            .unwrap_err();
+        let message = ProtoExclusionProof::from(proof);
+        let bytes = message.encode_to_vec();
+        let decoded =
+            ExclusionProof::<Sha256>::try_from(ProtoExclusionProof::decode(&*bytes).unwrap())
+                .unwrap();
+        assert_eq!(ProtoExclusionProof::from(decoded.clone()), message);
+        decoded.verify(root, missing_key).unwrap();
+    }
+    for missing_key in [KeyHash([0; 32]), KeyHash([0xff; 32])] {
+        let proof = tree
+            .get_with_exclusion_proof(missing_key, 0)
+            .unwrap()
+            .unwrap_err();
+        let decoded =
+            ExclusionProof::<Sha256>::try_from(ProtoExclusionProof::from(proof.clone())).unwrap();
+        assert_eq!(
+            core::mem::discriminant(&decoded),
+            core::mem::discriminant(&proof)
+        );
+        decoded.verify(root, missing_key).unwrap();
+    }
+}
+
+#[test]
+fn test_proto_proofs_malformed() {
+    let leaf_proof = |key_hash: Vec<u8>| ProtoSparseMerkleProof {
+        leaf: Some(ProtoSparseMerkleLeafNode {
+            key_hash,
+            value_hash: vec![0; 32],
+        }),
+        siblings: vec![],
+    };
+    assert!(SparseMerkleProof::<Sha256>::try_from(leaf_proof(vec![0; 32])).is_ok());
+    assert!(SparseMerkleProof::<Sha256>::try_from(leaf_proof(vec![0; 31])).is_err());
+
+    let siblings_proof = |siblings: Vec<Vec<u8>>| ProtoSparseMerkleProof {
+        leaf: None,
+        siblings,
+    };
+    assert!(SparseMerkleProof::<Sha256>::try_from(siblings_proof(vec![vec![0; 32]; 256])).is_ok());
+    assert!(SparseMerkleProof::<Sha256>::try_from(siblings_proof(vec![vec![0; 32]; 257])).is_err());
+    assert!(SparseMerkleProof::<Sha256>::try_from(siblings_proof(vec![vec![0; 33]])).is_err());
+
+    assert!(ExclusionProof::<Sha256>::try_from(ProtoExclusionProof::default()).is_err());
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    pruner::{HasVersionPins, Pruner, PruningStats, RetentionPolicy, StaleNodeIndexStore},
+    storage::NodeStats,
+    types::Version,
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[test]
+fn test_pruner() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+
+    let mut node_stats: Vec<NodeStats> = vec![];
+    for version in 0..4 {
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter()
+                    .skip(version as usize)
+                    .step_by(2)
+                    .map(|key| (*key, Some(vec![version as u8]))),
+                version,
+            )
+            .unwrap();
+        node_stats.extend(batch.node_stats.clone());
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    let expected_stats = |least_readable_version: Version| {
+        let stats = &node_stats[1..=least_readable_version as usize];
+        let pruned_nodes = stats.iter().map(|stats| stats.stale_nodes).sum();
+        let pruned_leaves = stats.iter().map(|stats| stats.stale_leaves).sum();
+        (pruned_nodes, pruned_leaves)
+    };
+
+    let pruner = Pruner::new(&db).with_batch_size(3);
+    assert_eq!(pruner.prune(0).unwrap(), PruningStats::default());
+
+    let num_nodes = db.num_nodes();
+    let stats = pruner.prune(2).unwrap();
+    assert_eq!((stats.pruned_nodes, stats.pruned_leaves), expected_stats(2));
+    assert!(stats.reclaimed_bytes > 0);
+    assert_eq!(db.num_nodes(), num_nodes - stats.pruned_nodes);
+
+    // Pruning again up to the same version has nothing left to do.
+    assert_eq!(pruner.prune(2).unwrap(), PruningStats::default());
+
+    let stats = pruner.prune(3).unwrap();
+    let (all_pruned_nodes, all_pruned_leaves) = expected_stats(3);
+    let (pruned_nodes, pruned_leaves) = expected_stats(2);
+    assert_eq!(stats.pruned_nodes, all_pruned_nodes - pruned_nodes);
+    assert_eq!(stats.pruned_leaves, all_pruned_leaves - pruned_leaves);
+
+    for (i, key) in keys.iter().enumerate() {
+        let (value, proof) = tree.get_with_proof(*key, 3).unwrap();
+        let expected_version = (0..4)
+            .rev()
+            .find(|version| *version <= i && (i - version) % 2 == 0)
+            .unwrap();
+        assert_eq!(value, Some(vec![expected_version as u8]));
+        proof
+            .verify(tree.get_root_hash(3).unwrap(), *key, value)
+            .unwrap();
+    }
+    assert!(tree.get_with_proof(keys[0], 0).is_err());
+}
+
+#[test]
+fn test_stale_node_batches() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    for version in 0..3 {
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter().map(|key| (*key, Some(vec![version as u8]))),
+                version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    let expected = db.get_stale_node_indices(1, usize::MAX).unwrap();
+    assert!(expected.len() > 7);
+
+    let pruner = Pruner::new(&db).with_batch_size(3);
+    let batches = pruner
+        .stale_node_batches(1, None)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert!(batches.iter().all(|batch| batch.len() <= 3));
+    assert_eq!(batches.concat(), expected);
+    // Nothing was deleted.
+    assert_eq!(db.get_stale_node_indices(1, usize::MAX).unwrap(), expected);
+
+    // Delete the first two batches, then resume from a persisted cursor.
+    let mut stale_node_batches = pruner.stale_node_batches(1, None);
+    for batch in stale_node_batches.by_ref().take(2) {
+        db.delete_stale_nodes(&batch.unwrap()).unwrap();
+    }
+    let cursor = stale_node_batches.cursor().cloned();
+    assert_eq!(cursor.as_ref(), Some(&expected[5]));
+    let remaining = pruner
+        .stale_node_batches(1, cursor)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(remaining.concat(), expected[6..]);
+    for batch in &remaining {
+        db.delete_stale_nodes(batch).unwrap();
+    }
+    assert!(db.get_stale_node_indices(1, usize::MAX).unwrap().is_empty());
+    assert_eq!(pruner.stale_node_batches(1, None).count(), 0);
+    tree.get_with_proof(keys[0], 2).unwrap();
+}
+
+#[test]
+fn test_pinned_versions_are_not_pruned() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    for version in 0..4 {
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter().map(|key| (*key, Some(vec![version as u8]))),
+                version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+    let pruner = Pruner::new(&db).with_version_pins(db.version_pins());
+
+    let guard = tree.pin_version(1).unwrap();
+    let other_guard = guard.clone();
+    assert!(tree.pin_version(4).is_err());
+    assert_eq!(db.version_pins().min_pinned_version(), Some(1));
+
+    // Pruning stops at the pinned version, which remains readable.
+    pruner.prune(3).unwrap();
+    let (value, _) = tree.get_with_proof(keys[0], 1).unwrap();
+    assert_eq!(value, Some(vec![1]));
+    assert!(tree.get_with_proof(keys[0], 0).is_err());
+    assert!(tree.pin_version(0).is_err());
+
+    // The version is pinned until every guard is dropped.
+    drop(guard);
+    assert!(db.version_pins().is_pinned(1));
+    assert!(pruner.stale_node_batches(3, None).all(|batch| batch
+        .unwrap()
+        .iter()
+        .all(|index| index.stale_since_version <= 1)));
+    drop(other_guard);
+    assert_eq!(db.version_pins().min_pinned_version(), None);
+
+    pruner.prune(3).unwrap();
+    assert!(tree.get_with_proof(keys[0], 1).is_err());
+    assert!(tree.pin_version(2).is_err());
+    let _guard = tree.pin_version(3).unwrap();
+}
+
+#[test]
+fn test_prune_with_policy() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    for version in 0..10 {
+        let (_, batch) = tree
+            .put_value_set(
+                keys.iter().map(|key| (*key, Some(vec![version as u8]))),
+                version,
+            )
+            .unwrap();
+        db.write_tree_update_batch(batch).unwrap();
+    }
+
+    let policy = RetentionPolicy::keep_latest(3).with_checkpoint_interval(4);
+    let retained: Vec<Version> = (0..10)
+        .filter(|version| policy.retains_version(*version, 9))
+        .collect();
+    assert_eq!(retained, vec![0, 4, 7, 8, 9]);
+
+    // Version 5 is pinned, so it is retained as well, along with the versions after it.
+    let guard = tree.pin_version(5).unwrap();
+    let pruner = Pruner::new(&db)
+        .with_batch_size(4)
+        .with_version_pins(db.version_pins());
+    assert!(pruner.prune_with_policy(&policy, 9).unwrap().pruned_nodes > 0);
+    drop(guard);
+    let stats = pruner.prune_with_policy(&policy, 9).unwrap();
+    assert!(stats.pruned_nodes > 0);
+    // Pruning again has nothing left to do, the nodes of the checkpoints being skipped.
+    assert_eq!(
+        pruner.prune_with_policy(&policy, 9).unwrap(),
+        PruningStats::default()
+    );
+
+    for version in 0..10 {
+        for key in &keys {
+            let result = tree.get_with_proof(*key, version);
+            if retained.contains(&version) {
+                let (value, proof) = result.unwrap();
+                assert_eq!(value, Some(vec![version as u8]));
+                proof
+                    .verify(tree.get_root_hash(version).unwrap(), *key, value)
+                    .unwrap();
+            } else {
+                assert!(result.is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use sha2::Sha256;
+
+use crate::{
+    pruner::{Pruner, StaleNodeIndexStore},
+    rocksdb_store::RocksDbTreeStore,
+    storage::{HasPreimage, TreeReader, TreeWriter},
+    JellyfishMerkleTree, KeyHash, SimpleHasher,
+};
+
+fn open_store() -> (tempfile::TempDir, RocksDbTreeStore) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = RocksDbTreeStore::open(dir.path()).unwrap();
+    (dir, store)
+}
+
+#[test]
+fn test_rocksdb_store_round_trip() {
+    let (_dir, db) = open_store();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+
+    let key1 = KeyHash::with::<Sha256>(b"key1");
+    let key2 = KeyHash::with::<Sha256>(b"key2");
+    let (root0, batch) = tree
+        .put_value_set(vec![(key1, Some(b"a".to_vec())), (key2, Some(vec![]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch, [(0, root0)]).unwrap();
+    let (root1, batch) = tree
+        .put_value_set(vec![(key1, None), (key2, Some(b"b".to_vec()))], 1)
+        .unwrap();
+    // The roots recorded by a batch are written with it.
+    db.write_tree_update_batch(batch, []).unwrap();
+
+    assert_eq!(tree.get(key1, 0).unwrap(), Some(b"a".to_vec()));
+    assert_eq!(tree.get(key2, 0).unwrap(), Some(vec![]));
+    assert_eq!(tree.get(key1, 1).unwrap(), None);
+    assert_eq!(tree.get(key2, 1).unwrap(), Some(b"b".to_vec()));
+
+    let (value, proof) = tree.get_with_proof(key2, 1).unwrap();
+    proof.verify(root1, key2, value.as_ref()).unwrap();
+
+    assert_eq!(db.get_root_hash_option(0).unwrap(), Some(root0));
+    assert_eq!(db.get_root_hash_option(1).unwrap(), Some(root1));
+    assert_eq!(db.get_root_hash_option(2).unwrap(), None);
+    assert_eq!(db.get_latest_version().unwrap(), Some(1));
+
+    assert!(db.get_stale_node_indices(0, usize::MAX).unwrap().is_empty());
+    let stale_node_indices = db.get_stale_node_indices(1, usize::MAX).unwrap();
+    assert!(!stale_node_indices.is_empty());
+    assert!(stale_node_indices
+        .iter()
+        .all(|index| index.stale_since_version == 1 && index.node_key.version() == 0));
+}
+
+#[test]
+fn test_rocksdb_store_rightmost_leaf() {
+    let (_dir, db) = open_store();
+    assert!(db.get_rightmost_leaf().unwrap().is_none());
+
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..10).map(|i| KeyHash([i; 32])).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 0)
+        .unwrap();
+    db.write_node_batch(&batch.node_batch).unwrap();
+
+    let (_, leaf) = db.get_rightmost_leaf().unwrap().unwrap();
+    assert_eq!(leaf.key_hash(), KeyHash([9; 32]));
+}
+
+#[test]
+fn test_rocksdb_store_preimages() {
+    let (_dir, db) = open_store();
+    let key_hash = KeyHash::with::<Sha256>(b"key");
+    assert_eq!(db.preimage(key_hash).unwrap(), None);
+    db.put_key_preimages([(key_hash, b"key".to_vec())]).unwrap();
+    assert_eq!(db.preimage(key_hash).unwrap(), Some(b"key".to_vec()));
+    let missing = KeyHash::with::<Sha256>(b"missing");
+    assert_eq!(
+        db.preimages(&[missing, key_hash]).unwrap(),
+        vec![None, Some(b"key".to_vec())]
+    );
+    assert_eq!(Sha256::hash(b"key"), key_hash.0);
+}
+
+#[test]
+fn test_rocksdb_store_pruning() {
+    let (_dir, db) = open_store();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let key = KeyHash::with::<Sha256>(b"key");
+    for version in 0..3 {
+        let (root_hash, batch) = tree
+            .put_value_set(vec![(key, Some(vec![version as u8]))], version)
+            .unwrap();
+        db.write_tree_update_batch(batch, [(version, root_hash)])
+            .unwrap();
+    }
+
+    let stats = Pruner::new(&db).prune(1).unwrap();
+    assert_eq!(stats.pruned_nodes, 1);
+    assert_eq!(stats.pruned_leaves, 1);
+    assert!(db.get_stale_node_indices(1, usize::MAX).unwrap().is_empty());
+    assert_eq!(db.get_stale_node_indices(2, usize::MAX).unwrap().len(), 1);
+    assert!(tree.get_root_hash_option(0).unwrap().is_none());
+    assert_eq!(tree.get(key, 2).unwrap(), Some(vec![2]));
+}
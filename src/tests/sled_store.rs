@@ -0,0 +1,74 @@
+use sha2::Sha256;
+
+use crate::{
+    sled_store::SledTreeStore,
+    storage::{HasPreimage, TreeReader, TreeWriter},
+    JellyfishMerkleTree, KeyHash,
+};
+
+fn open_store() -> SledTreeStore {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    SledTreeStore::new((*db).clone())
+}
+
+#[test]
+fn test_sled_store_round_trip() {
+    let db = open_store();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+
+    let key1 = KeyHash::with::<Sha256>(b"key1");
+    let key2 = KeyHash::with::<Sha256>(b"key2");
+    let (_, batch) = tree
+        .put_value_set(vec![(key1, Some(b"a".to_vec())), (key2, Some(vec![]))], 0)
+        .unwrap();
+    db.write_node_batch(&batch.node_batch).unwrap();
+    let (root1, batch) = tree
+        .put_value_set(vec![(key1, None), (key2, Some(b"b".to_vec()))], 1)
+        .unwrap();
+    db.write_node_batch(&batch.node_batch).unwrap();
+
+    assert_eq!(tree.get(key1, 0).unwrap(), Some(b"a".to_vec()));
+    assert_eq!(tree.get(key2, 0).unwrap(), Some(vec![]));
+    assert_eq!(tree.get(key1, 1).unwrap(), None);
+    assert_eq!(tree.get(key2, 1).unwrap(), Some(b"b".to_vec()));
+    assert_eq!(tree.get_root_hash(1).unwrap(), root1);
+    // The roots recorded by a batch are written with it.
+    assert_eq!(db.get_indexed_root_hash(1).unwrap(), Some(root1));
+    assert_eq!(db.get_indexed_root_hash(2).unwrap(), None);
+    db.put_root_hashes([(2, root1)]).unwrap();
+    assert_eq!(db.get_indexed_root_hash(2).unwrap(), Some(root1));
+    assert_eq!(tree.get_root_hash(1).unwrap(), root1);
+
+    let (value, proof) = tree.get_with_proof(key2, 1).unwrap();
+    proof.verify(root1, key2, value.as_ref()).unwrap();
+}
+
+#[test]
+fn test_sled_store_rightmost_leaf() {
+    let db = open_store();
+    assert!(db.get_rightmost_leaf().unwrap().is_none());
+
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..10).map(|i| KeyHash([i; 32])).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 0)
+        .unwrap();
+    db.write_node_batch(&batch.node_batch).unwrap();
+
+    let (_, leaf) = db.get_rightmost_leaf().unwrap().unwrap();
+    assert_eq!(leaf.key_hash(), KeyHash([9; 32]));
+}
+
+#[test]
+fn test_sled_store_preimages() {
+    let db = open_store();
+    let key_hash = KeyHash::with::<Sha256>(b"key");
+    assert_eq!(db.preimage(key_hash).unwrap(), None);
+    db.put_key_preimages([(key_hash, b"key".to_vec())]).unwrap();
+    assert_eq!(db.preimage(key_hash).unwrap(), Some(b"key".to_vec()));
+    let missing = KeyHash::with::<Sha256>(b"missing");
+    assert_eq!(
+        db.preimages(&[missing, key_hash]).unwrap(),
+        vec![None, Some(b"key".to_vec())]
+    );
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore, snapshot::import_snapshot, JellyfishMerkleTree, KeyHash,
+    SPARSE_MERKLE_PLACEHOLDER_HASH,
+};
+
+#[test]
+fn test_export_import_snapshot() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u16..2500)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (root_hash, batch) = tree
+        .put_value_set(
+            keys.iter()
+                .step_by(2)
+                .map(|key| (*key, Some(vec![1])))
+                .chain([(keys[1], None)]),
+            1,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    db.put_key_preimages([(keys[0], b"key0".to_vec())]);
+
+    let mut snapshot = vec![];
+    tree.export_snapshot_with_preimages(1, &db, &mut snapshot)
+        .unwrap();
+
+    let restored = Arc::new(MockTreeStore::default());
+    let mut preimages = vec![];
+    let imported = import_snapshot::<Sha256, _>(Arc::clone(&restored), snapshot.as_slice(), |p| {
+        preimages.extend(p);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(imported, (1, root_hash));
+    assert_eq!(preimages, vec![(keys[0], b"key0".to_vec())]);
+    // The restored tree only holds the nodes of version 1.
+    assert!(restored.num_nodes() < db.num_nodes());
+    let restored_tree = JellyfishMerkleTree::<_, Sha256>::new(restored.as_ref());
+    assert_eq!(restored_tree.get_root_hash(1).unwrap(), root_hash);
+    assert!(restored_tree.get_root_hash(0).is_err());
+    for key in &keys {
+        assert_eq!(
+            restored_tree.get(*key, 1).unwrap(),
+            tree.get(*key, 1).unwrap()
+        );
+    }
+
+    // Snapshots which do not match their root hash are rejected.
+    let mut tampered = vec![];
+    tree.export_snapshot(1, &mut tampered).unwrap();
+    let value_offset = 7 + 1 + 8 + 32 + 1 + 4 + 32 + 4;
+    tampered[value_offset] ^= 1;
+    assert!(import_snapshot::<Sha256, _>(
+        Arc::new(MockTreeStore::default()),
+        tampered.as_slice(),
+        |_| Ok(())
+    )
+    .is_err());
+
+    let mut trailing = vec![];
+    tree.export_snapshot(1, &mut trailing).unwrap();
+    trailing.push(0);
+    assert!(import_snapshot::<Sha256, _>(
+        Arc::new(MockTreeStore::default()),
+        trailing.as_slice(),
+        |_| Ok(())
+    )
+    .is_err());
+}
+
+#[test]
+fn test_export_import_empty_snapshot() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (root_hash, batch) = tree.put_value_set(vec![], 0).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(root_hash.0, SPARSE_MERKLE_PLACEHOLDER_HASH);
+
+    let mut snapshot = vec![];
+    tree.export_snapshot(0, &mut snapshot).unwrap();
+    let restored = Arc::new(MockTreeStore::default());
+    let imported =
+        import_snapshot::<Sha256, _>(Arc::clone(&restored), snapshot.as_slice(), |_| Ok(()))
+            .unwrap();
+    assert_eq!(imported, (0, root_hash));
+}
@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    storage::{TreeReader, TreeWriter},
+    subscription::{KeyChanges, SubscribedStore},
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[test]
+fn test_subscriptions() {
+    let store = SubscribedStore::new(MockTreeStore::default());
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&store);
+    let key1 = KeyHash([1; 32]);
+    let key2 = KeyHash([2; 32]);
+    let key3 = KeyHash([3; 32]);
+
+    let received = Arc::new(Mutex::new(vec![]));
+    let callback_received = Arc::clone(&received);
+    let callback_id = store
+        .subscribe([key1, key2], move |key_changes: &KeyChanges| {
+            callback_received.lock().unwrap().push(key_changes.clone())
+        })
+        .unwrap();
+    let (channel_id, receiver) = store.subscribe_channel([key2, key3]).unwrap();
+
+    // Two versions in one batch: the old values at the second version come from the first.
+    let (_, batch) = tree
+        .put_value_sets(
+            vec![
+                vec![(key1, Some(vec![1])), (key3, Some(vec![3]))],
+                vec![(key1, Some(vec![4])), (key2, Some(vec![2]))],
+            ],
+            0,
+        )
+        .unwrap();
+    store.write_node_batch(&batch.node_batch).unwrap();
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![
+            KeyChanges {
+                version: 0,
+                changes: vec![(key1, None, Some(vec![1]))],
+            },
+            KeyChanges {
+                version: 1,
+                changes: vec![
+                    (key1, Some(vec![1]), Some(vec![4])),
+                    (key2, None, Some(vec![2]))
+                ],
+            },
+        ]
+    );
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![
+            KeyChanges {
+                version: 0,
+                changes: vec![(key3, None, Some(vec![3]))],
+            },
+            KeyChanges {
+                version: 1,
+                changes: vec![(key2, None, Some(vec![2]))],
+            },
+        ]
+    );
+
+    // Rewriting a value is not a change, and unsubscribed callbacks are not called.
+    assert!(store.unsubscribe(callback_id).unwrap());
+    assert!(!store.unsubscribe(callback_id).unwrap());
+    let (_, batch) = tree
+        .put_value_set(vec![(key1, None), (key2, Some(vec![2])), (key3, None)], 2)
+        .unwrap();
+    store.write_node_batch(&batch.node_batch).unwrap();
+    assert_eq!(received.lock().unwrap().len(), 2);
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![KeyChanges {
+            version: 2,
+            changes: vec![(key3, Some(vec![3]), None)],
+        }]
+    );
+
+    // Dropping the receiver ends the channel subscription at the next notification.
+    drop(receiver);
+    let (_, batch) = tree.put_value_set(vec![(key3, Some(vec![5]))], 3).unwrap();
+    store.write_node_batch(&batch.node_batch).unwrap();
+    assert!(!store.unsubscribe(channel_id).unwrap());
+    assert_eq!(
+        store.inner().get_value_option(3, key3).unwrap(),
+        Some(vec![5])
+    );
+}
@@ -0,0 +1,79 @@
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    storage::{NibblePath, Node, NodeKey, TreeReader},
+    subtree::import_subtree,
+    JellyfishMerkleTree, KeyHash, SPARSE_MERKLE_PLACEHOLDER_HASH,
+};
+
+fn key_hash(first_byte: u8, i: u8) -> KeyHash {
+    let mut key_hash = KeyHash::with::<Sha256>([i]);
+    key_hash.0[0] = first_byte;
+    key_hash
+}
+
+#[test]
+fn test_export_import_subtree() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| key_hash(0x12, i)).collect();
+    let (_, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let other = key_hash(0x34, 0);
+    let (root_hash, batch) = tree
+        .put_value_set([(keys[0], Some(vec![1])), (other, Some(vec![2]))], 1)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // The subtree of the first nibble 1 holds all the keys but one, written at both versions.
+    let prefix = NibblePath::new_odd(vec![0x10]);
+    let subtree = tree.export_subtree(&prefix, 1).unwrap();
+    assert_eq!(subtree.values.len(), keys.len());
+    let Node::Internal(root) = db.get_node(&NodeKey::new_empty_path(1)).unwrap() else {
+        panic!("The root must be an internal node.");
+    };
+    let subtree_root_hash = root.child(1.into()).unwrap().hash;
+    assert_eq!(subtree.root_hash, subtree_root_hash);
+
+    let replica = MockTreeStore::default();
+    import_subtree::<Sha256>(&replica, &subtree, subtree_root_hash).unwrap();
+    assert_eq!(replica.num_nodes(), subtree.nodes.len());
+    assert!(import_subtree::<Sha256>(&replica, &subtree, [0; 32]).is_err());
+    let mut tampered = subtree.clone();
+    tampered.values[0].2 = vec![3];
+    assert!(tampered.verify::<Sha256>(subtree_root_hash).is_err());
+    let mut tampered = subtree.clone();
+    tampered.nodes.pop();
+    assert!(tampered.verify::<Sha256>(subtree_root_hash).is_err());
+
+    // A key stored in a leaf above the prefix is the root of the subtree.
+    let subtree = tree
+        .export_subtree(&NibblePath::new(vec![0x34]), 1)
+        .unwrap();
+    assert_eq!(subtree.values, vec![(1, other, vec![2])]);
+    subtree.verify::<Sha256>(subtree.root_hash).unwrap();
+
+    let subtree = tree
+        .export_subtree(&NibblePath::new(vec![0x56]), 1)
+        .unwrap();
+    assert!(subtree.nodes.is_empty());
+    subtree
+        .verify::<Sha256>(SPARSE_MERKLE_PLACEHOLDER_HASH)
+        .unwrap();
+
+    // The whole tree can be replicated from the subtree with an empty prefix.
+    let subtree = tree.export_subtree(&NibblePath::new(vec![]), 1).unwrap();
+    let replica = MockTreeStore::default();
+    import_subtree::<Sha256>(&replica, &subtree, root_hash.0).unwrap();
+    let replica_tree = JellyfishMerkleTree::<_, Sha256>::new(&replica);
+    assert_eq!(replica_tree.get_root_hash(1).unwrap(), root_hash);
+    for key in keys.iter().chain([&other]) {
+        assert_eq!(
+            replica_tree.get(*key, 1).unwrap(),
+            tree.get(*key, 1).unwrap()
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::io::Cursor;
+
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    node_type::NodeKey,
+    storage::TreeReader,
+    trace::{read_trace, replay, TraceRead, TraceRecorder, UNTAGGED},
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[test]
+fn test_trace_record_and_replay() {
+    let db = MockTreeStore::default();
+    let keys: Vec<KeyHash> = (0u8..20).map(|i| KeyHash([i; 32])).collect();
+    let (root, batch) = JellyfishMerkleTree::<_, Sha256>::new(&db)
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![1]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let recorder = TraceRecorder::new(&db, vec![]);
+    let (value, proof) = JellyfishMerkleTree::<_, Sha256>::new(&recorder.tagged("proof"))
+        .get_with_proof(keys[3], 0)
+        .unwrap();
+    proof.verify(root, keys[3], value).unwrap();
+    let values = JellyfishMerkleTree::<_, Sha256>::new(&recorder)
+        .multi_get(&keys[..5], 0)
+        .unwrap();
+    assert!(values.iter().all(|value| *value == Some(vec![1])));
+    let root_key = NodeKey::new_empty_path(0);
+    assert!(recorder.tagged("a tag").get_node_option(&root_key).is_err());
+
+    let (_, trace) = recorder.into_inner().unwrap();
+    let events = read_trace(Cursor::new(trace)).unwrap();
+    assert!(events.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+    let (proof_events, untagged_events): (Vec<_>, Vec<_>) =
+        events.iter().partition(|event| event.tag == "proof");
+    assert!(untagged_events.iter().all(|event| event.tag == UNTAGGED));
+    assert!(matches!(proof_events[0].read, TraceRead::Node(_)));
+    assert_eq!(
+        proof_events.last().unwrap().read,
+        TraceRead::Value {
+            max_version: 0,
+            key_hash: keys[3]
+        }
+    );
+    assert!(untagged_events
+        .iter()
+        .any(|event| matches!(event.read, TraceRead::Nodes(_))));
+
+    // Replaying against the same store reads the same nodes and values, all present.
+    let stats = replay(&db, &events).unwrap();
+    assert_eq!(stats.missing_nodes, 0);
+    assert_eq!(stats.value_reads, 6);
+    assert_eq!(stats.missing_values, 0);
+    assert_eq!(stats.elapsed_by_tag.len(), 2);
+
+    // Against an empty store, everything is missing.
+    let stats = replay(&MockTreeStore::default(), &events).unwrap();
+    assert_eq!(stats.missing_nodes, stats.node_reads);
+    assert_eq!(stats.missing_values, 6);
+
+    assert!(read_trace(Cursor::new("12 tag leaf 00")).is_err());
+}
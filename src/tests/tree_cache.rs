@@ -7,7 +7,7 @@ use sha2::Sha256;
 use crate::{
     mock::MockTreeStore,
     node_type::{Node, NodeKey},
-    storage::LeafNode,
+    storage::{LeafNode, StaleNodeKind},
     tree_cache::TreeCache,
     types::{nibble::nibble_path::NibblePath, Version, PRE_GENESIS_VERSION},
     KeyHash, OwnedValue, ValueHash,
@@ -79,18 +79,41 @@ fn test_freeze_with_delete() {
     cache.put_node(node1_key.clone(), node1.clone()).unwrap();
     let (node2, _, node2_key) = random_leaf_with_key(next_version);
     let node2: Node = node2.into();
-    cache
-        .put_node(node2_key.clone(), node2.clone().into())
-        .unwrap();
+    cache.put_node(node2_key.clone(), node2.clone()).unwrap();
     assert_eq!(cache.get_node(&node1_key).unwrap(), node1);
     assert_eq!(cache.get_node(&node2_key).unwrap(), node2);
-    cache.freeze().unwrap();
+    cache.freeze::<Sha256>().unwrap();
     assert_eq!(cache.get_node(&node1_key).unwrap(), node1);
     assert_eq!(cache.get_node(&node2_key).unwrap(), node2);
 
-    cache.delete_node(&node1_key, true /* is_leaf */);
-    cache.freeze().unwrap();
+    cache.delete_node(&node1_key, StaleNodeKind::of(&node1));
+    cache.freeze::<Sha256>().unwrap();
     let (_, update_batch) = cache.into();
     assert_eq!(update_batch.node_batch.nodes().len(), 3);
     assert_eq!(update_batch.stale_node_index_batch.len(), 1);
 }
+
+#[test]
+fn test_values_are_not_copied() {
+    let next_version = 0;
+    let db = MockTreeStore::default();
+    let mut cache = TreeCache::new(&db, next_version).unwrap();
+
+    let (leaf, value, _) = random_leaf_with_key(next_version);
+    let put_value = value.clone();
+    let value_ptr = put_value.as_ptr();
+    cache.put_value(next_version, leaf.key_hash(), Some(put_value));
+    let cached_value = cache.get_value_option(leaf.key_hash()).unwrap().unwrap();
+    assert_eq!(cached_value, value);
+    assert_eq!(cached_value.as_ptr(), value_ptr);
+    cache.freeze::<Sha256>().unwrap();
+    let frozen_value = cache.get_value_option(leaf.key_hash()).unwrap().unwrap();
+    assert_eq!(frozen_value.as_ptr(), value_ptr);
+
+    // The value moved into the cache is the one handed to the writer.
+    let (_, update_batch) = cache.into();
+    let batched_value = update_batch.node_batch.values()[&(next_version, leaf.key_hash())]
+        .as_ref()
+        .unwrap();
+    assert_eq!(batched_value.as_ptr(), value_ptr);
+}
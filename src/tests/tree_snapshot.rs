@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore, pruner::VersionPins, tree_snapshot::TreeSnapshot, KeyHash,
+    MissingRootError, Sha256JMT,
+};
+
+#[test]
+fn test_tree_snapshot() {
+    let db = Arc::new(MockTreeStore::default());
+    let tree = Sha256JMT::new(&*db);
+    let alice = KeyHash::with::<Sha256>(b"alice");
+    let bob = KeyHash::with::<Sha256>(b"bob");
+    let (root_hash, batch) = tree
+        .put_value_set([(alice, Some(b"1".to_vec()))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let snapshot = tree.snapshot(0).unwrap();
+    assert_eq!((snapshot.version(), snapshot.root_hash()), (0, root_hash));
+    assert!(!snapshot.is_pinned());
+    assert!(tree
+        .snapshot(1)
+        .err()
+        .unwrap()
+        .downcast_ref::<MissingRootError>()
+        .is_some());
+
+    // Snapshots owning a shared reader are handed to other threads, while the tree moves on.
+    let version_pins = VersionPins::new();
+    let shared = TreeSnapshot::<_, Sha256>::new_pinned(db.clone(), 0, &version_pins).unwrap();
+    assert!(shared.is_pinned() && version_pins.is_pinned(0));
+    let (_, batch) = tree
+        .put_value_set(
+            [(alice, Some(b"2".to_vec())), (bob, Some(b"3".to_vec()))],
+            1,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let handle = std::thread::spawn(move || {
+        let (value, proof) = shared.get_with_proof(alice).unwrap();
+        proof
+            .verify(shared.root_hash(), alice, value.as_ref())
+            .unwrap();
+        assert_eq!(value, Some(b"1".to_vec()));
+        assert_eq!(shared.get(bob).unwrap(), None);
+        let leaves: Vec<_> = shared.iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(leaves, vec![(alice, b"1".to_vec())]);
+    });
+    handle.join().unwrap();
+    // The pin is released with the snapshot.
+    assert!(!version_pins.is_pinned(0));
+
+    let snapshot = tree.snapshot(1).unwrap();
+    assert_eq!(snapshot.get(alice).unwrap(), Some(b"2".to_vec()));
+    assert_eq!(
+        snapshot.iter_from(bob).unwrap().count(),
+        1 + (bob < alice) as usize
+    );
+}
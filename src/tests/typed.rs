@@ -0,0 +1,77 @@
+use anyhow::{ensure, Result};
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    typed::{TypedTree, ValueCodec},
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[derive(Debug, PartialEq)]
+struct Balance {
+    amount: u64,
+}
+
+impl ValueCodec for Balance {
+    fn encode_value(&self) -> Result<Vec<u8>> {
+        Ok(self.amount.to_be_bytes().to_vec())
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        ensure!(bytes.len() == 8, "Invalid balance {:?}.", bytes);
+        Ok(Self {
+            amount: u64::from_be_bytes(bytes.try_into()?),
+        })
+    }
+}
+
+#[test]
+fn test_typed_tree() {
+    let db = MockTreeStore::default();
+    let tree = TypedTree::<_, Balance, Sha256>::new(&db);
+    let alice = KeyHash::with::<Sha256>(b"alice");
+    let bob = KeyHash::with::<Sha256>(b"bob");
+
+    let (root_hash, batch) = tree
+        .put_value_set(
+            [
+                (alice, Some(Balance { amount: 10 })),
+                (bob, Some(Balance { amount: 20 })),
+            ],
+            0,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(alice, 0).unwrap(), Some(Balance { amount: 10 }));
+    assert_eq!(tree.get_root_hash(0).unwrap(), root_hash);
+
+    // The tree commits to the encoded values.
+    let (untyped_root_hash, _) = JellyfishMerkleTree::<_, Sha256>::new(&MockTreeStore::default())
+        .put_value_set(
+            [
+                (alice, Some(10u64.to_be_bytes().to_vec())),
+                (bob, Some(20u64.to_be_bytes().to_vec())),
+            ],
+            0,
+        )
+        .unwrap();
+    assert_eq!(untyped_root_hash, root_hash);
+
+    let (balance, proof) = tree.get_with_proof(bob, 0).unwrap();
+    let balance = balance.unwrap();
+    proof
+        .verify_existence(root_hash, bob, balance.encode_value().unwrap())
+        .unwrap();
+
+    let (_, batch) = tree.put_value_set([(alice, None)], 1).unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert_eq!(tree.get(alice, 1).unwrap(), None);
+
+    // Bytes which are not a valid encoding cannot be read.
+    let (_, batch) = tree
+        .inner()
+        .put_value_set([(alice, Some(vec![1, 2, 3]))], 2)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    assert!(tree.get(alice, 2).is_err());
+}
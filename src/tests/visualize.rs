@@ -0,0 +1,70 @@
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    storage::{Nibble, NibblePath},
+    visualize::RenderFormat,
+    JellyfishMerkleTree, KeyHash,
+};
+
+#[test]
+fn test_render() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let (_, batch) = tree
+        .put_value_set([(KeyHash([0x00; 32]), Some(vec![0]))], 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+    let (_, batch) = tree
+        .put_value_set(
+            [
+                (KeyHash([0x11; 32]), Some(vec![1])),
+                (KeyHash([0x12; 32]), Some(vec![2])),
+            ],
+            1,
+        )
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    assert_eq!(
+        tree.render(0, RenderFormat::Ascii).unwrap(),
+        "leaf [] v0 #c429018c key 00000000 value 6e340b9c\n"
+    );
+    assert_eq!(
+        tree.render(1, RenderFormat::Ascii).unwrap(),
+        "internal [] v1 #69c7d7b8\n\
+        \x20 0: leaf [0] v1 #c429018c key 00000000 value 6e340b9c\n\
+        \x20 1: internal [1] v1 #c4471481\n\
+        \x20   1: leaf [11] v1 #1e6f147d key 11111111 value 4bf5122f\n\
+        \x20   2: leaf [12] v1 #70a699de key 12121212 value dbc1b4c9\n"
+    );
+    assert_eq!(
+        tree.render(1, RenderFormat::Dot).unwrap(),
+        "digraph jmt {\n\
+        \x20   node [shape=box];\n\
+        \x20   n0 [label=\"internal [] v1 #69c7d7b8\"];\n\
+        \x20   n1 [label=\"leaf [0] v1 #c429018c key 00000000 value 6e340b9c\"];\n\
+        \x20   n0 -> n1 [label=\"0\"];\n\
+        \x20   n2 [label=\"internal [1] v1 #c4471481\"];\n\
+        \x20   n3 [label=\"leaf [11] v1 #1e6f147d key 11111111 value 4bf5122f\"];\n\
+        \x20   n2 -> n3 [label=\"1\"];\n\
+        \x20   n4 [label=\"leaf [12] v1 #70a699de key 12121212 value dbc1b4c9\"];\n\
+        \x20   n2 -> n4 [label=\"2\"];\n\
+        \x20   n0 -> n2 [label=\"1\"];\n\
+        }\n"
+    );
+
+    let prefix: NibblePath = [Nibble::from(1)].into_iter().collect();
+    assert_eq!(
+        tree.render_subtree(&prefix, 1, RenderFormat::Ascii)
+            .unwrap(),
+        "internal [1] v1 #c4471481\n\
+        \x20 1: leaf [11] v1 #1e6f147d key 11111111 value 4bf5122f\n\
+        \x20 2: leaf [12] v1 #70a699de key 12121212 value dbc1b4c9\n"
+    );
+    let prefix: NibblePath = [Nibble::from(2)].into_iter().collect();
+    assert_eq!(
+        tree.render_subtree(&prefix, 1, RenderFormat::Dot).unwrap(),
+        "digraph jmt {\n    node [shape=box];\n}\n"
+    );
+}
@@ -0,0 +1,187 @@
+use std::{cell::RefCell, collections::BTreeSet};
+
+use anyhow::Result;
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    node_type::{LeafNode, Node, NodeKey},
+    storage::TreeReader,
+    witness::{compute_root_from_witness, Witness, WitnessTree},
+    JellyfishMerkleTree, KeyHash, OwnedValue, Version,
+};
+
+/// A store recording the nodes and values read from it.
+struct RecordingStore<'a> {
+    store: &'a MockTreeStore,
+    nodes: RefCell<BTreeSet<NodeKey>>,
+    values: RefCell<BTreeSet<KeyHash>>,
+}
+
+impl TreeReader for RecordingStore<'_> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.nodes.borrow_mut().insert(node_key.clone());
+        self.store.get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.values.borrow_mut().insert(key_hash);
+        self.store.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+}
+
+#[test]
+fn test_witness_tree() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u32..100)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    // Reads of some keys, then updates, deletions and insertions of others.
+    let mut value_set: Vec<(KeyHash, Option<OwnedValue>)> = keys[5..10]
+        .iter()
+        .map(|key| (*key, Some(vec![1])))
+        .collect();
+    value_set.extend(keys[10..13].iter().map(|key| (*key, None)));
+    value_set
+        .extend((100u32..105).map(|i| (KeyHash::with::<Sha256>(i.to_be_bytes()), Some(vec![2]))));
+    let recorder = RecordingStore {
+        store: &db,
+        nodes: Default::default(),
+        values: Default::default(),
+    };
+    let recorded_tree = JellyfishMerkleTree::<_, Sha256>::new(&recorder);
+    for key in &keys[..5] {
+        recorded_tree.get_with_proof(*key, 0).unwrap();
+    }
+    let (new_root_hash, _) = recorded_tree.put_value_set(value_set.clone(), 1).unwrap();
+
+    let nodes: Vec<(NodeKey, Node)> = recorder
+        .nodes
+        .borrow()
+        .iter()
+        .filter_map(|node_key| Some((node_key.clone(), db.get_node_option(node_key).unwrap()?)))
+        .collect();
+    let values: Vec<(KeyHash, OwnedValue)> = recorder
+        .values
+        .borrow()
+        .iter()
+        .map(|key| (*key, db.get_value(0, *key).unwrap()))
+        .collect();
+
+    let mut witness_tree = WitnessTree::<Sha256>::new(0, nodes.clone(), values.clone()).unwrap();
+    assert_eq!(witness_tree.root_hash().unwrap(), root_hash);
+    for key in &keys[..5] {
+        assert_eq!(witness_tree.get(*key).unwrap(), Some(vec![0]));
+    }
+    // The keys whose leaves are not witnessed cannot be read.
+    let witnessed_leaves: BTreeSet<KeyHash> = nodes
+        .iter()
+        .filter_map(|(_, node)| match node {
+            Node::Leaf(leaf) => Some(leaf.key_hash()),
+            _ => None,
+        })
+        .collect();
+    for key in keys[13..]
+        .iter()
+        .filter(|key| !witnessed_leaves.contains(key))
+    {
+        assert!(witness_tree.get(*key).is_err());
+    }
+    // Nor updated, which leaves the tree unchanged.
+    assert!(witness_tree.put_value_set([(keys[99], None)], 1).is_err());
+    assert!(witness_tree.put_value_set(value_set.clone(), 2).is_err());
+
+    assert_eq!(
+        witness_tree.put_value_set(value_set.clone(), 1).unwrap(),
+        new_root_hash
+    );
+    assert_eq!(witness_tree.version(), Some(1));
+    assert_eq!(witness_tree.root_hash().unwrap(), new_root_hash);
+    // The paths to the written keys only go through new nodes.
+    for (key, value) in value_set.iter().filter(|(_, value)| value.is_some()) {
+        assert_eq!(witness_tree.get(*key).unwrap(), *value);
+    }
+
+    // An inconsistent witness is rejected.
+    let mut tampered_values = values.clone();
+    tampered_values[0].1.push(0);
+    assert!(WitnessTree::<Sha256>::new(0, nodes.clone(), tampered_values).is_err());
+    let root_key = NodeKey::new_empty_path(0);
+    let without_root = nodes.iter().filter(|(node_key, _)| *node_key != root_key);
+    assert!(WitnessTree::<Sha256>::new(0, without_root.cloned(), values.clone()).is_err());
+    let mut tampered_nodes = nodes.clone();
+    let (_, leaf) = tampered_nodes
+        .iter_mut()
+        .find(|(_, node)| matches!(node, Node::Leaf(_)))
+        .unwrap();
+    *leaf = Node::new_leaf(keys[99], crate::ValueHash([0; 32]));
+    assert!(WitnessTree::<Sha256>::new(0, tampered_nodes, values).is_err());
+
+    // A tree can also be built from scratch.
+    let mut empty_tree = WitnessTree::<Sha256>::empty();
+    assert_eq!(
+        empty_tree
+            .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+            .unwrap(),
+        root_hash
+    );
+}
+
+#[test]
+fn test_generate_witness() {
+    let db = MockTreeStore::default();
+    let tree = JellyfishMerkleTree::<_, Sha256>::new(&db);
+    let keys: Vec<KeyHash> = (0u32..200)
+        .map(|i| KeyHash::with::<Sha256>(i.to_be_bytes()))
+        .collect();
+    let (root_hash, batch) = tree
+        .put_value_set(keys.iter().map(|key| (*key, Some(vec![0]))), 0)
+        .unwrap();
+    db.write_tree_update_batch(batch).unwrap();
+
+    let missing_key = KeyHash::with::<Sha256>(b"missing");
+    let reads = [keys[0], keys[1], keys[1], keys[2], missing_key];
+    let mut value_set: Vec<(KeyHash, Option<OwnedValue>)> = keys[1..20]
+        .iter()
+        .map(|key| (*key, Some(vec![1])))
+        .collect();
+    value_set.extend(keys[20..25].iter().map(|key| (*key, None)));
+    value_set.push((missing_key, Some(vec![2])));
+    let witness = tree.generate_witness(0, &reads, value_set.clone()).unwrap();
+    assert_eq!(witness.version(), 0);
+    assert!(witness.num_nodes() < db.num_nodes() / 2);
+    assert_eq!(witness.values().len(), 3);
+
+    let (new_root_hash, _) = tree.put_value_set(value_set.clone(), 1).unwrap();
+    let witness: Witness = bcs::from_bytes(&bcs::to_bytes(&witness).unwrap()).unwrap();
+    assert_eq!(witness.root_hash::<Sha256>().unwrap(), root_hash);
+    assert_eq!(
+        compute_root_from_witness::<Sha256>(&witness, value_set.clone()).unwrap(),
+        new_root_hash
+    );
+    // Updates outside of the witness fail.
+    assert!(compute_root_from_witness::<Sha256>(&witness, [(keys[199], None)]).is_err());
+    let mut witness_tree = WitnessTree::<Sha256>::from_witness(&witness).unwrap();
+    assert_eq!(witness_tree.root_hash().unwrap(), root_hash);
+    for key in reads {
+        assert_eq!(witness_tree.get(key).unwrap(), tree.get(key, 0).unwrap());
+    }
+    assert_eq!(
+        witness_tree.put_value_set(value_set, 1).unwrap(),
+        new_root_hash
+    );
+}
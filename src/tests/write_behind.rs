@@ -0,0 +1,102 @@
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use sha2::Sha256;
+
+use crate::{
+    mock::MockTreeStore,
+    node_type::{LeafNode, Node, NodeKey},
+    storage::{NodeBatch, TreeReader, TreeWriter},
+    write_behind::WriteBehindTree,
+    KeyHash, OwnedValue, RootHash, Sha256JMT, Version,
+};
+
+/// A store whose writes wait for `gate`, and fail once `fail` is set.
+#[derive(Default)]
+struct GatedStore {
+    inner: MockTreeStore,
+    gate: Mutex<()>,
+    fail: Mutex<bool>,
+}
+
+impl TreeReader for GatedStore {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.inner.get_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.inner.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.inner.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.inner.get_indexed_root_hashes(versions, limit)
+    }
+}
+
+impl TreeWriter for GatedStore {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        let _gate = self.gate.lock().unwrap();
+        if *self.fail.lock().unwrap() {
+            bail!("disk full");
+        }
+        self.inner.write_node_batch(node_batch)
+    }
+}
+
+#[test]
+fn test_write_behind_tree() {
+    let store = Arc::new(GatedStore::default());
+    let tree = WriteBehindTree::<_, Sha256>::new(store.clone(), 2);
+    let keys: Vec<KeyHash> = (0..4u8).map(|i| KeyHash([i; 32])).collect();
+
+    // Writes are held back, while commits and reads go on in memory.
+    let gate = store.gate.lock().unwrap();
+    let (root_hash, _) = tree.put_value_set([(keys[0], Some(vec![0]))], 0).unwrap();
+    let (_, stale) = tree.put_value_set([(keys[0], Some(vec![1]))], 1).unwrap();
+    assert_eq!(stale.len(), 1);
+    assert_eq!(tree.num_pending().unwrap(), 2);
+    assert_eq!(tree.tree().get(keys[0], 0).unwrap(), Some(vec![0]));
+    assert_eq!(tree.tree().get(keys[0], 1).unwrap(), Some(vec![1]));
+    assert_eq!(tree.tree().get_root_hash(0).unwrap(), root_hash);
+    assert_eq!(Sha256JMT::new(&store.inner).get(keys[0], 1).unwrap(), None);
+
+    // Commits block once too many batches are pending.
+    std::thread::scope(|scope| {
+        let commit = scope.spawn(|| tree.put_value_set([(keys[1], Some(vec![2]))], 2));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!commit.is_finished());
+        drop(gate);
+        commit.join().unwrap().unwrap();
+    });
+    tree.flush().unwrap();
+    assert_eq!(tree.num_pending().unwrap(), 0);
+    let written = Sha256JMT::new(&store.inner);
+    assert_eq!(written.get(keys[0], 2).unwrap(), Some(vec![1]));
+    assert_eq!(written.get(keys[1], 2).unwrap(), Some(vec![2]));
+    assert_eq!(
+        written.get_root_hash(2).unwrap(),
+        tree.tree().get_root_hash(2).unwrap()
+    );
+
+    // A failed write stops the pipeline.
+    *store.fail.lock().unwrap() = true;
+    tree.put_value_set([(keys[2], Some(vec![3]))], 3).unwrap();
+    assert!(tree.flush().is_err());
+    assert!(tree.put_value_set([(keys[3], Some(vec![4]))], 4).is_err());
+}
@@ -0,0 +1,335 @@
+//! Recording of the reads made by a tree, and their replay against another storage.
+//!
+//! A [`TraceRecorder`] wraps the storage of a tree, and writes every node and value read through it
+//! to a trace, together with the time elapsed since the recorder was created and a tag naming the
+//! operation which made the read. The trace can later be parsed with [`read_trace`] and replayed
+//! with [`replay`] against any [`TreeReader`], to benchmark storage backends against the access
+//! patterns of a production workload.
+//!
+//! A trace is a text file with one read per line, made of whitespace-separated fields:
+//!
+//! ```text
+//! <elapsed microseconds> <tag> node <encoded node key as hex>
+//! <elapsed microseconds> <tag> nodes <encoded node keys as hex, comma-separated>
+//! <elapsed microseconds> <tag> value <max version> <key hash as hex>
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    ops::Range,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure, format_err, Context, Result};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::TreeReader,
+    KeyHash, OwnedValue, RootHash, Version,
+};
+
+/// The tag of the reads made directly through a [`TraceRecorder`] rather than through
+/// [`TraceRecorder::tagged`].
+pub const UNTAGGED: &str = "untagged";
+
+/// A read recorded in a trace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TraceRead {
+    /// A single node, read with [`TreeReader::get_node_option`].
+    Node(NodeKey),
+    /// A batch of nodes, read with [`TreeReader::get_nodes`].
+    Nodes(Vec<NodeKey>),
+    /// A value, read with [`TreeReader::get_value_option`].
+    Value {
+        max_version: Version,
+        key_hash: KeyHash,
+    },
+}
+
+/// A line of a trace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// The time elapsed between the creation of the recorder and the read.
+    pub elapsed: Duration,
+    /// The tag of the operation which made the read.
+    pub tag: String,
+    /// The read.
+    pub read: TraceRead,
+}
+
+impl TraceEvent {
+    fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        write!(writer, "{} {} ", self.elapsed.as_micros(), self.tag)?;
+        match &self.read {
+            TraceRead::Node(node_key) => {
+                writeln!(writer, "node {}", hex::encode(node_key.encode()?))
+            }
+            TraceRead::Nodes(node_keys) => {
+                let encoded = node_keys
+                    .iter()
+                    .map(|node_key| Ok(hex::encode(node_key.encode()?)))
+                    .collect::<Result<Vec<_>>>()?;
+                writeln!(writer, "nodes {}", encoded.join(","))
+            }
+            TraceRead::Value {
+                max_version,
+                key_hash,
+            } => writeln!(writer, "value {} {}", max_version, hex::encode(key_hash.0)),
+        }?;
+        Ok(())
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        ensure!(fields.len() >= 4, "Too few fields.");
+        let elapsed = Duration::from_micros(fields[0].parse()?);
+        let tag = fields[1].to_string();
+        let read = match (fields[2], &fields[3..]) {
+            ("node", [node_key]) => TraceRead::Node(decode_node_key(node_key)?),
+            ("nodes", [node_keys]) => TraceRead::Nodes(
+                node_keys
+                    .split(',')
+                    .map(decode_node_key)
+                    .collect::<Result<_>>()?,
+            ),
+            ("value", [max_version, key_hash]) => {
+                let mut hash = [0; 32];
+                hex::decode_to_slice(key_hash, &mut hash)?;
+                TraceRead::Value {
+                    max_version: max_version.parse()?,
+                    key_hash: KeyHash(hash),
+                }
+            }
+            (kind, _) => bail!("Unexpected read {:?}.", kind),
+        };
+        Ok(Self { elapsed, tag, read })
+    }
+}
+
+fn decode_node_key(encoded: &str) -> Result<NodeKey> {
+    NodeKey::decode(&hex::decode(encoded)?)
+}
+
+/// A [`TreeReader`] recording every node and value read from another [`TreeReader`] to a trace.
+///
+/// Reads made directly through the recorder are tagged [`UNTAGGED`]; reads made through a view
+/// returned by [`tagged`](TraceRecorder::tagged) carry the tag of the view.
+pub struct TraceRecorder<R, W> {
+    reader: R,
+    writer: Mutex<W>,
+    start: Instant,
+}
+
+impl<R, W: Write> TraceRecorder<R, W> {
+    /// Creates a recorder of the reads made from `reader`, writing the trace to `writer`.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer: Mutex::new(writer),
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns a view of the recorder tagging its reads with `tag`, which cannot be empty nor
+    /// contain whitespace.
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> TaggedTraceRecorder<'a, R, W> {
+        TaggedTraceRecorder {
+            recorder: self,
+            tag,
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn inner(&self) -> &R {
+        &self.reader
+    }
+
+    /// Flushes the trace.
+    pub fn flush(&self) -> Result<()> {
+        self.lock()?.flush()?;
+        Ok(())
+    }
+
+    /// Returns the underlying reader and the writer of the trace, once flushed.
+    pub fn into_inner(self) -> Result<(R, W)> {
+        let mut writer = self
+            .writer
+            .into_inner()
+            .map_err(|_| format_err!("The trace lock is poisoned."))?;
+        writer.flush()?;
+        Ok((self.reader, writer))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, W>> {
+        self.writer
+            .lock()
+            .map_err(|_| format_err!("The trace lock is poisoned."))
+    }
+
+    fn record(&self, tag: &str, read: TraceRead) -> Result<()> {
+        ensure!(
+            !tag.is_empty() && !tag.contains(char::is_whitespace),
+            "Invalid trace tag {:?}.",
+            tag
+        );
+        let event = TraceEvent {
+            elapsed: self.start.elapsed(),
+            tag: tag.to_string(),
+            read,
+        };
+        event.write_to(&mut *self.lock()?)
+    }
+}
+
+/// A view of a [`TraceRecorder`] tagging its reads, see [`TraceRecorder::tagged`].
+pub struct TaggedTraceRecorder<'a, R, W> {
+    recorder: &'a TraceRecorder<R, W>,
+    tag: &'a str,
+}
+
+impl<R: TreeReader, W: Write> TreeReader for TaggedTraceRecorder<'_, R, W> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.recorder
+            .record(self.tag, TraceRead::Node(node_key.clone()))?;
+        self.recorder.reader.get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        self.recorder
+            .record(self.tag, TraceRead::Nodes(node_keys.to_vec()))?;
+        self.recorder.reader.get_nodes(node_keys)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.recorder.record(
+            self.tag,
+            TraceRead::Value {
+                max_version,
+                key_hash,
+            },
+        )?;
+        self.recorder.reader.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.recorder.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.recorder.reader.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.recorder
+            .reader
+            .get_indexed_root_hashes(versions, limit)
+    }
+}
+
+impl<R: TreeReader, W: Write> TreeReader for TraceRecorder<R, W> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        self.tagged(UNTAGGED).get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        self.tagged(UNTAGGED).get_nodes(node_keys)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.tagged(UNTAGGED)
+            .get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.reader.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.reader.get_indexed_root_hashes(versions, limit)
+    }
+}
+
+/// Parses a trace written by a [`TraceRecorder`].
+pub fn read_trace(reader: impl BufRead) -> Result<Vec<TraceEvent>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(i, line)| {
+            TraceEvent::parse(&line?).with_context(|| format!("Invalid trace line {}.", i + 1))
+        })
+        .collect()
+}
+
+/// What a call to [`replay`] read, and how long it took.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReplayStats {
+    /// The number of nodes read, including those read in batches.
+    pub node_reads: usize,
+    /// The number of nodes read which were missing.
+    pub missing_nodes: usize,
+    /// The number of values read.
+    pub value_reads: usize,
+    /// The number of values read which were missing.
+    pub missing_values: usize,
+    /// The total time spent reading.
+    pub elapsed: Duration,
+    /// The time spent reading, by tag.
+    pub elapsed_by_tag: BTreeMap<String, Duration>,
+}
+
+/// Replays the reads of `events` against `reader`, as fast as possible and in order.
+pub fn replay<R: TreeReader>(reader: &R, events: &[TraceEvent]) -> Result<ReplayStats> {
+    let mut stats = ReplayStats::default();
+    for event in events {
+        let start = Instant::now();
+        match &event.read {
+            TraceRead::Node(node_key) => {
+                stats.node_reads += 1;
+                if reader.get_node_option(node_key)?.is_none() {
+                    stats.missing_nodes += 1;
+                }
+            }
+            TraceRead::Nodes(node_keys) => {
+                stats.node_reads += node_keys.len();
+                let nodes = reader.get_nodes(node_keys)?;
+                stats.missing_nodes += nodes.iter().filter(|node| node.is_none()).count();
+            }
+            TraceRead::Value {
+                max_version,
+                key_hash,
+            } => {
+                stats.value_reads += 1;
+                if reader.get_value_option(*max_version, *key_hash)?.is_none() {
+                    stats.missing_values += 1;
+                }
+            }
+        }
+        let elapsed = start.elapsed();
+        stats.elapsed += elapsed;
+        *stats.elapsed_by_tag.entry(event.tag.clone()).or_default() += elapsed;
+    }
+    Ok(stats)
+}
@@ -1,33 +1,106 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{hash_map::Entry, BTreeMap, HashMap},
     convert::TryInto,
+    ops::{Bound, Range, RangeBounds},
+    sync::Arc,
 };
 
 use anyhow::{bail, ensure, format_err, Context, Result};
+use bytes::Bytes;
 use sha2::Sha256;
 
 use crate::{
-    node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeType},
-    storage::{TreeReader, TreeUpdateBatch},
+    error::{bail_corrupted, ensure_not_corrupted},
+    fork::BranchReader,
+    iterator::JellyfishMerkleIterator,
+    node_type::{Child, Children, InternalNode, LeafNode, Node, NodeKey, NodeRef, NodeType},
+    overlay::OverlayTree,
+    pruner::{HasVersionPins, VersionGuard},
+    storage::{HasPreimageIndex, StaleNodeKind, TreeReader, TreeUpdateBatch},
+    subtree::{self, SubtreeExport},
     tree_cache::TreeCache,
     types::{
         nibble::{
             nibble_path::{skip_common_prefix, NibbleIterator, NibblePath},
-            NibbleRangeIterator, ROOT_NIBBLE_HEIGHT,
+            Nibble, NibbleRangeIterator, ROOT_NIBBLE_HEIGHT,
+        },
+        proof::{
+            ExclusionProof, NonInclusionProof, SparseMerkleDeletionProof, SparseMerkleInternalNode,
+            SparseMerkleIntervalProof, SparseMerkleLeafNode, SparseMerkleMultiProof,
+            SparseMerkleNodePreimage, SparseMerkleProof, SparseMerkleRangeProof,
+            SparseMerkleUnchangedProof, SparseMerkleUpdateProof,
         },
-        proof::{SparseMerkleProof, SparseMerkleRangeProof},
         Version,
     },
-    Bytes32Ext, KeyHash, MissingRootError, OwnedValue, PhantomHasher, RootHash, SimpleHasher,
-    ValueHash,
+    Bytes32Ext, JmtError, KeyHash, MissingRootError, OwnedValue, PhantomHasher, RootHash,
+    SimpleHasher, ValueHash,
 };
 
 /// A [`JellyfishMerkleTree`] instantiated using the `sha2::Sha256` hasher.
 /// This is a sensible default choice for most applications.
 pub type Sha256JMT<'a, R> = JellyfishMerkleTree<'a, R, Sha256>;
 
+/// A [`JellyfishMerkleTree`] instantiated using the Keccak-256 hasher, whose node hashes can be
+/// recomputed on the EVM with the `KECCAK256` opcode.
+#[cfg(feature = "keccak")]
+pub type Keccak256JMT<'a, R> = JellyfishMerkleTree<'a, R, sha3::Keccak256>;
+
+/// The number of root hashes read at once by [`JellyfishMerkleTree::iter_root_hashes`].
+const ROOT_HASHES_PER_PAGE: usize = 1_000;
+
+/// The size of a [`SparseMerkleProof`], as returned by
+/// [`JellyfishMerkleTree::estimate_proof_size`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProofSizeEstimate {
+    /// Whether the proof holds a leaf.
+    pub has_leaf: bool,
+    /// The number of siblings of the proof, placeholders included.
+    pub num_siblings: usize,
+    /// The number of siblings of the proof which are not placeholders.
+    pub num_non_placeholder_siblings: usize,
+}
+
+impl ProofSizeEstimate {
+    /// Returns the length of the proof encoded with [`SparseMerkleProof::to_compact_bytes`].
+    pub fn compact_len(&self) -> usize {
+        let leaf_len = if self.has_leaf { 1 + 2 * 32 } else { 1 };
+        leaf_len + 2 + self.num_siblings.div_ceil(8) + 32 * self.num_non_placeholder_siblings
+    }
+
+    /// Returns the total length of the hashes held by the proof, its leaf and all its siblings,
+    /// as in its in-memory representation.
+    pub fn full_len(&self) -> usize {
+        let leaf_len = if self.has_leaf { 2 * 32 } else { 0 };
+        leaf_len + 32 * self.num_siblings
+    }
+}
+
+/// A key whose value changed between two versions, with its old and new values, as returned by
+/// [`JellyfishMerkleTree::diff`]. A value is `None` if the key does not exist at that version.
+pub type ValueChange = (KeyHash, Option<OwnedValue>, Option<OwnedValue>);
+
+/// Statistics about a tree at a version, as returned by [`JellyfishMerkleTree::stats`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TreeStats {
+    /// The number of leaves, i.e. of keys with a value.
+    pub leaf_count: usize,
+    /// The number of internal nodes.
+    pub internal_node_count: usize,
+    /// The number of nibbles in the path of the deepest node, 0 if the root is a leaf or if the
+    /// tree is empty.
+    pub max_depth: usize,
+    /// The total length of the values of all the leaves.
+    pub value_bytes: usize,
+}
+
 /// A Jellyfish Merkle tree data structure, parameterized by a [`TreeReader`] `R`
 /// and a [`SimpleHasher`] `H`. See [`crate`] for description.
+///
+/// A tree only borrows its reader, and holds no other state, so it is `Send` and `Sync` whenever
+/// `R` is `Sync`. Many threads can thus read committed versions through the same tree while
+/// another one builds the next version with [`put_value_set`](JellyfishMerkleTree::put_value_set):
+/// the new version is only visible to readers once its [`TreeUpdateBatch`] is written to the
+/// storage.
 pub struct JellyfishMerkleTree<'a, R, H: SimpleHasher> {
     reader: &'a R,
     leaf_count_migration: bool,
@@ -48,6 +121,11 @@ where
         }
     }
 
+    /// Returns the reader of the tree, for the other modules of this crate.
+    pub(crate) fn reader(&self) -> &'a R {
+        self.reader
+    }
+
     pub fn new_migration(reader: &'a R, leaf_count_migration: bool) -> Self {
         Self {
             reader,
@@ -68,11 +146,15 @@ where
                 None => unreachable!("{:?} can not be found in hash cache", node_key),
             }
         } else {
-            node.hash()
+            node.hash::<H>()
         }
     }
 
     /// The batch version of `put_value_sets`.
+    ///
+    /// Nodes are hashed on the calling thread. With the `rayon` feature, a single large value set
+    /// can instead be written with `par_put_value_set`, which builds and hashes the subtrees below
+    /// the root in parallel.
     pub fn batch_put_value_sets(
         &self,
         value_sets: Vec<Vec<(KeyHash, OwnedValue)>>,
@@ -85,9 +167,7 @@ where
             None => (0..value_sets.len()).map(|_| None).collect(),
         };
 
-        for (idx, (value_set, hash_set)) in
-            itertools::zip_eq(value_sets.into_iter(), hash_sets.into_iter()).enumerate()
-        {
+        for (idx, (value_set, hash_set)) in itertools::zip_eq(value_sets, hash_sets).enumerate() {
             assert!(
                 !value_set.is_empty(),
                 "Transactions that output empty write set should not be included.",
@@ -115,7 +195,7 @@ where
             tree_cache.set_root_node_key(new_root_node_key);
 
             // Freezes the current cache to make all contents in the current cache immutable.
-            tree_cache.freeze()?;
+            tree_cache.freeze::<H>()?;
         }
 
         Ok(tree_cache.into())
@@ -137,7 +217,7 @@ where
             Node::Internal(internal_node) => {
                 // We always delete the existing internal node here because it will not be referenced anyway
                 // since this version.
-                tree_cache.delete_node(&node_key, false /* is_leaf */);
+                tree_cache.delete_node(&node_key, StaleNodeKind::Internal);
 
                 // Reuse the current `InternalNode` in memory to create a new internal node.
                 let mut children: Children = internal_node.clone().into();
@@ -198,7 +278,12 @@ where
                 // We are on a leaf node but trying to insert another node, so we may diverge.
                 // We always delete the existing leaf node here because it will not be referenced anyway
                 // since this version.
-                tree_cache.delete_node(&node_key, true /* is_leaf */);
+                tree_cache.delete_node(
+                    &node_key,
+                    StaleNodeKind::Leaf {
+                        key_hash: leaf_node.key_hash(),
+                    },
+                );
                 node_key.set_version(version);
                 self.batch_create_subtree_with_existing_leaf(
                     node_key, version, leaf_node, kvs, depth, hash_cache, tree_cache,
@@ -206,14 +291,14 @@ where
             }
             Node::Null => {
                 if !node_key.nibble_path().is_empty() {
-                    bail!(
+                    bail_corrupted!(
                         "Null node exists for non-root node with node_key {:?}",
                         node_key
                     );
                 }
 
                 if node_key.version() == version {
-                    tree_cache.delete_node(&node_key, false /* is_leaf */);
+                    tree_cache.delete_node(&node_key, StaleNodeKind::Internal);
                 }
                 self.batch_create_subtree(
                     NodeKey::new_empty_path(version),
@@ -286,7 +371,7 @@ where
                     node_key.gen_child_node_key(version, existing_leaf_bucket);
                 children.insert(
                     existing_leaf_bucket,
-                    Child::new(existing_leaf_node.hash(), version, NodeType::Leaf),
+                    Child::new(existing_leaf_node.hash::<H>(), version, NodeType::Leaf),
                 );
 
                 tree_cache.put_node(existing_leaf_node_key, existing_leaf_node.into())?;
@@ -360,6 +445,125 @@ where
         Ok((root_hashes[0], tree_update_batch))
     }
 
+    /// Same as [`put_value_set`](JellyfishMerkleTree::put_value_set), but also returns the value
+    /// each key held before this version, in the order of `value_set`, or `None` for keys which
+    /// had no value. Prior values are only read for the keys found in the tree while inserting,
+    /// so new keys cost no extra read. A key given several times is reported every time with the
+    /// value it held before this version.
+    #[allow(clippy::type_complexity)]
+    pub fn put_value_set_with_prior(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(
+        RootHash,
+        TreeUpdateBatch,
+        Vec<(KeyHash, Option<OwnedValue>)>,
+    )> {
+        let mut tree_cache = TreeCache::new(self.reader, version)?;
+        let mut prior_values = vec![];
+        let mut first_prior_values: HashMap<KeyHash, Option<Bytes>> = HashMap::new();
+        for (i, (key, value)) in value_set.into_iter().enumerate() {
+            let action = if value.is_some() { "insert" } else { "delete" };
+            let value_hash = value.as_ref().map(|v| ValueHash::with::<H>(v));
+            let mut key_exists = false;
+            self.put(key, value_hash, version, &mut tree_cache, &mut key_exists)
+                .with_context(|| {
+                    format!(
+                        "failed to {} key {} for version {}, key = {:?}",
+                        action, i, version, key
+                    )
+                })?;
+            // A key given several times held the same value before this version every time, not
+            // the value given for it earlier in `value_set`.
+            let prior_value = match first_prior_values.entry(key) {
+                Entry::Occupied(o) => o.get().clone(),
+                Entry::Vacant(v) if key_exists => {
+                    v.insert(tree_cache.get_value_option(key)?).clone()
+                }
+                Entry::Vacant(v) => v.insert(None).clone(),
+            };
+            tree_cache.put_value(version, key, value);
+            prior_values.push((key, prior_value.as_deref().map(<[u8]>::to_vec)));
+        }
+        tree_cache.freeze::<H>()?;
+
+        let (root_hashes, tree_update_batch) = tree_cache.into();
+        Ok((root_hashes[0], tree_update_batch, prior_values))
+    }
+
+    /// Same as [`put_value_set`](JellyfishMerkleTree::put_value_set), but also returns the proof
+    /// of each key of `value_set` against the new root hash, in the order of `value_set`: an
+    /// inclusion proof for the keys written, and a proof of absence for the keys deleted. A key
+    /// given several times is proven with its last value every time.
+    ///
+    /// The proofs are built from the nodes of the returned batch, so that they are available
+    /// before the batch is written. The paths to the written keys only go through new nodes, and
+    /// cost no read; proving that a key was deleted may read the leaf which took its place.
+    pub fn put_value_set_with_proofs(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch, Vec<SparseMerkleProof<H>>)> {
+        let value_set: Vec<_> = value_set.into_iter().collect();
+        let keys: Vec<KeyHash> = value_set.iter().map(|(key, _)| *key).collect();
+        let (root_hash, batch) = self.put_value_set(value_set, version)?;
+
+        let reader = BranchReader::new(self.reader, vec![&batch]);
+        let proofs = JellyfishMerkleTree::<_, H>::new(&reader)
+            .multi_get_with_proof(&keys, version)?
+            .into_iter()
+            .map(|(_, proof)| proof)
+            .collect();
+        Ok((root_hash, batch, proofs))
+    }
+
+    /// Deletes `key` at `version`, and returns the new root hash and batch as
+    /// [`put_value_set`](JellyfishMerkleTree::put_value_set) does, together with the value `key`
+    /// held at `version - 1` and the proof of its deletion, as
+    /// [`get_deletion_proof`](JellyfishMerkleTree::get_deletion_proof) would return once the
+    /// batch is written.
+    ///
+    /// Fails if `key` does not exist at `version - 1`.
+    #[allow(clippy::type_complexity)]
+    pub fn delete_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(
+        RootHash,
+        TreeUpdateBatch,
+        OwnedValue,
+        SparseMerkleDeletionProof<H>,
+    )> {
+        let previous_version = version
+            .checked_sub(1)
+            .ok_or_else(|| format_err!("No key can be deleted at version 0."))?;
+        let (deleted_value, inclusion_proof) = self.get_with_proof(key, previous_version)?;
+        let deleted_value = deleted_value.ok_or_else(|| {
+            format_err!(
+                "Key {:?} does not exist at version {}.",
+                key,
+                previous_version
+            )
+        })?;
+        let (root_hash, batch, mut proofs) =
+            self.put_value_set_with_proofs([(key, None)], version)?;
+        let exclusion_proof = proofs.pop().expect("One proof per key.");
+        let proof = SparseMerkleDeletionProof::new(
+            self.get_root_hash(previous_version)?,
+            root_hash,
+            inclusion_proof,
+            exclusion_proof,
+        );
+        Ok((root_hash, batch, deleted_value, proof))
+    }
+
+    /// Applies `value_sets` as consecutive versions starting at `first_version`, and returns the
+    /// root hash of each version together with a single batch merging the changes of all of them.
+    /// The nodes written at one version are read back from memory when applying the next, rather
+    /// than from the reader, so several versions ready at once can be committed in one pass.
+    ///
     /// Returns the new nodes and values in a batch after applying `value_set`. For
     /// example, if after transaction `T_i` the committed state of tree in the persistent storage
     /// looks like the following structure:
@@ -416,7 +620,7 @@ where
                     let action = if value.is_some() { "insert" } else { "delete" };
                     let value_hash = value.as_ref().map(|v| ValueHash::with::<H>(v));
                     tree_cache.put_value(version, key, value);
-                    self.put(key, value_hash, version, &mut tree_cache)
+                    self.put(key, value_hash, version, &mut tree_cache, &mut false)
                         .with_context(|| {
                             format!(
                                 "failed to {} key {} for version {}, key = {:?}",
@@ -425,7 +629,7 @@ where
                         })
                 })?;
             // Freezes the current cache to make all contents in the current cache immutable.
-            tree_cache.freeze()?;
+            tree_cache.freeze::<H>()?;
         }
 
         Ok(tree_cache.into())
@@ -437,10 +641,11 @@ where
         value: Option<ValueHash>,
         version: Version,
         tree_cache: &mut TreeCache<R>,
+        key_exists: &mut bool,
     ) -> Result<()> {
         // tree_cache.ensure_initialized()?;
 
-        let nibble_path = NibblePath::new(key.0.to_vec());
+        let nibble_path = NibblePath::from_bytes(&key.0);
 
         // Get the root node. If this is the first operation, it would get the root node from the
         // underlying db. Otherwise it most likely would come from `cache`.
@@ -448,7 +653,14 @@ where
         let mut nibble_iter = nibble_path.nibbles();
 
         // Start insertion from the root node.
-        match self.insert_at(root_node_key, version, &mut nibble_iter, value, tree_cache)? {
+        match self.insert_at(
+            root_node_key,
+            version,
+            &mut nibble_iter,
+            value,
+            tree_cache,
+            key_exists,
+        )? {
             PutResult::Updated((new_root_node_key, _)) => {
                 tree_cache.set_root_node_key(new_root_node_key);
             }
@@ -469,7 +681,8 @@ where
     /// Helper function for recursive insertion into the subtree that starts from the current
     /// [`NodeKey`](node_type/struct.NodeKey.html). Returns the newly inserted node.
     /// It is safe to use recursion here because the max depth is limited by the key length which
-    /// for this tree is the length of the hash of account addresses.
+    /// for this tree is the length of the hash of account addresses. Sets `key_exists` if the key
+    /// was found in the tree.
     fn insert_at(
         &self,
         node_key: NodeKey,
@@ -477,6 +690,7 @@ where
         nibble_iter: &mut NibbleIterator,
         value: Option<ValueHash>,
         tree_cache: &mut TreeCache<R>,
+        key_exists: &mut bool,
     ) -> Result<PutResult<(NodeKey, Node)>> {
         // Because deletions could cause the root node not to exist, we try to get the root node,
         // and if it doesn't exist, we synthesize a `Null` node, noting that it hasn't yet been
@@ -494,6 +708,7 @@ where
                 nibble_iter,
                 value,
                 tree_cache,
+                key_exists,
             ),
             Node::Leaf(leaf_node) => self.insert_at_leaf_node(
                 node_key,
@@ -502,17 +717,18 @@ where
                 nibble_iter,
                 value,
                 tree_cache,
+                key_exists,
             ),
             Node::Null => {
                 if !node_key.nibble_path().is_empty() {
-                    bail!(
+                    bail_corrupted!(
                         "Null node exists for non-root node with node_key {:?}",
                         node_key
                     );
                 }
                 // Delete the old null node if the at the same version
                 if node_key.version() == version && node_already_exists {
-                    tree_cache.delete_node(&node_key, false /* is_leaf */);
+                    tree_cache.delete_node(&node_key, StaleNodeKind::Internal);
                 }
                 if let Some(value) = value {
                     // If we're inserting into the null root node, we should change it to be a leaf node
@@ -534,6 +750,7 @@ where
     /// Helper function for recursive insertion into the subtree that starts from the current
     /// `internal_node`. Returns the newly inserted node with its
     /// [`NodeKey`](node_type/struct.NodeKey.html).
+    #[allow(clippy::too_many_arguments)]
     fn insert_at_internal_node(
         &self,
         mut node_key: NodeKey,
@@ -542,6 +759,7 @@ where
         nibble_iter: &mut NibbleIterator,
         value: Option<ValueHash>,
         tree_cache: &mut TreeCache<R>,
+        key_exists: &mut bool,
     ) -> Result<PutResult<(NodeKey, Node)>> {
         // Find the next node to visit following the next nibble as index.
         let child_index = nibble_iter.next().expect("Ran out of nibbles");
@@ -551,7 +769,14 @@ where
         let result = match internal_node.child(child_index) {
             Some(child) => {
                 let child_node_key = node_key.gen_child_node_key(child.version, child_index);
-                self.insert_at(child_node_key, version, nibble_iter, value, tree_cache)?
+                self.insert_at(
+                    child_node_key,
+                    version,
+                    nibble_iter,
+                    value,
+                    tree_cache,
+                    key_exists,
+                )?
             }
             None => {
                 if let Some(value) = value {
@@ -580,7 +805,7 @@ where
                 // update child
                 children.insert(
                     child_index,
-                    Child::new(new_node.hash(), version, new_node.node_type()),
+                    Child::new(new_node.hash::<H>(), version, new_node.node_type()),
                 );
             }
             PutResult::Removed => {
@@ -591,7 +816,7 @@ where
 
         // We always delete the existing internal node here because it will not be referenced anyway
         // since this version.
-        tree_cache.delete_node(&node_key, false /* is_leaf */);
+        tree_cache.delete_node(&node_key, StaleNodeKind::Internal);
 
         let mut it = children.iter();
         if let Some((child_nibble, child)) = it.next() {
@@ -599,7 +824,7 @@ where
                 // internal node has only one child left and it's leaf node, replace it with the leaf node
                 let child_key = node_key.gen_child_node_key(child.version, child_nibble);
                 let child_node = tree_cache.get_node(&child_key)?;
-                tree_cache.delete_node(&child_key, true /* is_leaf */);
+                tree_cache.delete_node(&child_key, StaleNodeKind::of(&child_node));
 
                 node_key.set_version(version);
                 tree_cache.put_node(node_key.clone(), child_node.clone())?;
@@ -623,6 +848,7 @@ where
     /// Helper function for recursive insertion into the subtree that starts from the
     /// `existing_leaf_node`. Returns the newly inserted node with its
     /// [`NodeKey`](node_type/struct.NodeKey.html).
+    #[allow(clippy::too_many_arguments)]
     fn insert_at_leaf_node(
         &self,
         mut node_key: NodeKey,
@@ -631,12 +857,13 @@ where
         nibble_iter: &mut NibbleIterator,
         value_hash: Option<ValueHash>,
         tree_cache: &mut TreeCache<R>,
+        key_exists: &mut bool,
     ) -> Result<PutResult<(NodeKey, Node)>> {
         // 1. Make sure that the existing leaf nibble_path has the same prefix as the already
         // visited part of the nibble iter of the incoming key and advances the existing leaf
         // nibble iterator by the length of that prefix.
         let mut visited_nibble_iter = nibble_iter.visited_nibbles();
-        let existing_leaf_nibble_path = NibblePath::new(existing_leaf_node.key_hash().0.to_vec());
+        let existing_leaf_nibble_path = NibblePath::from_bytes(&existing_leaf_node.key_hash().0);
         let mut existing_leaf_nibble_iter = existing_leaf_nibble_path.nibbles();
         skip_common_prefix(&mut visited_nibble_iter, &mut existing_leaf_nibble_iter);
 
@@ -659,7 +886,13 @@ where
         // just need to update its value.
         if nibble_iter.is_finished() {
             assert!(existing_leaf_nibble_iter_below_internal.is_finished());
-            tree_cache.delete_node(&node_key, true /* is_leaf */);
+            *key_exists = true;
+            tree_cache.delete_node(
+                &node_key,
+                StaleNodeKind::Leaf {
+                    key_hash: existing_leaf_node.key_hash(),
+                },
+            );
             if let Some(value_hash) = value_hash {
                 // The new leaf node will have the same nibble_path with a new version as node_key.
                 node_key.set_version(version);
@@ -677,7 +910,12 @@ where
         }
 
         if let Some(value) = value_hash {
-            tree_cache.delete_node(&node_key, true /* is_leaf */);
+            tree_cache.delete_node(
+                &node_key,
+                StaleNodeKind::Leaf {
+                    key_hash: existing_leaf_node.key_hash(),
+                },
+            );
 
             // 2.2. both are unfinished(They have keys with same length so it's impossible to have one
             // finished and the other not). This means the incoming key forks at some point between the
@@ -695,7 +933,7 @@ where
             let mut children = Children::new();
             children.insert(
                 existing_leaf_index,
-                Child::new(existing_leaf_node.hash(), version, NodeType::Leaf),
+                Child::new(existing_leaf_node.hash::<H>(), version, NodeType::Leaf),
             );
             node_key = NodeKey::new(version, common_nibble_path.clone());
             tree_cache.put_node(
@@ -711,7 +949,7 @@ where
             )?;
             children.insert(
                 new_leaf_index,
-                Child::new(new_leaf_node.hash(), version, NodeType::Leaf),
+                Child::new(new_leaf_node.hash::<H>(), version, NodeType::Leaf),
             );
 
             let internal_node = InternalNode::new_migration(children, self.leaf_count_migration);
@@ -727,7 +965,7 @@ where
                 children.insert(
                     nibble,
                     Child::new(
-                        next_internal_node.hash(),
+                        next_internal_node.hash::<H>(),
                         version,
                         next_internal_node.node_type(),
                     ),
@@ -774,61 +1012,73 @@ where
         key: KeyHash,
         version: Version,
     ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        let proof = self.get_proof(key, version)?;
+        let value = match proof.leaf() {
+            Some(leaf) if leaf.key_hash() == key => Some(self.reader.get_value(version, key)?),
+            _ => None,
+        };
+        Ok((value, proof))
+    }
+
+    /// Returns the merkle proof of `key` at `version`, without reading its value.
+    ///
+    /// If `key` exists, the leaf of the proof is its own and holds the hash of its value, which is
+    /// enough to serve the proof to a client that already has the value, or to check a value
+    /// against it.
+    pub fn get_proof(&self, key: KeyHash, version: Version) -> Result<SparseMerkleProof<H>> {
         // Empty tree just returns proof with no sibling hash.
         let mut next_node_key = NodeKey::new_empty_path(version);
         let mut siblings = vec![];
-        let nibble_path = NibblePath::new(key.0.to_vec());
+        let nibble_path = NibblePath::from_bytes(&key.0);
         let mut nibble_iter = nibble_path.nibbles();
 
         // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
         // in the tree structure.
         for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
-            let next_node = self.reader.get_node(&next_node_key).map_err(|err| {
-                if nibble_depth == 0 {
-                    MissingRootError { version }.into()
-                } else {
-                    err
+            // Nodes are decoded without allocating their children when the reader keeps them
+            // encoded.
+            let encoded_node = self.reader.get_encoded_node_option(&next_node_key)?;
+            let decoded_node;
+            let next_node = match &encoded_node {
+                Some(encoded_node) => Node::decode_ref(encoded_node)?,
+                None => {
+                    decoded_node = self.reader.get_node(&next_node_key).map_err(|err| {
+                        if nibble_depth == 0 {
+                            MissingRootError { version }.into()
+                        } else {
+                            err
+                        }
+                    })?;
+                    decoded_node.to_ref()
                 }
-            })?;
+            };
             match next_node {
-                Node::Internal(internal_node) => {
+                NodeRef::Internal(internal_node) => {
                     let queried_child_index = nibble_iter
                         .next()
-                        .ok_or_else(|| format_err!("ran out of nibbles"))?;
-                    let (child_node_key, mut siblings_in_internal) =
-                        internal_node.get_child_with_siblings(&next_node_key, queried_child_index);
-                    siblings.append(&mut siblings_in_internal);
+                        .ok_or_else(|| JmtError::Corruption("ran out of nibbles".into()))?;
+                    let child_node_key = internal_node.push_child_with_siblings::<H>(
+                        &next_node_key,
+                        queried_child_index,
+                        &mut siblings,
+                    );
                     next_node_key = match child_node_key {
                         Some(node_key) => node_key,
                         None => {
-                            return Ok((
-                                None,
-                                SparseMerkleProof::new(None, {
-                                    siblings.reverse();
-                                    siblings
-                                }),
-                            ))
+                            siblings.reverse();
+                            return Ok(SparseMerkleProof::new(None, siblings));
                         }
                     };
                 }
-                Node::Leaf(leaf_node) => {
-                    return Ok((
-                        if leaf_node.key_hash() == key {
-                            Some(self.reader.get_value(version, leaf_node.key_hash())?)
-                        } else {
-                            None
-                        },
-                        SparseMerkleProof::new(Some(leaf_node.into()), {
-                            siblings.reverse();
-                            siblings
-                        }),
-                    ));
+                NodeRef::Leaf(leaf_node) => {
+                    siblings.reverse();
+                    return Ok(SparseMerkleProof::new(Some(leaf_node.into()), siblings));
                 }
-                Node::Null => {
+                NodeRef::Null => {
                     if nibble_depth == 0 {
-                        return Ok((None, SparseMerkleProof::new(None, vec![])));
+                        return Ok(SparseMerkleProof::new(None, vec![]));
                     } else {
-                        bail!(
+                        bail_corrupted!(
                             "Non-root null node exists with node key {:?}",
                             next_node_key
                         );
@@ -836,7 +1086,509 @@ where
                 }
             }
         }
-        bail!("Jellyfish Merkle tree has cyclic graph inside.");
+        bail_corrupted!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the size of the proof [`get_with_proof`](JellyfishMerkleTree::get_with_proof) would
+    /// return for `key` at `version`, without computing it.
+    ///
+    /// The same nodes are read as for the proof itself, but none of the siblings is hashed and the
+    /// value is not read, so this is much cheaper, e.g. to enforce a response budget before
+    /// generating proofs.
+    pub fn estimate_proof_size(&self, key: KeyHash, version: Version) -> Result<ProofSizeEstimate> {
+        let (leaf, sibling_presence) = self.get_proof_shape(key, version)?;
+        Ok(ProofSizeEstimate {
+            has_leaf: leaf.is_some(),
+            num_siblings: sibling_presence.len(),
+            num_non_placeholder_siblings: sibling_presence.iter().filter(|p| **p).count(),
+        })
+    }
+
+    /// Returns the leaf of the proof of `key` at `version`, if any, and whether each of its
+    /// siblings is a non-placeholder, ordered from the bottom level to the root level like the
+    /// siblings of a [`SparseMerkleProof`].
+    pub(crate) fn get_proof_shape(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<LeafNode>, Vec<bool>)> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let mut sibling_presence = vec![];
+        let nibble_path = NibblePath::from_bytes(&key.0);
+        let mut nibble_iter = nibble_path.nibbles();
+
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let encoded_node = self.reader.get_encoded_node_option(&next_node_key)?;
+            let decoded_node;
+            let next_node = match &encoded_node {
+                Some(encoded_node) => Node::decode_ref(encoded_node)?,
+                None => {
+                    decoded_node = self.reader.get_node(&next_node_key).map_err(|err| {
+                        if nibble_depth == 0 {
+                            MissingRootError { version }.into()
+                        } else {
+                            err
+                        }
+                    })?;
+                    decoded_node.to_ref()
+                }
+            };
+            let leaf = match next_node {
+                NodeRef::Internal(internal_node) => {
+                    let queried_child_index = nibble_iter
+                        .next()
+                        .ok_or_else(|| JmtError::Corruption("ran out of nibbles".into()))?;
+                    match internal_node.push_child_with_sibling_presence(
+                        &next_node_key,
+                        queried_child_index,
+                        &mut sibling_presence,
+                    ) {
+                        Some(node_key) => {
+                            next_node_key = node_key;
+                            continue;
+                        }
+                        None => None,
+                    }
+                }
+                NodeRef::Leaf(leaf_node) => Some(leaf_node),
+                NodeRef::Null if nibble_depth == 0 => None,
+                NodeRef::Null => bail_corrupted!(
+                    "Non-root null node exists with node key {:?}",
+                    next_node_key
+                ),
+            };
+            sibling_presence.reverse();
+            return Ok((leaf, sibling_presence));
+        }
+        bail_corrupted!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the value `key` had before being deleted at `version`, and a proof of the deletion:
+    /// a proof that `key` exists at `version - 1` and a proof that it does not exist at
+    /// `version`, along with the root hashes of both versions.
+    ///
+    /// Fails if `key` did not exist at `version - 1`, or still exists at `version`.
+    pub fn get_deletion_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(OwnedValue, SparseMerkleDeletionProof<H>)> {
+        let previous_version = version
+            .checked_sub(1)
+            .ok_or_else(|| format_err!("No key can be deleted at version 0."))?;
+        let (deleted_value, inclusion_proof) = self.get_with_proof(key, previous_version)?;
+        let deleted_value = deleted_value.ok_or_else(|| {
+            format_err!(
+                "Key {:?} does not exist at version {}.",
+                key,
+                previous_version
+            )
+        })?;
+        let (value, exclusion_proof) = self.get_with_proof(key, version)?;
+        ensure!(
+            value.is_none(),
+            "Key {:?} still exists at version {}.",
+            key,
+            version
+        );
+        Ok((
+            deleted_value,
+            SparseMerkleDeletionProof::new(
+                self.get_root_hash(previous_version)?,
+                self.get_root_hash(version)?,
+                inclusion_proof,
+                exclusion_proof,
+            ),
+        ))
+    }
+
+    /// Returns the value of `key` at `start_version` and a proof that it was not modified through
+    /// `end_version`, which fails if the value changed in between.
+    ///
+    /// The version since which `key` has had its value is found by walking back the versions of
+    /// its leaves from `end_version`, see [`SparseMerkleUnchangedProof`].
+    pub fn get_unchanged_proof(
+        &self,
+        key: KeyHash,
+        start_version: Version,
+        end_version: Version,
+    ) -> Result<(OwnedValue, SparseMerkleUnchangedProof<H>)> {
+        ensure!(
+            start_version <= end_version,
+            "Start version {} is after end version {}.",
+            start_version,
+            end_version
+        );
+        let (mut since_version, value_hash) =
+            self.get_value_hash_since(key, end_version)?
+                .ok_or(MissingRootError {
+                    version: end_version,
+                })?;
+        ensure!(
+            value_hash.is_some(),
+            "Key {:?} does not exist at version {}.",
+            key,
+            end_version
+        );
+        // Leaves are also moved without their values changing, when other keys are written.
+        while since_version > start_version {
+            match self.get_value_hash_since(key, since_version - 1)? {
+                Some((earlier_version, earlier_hash)) if earlier_hash == value_hash => {
+                    since_version = earlier_version
+                }
+                _ => bail!(
+                    "Key {:?} was modified at version {}, after version {}.",
+                    key,
+                    since_version,
+                    start_version
+                ),
+            }
+        }
+        let (value, start_proof) = self.get_with_proof(key, start_version)?;
+        let value = value.ok_or_else(|| {
+            format_err!("Key {:?} does not exist at version {}.", key, start_version)
+        })?;
+        let end_proof = self.get_proof(key, end_version)?;
+        Ok((
+            value,
+            SparseMerkleUnchangedProof::new(
+                since_version,
+                start_version,
+                end_version,
+                self.get_root_hash(start_version)?,
+                self.get_root_hash(end_version)?,
+                start_proof,
+                end_proof,
+            ),
+        ))
+    }
+
+    /// Like [`put_value_set`](JellyfishMerkleTree::put_value_set), but also returns a
+    /// [`SparseMerkleUpdateProof`] that the updates turn the root hash at `version - 1` into the
+    /// returned root hash, which can be checked without any other state.
+    ///
+    /// Each update is proven against the tree with the previous ones applied, so the updates are
+    /// also applied one at a time, in memory, which makes this much more expensive than
+    /// [`put_value_set`](JellyfishMerkleTree::put_value_set).
+    pub fn put_value_set_with_update_proof(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, SparseMerkleUpdateProof<H>, TreeUpdateBatch)> {
+        let value_set: BTreeMap<_, _> = value_set.into_iter().collect();
+        let (root_hash, batch) = self.put_value_set(value_set.clone(), version)?;
+
+        // The intermediate trees are built as scratch versions of an overlay, one per update,
+        // starting at `version`.
+        let mut overlay = OverlayTree::<&R, H>::new(self.reader);
+        let mut steps = Vec::with_capacity(value_set.len());
+        for (scratch_version, (key, value)) in (version..).zip(value_set) {
+            let (proof, sibling_preimage) = match scratch_version.checked_sub(1) {
+                Some(previous_version) => {
+                    let tree = overlay.tree();
+                    let (previous_value, proof) = tree.get_with_proof(key, previous_version)?;
+                    let sibling_preimage = if value.is_none() && previous_value.is_some() {
+                        deleted_leaf_sibling_preimage(&tree, key, &proof, previous_version)?
+                    } else {
+                        None
+                    };
+                    (proof, sibling_preimage)
+                }
+                None => (SparseMerkleProof::new(None, vec![]), None),
+            };
+            steps.push((proof, sibling_preimage));
+            overlay.put_value_set(vec![(key, value)], scratch_version)?;
+        }
+        if let Some((_, scratch_root_hash)) = overlay.pending_root_hashes().last() {
+            ensure!(
+                *scratch_root_hash == root_hash,
+                "Applying the updates one at a time did not yield the same root hash."
+            );
+        }
+
+        Ok((root_hash, SparseMerkleUpdateProof::new(steps), batch))
+    }
+
+    /// Returns the value of `key` with its inclusion proof if it exists at `version`, or an
+    /// [`ExclusionProof`] made of the inclusion proofs of its neighbors otherwise.
+    ///
+    /// Fails if the tree is empty at `version`, since there are no neighbors to prove anything
+    /// with.
+    #[allow(clippy::type_complexity)]
+    pub fn get_with_exclusion_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<core::result::Result<(OwnedValue, SparseMerkleProof<H>), ExclusionProof<H>>> {
+        if let (Some(value), proof) = self.get_with_proof(key, version)? {
+            return Ok(Ok((value, proof)));
+        }
+
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self.get_root_node(version)?;
+        let left_neighbor =
+            self.search_closest_leaf(&root_node_key, root_node.clone(), key, 0, true)?;
+        let right_neighbor = self.search_closest_leaf(&root_node_key, root_node, key, 0, false)?;
+        let proof_of = |neighbor: LeafNode| -> Result<SparseMerkleProof<H>> {
+            self.get_proof(neighbor.key_hash(), version)
+        };
+        Ok(Err(match (left_neighbor, right_neighbor) {
+            (None, Some(right)) => ExclusionProof::Leftmost {
+                leftmost_right_proof: proof_of(right)?,
+            },
+            (Some(left), Some(right)) => ExclusionProof::Middle {
+                leftmost_right_proof: proof_of(right)?,
+                rightmost_left_proof: proof_of(left)?,
+            },
+            (Some(left), None) => ExclusionProof::Rightmost {
+                rightmost_left_proof: proof_of(left)?,
+            },
+            (None, None) => bail!(
+                "Cannot prove the exclusion of {:?} from the empty tree at version {}.",
+                key,
+                version
+            ),
+        }))
+    }
+
+    /// Returns a [`NonInclusionProof`] of `key` at `version`.
+    ///
+    /// Fails if `key` exists at `version`, or if the tree is empty at `version`.
+    pub fn get_non_inclusion_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<NonInclusionProof<H>> {
+        match self.get_with_exclusion_proof(key, version)? {
+            Ok(_) => bail!("Key {:?} exists at version {}.", key, version),
+            Err(exclusion_proof) => Ok(NonInclusionProof::new(key, exclusion_proof)),
+        }
+    }
+
+    /// Returns the leaf of the subtree rooted at `node` with the closest key hash before `key` if
+    /// `before` is true, or after `key` otherwise.
+    fn search_closest_leaf(
+        &self,
+        node_key: &NodeKey,
+        node: Node,
+        key: KeyHash,
+        depth: usize,
+        before: bool,
+    ) -> Result<Option<LeafNode>> {
+        match node {
+            Node::Null => Ok(None),
+            Node::Leaf(leaf_node) => {
+                let is_on_the_side = if before {
+                    leaf_node.key_hash() < key
+                } else {
+                    leaf_node.key_hash() > key
+                };
+                Ok(is_on_the_side.then_some(leaf_node))
+            }
+            Node::Internal(internal_node) => {
+                ensure_not_corrupted!(
+                    depth < ROOT_NIBBLE_HEIGHT,
+                    "Jellyfish Merkle tree has cyclic graph inside."
+                );
+                let nibble = u8::from(key.0.get_nibble(depth));
+                // The child on the path of `key` may hold the closest leaf; otherwise it is the
+                // last (or first) leaf of the closest child on the side of `key`.
+                let candidates: Vec<u8> = if before {
+                    (0..=nibble).rev().collect()
+                } else {
+                    (nibble..16).collect()
+                };
+                for (i, n) in candidates.into_iter().enumerate() {
+                    let n = Nibble::from(n);
+                    let child = match internal_node.child(n) {
+                        Some(child) => child,
+                        None => continue,
+                    };
+                    let child_node_key = node_key.gen_child_node_key(child.version, n);
+                    let child_node = self.reader.get_node(&child_node_key)?;
+                    let leaf = if i == 0 {
+                        self.search_closest_leaf(
+                            &child_node_key,
+                            child_node,
+                            key,
+                            depth + 1,
+                            before,
+                        )?
+                    } else {
+                        Some(self.extreme_leaf(child_node_key, child_node, depth + 1, before)?)
+                    };
+                    if leaf.is_some() {
+                        return Ok(leaf);
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the last leaf of the subtree rooted at `node` if `last` is true, or its first leaf
+    /// otherwise.
+    fn extreme_leaf(
+        &self,
+        mut node_key: NodeKey,
+        mut node: Node,
+        mut depth: usize,
+        last: bool,
+    ) -> Result<LeafNode> {
+        loop {
+            match node {
+                Node::Leaf(leaf_node) => return Ok(leaf_node),
+                Node::Null => {
+                    bail_corrupted!("Non-root null node exists with node key {:?}", node_key)
+                }
+                Node::Internal(internal_node) => {
+                    ensure_not_corrupted!(
+                        depth < ROOT_NIBBLE_HEIGHT,
+                        "Jellyfish Merkle tree has cyclic graph inside."
+                    );
+                    let (existence_bitmap, _) = internal_node.generate_bitmaps();
+                    let n = Nibble::from(if last {
+                        15 - existence_bitmap.leading_zeros() as u8
+                    } else {
+                        existence_bitmap.trailing_zeros() as u8
+                    });
+                    let child = internal_node
+                        .child(n)
+                        .expect("The existence bitmap only has bits of existing children.");
+                    node_key = node_key.gen_child_node_key(child.version, n);
+                    node = self.reader.get_node(&node_key)?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the values (if applicable) of all `keys`, in the same order, and a single
+    /// [`SparseMerkleMultiProof`] authenticating all of them, in which siblings shared between
+    /// the paths of different keys are included only once.
+    pub fn get_with_multiproof(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<(Vec<Option<OwnedValue>>, SparseMerkleMultiProof<H>)> {
+        let mut values = Vec::with_capacity(keys.len());
+        let mut leaves = Vec::with_capacity(keys.len());
+        let mut proofs = Vec::with_capacity(keys.len());
+        for (key, (value, proof)) in keys.iter().zip(self.multi_get_with_proof(keys, version)?) {
+            values.push(value);
+            leaves.push((proof.leaf(), proof.siblings().len()));
+            proofs.push((*key, proof));
+        }
+
+        proofs.sort_by_key(|(key, _)| *key);
+        proofs.dedup_by_key(|(key, _)| *key);
+        let mut siblings = vec![];
+        if !proofs.is_empty() {
+            collect_multiproof_siblings(&proofs, 0, &mut siblings);
+        }
+
+        Ok((values, SparseMerkleMultiProof::new(leaves, siblings)))
+    }
+
+    /// Returns all the key-value pairs whose key hashes fall within `[start, end]`, in ascending
+    /// order, and a [`SparseMerkleIntervalProof`] showing that these are all such pairs.
+    #[allow(clippy::type_complexity)]
+    pub fn get_interval_proof(
+        &self,
+        start: KeyHash,
+        end: KeyHash,
+        version: Version,
+    ) -> Result<(Vec<(KeyHash, OwnedValue)>, SparseMerkleIntervalProof<H>)> {
+        ensure!(
+            start <= end,
+            "Interval start {:?} is greater than its end {:?}.",
+            start,
+            end
+        );
+
+        let mut keys = vec![start, end];
+        let root_node_key = NodeKey::new_empty_path(version);
+        let root_node = self
+            .reader
+            .get_node(&root_node_key)
+            .map_err(|_| MissingRootError { version })?;
+        self.collect_key_hashes_in_interval(root_node_key, root_node, start, end, &mut keys)?;
+
+        let (values, multiproof) = self.get_with_multiproof(&keys, version)?;
+        let leaves = keys
+            .into_iter()
+            .zip(values)
+            .skip(2)
+            .map(|(key, value)| {
+                value
+                    .map(|value| (key, value))
+                    .ok_or_else(|| format_err!("Value of key {:?} is missing.", key))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok((leaves, SparseMerkleIntervalProof::new(multiproof)))
+    }
+
+    /// Returns up to `limit` key-value pairs of the tree at `version`, in key hash order starting
+    /// from the first key hash greater than or equal to `start`, with a proof binding the whole
+    /// page to the root hash.
+    ///
+    /// The proof is an interval proof from `start` to the last key hash of the page, or to the end
+    /// of the key space if the page holds fewer than `limit` pairs, so that a short page also
+    /// proves that there is nothing left after it. It is checked with
+    /// [`SparseMerkleIntervalProof::verify_range`].
+    #[allow(clippy::type_complexity)]
+    pub fn get_range_with_proof(
+        &self,
+        start: KeyHash,
+        limit: usize,
+        version: Version,
+    ) -> Result<(Vec<(KeyHash, OwnedValue)>, SparseMerkleIntervalProof<H>)> {
+        ensure!(limit > 0, "The page size limit must be positive.");
+        let end = match self.iter_from(start, version)?.nth(limit - 1).transpose()? {
+            Some((last_key, _)) => last_key,
+            None => KeyHash([0xFF; 32]),
+        };
+        self.get_interval_proof(start, end, version)
+    }
+
+    /// Appends the key hashes of all the leaves under `node` that fall within `[start, end]` to
+    /// `key_hashes`, in ascending order.
+    fn collect_key_hashes_in_interval(
+        &self,
+        node_key: NodeKey,
+        node: Node,
+        start: KeyHash,
+        end: KeyHash,
+        key_hashes: &mut Vec<KeyHash>,
+    ) -> Result<()> {
+        match node {
+            Node::Internal(internal_node) => {
+                for (nibble, child) in internal_node.children_sorted() {
+                    let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                    let (min_key, max_key) = nibble_path_key_bounds(child_node_key.nibble_path());
+                    if max_key < start || end < min_key {
+                        continue;
+                    }
+                    let child_node = self.reader.get_node(&child_node_key)?;
+                    self.collect_key_hashes_in_interval(
+                        child_node_key,
+                        child_node,
+                        start,
+                        end,
+                        key_hashes,
+                    )?;
+                }
+            }
+            Node::Leaf(leaf_node) => {
+                let key_hash = leaf_node.key_hash();
+                if start <= key_hash && key_hash <= end {
+                    key_hashes.push(key_hash);
+                }
+            }
+            Node::Null => {}
+        }
+        Ok(())
     }
 
     fn get_without_proof(&self, key: KeyHash, version: Version) -> Result<Option<OwnedValue>> {
@@ -848,7 +1600,7 @@ where
         &self,
         rightmost_key_to_prove: KeyHash,
         version: Version,
-    ) -> Result<SparseMerkleRangeProof> {
+    ) -> Result<SparseMerkleRangeProof<H>> {
         let (account, proof) = self.get_with_proof(rightmost_key_to_prove, version)?;
         ensure!(account.is_some(), "rightmost_key_to_prove must exist.");
 
@@ -878,6 +1630,636 @@ where
         self.get_without_proof(key, version)
     }
 
+    /// Returns whether `key` exists at `version`.
+    ///
+    /// Only the nodes on the path of `key` are read, neither the value of `key` nor the hashes of
+    /// the siblings, so this is cheaper than [`get`](JellyfishMerkleTree::get) or
+    /// [`get_proof`](JellyfishMerkleTree::get_proof) when the value is not needed.
+    pub fn contains_key(&self, key: KeyHash, version: Version) -> Result<bool> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let nibble_path = NibblePath::from_bytes(&key.0);
+        let mut nibble_iter = nibble_path.nibbles();
+
+        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
+        // in the tree structure.
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let encoded_node = self.reader.get_encoded_node_option(&next_node_key)?;
+            let decoded_node;
+            let next_node = match &encoded_node {
+                Some(encoded_node) => Node::decode_ref(encoded_node)?,
+                None => {
+                    decoded_node = self.reader.get_node(&next_node_key).map_err(|err| {
+                        if nibble_depth == 0 {
+                            MissingRootError { version }.into()
+                        } else {
+                            err
+                        }
+                    })?;
+                    decoded_node.to_ref()
+                }
+            };
+            match next_node {
+                NodeRef::Internal(internal_node) => {
+                    let queried_child_index = nibble_iter
+                        .next()
+                        .ok_or_else(|| JmtError::Corruption("ran out of nibbles".into()))?;
+                    next_node_key = match internal_node
+                        .get_child_without_siblings(&next_node_key, queried_child_index)
+                    {
+                        Some(node_key) => node_key,
+                        None => return Ok(false),
+                    };
+                }
+                NodeRef::Leaf(leaf_node) => return Ok(leaf_node.key_hash() == key),
+                NodeRef::Null => {
+                    ensure_not_corrupted!(
+                        nibble_depth == 0,
+                        "Non-root null node exists with node key {:?}",
+                        next_node_key
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+        bail_corrupted!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the changes of the value of `key` at the versions in `versions`, in ascending order
+    /// of version, as the version of each change and the value set then, or `None` if `key` was
+    /// deleted.
+    ///
+    /// Rather than reading every version, the history is walked back from the end of `versions`
+    /// using the versions of the nodes on the path of `key`: the node ending that path, its leaf or
+    /// the node proving it absent, tells since when `key` has had its value. The walk starts from
+    /// the root of the last version of `versions`, so nothing is returned if it is missing, and
+    /// stops at the start of `versions`, or at the first version whose root is missing, before
+    /// which `key` is considered absent.
+    pub fn get_value_history(
+        &self,
+        key: KeyHash,
+        versions: Range<Version>,
+    ) -> Result<Vec<(Version, Option<OwnedValue>)>> {
+        let mut changes = vec![];
+        // The value hash of the key, and the earliest version since which it has been held.
+        let mut current: Option<(Version, Option<ValueHash>)> = None;
+        let mut next_version = versions.end.checked_sub(1).filter(|_| !versions.is_empty());
+        while let Some(version) = next_version {
+            let Some((since, value_hash)) = self.get_value_hash_since(key, version)? else {
+                break;
+            };
+            match current {
+                Some((_, current_hash)) if current_hash == value_hash => {}
+                Some(change) => changes.push(change),
+                None => {}
+            }
+            current = Some((since, value_hash));
+            // The version before the start of `versions` is still read, to tell whether the value
+            // held at the start was set then.
+            next_version = since.checked_sub(1).filter(|_| since >= versions.start);
+        }
+        // Unless it is out of `versions`, the walk stopped before the first version of the tree, so
+        // the last value found, if any, was set at its version.
+        if let Some((since, Some(value_hash))) = current {
+            changes.push((since, Some(value_hash)));
+        }
+        changes.retain(|(since, _)| *since >= versions.start);
+        changes
+            .into_iter()
+            .rev()
+            .map(|(since, value_hash)| {
+                let value = match value_hash {
+                    Some(_) => Some(self.reader.get_value(since, key)?),
+                    None => None,
+                };
+                Ok((since, value))
+            })
+            .collect()
+    }
+
+    /// Returns the value hash of `key` at `version`, or `None` if `key` is absent, together with
+    /// the earliest version since which it is known to be unchanged: the version of the node ending
+    /// the path of `key`. Returns `None` if there is no root at `version`.
+    fn get_value_hash_since(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<Option<(Version, Option<ValueHash>)>> {
+        let mut next_node_key = NodeKey::new_empty_path(version);
+        let nibble_path = NibblePath::from_bytes(&key.0);
+        let mut nibble_iter = nibble_path.nibbles();
+
+        // We limit the number of loops here deliberately to avoid potential cyclic graph bugs
+        // in the tree structure.
+        for nibble_depth in 0..=ROOT_NIBBLE_HEIGHT {
+            let node = match self.reader.get_node_option(&next_node_key)? {
+                Some(node) => node,
+                None if nibble_depth == 0 => return Ok(None),
+                None => {
+                    return Err(JmtError::MissingNode {
+                        node_key: next_node_key,
+                    }
+                    .into())
+                }
+            };
+            match node {
+                Node::Internal(internal_node) => {
+                    let queried_child_index = nibble_iter
+                        .next()
+                        .ok_or_else(|| JmtError::Corruption("ran out of nibbles".into()))?;
+                    next_node_key = match internal_node
+                        .get_child_without_siblings(&next_node_key, queried_child_index)
+                    {
+                        Some(node_key) => node_key,
+                        None => return Ok(Some((next_node_key.version(), None))),
+                    };
+                }
+                Node::Leaf(leaf_node) => {
+                    let value_hash = (leaf_node.key_hash() == key).then(|| leaf_node.value_hash());
+                    return Ok(Some((next_node_key.version(), value_hash)));
+                }
+                Node::Null => {
+                    ensure_not_corrupted!(
+                        nibble_depth == 0,
+                        "Non-root null node exists with node key {:?}",
+                        next_node_key
+                    );
+                    return Ok(Some((next_node_key.version(), None)));
+                }
+            }
+        }
+        bail_corrupted!("Jellyfish Merkle tree has cyclic graph inside.");
+    }
+
+    /// Returns the values (if applicable) of all `keys` at `version`, in the same order.
+    ///
+    /// The keys are looked up together in a single traversal of the tree, so that each node on
+    /// their paths is read once however many of the keys go through it, and only the values of the
+    /// keys which exist are read.
+    pub fn multi_get(&self, keys: &[KeyHash], version: Version) -> Result<Vec<Option<OwnedValue>>> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|i| keys[*i]);
+        let mut sorted_keys: Vec<KeyHash> = order.iter().map(|i| keys[*i]).collect();
+        sorted_keys.dedup();
+
+        let mut existing_keys = vec![];
+        if !sorted_keys.is_empty() {
+            let root_node_key = NodeKey::new_empty_path(version);
+            let root_node = self
+                .reader
+                .get_node_option(&root_node_key)?
+                .ok_or(MissingRootError { version })?;
+            self.collect_existing_keys(
+                &root_node_key,
+                &root_node,
+                &sorted_keys,
+                0,
+                &mut existing_keys,
+            )?;
+        }
+
+        let mut values = vec![None; keys.len()];
+        let mut existing_keys = existing_keys.into_iter().peekable();
+        for indices in order.chunk_by(|a, b| keys[*a] == keys[*b]) {
+            let key = keys[indices[0]];
+            if existing_keys.next_if_eq(&key).is_some() {
+                let value = self.reader.get_value(version, key)?;
+                for i in &indices[1..] {
+                    values[*i] = Some(value.clone());
+                }
+                values[indices[0]] = Some(value);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns the values (if applicable) of all `keys` at `version` with their merkle proofs, in
+    /// the same order.
+    ///
+    /// Like [`multi_get`](JellyfishMerkleTree::multi_get), the keys are looked up together in a
+    /// single traversal of the tree, so that each node on their paths is read once however many of
+    /// the keys go through it. Each proof is the one
+    /// [`get_with_proof`](JellyfishMerkleTree::get_with_proof) returns; see
+    /// [`get_with_multiproof`](JellyfishMerkleTree::get_with_multiproof) for a single proof
+    /// sharing the siblings of all the keys.
+    #[allow(clippy::type_complexity)]
+    pub fn multi_get_with_proof(
+        &self,
+        keys: &[KeyHash],
+        version: Version,
+    ) -> Result<Vec<(Option<OwnedValue>, SparseMerkleProof<H>)>> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|i| keys[*i]);
+        let mut sorted_keys: Vec<KeyHash> = order.iter().map(|i| keys[*i]).collect();
+        sorted_keys.dedup();
+
+        let mut proofs = Vec::with_capacity(sorted_keys.len());
+        if !sorted_keys.is_empty() {
+            let root_node_key = NodeKey::new_empty_path(version);
+            let root_node = self
+                .reader
+                .get_node_option(&root_node_key)?
+                .ok_or(MissingRootError { version })?;
+            self.collect_proofs(
+                &root_node_key,
+                &root_node,
+                &sorted_keys,
+                0,
+                &mut vec![],
+                &mut proofs,
+            )?;
+        }
+
+        let mut results: Vec<_> = (0..keys.len()).map(|_| None).collect();
+        for (indices, proof) in order.chunk_by(|a, b| keys[*a] == keys[*b]).zip(proofs) {
+            let key = keys[indices[0]];
+            let value = match proof.leaf() {
+                Some(leaf) if leaf.key_hash() == key => Some(self.reader.get_value(version, key)?),
+                _ => None,
+            };
+            for i in &indices[1..] {
+                let proof = SparseMerkleProof::new(proof.leaf(), proof.siblings().to_vec());
+                results[*i] = Some((value.clone(), proof));
+            }
+            results[indices[0]] = Some((value, proof));
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("Every key has a proof."))
+            .collect())
+    }
+
+    /// Reads the distinct nodes among `node_keys` with a single call to
+    /// [`TreeReader::get_nodes`], failing if any of them is missing. Keys of different children of
+    /// an internal node may lead to the same leaf, which is then read once.
+    fn get_children<'k>(
+        &self,
+        node_keys: impl IntoIterator<Item = &'k Option<NodeKey>>,
+    ) -> Result<HashMap<NodeKey, Node>> {
+        let mut node_keys: Vec<NodeKey> = node_keys.into_iter().flatten().cloned().collect();
+        node_keys.dedup();
+        let nodes = self.reader.get_nodes(&node_keys)?;
+        node_keys
+            .into_iter()
+            .zip(nodes)
+            .map(|(node_key, node)| {
+                let node = node.ok_or_else(|| JmtError::MissingNode {
+                    node_key: node_key.clone(),
+                })?;
+                Ok((node_key, node))
+            })
+            .collect()
+    }
+
+    /// Appends to `proofs` the proofs of `keys` in the subtree rooted at `node`, in order, given
+    /// the siblings of `node` from the root down. `keys` must be sorted and deduplicated, and all
+    /// go through `node`.
+    fn collect_proofs(
+        &self,
+        node_key: &NodeKey,
+        node: &Node,
+        keys: &[KeyHash],
+        depth: usize,
+        siblings: &mut Vec<[u8; 32]>,
+        proofs: &mut Vec<SparseMerkleProof<H>>,
+    ) -> Result<()> {
+        let proof_of = |leaf: Option<SparseMerkleLeafNode>, siblings: &[[u8; 32]]| {
+            SparseMerkleProof::new(leaf, siblings.iter().rev().copied().collect())
+        };
+        match node {
+            Node::Internal(internal_node) => {
+                ensure_not_corrupted!(
+                    depth < ROOT_NIBBLE_HEIGHT,
+                    "Jellyfish Merkle tree has cyclic graph inside."
+                );
+                let internal_node = internal_node.to_ref();
+                let children: Vec<_> = keys
+                    .chunk_by(|a, b| a.0.get_nibble(depth) == b.0.get_nibble(depth))
+                    .map(|child_keys| {
+                        let nibble = child_keys[0].0.get_nibble(depth);
+                        let (child_node_key, child_siblings) =
+                            internal_node.get_child_with_siblings::<H>(node_key, nibble);
+                        (child_keys, child_node_key, child_siblings)
+                    })
+                    .collect();
+                let child_nodes = self.get_children(children.iter().map(|child| &child.1))?;
+                for (child_keys, child_node_key, child_siblings) in children {
+                    let num_siblings = siblings.len();
+                    siblings.extend(child_siblings);
+                    match child_node_key {
+                        Some(child_node_key) => self.collect_proofs(
+                            &child_node_key,
+                            &child_nodes[&child_node_key],
+                            child_keys,
+                            depth + 1,
+                            siblings,
+                            proofs,
+                        )?,
+                        None => proofs.extend(child_keys.iter().map(|_| proof_of(None, siblings))),
+                    }
+                    siblings.truncate(num_siblings);
+                }
+            }
+            Node::Leaf(leaf_node) => {
+                let leaf = SparseMerkleLeafNode::new(leaf_node.key_hash(), leaf_node.value_hash());
+                proofs.extend(keys.iter().map(|_| proof_of(Some(leaf), siblings)));
+            }
+            Node::Null => {
+                ensure_not_corrupted!(
+                    depth == 0,
+                    "Non-root null node exists with node key {:?}",
+                    node_key
+                );
+                proofs.extend(keys.iter().map(|_| proof_of(None, siblings)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends to `existing_keys` those of `keys` which are in the subtree rooted at `node`, in
+    /// order. `keys` must be sorted and deduplicated, and all go through `node`.
+    fn collect_existing_keys(
+        &self,
+        node_key: &NodeKey,
+        node: &Node,
+        keys: &[KeyHash],
+        depth: usize,
+        existing_keys: &mut Vec<KeyHash>,
+    ) -> Result<()> {
+        match node {
+            Node::Internal(internal_node) => {
+                ensure_not_corrupted!(
+                    depth < ROOT_NIBBLE_HEIGHT,
+                    "Jellyfish Merkle tree has cyclic graph inside."
+                );
+                let children: Vec<_> = keys
+                    .chunk_by(|a, b| a.0.get_nibble(depth) == b.0.get_nibble(depth))
+                    .map(|child_keys| {
+                        let nibble = child_keys[0].0.get_nibble(depth);
+                        let child_node_key =
+                            internal_node.get_child_without_siblings(node_key, nibble);
+                        (child_keys, child_node_key)
+                    })
+                    .collect();
+                let child_nodes = self.get_children(children.iter().map(|child| &child.1))?;
+                for (child_keys, child_node_key) in children {
+                    if let Some(child_node_key) = child_node_key {
+                        self.collect_existing_keys(
+                            &child_node_key,
+                            &child_nodes[&child_node_key],
+                            child_keys,
+                            depth + 1,
+                            existing_keys,
+                        )?;
+                    }
+                }
+            }
+            Node::Leaf(leaf_node) => {
+                if keys.binary_search(&leaf_node.key_hash()).is_ok() {
+                    existing_keys.push(leaf_node.key_hash());
+                }
+            }
+            Node::Null => ensure_not_corrupted!(
+                depth == 0,
+                "Non-root null node exists with node key {:?}",
+                node_key
+            ),
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over all the key-value pairs of the tree at `version`, in key hash
+    /// order.
+    pub fn iter(&self, version: Version) -> Result<JellyfishMerkleIterator<&'a R>> {
+        self.iter_from(KeyHash([0; 32]), version)
+    }
+
+    /// Returns an iterator over the key-value pairs of the tree at `version`, in key hash order,
+    /// starting from the first key hash greater than or equal to `starting_key`.
+    ///
+    /// Large scans can be paginated by starting each page right after the last key hash of the
+    /// previous one, without walking the tree from its leftmost leaf again.
+    pub fn iter_from(
+        &self,
+        starting_key: KeyHash,
+        version: Version,
+    ) -> Result<JellyfishMerkleIterator<&'a R>> {
+        JellyfishMerkleIterator::new(Arc::new(self.reader), version, starting_key)
+    }
+
+    /// Returns an iterator over the keys whose preimage starts with `prefix` and which have a value
+    /// at `version`, in lexicographic order of their preimages, along with their values.
+    ///
+    /// The preimages are listed by `index`, so keys whose preimage it does not know are skipped.
+    /// Each value is then read from the tree, as with [`get`](JellyfishMerkleTree::get).
+    pub fn iter_preimage_prefix<'b, I: HasPreimageIndex>(
+        &'b self,
+        index: &'b I,
+        prefix: &[u8],
+        version: Version,
+    ) -> Result<impl Iterator<Item = Result<(Vec<u8>, OwnedValue)>> + 'b> {
+        let preimages = index.preimages_with_prefix(prefix)?;
+        Ok(preimages.filter_map(move |entry| {
+            let (preimage, key_hash) = match entry {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            match self.get(key_hash, version) {
+                Ok(Some(value)) => Some(Ok((preimage, value))),
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            }
+        }))
+    }
+
+    /// Returns all the nodes and values of the tree at `version` below `prefix`, along with the
+    /// hash of the root of that subtree, to be imported into another store with
+    /// [`import_subtree`](crate::subtree::import_subtree).
+    pub fn export_subtree(&self, prefix: &NibblePath, version: Version) -> Result<SubtreeExport> {
+        let mut node_key = NodeKey::new_empty_path(version);
+        let mut node = self.get_root_node(version)?;
+        let mut subtree_root = None;
+        let mut nibbles = prefix.nibbles();
+        loop {
+            match node {
+                Node::Internal(internal) => match nibbles.next() {
+                    Some(nibble) => match internal.child(nibble) {
+                        Some(child) => {
+                            node_key = node_key.gen_child_node_key(child.version, nibble);
+                            node = self.reader.get_node(&node_key)?;
+                        }
+                        None => break,
+                    },
+                    None => {
+                        subtree_root = Some((node_key, Node::Internal(internal)));
+                        break;
+                    }
+                },
+                Node::Leaf(leaf) => {
+                    if subtree::starts_with(&NibblePath::from_bytes(&leaf.key_hash().0), prefix) {
+                        subtree_root = Some((node_key, Node::Leaf(leaf)));
+                    }
+                    break;
+                }
+                Node::Null => break,
+            }
+        }
+
+        let mut export = SubtreeExport {
+            version,
+            prefix: prefix.clone(),
+            root_hash: H::PLACEHOLDER_HASH,
+            nodes: vec![],
+            values: vec![],
+        };
+        let Some((root_key, root_node)) = subtree_root else {
+            return Ok(export);
+        };
+        export.root_hash = root_node.hash::<H>();
+        let mut stack = vec![(root_key, root_node)];
+        while let Some((node_key, node)) = stack.pop() {
+            match &node {
+                Node::Internal(internal) => {
+                    // Children are pushed in reverse, so that nodes are exported in key order.
+                    let children: Vec<_> = internal.children_sorted().collect();
+                    for (nibble, child) in children.into_iter().rev() {
+                        let child_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = self.reader.get_node(&child_key)?;
+                        stack.push((child_key, child_node));
+                    }
+                }
+                Node::Leaf(leaf) => {
+                    let value = self.reader.get_value(node_key.version(), leaf.key_hash())?;
+                    export
+                        .values
+                        .push((node_key.version(), leaf.key_hash(), value));
+                }
+                Node::Null => bail_corrupted!("Unexpected null node {:?} in a subtree.", node_key),
+            }
+            export.nodes.push((node_key, node));
+        }
+        Ok(export)
+    }
+
+    /// Returns the keys whose values differ between `old_version` and `new_version`, in key hash
+    /// order, with their values at both versions (`None` where the key does not exist).
+    ///
+    /// Both trees are walked together from their roots, and subtrees with the same hash in both
+    /// versions are skipped, so the cost is proportional to the number of changed keys rather than
+    /// to the size of the trees. Values are read lazily, as the iterator advances.
+    pub fn diff(
+        &self,
+        old_version: Version,
+        new_version: Version,
+    ) -> Result<impl Iterator<Item = Result<ValueChange>> + 'a> {
+        let old_root = DiffSubtree::from_node::<H>(
+            NodeKey::new_empty_path(old_version),
+            self.get_root_node(old_version)?,
+            None,
+        );
+        let new_root = DiffSubtree::from_node::<H>(
+            NodeKey::new_empty_path(new_version),
+            self.get_root_node(new_version)?,
+            None,
+        );
+        let mut changed_keys = vec![];
+        self.diff_subtrees(old_root, new_root, 0, &mut changed_keys)?;
+
+        let reader = self.reader;
+        Ok(changed_keys
+            .into_iter()
+            .map(move |(key, exists_in_old, exists_in_new)| {
+                let old_value = if exists_in_old {
+                    Some(reader.get_value(old_version, key)?)
+                } else {
+                    None
+                };
+                let new_value = if exists_in_new {
+                    Some(reader.get_value(new_version, key)?)
+                } else {
+                    None
+                };
+                Ok((key, old_value, new_value))
+            }))
+    }
+
+    /// Appends to `changed_keys` the keys whose leaves differ between the `old` and `new`
+    /// subtrees at `depth`, along with whether they exist in each subtree.
+    fn diff_subtrees(
+        &self,
+        old: DiffSubtree,
+        new: DiffSubtree,
+        depth: usize,
+        changed_keys: &mut Vec<(KeyHash, bool, bool)>,
+    ) -> Result<()> {
+        if matches!(old, DiffSubtree::Internal(..)) || matches!(new, DiffSubtree::Internal(..)) {
+            ensure_not_corrupted!(
+                depth < ROOT_NIBBLE_HEIGHT,
+                "Jellyfish Merkle tree has cyclic graph inside."
+            );
+            for n in 0..16u8 {
+                let nibble = Nibble::from(n);
+                let old_child = self.diff_child(&old, nibble, depth)?;
+                let new_child = self.diff_child(&new, nibble, depth)?;
+                if old_child.hash::<H>() != new_child.hash::<H>() {
+                    self.diff_subtrees(old_child, new_child, depth + 1, changed_keys)?;
+                }
+            }
+            return Ok(());
+        }
+
+        match (old, new) {
+            (DiffSubtree::Leaf(old_leaf), DiffSubtree::Leaf(new_leaf))
+                if old_leaf.key_hash() == new_leaf.key_hash() =>
+            {
+                if old_leaf.value_hash() != new_leaf.value_hash() {
+                    changed_keys.push((old_leaf.key_hash(), true, true));
+                }
+            }
+            (old, new) => {
+                let mut leaves = [(old, true, false), (new, false, true)]
+                    .into_iter()
+                    .filter_map(|(subtree, in_old, in_new)| match subtree {
+                        DiffSubtree::Leaf(leaf) => Some((leaf.key_hash(), in_old, in_new)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                leaves.sort_by_key(|(key, _, _)| *key);
+                changed_keys.extend(leaves);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the child at `nibble` of a subtree at `depth`. The child of a leaf is the leaf
+    /// itself if its key goes through `nibble`, and nothing otherwise.
+    fn diff_child(
+        &self,
+        subtree: &DiffSubtree,
+        nibble: Nibble,
+        depth: usize,
+    ) -> Result<DiffSubtree> {
+        Ok(match subtree {
+            DiffSubtree::Empty => DiffSubtree::Empty,
+            DiffSubtree::Leaf(leaf) => {
+                if leaf.key_hash().0.get_nibble(depth) == nibble {
+                    DiffSubtree::Leaf(leaf.clone())
+                } else {
+                    DiffSubtree::Empty
+                }
+            }
+            DiffSubtree::Internal(node_key, internal_node, _) => {
+                match internal_node.child(nibble) {
+                    Some(child) => {
+                        let child_node_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = self.reader.get_node(&child_node_key)?;
+                        DiffSubtree::from_node::<H>(child_node_key, child_node, Some(child.hash))
+                    }
+                    None => DiffSubtree::Empty,
+                }
+            }
+        })
+    }
+
     fn get_root_node(&self, version: Version) -> Result<Node> {
         self.get_root_node_option(version)?
             .ok_or_else(|| format_err!("Root node not found for version {}.", version))
@@ -888,14 +2270,70 @@ where
         self.reader.get_node_option(&root_node_key)
     }
 
+    /// Returns the root hash of the tree at `version`.
+    ///
+    /// The root hash is read from the index of the storage if it keeps one (see
+    /// [`TreeReader::get_indexed_root_hash`]), and computed from the root node otherwise.
     pub fn get_root_hash(&self, version: Version) -> Result<RootHash> {
-        self.get_root_node(version).map(|n| RootHash(n.hash()))
+        if let Some(root_hash) = self.reader.get_indexed_root_hash(version)? {
+            return Ok(root_hash);
+        }
+        self.get_root_node(version).map(|n| RootHash(n.hash::<H>()))
+    }
+
+    /// Returns an iterator over the root hashes recorded in the index of the storage for the
+    /// versions in `versions`, in ascending order of version.
+    ///
+    /// The root hashes are read in pages from [`TreeReader::get_indexed_root_hashes`], without
+    /// reading any node, so versions whose root hash was not recorded are skipped, even if their
+    /// root node exists.
+    pub fn iter_root_hashes(
+        &self,
+        versions: impl RangeBounds<Version>,
+    ) -> impl Iterator<Item = Result<(Version, RootHash)>> + 'a {
+        let mut next_version = match versions.start_bound() {
+            Bound::Included(version) => Some(*version),
+            Bound::Excluded(version) => version.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let end = match versions.end_bound() {
+            Bound::Included(version) => version.saturating_add(1),
+            Bound::Excluded(version) => *version,
+            Bound::Unbounded => Version::MAX,
+        };
+        let reader = self.reader;
+        let mut page = Vec::new().into_iter();
+        std::iter::from_fn(move || loop {
+            if let Some(root_hash) = page.next() {
+                return Some(Ok(root_hash));
+            }
+            let start = next_version.filter(|start| *start < end)?;
+            match reader.get_indexed_root_hashes(start..end, ROOT_HASHES_PER_PAGE) {
+                Ok(root_hashes) => {
+                    next_version = match root_hashes.last() {
+                        Some((version, _)) if root_hashes.len() == ROOT_HASHES_PER_PAGE => {
+                            version.checked_add(1)
+                        }
+                        _ => None,
+                    };
+                    page = root_hashes.into_iter();
+                }
+                Err(error) => {
+                    next_version = None;
+                    return Some(Err(error));
+                }
+            }
+        })
     }
 
+    /// Returns the root hash of the tree at `version`, or `None` if there is no such root.
     pub fn get_root_hash_option(&self, version: Version) -> Result<Option<RootHash>> {
+        if let Some(root_hash) = self.reader.get_indexed_root_hash(version)? {
+            return Ok(Some(root_hash));
+        }
         Ok(self
             .get_root_node_option(version)?
-            .map(|n| RootHash(n.hash())))
+            .map(|n| RootHash(n.hash::<H>())))
     }
 
     // TODO: should this be public? seems coupled to tests?
@@ -910,6 +2348,221 @@ where
             Ok(None)
         }
     }
+
+    /// Returns statistics about the tree at `version`, computed by visiting all its nodes and
+    /// reading the value of each leaf.
+    pub fn stats(&self, version: Version) -> Result<TreeStats> {
+        let mut stats = TreeStats::default();
+        let mut stack = vec![(
+            NodeKey::new_empty_path(version),
+            self.get_root_node(version)?,
+        )];
+        while let Some((node_key, node)) = stack.pop() {
+            stats.max_depth = stats.max_depth.max(node_key.nibble_path().num_nibbles());
+            match node {
+                Node::Internal(internal) => {
+                    stats.internal_node_count += 1;
+                    for (nibble, child) in internal.children_sorted() {
+                        let child_key = node_key.gen_child_node_key(child.version, nibble);
+                        let child_node = self.reader.get_node(&child_key)?;
+                        stack.push((child_key, child_node));
+                    }
+                }
+                Node::Leaf(leaf) => {
+                    stats.leaf_count += 1;
+                    stats.value_bytes += self
+                        .reader
+                        .get_value(node_key.version(), leaf.key_hash())?
+                        .len();
+                }
+                Node::Null => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader + HasVersionPins,
+    H: SimpleHasher,
+{
+    /// Pins `version` against pruning until the returned guard is dropped, so that the tree
+    /// remains readable at `version` meanwhile, e.g. while iterating over it or generating proofs.
+    /// Pruning is only held back by a [`Pruner`](crate::pruner::Pruner) given the pins of the
+    /// store with [`with_version_pins`](crate::pruner::Pruner::with_version_pins).
+    ///
+    /// Fails if `version` was already pruned, or if the tree has no root at `version`.
+    pub fn pin_version(&self, version: Version) -> Result<VersionGuard> {
+        let guard = self.reader.version_pins().pin(version)?;
+        // The root is checked once pinned, so that it cannot be pruned in between.
+        self.reader
+            .get_node_option(&NodeKey::new_empty_path(version))?
+            .ok_or(MissingRootError { version })?;
+        Ok(guard)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader + Sync,
+    H: SimpleHasher,
+{
+    /// Like [`batch_put_value_sets`](JellyfishMerkleTree::batch_put_value_sets) with a single
+    /// value set, but builds the subtrees below the root on the rayon thread pool.
+    ///
+    /// Keys are partitioned by their first nibble, and the subtree of each partition is built in
+    /// its own cache, in parallel. The caches are then merged in nibble order, so the result is
+    /// the same as that of the sequential path. This pays off for large value sets, with 100k
+    /// keys or more; a tree whose root is a leaf is updated sequentially.
+    pub fn par_put_value_set(
+        &self,
+        value_set: Vec<(KeyHash, OwnedValue)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        use rayon::prelude::*;
+
+        assert!(
+            !value_set.is_empty(),
+            "Transactions that output empty write set should not be included.",
+        );
+        let mut tree_cache = TreeCache::new(self.reader, version)?;
+        let deduped_and_sorted_kvs = value_set
+            .into_iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(key, value)| {
+                let value_hash = ValueHash::with::<H>(value.as_slice());
+                tree_cache.put_value(version, key, Some(value));
+                (key, value_hash)
+            })
+            .collect::<Vec<_>>();
+
+        let root_node_key = tree_cache.get_root_node_key().clone();
+        let mut children = match tree_cache.get_node(&root_node_key)? {
+            Node::Internal(internal_node) => {
+                tree_cache.delete_node(&root_node_key, StaleNodeKind::Internal);
+                Children::from(internal_node)
+            }
+            Node::Null if deduped_and_sorted_kvs.len() > 1 => {
+                if root_node_key.version() == version {
+                    tree_cache.delete_node(&root_node_key, StaleNodeKind::Internal);
+                }
+                Children::new()
+            }
+            _ => {
+                // There is nothing to split below a leaf or a single key.
+                let (new_root_node_key, _) = self.batch_insert_at(
+                    root_node_key,
+                    version,
+                    &deduped_and_sorted_kvs,
+                    0,
+                    &None,
+                    &mut tree_cache,
+                )?;
+                return self.freeze_single_version(tree_cache, new_root_node_key);
+            }
+        };
+
+        let kvs = &deduped_and_sorted_kvs;
+        let subtrees = NibbleRangeIterator::new(kvs, 0)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(left, right)| {
+                let child_index = kvs[left].0 .0.get_nibble(0);
+                let mut subtree_cache = TreeCache::new_subtree(self.reader, version);
+                let (_, child_node) = match children.get(child_index) {
+                    Some(child) => self.batch_insert_at(
+                        root_node_key.gen_child_node_key(child.version, child_index),
+                        version,
+                        &kvs[left..=right],
+                        1,
+                        &None,
+                        &mut subtree_cache,
+                    )?,
+                    None => self.batch_create_subtree(
+                        root_node_key.gen_child_node_key(version, child_index),
+                        version,
+                        &kvs[left..=right],
+                        1,
+                        &None,
+                        &mut subtree_cache,
+                    )?,
+                };
+                Ok((
+                    child_index,
+                    child_node.hash::<H>(),
+                    child_node.node_type(),
+                    subtree_cache,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (child_index, child_hash, child_node_type, subtree_cache) in subtrees {
+            tree_cache.merge(subtree_cache)?;
+            children.insert(
+                child_index,
+                Child::new(child_hash, version, child_node_type),
+            );
+        }
+        let new_root_node_key = NodeKey::new_empty_path(version);
+        tree_cache.put_node(
+            new_root_node_key.clone(),
+            InternalNode::new_migration(children, self.leaf_count_migration).into(),
+        )?;
+        self.freeze_single_version(tree_cache, new_root_node_key)
+    }
+
+    fn freeze_single_version(
+        &self,
+        mut tree_cache: TreeCache<R>,
+        root_node_key: NodeKey,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        tree_cache.set_root_node_key(root_node_key);
+        tree_cache.freeze::<H>()?;
+        let (root_hashes, tree_update_batch): (Vec<RootHash>, TreeUpdateBatch) = tree_cache.into();
+        Ok((root_hashes[0], tree_update_batch))
+    }
+}
+
+/// Returns the smallest and the largest key hash whose path goes through `nibble_path`.
+fn nibble_path_key_bounds(nibble_path: &NibblePath) -> (KeyHash, KeyHash) {
+    let mut min_key = [0u8; 32];
+    min_key[..nibble_path.bytes().len()].copy_from_slice(nibble_path.bytes());
+    let mut max_key = min_key;
+    for i in nibble_path.num_nibbles()..ROOT_NIBBLE_HEIGHT {
+        max_key[i / 2] |= if i.is_multiple_of(2) { 0xf0 } else { 0x0f };
+    }
+    (KeyHash(min_key), KeyHash(max_key))
+}
+
+/// Collects the siblings of the subtree at bit depth `depth` that contains the paths of all the
+/// given keys, which must be sorted, distinct and share their first `depth` bits, in the order in
+/// which [`SparseMerkleMultiProof::verify`] consumes them.
+fn collect_multiproof_siblings<H: SimpleHasher>(
+    proofs: &[(KeyHash, SparseMerkleProof<H>)],
+    depth: usize,
+    siblings: &mut Vec<[u8; 32]>,
+) {
+    // Keys sharing a prefix walk through the same nodes, so if one path ends here, all do.
+    if proofs[0].1.siblings().len() == depth {
+        return;
+    }
+
+    // The siblings in a `SparseMerkleProof` are ordered from the bottom level to the root level.
+    let sibling_at_depth = |proof: &SparseMerkleProof<H>| {
+        let proof_siblings = proof.siblings();
+        proof_siblings[proof_siblings.len() - 1 - depth]
+    };
+    let split = proofs.partition_point(|(key, _)| !key.0.iter_bits().nth(depth).unwrap());
+    for half in [&proofs[..split], &proofs[split..]] {
+        if half.is_empty() {
+            siblings.push(sibling_at_depth(&proofs[0].1));
+        } else {
+            collect_multiproof_siblings(half, depth + 1, siblings);
+        }
+    }
 }
 
 /// The result of putting a single key-value pair into the tree, or deleting a key.
@@ -921,3 +2574,93 @@ enum PutResult<T> {
     // Key to delete not found.
     NotChanged,
 }
+
+/// One side of a subtree compared by [`JellyfishMerkleTree::diff`]. Internal nodes come with their
+/// hash, as recorded in their parent, so that identical subtrees are skipped without hashing them.
+enum DiffSubtree {
+    Empty,
+    Leaf(LeafNode),
+    Internal(NodeKey, InternalNode, [u8; 32]),
+}
+
+impl DiffSubtree {
+    fn from_node<H: SimpleHasher>(node_key: NodeKey, node: Node, hash: Option<[u8; 32]>) -> Self {
+        match node {
+            Node::Null => DiffSubtree::Empty,
+            Node::Leaf(leaf_node) => DiffSubtree::Leaf(leaf_node),
+            Node::Internal(internal_node) => {
+                let hash = hash.unwrap_or_else(|| internal_node.hash::<H>());
+                DiffSubtree::Internal(node_key, internal_node, hash)
+            }
+        }
+    }
+
+    fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        match self {
+            DiffSubtree::Empty => H::PLACEHOLDER_HASH,
+            DiffSubtree::Leaf(leaf_node) => leaf_node.hash::<H>(),
+            DiffSubtree::Internal(_, _, hash) => *hash,
+        }
+    }
+}
+
+/// Returns the preimage of the lowest sibling of `key` which is not a placeholder, given the
+/// inclusion `proof` of `key` at `version`, or `None` if all its siblings are placeholders.
+///
+/// The sibling is the root of the subtree on the other side of the path of `key`, so the preimage
+/// is read from the proof of a key in that subtree.
+fn deleted_leaf_sibling_preimage<R: TreeReader, H: SimpleHasher>(
+    tree: &JellyfishMerkleTree<'_, R, H>,
+    key: KeyHash,
+    proof: &SparseMerkleProof<H>,
+    version: Version,
+) -> Result<Option<SparseMerkleNodePreimage>> {
+    let siblings = proof.siblings();
+    let lowest_sibling = match (0..siblings.len()).find(|i| siblings[*i] != H::PLACEHOLDER_HASH) {
+        Some(lowest_sibling) => lowest_sibling,
+        None => return Ok(None),
+    };
+    // The sibling is at depth `sibling_depth`, under the path of `key` with its last bit flipped.
+    let sibling_depth = siblings.len() - lowest_sibling;
+    let mut sibling_key = key.0;
+    sibling_key[(sibling_depth - 1) / 8] ^= 0x80 >> ((sibling_depth - 1) % 8);
+    let sibling_key = KeyHash(sibling_key);
+
+    let sibling_proof = tree.get_proof(sibling_key, version)?;
+    let sibling_siblings = sibling_proof.siblings();
+    if sibling_siblings.len() == sibling_depth {
+        let leaf = sibling_proof
+            .leaf()
+            .ok_or_else(|| format_err!("Missing sibling of a deleted leaf."))?;
+        return Ok(Some(SparseMerkleNodePreimage::Leaf(leaf)));
+    }
+    ensure!(
+        sibling_siblings.len() > sibling_depth,
+        "The proof of a key in the sibling subtree is shorter than the path to the subtree."
+    );
+
+    // Hash the path of `sibling_key` up to the child of the sibling at depth `sibling_depth + 1`.
+    let num_lower_siblings = sibling_siblings.len() - sibling_depth - 1;
+    let path_child = sibling_siblings[..num_lower_siblings]
+        .iter()
+        .enumerate()
+        .fold(
+            sibling_proof
+                .leaf()
+                .map_or(H::PLACEHOLDER_HASH, |leaf| leaf.hash::<H>()),
+            |hash, (i, sibling_hash)| {
+                if sibling_key.0.get_bit(sibling_siblings.len() - 1 - i) {
+                    SparseMerkleInternalNode::new(*sibling_hash, hash).hash::<H>()
+                } else {
+                    SparseMerkleInternalNode::new(hash, *sibling_hash).hash::<H>()
+                }
+            },
+        );
+    let other_child = sibling_siblings[num_lower_siblings];
+    let (left, right) = if sibling_key.0.get_bit(sibling_depth) {
+        (other_child, path_child)
+    } else {
+        (path_child, other_child)
+    };
+    Ok(Some(SparseMerkleNodePreimage::Internal { left, right }))
+}
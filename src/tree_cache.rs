@@ -36,6 +36,7 @@
 //! left child and the new root. We should
 //!   1) create a new version for `key1` child.
 //!   2) update `root1'` directly instead of making another version.
+//!
 //! The resulting tree should look like:
 //!
 //! ```text
@@ -59,25 +60,30 @@
 //! collection of the following operations:
 //!   - Put a new node.
 //!   - Delete a node.
+//!
 //! When we apply these operations on a multi-version tree:
 //!   1) Put a new node.
 //!   2) When we remove a node, if the node is in the previous on-disk version, we don't need to do
 //!      anything. Otherwise we delete it from the tree cache.
+//!
 //! Updating node could be operated as deletion of the node followed by insertion of the updated
 //! node.
 
-use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
+use std::collections::{hash_map::Entry, BTreeSet, HashMap};
 
 use anyhow::{bail, Result};
+use bytes::Bytes;
 
+#[cfg(feature = "metrics")]
+use crate::metrics::DIEM_JELLYFISH_STORAGE_READS;
 use crate::{
-    metrics::DIEM_JELLYFISH_STORAGE_READS,
     node_type::{Node, NodeKey},
     storage::{
-        NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, TreeReader, TreeUpdateBatch,
+        NodeBatch, NodeStats, StaleNodeIndex, StaleNodeIndexBatch, StaleNodeKind, TreeReader,
+        TreeUpdateBatch,
     },
     types::{Version, PRE_GENESIS_VERSION},
-    KeyHash, OwnedValue, RootHash,
+    KeyHash, OwnedValue, RootHash, SimpleHasher,
 };
 
 /// `FrozenTreeCache` is used as a field of `TreeCache` storing all the nodes and values that
@@ -125,13 +131,13 @@ pub struct TreeCache<'a, R> {
     // The Hashmap guarantees that if the same (version, key) pair is written several times, only the last
     // change is saved, which means that the TreeWriter can process node batches in parallel without racing.
     // The batch APIs already deduplicate operations on each key, so they don't need this HashMap.
-    value_cache: HashMap<(Version, KeyHash), Option<OwnedValue>>,
+    value_cache: HashMap<(Version, KeyHash), Option<Bytes>>,
 
     /// # of leaves in the `node_cache`,
     num_new_leaves: usize,
 
-    /// Partial stale log. `NodeKey` to identify the stale record.
-    stale_node_index_cache: HashSet<NodeKey>,
+    /// Partial stale log. `NodeKey` to identify the stale record, with the kind of the node.
+    stale_node_index_cache: HashMap<NodeKey, StaleNodeKind>,
 
     /// # of leaves in the `stale_node_index_cache`,
     num_stale_leaves: usize,
@@ -174,7 +180,7 @@ where
         };
         Ok(Self {
             node_cache,
-            stale_node_index_cache: HashSet::new(),
+            stale_node_index_cache: HashMap::new(),
             frozen_cache: FrozenTreeCache::new(),
             root_node_key,
             next_version,
@@ -185,6 +191,44 @@ where
         })
     }
 
+    /// Constructs an empty `TreeCache` to collect the updates of a single subtree at
+    /// `next_version`, which are later [`merge`](TreeCache::merge)d into the cache of the whole
+    /// tree. Its root node key is unused.
+    #[cfg(feature = "rayon")]
+    pub fn new_subtree(reader: &'a R, next_version: Version) -> Self {
+        Self {
+            node_cache: HashMap::new(),
+            stale_node_index_cache: HashMap::new(),
+            frozen_cache: FrozenTreeCache::new(),
+            root_node_key: NodeKey::new_empty_path(next_version),
+            next_version,
+            reader,
+            num_stale_leaves: 0,
+            num_new_leaves: 0,
+            value_cache: Default::default(),
+        }
+    }
+
+    /// Moves the pending updates of `other`, a cache created by
+    /// [`new_subtree`](TreeCache::new_subtree) for the same version, into this cache. The two
+    /// caches must have touched disjoint sets of nodes.
+    #[cfg(feature = "rayon")]
+    pub fn merge(&mut self, other: TreeCache<'a, R>) -> Result<()> {
+        for (node_key, node) in other.node_cache {
+            self.put_node(node_key, node)?;
+        }
+        for (node_key, node_kind) in other.stale_node_index_cache {
+            let is_new_entry = self
+                .stale_node_index_cache
+                .insert(node_key, node_kind)
+                .is_none();
+            assert!(is_new_entry, "Node gets stale twice unexpectedly.");
+        }
+        self.num_stale_leaves += other.num_stale_leaves;
+        self.value_cache.extend(other.value_cache);
+        Ok(())
+    }
+
     /// Gets a node with given node key. If it doesn't exist in node cache, read from `reader`.
     pub fn get_node(&self, node_key: &NodeKey) -> Result<Node> {
         Ok(if let Some(node) = self.node_cache.get(node_key) {
@@ -192,6 +236,7 @@ where
         } else if let Some(node) = self.frozen_cache.node_cache.nodes().get(node_key) {
             node.clone()
         } else {
+            #[cfg(feature = "metrics")]
             DIEM_JELLYFISH_STORAGE_READS.inc();
             self.reader.get_node(node_key)?
         })
@@ -205,6 +250,7 @@ where
         } else if let Some(node) = self.frozen_cache.node_cache.nodes().get(node_key) {
             Some(node.clone())
         } else {
+            #[cfg(feature = "metrics")]
             DIEM_JELLYFISH_STORAGE_READS.inc();
             self.reader.get_node_option(node_key)?
         })
@@ -234,27 +280,63 @@ where
         Ok(())
     }
 
+    /// Puts the value of `key_hash` at `version`, moving it into the cache without copying it.
     pub fn put_value(&mut self, version: Version, key_hash: KeyHash, value: Option<OwnedValue>) {
-        self.value_cache.insert((version, key_hash), value);
+        self.value_cache
+            .insert((version, key_hash), value.map(Bytes::from));
+    }
+
+    /// Gets the latest value of `key_hash`, either pending in this cache or committed to `reader`
+    /// before the first version of this cache. Returns `None` if the key has no value. Pending
+    /// values are shared with the cache rather than copied.
+    pub fn get_value_option(&self, key_hash: KeyHash) -> Result<Option<Bytes>> {
+        if let Some(value) = self.value_cache.get(&(self.next_version, key_hash)) {
+            return Ok(value.clone());
+        }
+        let first_version = self.next_version - self.frozen_cache.root_hashes.len() as Version;
+        for version in (first_version..self.next_version).rev() {
+            if let Some(value) = self
+                .frozen_cache
+                .node_cache
+                .values()
+                .get(&(version, key_hash))
+            {
+                return Ok(value.clone());
+            }
+        }
+        match first_version.checked_sub(1) {
+            Some(max_version) => Ok(self
+                .reader
+                .get_value_option(max_version, key_hash)?
+                .map(Bytes::from)),
+            None => Ok(None),
+        }
     }
 
     /// Deletes a node with given hash.
-    pub fn delete_node(&mut self, old_node_key: &NodeKey, is_leaf: bool) {
+    pub fn delete_node(&mut self, old_node_key: &NodeKey, node_kind: StaleNodeKind) {
         // If node cache doesn't have this node, it means the node is in the previous version of
         // the tree on the disk.
         if self.node_cache.remove(old_node_key).is_none() {
-            let is_new_entry = self.stale_node_index_cache.insert(old_node_key.clone());
+            let is_new_entry = self
+                .stale_node_index_cache
+                .insert(old_node_key.clone(), node_kind)
+                .is_none();
             assert!(is_new_entry, "Node gets stale twice unexpectedly.");
-            if is_leaf {
+            if node_kind.is_leaf() {
                 self.num_stale_leaves += 1;
             }
-        } else if is_leaf {
+        } else if node_kind.is_leaf() {
             self.num_new_leaves -= 1;
         }
     }
 
     /// Freezes all the contents in cache to be immutable and clear `node_cache`.
-    pub fn freeze(&mut self) -> Result<()> {
+    ///
+    /// The hash of a node is computed when its parent is created, so freezing hashes the root
+    /// node only. With the `rayon` feature, the subtrees below the root are built, and hashed, in
+    /// parallel by `JellyfishMerkleTree::par_put_value_set`.
+    pub fn freeze<H: SimpleHasher>(&mut self) -> Result<()> {
         let mut root_node_key = self.get_root_node_key().clone();
 
         let root_node = if let Some(root_node) = self.get_node_option(&root_node_key)? {
@@ -272,7 +354,7 @@ where
         // they can be extracted later after a sequence of transactions:
         self.frozen_cache
             .root_hashes
-            .push(RootHash(root_node.hash()));
+            .push(RootHash(root_node.hash::<H>()));
 
         // If the effect of this set of changes has been to do nothing, we still need to create a
         // new root node that matches the anticipated version; we do this by copying the previous
@@ -307,9 +389,10 @@ where
             .extend(
                 self.stale_node_index_cache
                     .drain()
-                    .map(|node_key| StaleNodeIndex {
+                    .map(|(node_key, node_kind)| StaleNodeIndex {
                         stale_since_version,
                         node_key,
+                        node_kind,
                     }),
             );
 
@@ -329,10 +412,16 @@ where
     R: 'a + TreeReader,
 {
     fn from(tree_cache: TreeCache<'a, R>) -> Self {
+        let mut node_batch = tree_cache.frozen_cache.node_cache;
+        let first_version =
+            tree_cache.next_version - tree_cache.frozen_cache.root_hashes.len() as Version;
+        for (version, root_hash) in (first_version..).zip(&tree_cache.frozen_cache.root_hashes) {
+            node_batch.insert_root_hash(version, *root_hash);
+        }
         (
             tree_cache.frozen_cache.root_hashes,
             TreeUpdateBatch {
-                node_batch: tree_cache.frozen_cache.node_cache,
+                node_batch,
                 stale_node_index_batch: tree_cache.frozen_cache.stale_node_index_cache,
                 node_stats: tree_cache.frozen_cache.node_stats,
             },
@@ -0,0 +1,207 @@
+//! Read-only handles on a tree at a version.
+//!
+//! A [`TreeSnapshot`] reads the tree at a single version, without borrowing a
+//! [`JellyfishMerkleTree`]: it owns its reader, which is either a reference to the storage, as in
+//! the snapshots returned by [`JellyfishMerkleTree::snapshot`], or a shared handle such as an
+//! [`Arc`](std::sync::Arc), so that it can be handed to request handlers, e.g. on other threads.
+//!
+//! The root node of the version is read once, when the snapshot is taken, and served from memory
+//! afterwards. A snapshot taken with [`TreeSnapshot::new_pinned`] also pins its version against
+//! pruning until it is dropped, see [`VersionPins`].
+
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::{
+    iterator::JellyfishMerkleIterator,
+    node_type::{LeafNode, Node, NodeKey},
+    proof::SparseMerkleProof,
+    pruner::{VersionGuard, VersionPins},
+    reader::read_missing_nodes,
+    storage::TreeReader,
+    JellyfishMerkleTree, KeyHash, MissingRootError, OwnedValue, PhantomHasher, RootHash,
+    SimpleHasher, Version,
+};
+
+/// A read-only view of a tree at a version, see the [module](self) documentation.
+pub struct TreeSnapshot<R, H: SimpleHasher> {
+    reader: R,
+    version: Version,
+    root_key: NodeKey,
+    root: Node,
+    root_hash: RootHash,
+    guard: Option<VersionGuard>,
+    _phantom_hasher: PhantomHasher<H>,
+}
+
+impl<R, H> TreeSnapshot<R, H>
+where
+    R: TreeReader,
+    H: SimpleHasher,
+{
+    /// Takes a snapshot of the tree at `version` read from `reader`. Fails with a
+    /// [`MissingRootError`] if there is no root at `version`.
+    pub fn new(reader: R, version: Version) -> Result<Self> {
+        Self::with_guard(reader, version, None)
+    }
+
+    /// Same as [`new`](TreeSnapshot::new), but also pins `version` in `version_pins` until the
+    /// snapshot is dropped, so that a [`Pruner`](crate::pruner::Pruner) sharing them does not
+    /// delete the nodes it reads.
+    pub fn new_pinned(reader: R, version: Version, version_pins: &VersionPins) -> Result<Self> {
+        let guard = version_pins.pin(version)?;
+        Self::with_guard(reader, version, Some(guard))
+    }
+
+    fn with_guard(reader: R, version: Version, guard: Option<VersionGuard>) -> Result<Self> {
+        let root_key = NodeKey::new_empty_path(version);
+        let root = reader
+            .get_node_option(&root_key)?
+            .ok_or(MissingRootError { version })?;
+        let root_hash = RootHash(root.hash::<H>());
+        Ok(Self {
+            reader,
+            version,
+            root_key,
+            root,
+            root_hash,
+            guard,
+            _phantom_hasher: Default::default(),
+        })
+    }
+
+    /// Returns the version of the snapshot.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the root hash of the tree at the version of the snapshot.
+    pub fn root_hash(&self) -> RootHash {
+        self.root_hash
+    }
+
+    /// Returns true if the version of the snapshot is pinned against pruning.
+    pub fn is_pinned(&self) -> bool {
+        self.guard.is_some()
+    }
+
+    /// Returns the underlying reader.
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the value of `key`, as [`JellyfishMerkleTree::get`] does.
+    pub fn get(&self, key: KeyHash) -> Result<Option<OwnedValue>> {
+        JellyfishMerkleTree::<_, H>::new(&self.cached_root()).get(key, self.version)
+    }
+
+    /// Returns the value of `key` and its proof, as [`JellyfishMerkleTree::get_with_proof`] does.
+    pub fn get_with_proof(
+        &self,
+        key: KeyHash,
+    ) -> Result<(Option<OwnedValue>, SparseMerkleProof<H>)> {
+        JellyfishMerkleTree::<_, H>::new(&self.cached_root()).get_with_proof(key, self.version)
+    }
+
+    /// Returns an iterator over all the key-value pairs of the snapshot, in key hash order.
+    pub fn iter(&self) -> Result<JellyfishMerkleIterator<&R>> {
+        JellyfishMerkleTree::<_, H>::new(&self.reader).iter(self.version)
+    }
+
+    /// Returns an iterator over the key-value pairs of the snapshot, in key hash order, starting
+    /// from the first key hash greater than or equal to `starting_key`.
+    pub fn iter_from(&self, starting_key: KeyHash) -> Result<JellyfishMerkleIterator<&R>> {
+        JellyfishMerkleTree::<_, H>::new(&self.reader).iter_from(starting_key, self.version)
+    }
+
+    fn cached_root(&self) -> CachedRoot<'_, R> {
+        CachedRoot {
+            reader: &self.reader,
+            root_key: &self.root_key,
+            root: &self.root,
+        }
+    }
+}
+
+impl<R: Clone, H: SimpleHasher> Clone for TreeSnapshot<R, H> {
+    fn clone(&self) -> Self {
+        Self {
+            reader: self.reader.clone(),
+            version: self.version,
+            root_key: self.root_key.clone(),
+            root: self.root.clone(),
+            root_hash: self.root_hash,
+            guard: self.guard.clone(),
+            _phantom_hasher: Default::default(),
+        }
+    }
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Takes a read-only snapshot of the tree at `version`, which reads from the storage of the
+    /// tree without borrowing the tree itself.
+    pub fn snapshot(&self, version: Version) -> Result<TreeSnapshot<&'a R, H>> {
+        TreeSnapshot::new(self.reader(), version)
+    }
+}
+
+/// A [`TreeReader`] serving the root node of a snapshot from memory.
+struct CachedRoot<'s, R> {
+    reader: &'s R,
+    root_key: &'s NodeKey,
+    root: &'s Node,
+}
+
+impl<R: TreeReader> TreeReader for CachedRoot<'_, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        if node_key == self.root_key {
+            return Ok(Some(self.root.clone()));
+        }
+        self.reader.get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let mut nodes: Vec<Option<Node>> = node_keys
+            .iter()
+            .map(|node_key| (node_key == self.root_key).then(|| self.root.clone()))
+            .collect();
+        read_missing_nodes(self.reader, node_keys, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        if node_key == self.root_key {
+            return Ok(None);
+        }
+        self.reader.get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.reader.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        self.reader.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        self.reader.get_indexed_root_hashes(versions, limit)
+    }
+}
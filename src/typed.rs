@@ -0,0 +1,103 @@
+//! Trees holding typed values instead of raw bytes.
+//!
+//! A [`JellyfishMerkleTree`] stores and hashes values as byte vectors. A [`TypedTree`] wraps it
+//! with a [`ValueCodec`], so that values are encoded when they are put and decoded when they are
+//! read. The value hashes committed to by the tree are hashes of the encoded values, so proofs
+//! returned by a [`TypedTree`] are verified against [`ValueCodec::encode_value`].
+
+use core::marker::PhantomData;
+
+use anyhow::Result;
+
+use crate::{
+    proof::SparseMerkleProof,
+    storage::{TreeReader, TreeUpdateBatch},
+    JellyfishMerkleTree, KeyHash, OwnedValue, RootHash, SimpleHasher, Version,
+};
+
+/// Encodes values to the bytes stored in a tree, and decodes them back.
+///
+/// The encoding must be deterministic: the same value must always be encoded to the same bytes,
+/// since the tree commits to the hash of the encoded value.
+pub trait ValueCodec: Sized {
+    /// Encodes the value to the bytes stored in the tree.
+    fn encode_value(&self) -> Result<OwnedValue>;
+
+    /// Decodes a value from the bytes stored in the tree.
+    fn decode_value(bytes: &[u8]) -> Result<Self>;
+}
+
+impl ValueCodec for OwnedValue {
+    fn encode_value(&self) -> Result<OwnedValue> {
+        Ok(self.clone())
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// A [`JellyfishMerkleTree`] whose values are of type `T`, encoded with its [`ValueCodec`].
+pub struct TypedTree<'a, R, T, H: SimpleHasher> {
+    tree: JellyfishMerkleTree<'a, R, H>,
+    _phantom_value: PhantomData<fn() -> T>,
+}
+
+impl<'a, R, T, H> TypedTree<'a, R, T, H>
+where
+    R: 'a + TreeReader,
+    T: ValueCodec,
+    H: SimpleHasher,
+{
+    /// Creates a `TypedTree` backed by the given [`TreeReader`].
+    pub fn new(reader: &'a R) -> Self {
+        Self {
+            tree: JellyfishMerkleTree::new(reader),
+            _phantom_value: PhantomData,
+        }
+    }
+
+    /// Returns the underlying tree, holding the encoded values.
+    pub fn inner(&self) -> &JellyfishMerkleTree<'a, R, H> {
+        &self.tree
+    }
+
+    /// Encodes the values of `value_set` and puts them at `version`, as
+    /// [`JellyfishMerkleTree::put_value_set`] does.
+    pub fn put_value_set(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<T>)>,
+        version: Version,
+    ) -> Result<(RootHash, TreeUpdateBatch)> {
+        let value_set = value_set
+            .into_iter()
+            .map(|(key_hash, value)| Ok((key_hash, value.map(|v| v.encode_value()).transpose()?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.tree.put_value_set(value_set, version)
+    }
+
+    /// Returns the decoded value of `key` at `version`, if any.
+    pub fn get(&self, key: KeyHash, version: Version) -> Result<Option<T>> {
+        self.tree
+            .get(key, version)?
+            .map(|bytes| T::decode_value(&bytes))
+            .transpose()
+    }
+
+    /// Returns the decoded value of `key` at `version`, if any, and the corresponding merkle
+    /// proof. The proof commits to the encoded value.
+    pub fn get_with_proof(
+        &self,
+        key: KeyHash,
+        version: Version,
+    ) -> Result<(Option<T>, SparseMerkleProof<H>)> {
+        let (value, proof) = self.tree.get_with_proof(key, version)?;
+        let value = value.map(|bytes| T::decode_value(&bytes)).transpose()?;
+        Ok((value, proof))
+    }
+
+    /// Returns the root hash of the tree at `version`.
+    pub fn get_root_hash(&self, version: Version) -> Result<RootHash> {
+        self.tree.get_root_hash(version)
+    }
+}
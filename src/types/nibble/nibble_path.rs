@@ -10,10 +10,14 @@ use mirai_annotations::*;
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest::{collection::vec, prelude::*};
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 
 use crate::types::nibble::{Nibble, ROOT_NIBBLE_HEIGHT};
 
 /// NibblePath defines a path in Merkle tree in the unit of nibble (4 bits).
+///
+/// The bytes of a path are stored inline, so that creating, cloning and extending the paths of
+/// node keys does not allocate. They serialize as a vector of bytes would.
 #[derive(Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct NibblePath {
     /// Indicates the total number of nibbles in bytes. Either `bytes.len() * 2 - 1` or
@@ -23,7 +27,7 @@ pub struct NibblePath {
     num_nibbles: usize,
     /// The underlying bytes that stores the path, 2 nibbles per byte. If the number of nibbles is
     /// odd, the second half of the last byte must be 0.
-    bytes: Vec<u8>,
+    bytes: SmallVec<[u8; ROOT_NIBBLE_HEIGHT / 2]>,
     // invariant num_nibbles <= ROOT_NIBBLE_HEIGHT
 }
 
@@ -38,7 +42,7 @@ impl fmt::Debug for NibblePath {
 /// Convert a vector of bytes into `NibblePath` using the lower 4 bits of each byte as nibble.
 impl FromIterator<Nibble> for NibblePath {
     fn from_iter<I: IntoIterator<Item = Nibble>>(iter: I) -> Self {
-        let mut nibble_path = NibblePath::new(vec![]);
+        let mut nibble_path = NibblePath::from_bytes(&[]);
         for nibble in iter {
             nibble_path.push(nibble);
         }
@@ -86,9 +90,18 @@ prop_compose! {
 impl NibblePath {
     /// Creates a new `NibblePath` from a vector of bytes assuming each byte has 2 nibbles.
     pub fn new(bytes: Vec<u8>) -> Self {
+        Self::from_bytes(&bytes)
+    }
+
+    /// Similar to `new()` but copies the bytes from a slice, such as a key hash, without
+    /// allocating.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
         checked_precondition!(bytes.len() <= ROOT_NIBBLE_HEIGHT / 2);
         let num_nibbles = bytes.len() * 2;
-        NibblePath { num_nibbles, bytes }
+        NibblePath {
+            num_nibbles,
+            bytes: SmallVec::from_slice(bytes),
+        }
     }
 
     /// Similar to `new()` but assumes that the bytes have one less nibble.
@@ -100,13 +113,16 @@ impl NibblePath {
             "Last nibble must be 0."
         );
         let num_nibbles = bytes.len() * 2 - 1;
-        NibblePath { num_nibbles, bytes }
+        NibblePath {
+            num_nibbles,
+            bytes: SmallVec::from_slice(&bytes),
+        }
     }
 
     /// Adds a nibble to the end of the nibble path.
     pub fn push(&mut self, nibble: Nibble) {
         assert!(ROOT_NIBBLE_HEIGHT > self.num_nibbles);
-        if self.num_nibbles % 2 == 0 {
+        if self.num_nibbles.is_multiple_of(2) {
             self.bytes.push(u8::from(nibble) << 4);
         } else {
             self.bytes[self.num_nibbles / 2] |= u8::from(nibble);
@@ -116,7 +132,7 @@ impl NibblePath {
 
     /// Pops a nibble from the end of the nibble path.
     pub fn pop(&mut self) -> Option<Nibble> {
-        let poped_nibble = if self.num_nibbles % 2 == 0 {
+        let poped_nibble = if self.num_nibbles.is_multiple_of(2) {
             self.bytes.last_mut().map(|last_byte| {
                 let nibble = *last_byte & 0x0f;
                 *last_byte &= 0xf0;
@@ -134,7 +150,7 @@ impl NibblePath {
     /// Returns the last nibble.
     pub fn last(&self) -> Option<Nibble> {
         let last_byte_option = self.bytes.last();
-        if self.num_nibbles % 2 == 0 {
+        if self.num_nibbles.is_multiple_of(2) {
             last_byte_option.map(|last_byte| Nibble::from(*last_byte & 0x0f))
         } else {
             let last_byte = last_byte_option.expect("Last byte must exist if num_nibbles is odd.");
@@ -157,7 +173,7 @@ impl NibblePath {
     }
 
     /// Get a bit iterator iterates over the whole nibble path.
-    pub fn bits(&self) -> BitIterator {
+    pub fn bits(&self) -> BitIterator<'_> {
         assume!(self.num_nibbles <= ROOT_NIBBLE_HEIGHT); // invariant
         BitIterator {
             nibble_path: self,
@@ -166,7 +182,7 @@ impl NibblePath {
     }
 
     /// Get a nibble iterator iterates over the whole nibble path.
-    pub fn nibbles(&self) -> NibbleIterator {
+    pub fn nibbles(&self) -> NibbleIterator<'_> {
         assume!(self.num_nibbles <= ROOT_NIBBLE_HEIGHT); // invariant
         NibbleIterator::new(self, 0, self.num_nibbles)
     }
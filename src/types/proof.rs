@@ -2,6 +2,27 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Merkle proof types.
+//!
+//! # Serialization
+//!
+//! Proofs implement serde's `Serialize` and `Deserialize` whatever their hasher, which does not
+//! appear in their serialized form. Binary formats such as BCS encode hashes as 32-byte tuples.
+//! Human-readable formats such as JSON encode every hash, including key hashes, value hashes and
+//! root hashes, as a lowercase hex string of 64 characters, so that a [`SparseMerkleProof`] reads:
+//!
+//! ```json
+//! {
+//!   "leaf": { "key_hash": "9f86…0a08", "value_hash": "2c26…7ae" },
+//!   "siblings": ["5350…5f5f", "e3b0…b855"]
+//! }
+//! ```
+//!
+//! where `leaf` is `null` when the proof has no leaf. Enums are tagged externally with the
+//! snake-cased name of their variant, e.g. `{"middle": {"leftmost_right_proof": …,
+//! "rightmost_left_proof": …}}` for an [`ExclusionProof::Middle`], `{"leftmost": …}` and
+//! `{"rightmost": …}` for its other variants, and `{"leaf": …}` or `{"internal": {"left": …,
+//! "right": …}}` for a [`SparseMerkleNodePreimage`]. Other proofs are objects whose fields are
+//! named after their accessors.
 
 pub(crate) mod definition;
 #[cfg(any(test, feature = "fuzzing"))]
@@ -11,8 +32,21 @@ pub(crate) mod proptest_proof;
 use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
 
-pub use self::definition::{SparseMerkleProof, SparseMerkleRangeProof};
-use crate::{KeyHash, ValueHash};
+pub use self::definition::{
+    ExclusionProof, NonInclusionProof, SparseMerkleDeletionProof, SparseMerkleIntervalProof,
+    SparseMerkleMultiProof, SparseMerkleNodePreimage, SparseMerkleProof, SparseMerkleRangeProof,
+    SparseMerkleUnchangedProof, SparseMerkleUpdateProof,
+};
+use crate::{KeyHash, SimpleHasher, ValueHash};
+
+/// The default domain separator prepended to the children of an internal node before hashing,
+/// see [`SimpleHasher::INTERNAL_DOMAIN_SEPARATOR`]. The vowel is chopped so that the separator fits
+/// in 16 bytes.
+pub const INTERNAL_DOMAIN_SEPARATOR: &[u8] = b"JMT::IntrnalNode";
+
+/// The default domain separator prepended to the key and value hashes of a leaf node before
+/// hashing, see [`SimpleHasher::LEAF_DOMAIN_SEPARATOR`].
+pub const LEAF_DOMAIN_SEPARATOR: &[u8] = b"JMT::LeafNode";
 
 pub(crate) struct SparseMerkleInternalNode {
     left_child: [u8; 32],
@@ -27,18 +61,27 @@ impl SparseMerkleInternalNode {
         }
     }
 
-    pub fn hash(&self) -> [u8; 32] {
-        use sha2::Digest;
-        let mut hasher = sha2::Sha256::new();
-        // chop a vowel to fit in 16 bytes
-        hasher.update(b"JMT::IntrnalNode");
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        let mut hasher = H::new();
+        hasher.update(H::INTERNAL_DOMAIN_SEPARATOR);
         hasher.update(&self.left_child);
         hasher.update(&self.right_child);
-        *hasher.finalize().as_ref()
+        let hash = hasher.finalize();
+        #[cfg(feature = "leaf-count-commitment")]
+        let hash = with_leaf_count(
+            hash,
+            leaf_count_of::<H>(&self.left_child)
+                .saturating_add(leaf_count_of::<H>(&self.right_child)),
+        );
+        hash
     }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 #[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
 pub struct SparseMerkleLeafNode {
     key_hash: KeyHash,
@@ -53,16 +96,47 @@ impl SparseMerkleLeafNode {
         }
     }
 
-    pub(crate) fn key_hash(&self) -> KeyHash {
+    /// Returns the key hash of the leaf.
+    pub fn key_hash(&self) -> KeyHash {
         self.key_hash
     }
 
-    pub(crate) fn hash(&self) -> [u8; 32] {
-        use sha2::Digest;
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(b"JMT::LeafNode");
+    #[cfg(any(test, feature = "proto", feature = "keccak"))]
+    pub(crate) fn value_hash(&self) -> ValueHash {
+        self.value_hash
+    }
+
+    pub(crate) fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        let mut hasher = H::new();
+        hasher.update(H::LEAF_DOMAIN_SEPARATOR);
         hasher.update(&self.key_hash.0);
         hasher.update(&self.value_hash.0);
-        *hasher.finalize().as_ref()
+        let hash = hasher.finalize();
+        #[cfg(feature = "leaf-count-commitment")]
+        let hash = with_leaf_count(hash, 1);
+        hash
+    }
+}
+
+/// Returns the number of leaves committed to by a node hash: zero for the placeholder, otherwise
+/// the big-endian count in the last [`LEAF_COUNT_BYTES`] bytes.
+#[cfg(feature = "leaf-count-commitment")]
+pub(crate) fn leaf_count_of<H: SimpleHasher>(hash: &[u8; 32]) -> u64 {
+    if *hash == H::PLACEHOLDER_HASH {
+        return 0;
     }
+    let mut count = [0u8; LEAF_COUNT_BYTES];
+    count.copy_from_slice(&hash[32 - LEAF_COUNT_BYTES..]);
+    u64::from_be_bytes(count)
+}
+
+/// The number of trailing bytes of a node hash that hold its leaf count when the
+/// `leaf-count-commitment` feature is enabled.
+#[cfg(feature = "leaf-count-commitment")]
+pub(crate) const LEAF_COUNT_BYTES: usize = 8;
+
+#[cfg(feature = "leaf-count-commitment")]
+fn with_leaf_count(mut hash: [u8; 32], count: u64) -> [u8; 32] {
+    hash[32 - LEAF_COUNT_BYTES..].copy_from_slice(&count.to_be_bytes());
+    hash
 }
@@ -3,18 +3,26 @@
 
 //! This module has definition of various proofs.
 
-use anyhow::{bail, ensure, format_err, Result};
+use alloc::{vec, vec::Vec};
+
+use anyhow::{ensure, Result};
 use serde::{Deserialize, Serialize};
 
 use super::{SparseMerkleInternalNode, SparseMerkleLeafNode};
 use crate::{
+    error::{bail_invalid_proof, ensure_valid_proof, invalid_proof},
+    types::Version,
     Bytes32Ext, KeyHash, PhantomHasher, RootHash, SimpleHasher, ValueHash,
-    SPARSE_MERKLE_PLACEHOLDER_HASH,
 };
 
 /// A proof that can be used to authenticate an element in a Sparse Merkle Tree given trusted root
 /// hash. For example, `TransactionInfoToAccountProof` can be constructed on top of this structure.
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
 pub struct SparseMerkleProof<H: SimpleHasher> {
     /// This proof can be used to authenticate whether a given leaf exists in the tree or not.
     ///     - If this is `Some(leaf_node)`
@@ -29,16 +37,19 @@ pub struct SparseMerkleProof<H: SimpleHasher> {
 
     /// All siblings in this proof, including the default ones. Siblings are ordered from the bottom
     /// level to the root level.
+    #[serde(with = "crate::hex_serde::hashes")]
     siblings: Vec<[u8; 32]>,
 
     /// A marker type showing which hash function is used in this proof.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    #[serde(skip)]
     phantom_hasher: PhantomHasher<H>,
 }
 
 // Deriving Debug fails since H is not Debug though phantom_hasher implements it
 // generically. Implement Debug manually as a workaround to enable Proptest
-impl<H: SimpleHasher> std::fmt::Debug for SparseMerkleProof<H> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("SparseMerkleProof")
             .field("leaf", &self.leaf)
             .field("siblings", &self.siblings)
@@ -67,6 +78,97 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
         &self.siblings
     }
 
+    /// Encodes this proof compactly, leaving out its placeholder siblings, which make up most of a
+    /// typical proof.
+    ///
+    /// The encoding is made of:
+    /// - a byte which is `1` if the proof has a leaf and `0` otherwise, followed by the key hash
+    ///   and value hash of the leaf, if any;
+    /// - the number of siblings, as a big-endian `u16`;
+    /// - a bitmap with one bit per sibling, from the most significant bit of the first byte on,
+    ///   which is set if the sibling is not a placeholder;
+    /// - the siblings which are not placeholders, in order.
+    ///
+    /// Use [`from_compact_bytes`](SparseMerkleProof::from_compact_bytes) to decode it.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self.leaf {
+            Some(leaf) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&leaf.key_hash.0);
+                bytes.extend_from_slice(&leaf.value_hash.0);
+            }
+            None => bytes.push(0),
+        }
+
+        bytes.extend_from_slice(&(self.siblings.len() as u16).to_be_bytes());
+        let mut bitmap = vec![0u8; self.siblings.len().div_ceil(8)];
+        for (i, sibling) in self.siblings.iter().enumerate() {
+            if *sibling != H::PLACEHOLDER_HASH {
+                bitmap[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        bytes.extend_from_slice(&bitmap);
+        for sibling in &self.siblings {
+            if *sibling != H::PLACEHOLDER_HASH {
+                bytes.extend_from_slice(sibling);
+            }
+        }
+        bytes
+    }
+
+    /// Decodes a proof encoded by [`to_compact_bytes`](SparseMerkleProof::to_compact_bytes).
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let mut read = |len: usize| -> Result<&[u8]> {
+            ensure_valid_proof!(reader.len() >= len, "Compact proof is truncated.");
+            let (read, rest) = reader.split_at(len);
+            reader = rest;
+            Ok(read)
+        };
+        let read_hash = |bytes: &[u8]| -> [u8; 32] { bytes.try_into().expect("Read 32 bytes.") };
+
+        let leaf = match read(1)?[0] {
+            0 => None,
+            1 => Some(SparseMerkleLeafNode::new(
+                KeyHash(read_hash(read(32)?)),
+                ValueHash(read_hash(read(32)?)),
+            )),
+            tag => bail_invalid_proof!("Invalid leaf tag {} in compact proof.", tag),
+        };
+
+        let num_siblings = u16::from_be_bytes(read(2)?.try_into().expect("Read 2 bytes.")) as usize;
+        ensure_valid_proof!(
+            num_siblings <= 256,
+            "Compact proof has more than {} ({}) siblings.",
+            256,
+            num_siblings,
+        );
+        let bitmap = read(num_siblings.div_ceil(8))?.to_vec();
+        if !num_siblings.is_multiple_of(8) {
+            ensure_valid_proof!(
+                bitmap[num_siblings / 8] & (0xFF >> (num_siblings % 8)) == 0,
+                "Compact proof has non-zero padding bits in its sibling bitmap."
+            );
+        }
+        let siblings = (0..num_siblings)
+            .map(|i| {
+                if bitmap[i / 8] & (0x80 >> (i % 8)) != 0 {
+                    Ok(read_hash(read(32)?))
+                } else {
+                    Ok(H::PLACEHOLDER_HASH)
+                }
+            })
+            .collect::<Result<_>>()?;
+        ensure_valid_proof!(
+            reader.is_empty(),
+            "Compact proof has {} trailing bytes.",
+            reader.len()
+        );
+
+        Ok(Self::new(leaf, siblings))
+    }
+
     /// Verifies an element whose key is `element_key` and value is
     /// `element_value` exists in the Sparse Merkle Tree using the provided proof.
     pub fn verify_existence<V: AsRef<[u8]>>(
@@ -88,6 +190,26 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
         self.verify(expected_root_hash, element_key, None::<&[u8]>)
     }
 
+    /// Verifies that `element_key` has the value `element_value` in a tree where every absent key
+    /// is considered to have the value `default_value`, such as one written through a
+    /// [`DefaultValueTree`](crate::default_value::DefaultValueTree).
+    ///
+    /// The default value is never stored, so the proof of a key with the default value must be a
+    /// non-inclusion proof, while the proof of any other value must be an inclusion proof.
+    pub fn verify_with_default<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        element_key: KeyHash,
+        element_value: V,
+        default_value: &[u8],
+    ) -> Result<()> {
+        if element_value.as_ref() == default_value {
+            self.verify_nonexistence(expected_root_hash, element_key)
+        } else {
+            self.verify_existence(expected_root_hash, element_key, element_value)
+        }
+    }
+
     /// If `element_value` is present, verifies an element whose key is `element_key` and value is
     /// `element_value` exists in the Sparse Merkle Tree using the provided proof. Otherwise
     /// verifies the proof is a valid non-inclusion proof that shows this key doesn't exist in the
@@ -98,78 +220,361 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
         element_key: KeyHash,
         element_value: Option<V>,
     ) -> Result<()> {
-        ensure!(
-            self.siblings.len() <= 256,
-            "Sparse Merkle Tree proof has more than {} ({}) siblings.",
-            256,
+        verify_path::<H, V>(
+            expected_root_hash,
+            element_key,
+            element_value,
+            self.leaf,
             self.siblings.len(),
-        );
+            self.siblings.iter().copied(),
+        )
+    }
 
-        match (element_value, self.leaf) {
-            (Some(value), Some(leaf)) => {
-                // This is an inclusion proof, so the key and value hash provided in the proof
-                // should match element_key and element_value_hash. `siblings` should prove the
-                // route from the leaf node to the root.
-                ensure!(
-                    element_key == leaf.key_hash,
-                    "Keys do not match. Key in proof: {:?}. Expected key: {:?}.",
-                    leaf.key_hash,
-                    element_key
-                );
-                let hash: ValueHash = ValueHash::with::<H>(value);
-                ensure!(
-                    hash == leaf.value_hash,
-                    "Value hashes do not match. Value hash in proof: {:?}. \
-                     Expected value hash: {:?}",
-                    leaf.value_hash,
-                    hash,
+    /// Same as [`verify`](SparseMerkleProof::verify), but for a proof encoded by
+    /// [`to_compact_bytes`](SparseMerkleProof::to_compact_bytes), which is verified in place.
+    ///
+    /// Unlike decoding the proof with [`from_compact_bytes`](SparseMerkleProof::from_compact_bytes)
+    /// and then verifying it, this does not allocate unless verification fails: siblings are read
+    /// from `bytes` one at a time as they are folded into the root hash.
+    pub fn verify_compact_bytes<V: AsRef<[u8]>>(
+        bytes: &[u8],
+        expected_root_hash: RootHash,
+        element_key: KeyHash,
+        element_value: Option<V>,
+    ) -> Result<()> {
+        let read_hash = |bytes: &[u8]| -> [u8; 32] { bytes.try_into().expect("Read 32 bytes.") };
+
+        let (leaf, rest) = match bytes.split_first() {
+            Some((0, rest)) => (None, rest),
+            Some((1, rest)) => {
+                ensure_valid_proof!(rest.len() >= 64, "Compact proof is truncated.");
+                let leaf = SparseMerkleLeafNode::new(
+                    KeyHash(read_hash(&rest[..32])),
+                    ValueHash(read_hash(&rest[32..64])),
                 );
+                (Some(leaf), &rest[64..])
             }
-            (Some(_value), None) => bail!("Expected inclusion proof. Found non-inclusion proof."),
-            (None, Some(leaf)) => {
-                // This is a non-inclusion proof. The proof intends to show that if a leaf node
-                // representing `element_key` is inserted, it will break a currently existing leaf
-                // node represented by `proof_key` into a branch. `siblings` should prove the
-                // route from that leaf node to the root.
-                ensure!(
-                    element_key != leaf.key_hash,
-                    "Expected non-inclusion proof, but key exists in proof.",
-                );
-                ensure!(
-                    element_key.0.common_prefix_bits_len(&leaf.key_hash.0) >= self.siblings.len(),
-                    "Key would not have ended up in the subtree where the provided key in proof \
-                     is the only existing key, if it existed. So this is not a valid \
-                     non-inclusion proof.",
-                );
+            Some((tag, _)) => bail_invalid_proof!("Invalid leaf tag {} in compact proof.", tag),
+            None => bail_invalid_proof!("Compact proof is truncated."),
+        };
+
+        ensure_valid_proof!(rest.len() >= 2, "Compact proof is truncated.");
+        let num_siblings = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        ensure_valid_proof!(
+            num_siblings <= 256,
+            "Compact proof has more than {} ({}) siblings.",
+            256,
+            num_siblings,
+        );
+        let rest = &rest[2..];
+        ensure_valid_proof!(
+            rest.len() >= num_siblings.div_ceil(8),
+            "Compact proof is truncated."
+        );
+        let (bitmap, mut hashes) = rest.split_at(num_siblings.div_ceil(8));
+        if !num_siblings.is_multiple_of(8) {
+            ensure_valid_proof!(
+                bitmap[num_siblings / 8] & (0xFF >> (num_siblings % 8)) == 0,
+                "Compact proof has non-zero padding bits in its sibling bitmap."
+            );
+        }
+        let num_hashes = bitmap
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum::<usize>();
+        ensure_valid_proof!(
+            hashes.len() == num_hashes * 32,
+            "Compact proof has {} bytes of siblings, expected {}.",
+            hashes.len(),
+            num_hashes * 32,
+        );
+
+        let siblings = (0..num_siblings).map(|i| {
+            if bitmap[i / 8] & (0x80 >> (i % 8)) != 0 {
+                let (hash, rest) = hashes.split_at(32);
+                hashes = rest;
+                read_hash(hash)
+            } else {
+                H::PLACEHOLDER_HASH
             }
-            (None, None) => {
-                // This is a non-inclusion proof. The proof intends to show that if a leaf node
-                // representing `element_key` is inserted, it will show up at a currently empty
-                // position. `sibling` should prove the route from this empty position to the root.
+        });
+        verify_path::<H, V>(
+            expected_root_hash,
+            element_key,
+            element_value,
+            leaf,
+            num_siblings,
+            siblings,
+        )
+    }
+}
+
+/// Verifies the path of `element_key` made of `leaf` and `num_siblings` siblings, given from the
+/// bottom level to the root level, against `expected_root_hash`. See [`SparseMerkleProof::verify`].
+fn verify_path<H: SimpleHasher, V: AsRef<[u8]>>(
+    expected_root_hash: RootHash,
+    element_key: KeyHash,
+    element_value: Option<V>,
+    leaf: Option<SparseMerkleLeafNode>,
+    num_siblings: usize,
+    siblings: impl Iterator<Item = [u8; 32]>,
+) -> Result<()> {
+    ensure_valid_proof!(
+        num_siblings <= 256,
+        "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+        256,
+        num_siblings,
+    );
+
+    match (element_value, leaf) {
+        (Some(value), Some(leaf)) => {
+            // This is an inclusion proof, so the key and value hash provided in the proof
+            // should match element_key and element_value_hash. `siblings` should prove the
+            // route from the leaf node to the root.
+            ensure_valid_proof!(
+                element_key == leaf.key_hash,
+                "Keys do not match. Key in proof: {:?}. Expected key: {:?}.",
+                leaf.key_hash,
+                element_key
+            );
+            let hash: ValueHash = ValueHash::with::<H>(value);
+            ensure_valid_proof!(
+                hash == leaf.value_hash,
+                "Value hashes do not match. Value hash in proof: {:?}. \
+                 Expected value hash: {:?}",
+                leaf.value_hash,
+                hash,
+            );
+        }
+        (Some(_value), None) => {
+            bail_invalid_proof!("Expected inclusion proof. Found non-inclusion proof.")
+        }
+        (None, Some(leaf)) => {
+            // This is a non-inclusion proof. The proof intends to show that if a leaf node
+            // representing `element_key` is inserted, it will break a currently existing leaf
+            // node represented by `proof_key` into a branch. `siblings` should prove the
+            // route from that leaf node to the root.
+            ensure_valid_proof!(
+                element_key != leaf.key_hash,
+                "Expected non-inclusion proof, but key exists in proof.",
+            );
+            ensure_valid_proof!(
+                element_key.0.common_prefix_bits_len(&leaf.key_hash.0) >= num_siblings,
+                "Key would not have ended up in the subtree where the provided key in proof \
+                 is the only existing key, if it existed. So this is not a valid \
+                 non-inclusion proof.",
+            );
+        }
+        (None, None) => {
+            // This is a non-inclusion proof. The proof intends to show that if a leaf node
+            // representing `element_key` is inserted, it will show up at a currently empty
+            // position. `sibling` should prove the route from this empty position to the root.
+        }
+    }
+
+    let current_hash = leaf.map_or(H::PLACEHOLDER_HASH, |leaf| leaf.hash::<H>());
+    let actual_root_hash = siblings
+        .zip(element_key.0.iter_bits().rev().skip(256 - num_siblings))
+        .fold(current_hash, |hash, (sibling_hash, bit)| {
+            if bit {
+                SparseMerkleInternalNode::new(sibling_hash, hash).hash::<H>()
+            } else {
+                SparseMerkleInternalNode::new(hash, sibling_hash).hash::<H>()
             }
+        });
+    ensure_valid_proof!(
+        actual_root_hash == expected_root_hash.0,
+        "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+        actual_root_hash,
+        expected_root_hash,
+    );
+
+    Ok(())
+}
+
+/// A proof that can be used to authenticate a batch of keys against a trusted root hash at once.
+///
+/// It carries the same information as one [`SparseMerkleProof`] per key, but every sibling is
+/// included at most once: siblings on the shared upper parts of the paths, as well as siblings
+/// which are themselves on the path of another key in the batch, are not repeated.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct SparseMerkleMultiProof<H: SimpleHasher> {
+    /// For each requested key, in the order the keys were requested, the leaf found at the end of
+    /// its path (if any) and the depth in bits at which the path ends. The leaf has the same
+    /// meaning as [`SparseMerkleProof::leaf`].
+    leaves: Vec<(Option<SparseMerkleLeafNode>, usize)>,
+
+    /// The deduplicated siblings, in the order they are encountered by a depth first, left to
+    /// right traversal of the union of all paths starting from the root.
+    #[serde(with = "crate::hex_serde::hashes")]
+    siblings: Vec<[u8; 32]>,
+
+    /// A marker type showing which hash function is used in this proof.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    #[serde(skip)]
+    phantom_hasher: PhantomHasher<H>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleMultiProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleMultiProof")
+            .field("leaves", &self.leaves)
+            .field("siblings", &self.siblings)
+            .field("phantom_hasher", &self.phantom_hasher)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleMultiProof<H> {
+    /// Constructs a new `SparseMerkleMultiProof` using the per-key leaves and path depths, and
+    /// the deduplicated list of siblings.
+    pub(crate) fn new(
+        leaves: Vec<(Option<SparseMerkleLeafNode>, usize)>,
+        siblings: Vec<[u8; 32]>,
+    ) -> Self {
+        SparseMerkleMultiProof {
+            leaves,
+            siblings,
+            phantom_hasher: Default::default(),
         }
+    }
+
+    /// Returns the leaf and the path depth for each key, in the order the keys were requested.
+    pub fn leaves(&self) -> &[(Option<SparseMerkleLeafNode>, usize)] {
+        &self.leaves
+    }
+
+    /// Returns the deduplicated list of siblings in this proof.
+    pub fn siblings(&self) -> &[[u8; 32]] {
+        &self.siblings
+    }
+
+    /// Verifies that each of `element_keys` maps to the value at the same position in
+    /// `element_values` in the Sparse Merkle Tree with root `expected_root_hash`. A value of
+    /// `None` means the proof must show that the corresponding key doesn't exist in the tree.
+    ///
+    /// `element_keys` must be given in the same order as when the proof was generated.
+    pub fn verify<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        element_keys: &[KeyHash],
+        element_values: &[Option<V>],
+    ) -> Result<()> {
+        self.verify_impl(expected_root_hash, element_keys, element_values, None)
+    }
+
+    /// Same as [`verify`](SparseMerkleMultiProof::verify), but if `interval` is given, also
+    /// ensures that none of the siblings covers a non-empty subtree overlapping it.
+    fn verify_impl<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        element_keys: &[KeyHash],
+        element_values: &[Option<V>],
+        interval: Option<(KeyHash, KeyHash)>,
+    ) -> Result<()> {
+        ensure_valid_proof!(
+            element_keys.len() == element_values.len(),
+            "Number of keys ({}) does not match number of values ({}).",
+            element_keys.len(),
+            element_values.len(),
+        );
+        ensure_valid_proof!(
+            element_keys.len() == self.leaves.len(),
+            "Number of keys ({}) does not match number of leaves in proof ({}).",
+            element_keys.len(),
+            self.leaves.len(),
+        );
 
-        let current_hash = self
-            .leaf
-            .map_or(SPARSE_MERKLE_PLACEHOLDER_HASH, |leaf| leaf.hash());
-        let actual_root_hash = self
-            .siblings
+        let mut paths = Vec::with_capacity(element_keys.len());
+        for ((element_key, element_value), (leaf, depth)) in element_keys
             .iter()
-            .zip(
-                element_key
-                    .0
-                    .iter_bits()
-                    .rev()
-                    .skip(256 - self.siblings.len()),
-            )
-            .fold(current_hash, |hash, (sibling_hash, bit)| {
-                if bit {
-                    SparseMerkleInternalNode::new(*sibling_hash, hash).hash()
-                } else {
-                    SparseMerkleInternalNode::new(hash, *sibling_hash).hash()
+            .zip(element_values.iter())
+            .zip(self.leaves.iter())
+        {
+            ensure_valid_proof!(
+                *depth <= 256,
+                "Sparse Merkle Tree multiproof has a path of depth {} > {}.",
+                depth,
+                256,
+            );
+
+            match (element_value, leaf) {
+                (Some(value), Some(leaf)) => {
+                    ensure_valid_proof!(
+                        *element_key == leaf.key_hash,
+                        "Keys do not match. Key in proof: {:?}. Expected key: {:?}.",
+                        leaf.key_hash,
+                        element_key
+                    );
+                    let hash: ValueHash = ValueHash::with::<H>(value);
+                    ensure_valid_proof!(
+                        hash == leaf.value_hash,
+                        "Value hashes do not match. Value hash in proof: {:?}. \
+                         Expected value hash: {:?}",
+                        leaf.value_hash,
+                        hash,
+                    );
                 }
-            });
-        ensure!(
+                (Some(_value), None) => {
+                    bail_invalid_proof!(
+                        "Expected inclusion proof for {:?}. Found non-inclusion proof.",
+                        element_key
+                    )
+                }
+                (None, Some(leaf)) => {
+                    ensure_valid_proof!(
+                        *element_key != leaf.key_hash,
+                        "Expected non-inclusion proof, but key {:?} exists in proof.",
+                        element_key,
+                    );
+                    ensure_valid_proof!(
+                        element_key.0.common_prefix_bits_len(&leaf.key_hash.0) >= *depth,
+                        "Key {:?} would not have ended up in the subtree where the provided key \
+                         in proof is the only existing key, if it existed. So this is not a \
+                         valid non-inclusion proof.",
+                        element_key,
+                    );
+                }
+                (None, None) => {}
+            }
+
+            paths.push((*element_key, *leaf, *depth));
+        }
+
+        if paths.is_empty() {
+            ensure_valid_proof!(
+                self.siblings.is_empty(),
+                "Multiproof for no keys should not have any siblings."
+            );
+            return Ok(());
+        }
+
+        // Requesting the same key twice is allowed, as long as both paths agree.
+        paths.sort_by_key(|(key, _, _)| *key);
+        for pair in paths.windows(2) {
+            ensure_valid_proof!(
+                pair[0].0 != pair[1].0 || pair[0] == pair[1],
+                "Key {:?} appears twice in proof with different paths.",
+                pair[0].0,
+            );
+        }
+        paths.dedup();
+
+        let mut sibling_iter = self.siblings.iter();
+        let actual_root_hash =
+            multiproof_subtree_hash::<H>(&paths, 0, &mut sibling_iter, interval)?;
+        ensure_valid_proof!(
+            sibling_iter.next().is_none(),
+            "Sparse Merkle Tree multiproof has unused siblings."
+        );
+        ensure_valid_proof!(
             actual_root_hash == expected_root_hash.0,
             "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
             actual_root_hash,
@@ -180,6 +585,205 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
     }
 }
 
+/// Computes the hash of the subtree at bit depth `depth` that contains the paths of all the given
+/// keys, which must be sorted, distinct and share their first `depth` bits. Siblings are consumed
+/// from `siblings` in the order in which they are laid out in [`SparseMerkleMultiProof`]. If
+/// `interval` is given, no sibling may stand for a non-empty subtree overlapping it.
+fn multiproof_subtree_hash<H: SimpleHasher>(
+    paths: &[(KeyHash, Option<SparseMerkleLeafNode>, usize)],
+    depth: usize,
+    siblings: &mut core::slice::Iter<'_, [u8; 32]>,
+    interval: Option<(KeyHash, KeyHash)>,
+) -> Result<[u8; 32]> {
+    let (_, leaf, _) = paths[0];
+    if paths.iter().any(|(_, _, path_depth)| *path_depth == depth) {
+        // All keys reaching this subtree end up at the same leaf or empty position.
+        ensure_valid_proof!(
+            paths
+                .iter()
+                .all(|(_, other_leaf, path_depth)| *path_depth == depth && *other_leaf == leaf),
+            "Paths in multiproof end inconsistently at depth {}.",
+            depth,
+        );
+        return Ok(leaf.map_or(H::PLACEHOLDER_HASH, |leaf| leaf.hash::<H>()));
+    }
+
+    let split = paths.partition_point(|(key, _, _)| !key.0.iter_bits().nth(depth).unwrap());
+    let mut next_hash = |half: &[_], bit: bool| -> Result<[u8; 32]> {
+        if !half.is_empty() {
+            return multiproof_subtree_hash::<H>(half, depth + 1, siblings, interval);
+        }
+        let sibling = siblings
+            .next()
+            .copied()
+            .ok_or_else(|| invalid_proof!("Missing sibling at depth {}.", depth))?;
+        // Only empty subtrees may be summarized by a sibling within the interval.
+        if let Some((start, end)) = interval {
+            let (min_key, max_key) = subtree_key_bounds(paths[0].0, depth, bit);
+            ensure_valid_proof!(
+                sibling == H::PLACEHOLDER_HASH || max_key < start || end < min_key,
+                "Sibling at depth {} covers keys in the interval, which are not in the proof.",
+                depth,
+            );
+        }
+        Ok(sibling)
+    };
+    let left_hash = next_hash(&paths[..split], false)?;
+    let right_hash = next_hash(&paths[split..], true)?;
+    Ok(SparseMerkleInternalNode::new(left_hash, right_hash).hash::<H>())
+}
+
+/// Returns the smallest and the largest key in the subtree rooted at bit depth `depth + 1` whose
+/// path consists of the first `depth` bits of `key` followed by `bit`.
+fn subtree_key_bounds(key: KeyHash, depth: usize, bit: bool) -> (KeyHash, KeyHash) {
+    let mut min_key = key.0;
+    let mut max_key = key.0;
+    for i in depth..256 {
+        let mask = 1 << (7 - i % 8);
+        let (min_bit, max_bit) = if i == depth {
+            (bit, bit)
+        } else {
+            (false, true)
+        };
+        for (bytes, set) in [(&mut min_key, min_bit), (&mut max_key, max_bit)] {
+            if set {
+                bytes[i / 8] |= mask;
+            } else {
+                bytes[i / 8] &= !mask;
+            }
+        }
+    }
+    (KeyHash(min_key), KeyHash(max_key))
+}
+
+/// A proof that a list of leaves is exactly the set of leaves whose key hashes fall within an
+/// interval `[start, end]` of a Sparse Merkle Tree, given a trusted root hash.
+///
+/// Unlike [`SparseMerkleRangeProof`], which authenticates everything to the left of a known
+/// rightmost leaf, this proof can be used to authenticate any contiguous interval of key hashes.
+/// It consists of the paths to both ends of the interval and to every leaf within it; the
+/// non-empty siblings along these paths must all lie outside of the interval, which shows that no
+/// leaf was left out.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct SparseMerkleIntervalProof<H: SimpleHasher> {
+    /// A multiproof for `start`, `end`, and then each leaf within the interval, in order.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    multiproof: SparseMerkleMultiProof<H>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleIntervalProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleIntervalProof")
+            .field("multiproof", &self.multiproof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleIntervalProof<H> {
+    /// Constructs a new `SparseMerkleIntervalProof` from a multiproof for the keys `start`, `end`
+    /// and then all the leaves within `[start, end]` in ascending order.
+    pub(crate) fn new(multiproof: SparseMerkleMultiProof<H>) -> Self {
+        Self { multiproof }
+    }
+
+    /// Returns the underlying multiproof.
+    pub fn multiproof(&self) -> &SparseMerkleMultiProof<H> {
+        &self.multiproof
+    }
+
+    /// Verifies a page returned by
+    /// [`JellyfishMerkleTree::get_range_with_proof`](crate::JellyfishMerkleTree::get_range_with_proof):
+    /// that `leaves`, given in ascending order of key hashes, are the first `limit` key-value pairs
+    /// whose key hashes are greater than or equal to `start` in the Sparse Merkle Tree with root
+    /// `expected_root_hash`, or all of them if there are fewer than `limit`.
+    pub fn verify_range<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        start: KeyHash,
+        limit: usize,
+        leaves: &[(KeyHash, V)],
+    ) -> Result<()> {
+        ensure!(limit > 0, "The page size limit must be positive.");
+        ensure_valid_proof!(
+            leaves.len() <= limit,
+            "The page holds {} leaves, more than its limit of {}.",
+            leaves.len(),
+            limit
+        );
+        let end = match leaves.last() {
+            Some((last_key, _)) if leaves.len() == limit => *last_key,
+            _ => KeyHash([0xFF; 32]),
+        };
+        self.verify(expected_root_hash, start, end, leaves)
+    }
+
+    /// Verifies that `leaves`, given in ascending order of key hashes, are all the key-value pairs
+    /// whose key hashes fall within `[start, end]` in the Sparse Merkle Tree with root
+    /// `expected_root_hash`.
+    pub fn verify<V: AsRef<[u8]>>(
+        &self,
+        expected_root_hash: RootHash,
+        start: KeyHash,
+        end: KeyHash,
+        leaves: &[(KeyHash, V)],
+    ) -> Result<()> {
+        ensure_valid_proof!(
+            start <= end,
+            "Interval start {:?} is greater than its end {:?}.",
+            start,
+            end
+        );
+        for pair in leaves.windows(2) {
+            ensure_valid_proof!(
+                pair[0].0 < pair[1].0,
+                "Leaves are not sorted in strictly ascending order of key hashes."
+            );
+        }
+        if let (Some((first, _)), Some((last, _))) = (leaves.first(), leaves.last()) {
+            ensure_valid_proof!(
+                start <= *first && *last <= end,
+                "Leaves are not all within the interval."
+            );
+        }
+
+        let value_of = |key: KeyHash| {
+            leaves
+                .binary_search_by_key(&key, |(leaf_key, _)| *leaf_key)
+                .ok()
+                .map(|i| leaves[i].1.as_ref())
+        };
+        let mut keys = vec![start, end];
+        let mut values = vec![value_of(start), value_of(end)];
+        for (key, value) in leaves {
+            keys.push(*key);
+            values.push(Some(value.as_ref()));
+        }
+
+        // A leaf found at the end of the path to either end of the interval is only fine if it is
+        // outside of the interval, or if it is among the provided leaves.
+        for (leaf, _) in self.multiproof.leaves().iter().take(2) {
+            if let Some(leaf) = leaf {
+                let key = leaf.key_hash();
+                ensure_valid_proof!(
+                    key < start || end < key || value_of(key).is_some(),
+                    "Leaf {:?} within the interval is missing.",
+                    key,
+                );
+            }
+        }
+
+        self.multiproof
+            .verify_impl(expected_root_hash, &keys, &values, Some((start, end)))
+    }
+}
+
 /// Note: this is not a range proof in the sense that a range of nodes is verified!
 /// Instead, it verifies the entire left part of the tree up to a known rightmost node.
 /// See the description below.
@@ -203,17 +807,42 @@ impl<H: SimpleHasher> SparseMerkleProof<H> {
 ///
 /// if the proof wants show that `[a, b, c, d, e]` exists in the tree, it would need the siblings
 /// `X` and `h` on the right.
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub struct SparseMerkleRangeProof {
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct SparseMerkleRangeProof<H: SimpleHasher> {
     /// The vector of siblings on the right of the path from root to last leaf. The ones near the
     /// bottom are at the beginning of the vector. In the above example, it's `[X, h]`.
+    #[serde(with = "crate::hex_serde::hashes")]
     right_siblings: Vec<[u8; 32]>,
+
+    /// A marker type showing which hash function is used in this proof.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    #[serde(skip)]
+    phantom_hasher: PhantomHasher<H>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleRangeProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleRangeProof")
+            .field("right_siblings", &self.right_siblings)
+            .field("phantom_hasher", &self.phantom_hasher)
+            .finish()
+    }
 }
 
-impl SparseMerkleRangeProof {
+impl<H: SimpleHasher> SparseMerkleRangeProof<H> {
     /// Constructs a new `SparseMerkleRangeProof`.
     pub(crate) fn new(right_siblings: Vec<[u8; 32]>) -> Self {
-        Self { right_siblings }
+        Self {
+            right_siblings,
+            phantom_hasher: Default::default(),
+        }
     }
 
     /// Returns the right siblings.
@@ -233,7 +862,7 @@ impl SparseMerkleRangeProof {
         let mut left_sibling_iter = left_siblings.iter();
         let mut right_sibling_iter = self.right_siblings().iter();
 
-        let mut current_hash = rightmost_known_leaf.hash();
+        let mut current_hash = rightmost_known_leaf.hash::<H>();
         for bit in rightmost_known_leaf
             .key_hash()
             .0
@@ -245,7 +874,7 @@ impl SparseMerkleRangeProof {
                 (
                     *left_sibling_iter
                         .next()
-                        .ok_or_else(|| format_err!("Missing left sibling."))?,
+                        .ok_or_else(|| invalid_proof!("Missing left sibling."))?,
                     current_hash,
                 )
             } else {
@@ -253,13 +882,13 @@ impl SparseMerkleRangeProof {
                     current_hash,
                     *right_sibling_iter
                         .next()
-                        .ok_or_else(|| format_err!("Missing right sibling."))?,
+                        .ok_or_else(|| invalid_proof!("Missing right sibling."))?,
                 )
             };
-            current_hash = SparseMerkleInternalNode::new(left_hash, right_hash).hash();
+            current_hash = SparseMerkleInternalNode::new(left_hash, right_hash).hash::<H>();
         }
 
-        ensure!(
+        ensure_valid_proof!(
             current_hash == expected_root_hash.0,
             "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
             current_hash,
@@ -269,3 +898,837 @@ impl SparseMerkleRangeProof {
         Ok(())
     }
 }
+
+/// A proof that a key was deleted from the tree at some version: it holds a proof that the key
+/// existed with some value in the tree at the previous version, and a proof that it does not exist
+/// at the version of the deletion, along with the root hashes of both versions.
+///
+/// Unlike a plain non-inclusion proof, this shows that the key was removed rather than never
+/// written.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct SparseMerkleDeletionProof<H: SimpleHasher> {
+    /// The root hash of the tree before the deletion.
+    old_root_hash: RootHash,
+
+    /// The root hash of the tree after the deletion.
+    new_root_hash: RootHash,
+
+    /// The proof that the key exists in the tree before the deletion.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    inclusion_proof: SparseMerkleProof<H>,
+
+    /// The proof that the key does not exist in the tree after the deletion.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    exclusion_proof: SparseMerkleProof<H>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleDeletionProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleDeletionProof")
+            .field("old_root_hash", &self.old_root_hash)
+            .field("new_root_hash", &self.new_root_hash)
+            .field("inclusion_proof", &self.inclusion_proof)
+            .field("exclusion_proof", &self.exclusion_proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleDeletionProof<H> {
+    /// Constructs a new `SparseMerkleDeletionProof`.
+    pub(crate) fn new(
+        old_root_hash: RootHash,
+        new_root_hash: RootHash,
+        inclusion_proof: SparseMerkleProof<H>,
+        exclusion_proof: SparseMerkleProof<H>,
+    ) -> Self {
+        Self {
+            old_root_hash,
+            new_root_hash,
+            inclusion_proof,
+            exclusion_proof,
+        }
+    }
+
+    /// Returns the root hash of the tree before the deletion.
+    pub fn old_root_hash(&self) -> RootHash {
+        self.old_root_hash
+    }
+
+    /// Returns the root hash of the tree after the deletion.
+    pub fn new_root_hash(&self) -> RootHash {
+        self.new_root_hash
+    }
+
+    /// Returns the proof that the key exists in the tree before the deletion.
+    pub fn inclusion_proof(&self) -> &SparseMerkleProof<H> {
+        &self.inclusion_proof
+    }
+
+    /// Returns the proof that the key does not exist in the tree after the deletion.
+    pub fn exclusion_proof(&self) -> &SparseMerkleProof<H> {
+        &self.exclusion_proof
+    }
+
+    /// Verifies that `element_key` had the value `deleted_value` in the tree whose root hash is
+    /// `expected_old_root_hash`, and does not exist in the tree whose root hash is
+    /// `expected_new_root_hash`.
+    pub fn verify<V: AsRef<[u8]>>(
+        &self,
+        expected_old_root_hash: RootHash,
+        expected_new_root_hash: RootHash,
+        element_key: KeyHash,
+        deleted_value: V,
+    ) -> Result<()> {
+        ensure_valid_proof!(
+            self.old_root_hash == expected_old_root_hash,
+            "Old root hashes do not match. Root hash in proof: {:?}. Expected root hash: {:?}.",
+            self.old_root_hash,
+            expected_old_root_hash,
+        );
+        ensure_valid_proof!(
+            self.new_root_hash == expected_new_root_hash,
+            "New root hashes do not match. Root hash in proof: {:?}. Expected root hash: {:?}.",
+            self.new_root_hash,
+            expected_new_root_hash,
+        );
+        self.inclusion_proof
+            .verify_existence(self.old_root_hash, element_key, deleted_value)?;
+        self.exclusion_proof
+            .verify_nonexistence(self.new_root_hash, element_key)
+    }
+}
+
+/// A proof that a key had the same value from a start version through an end version: it holds
+/// proofs that the key had the value at both versions, along with their root hashes, and the
+/// version since which the key has had the value, which is no later than the start version.
+///
+/// That version is found from the versions of the leaves of the key, since a leaf is replaced
+/// whenever its value changes. Note that the root hashes do not commit to the versions of nodes,
+/// so it is vouched for by the prover: what is verified against the root hashes is only that the
+/// key has the value at both versions.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct SparseMerkleUnchangedProof<H: SimpleHasher> {
+    /// The version since which the key has had the value.
+    since_version: Version,
+
+    /// The first version at which the key is proven to have the value.
+    start_version: Version,
+
+    /// The last version at which the key is proven to have the value.
+    end_version: Version,
+
+    /// The root hash of the tree at the start version.
+    start_root_hash: RootHash,
+
+    /// The root hash of the tree at the end version.
+    end_root_hash: RootHash,
+
+    /// The proof that the key has the value at the start version.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    start_proof: SparseMerkleProof<H>,
+
+    /// The proof that the key has the value at the end version.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    end_proof: SparseMerkleProof<H>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleUnchangedProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleUnchangedProof")
+            .field("since_version", &self.since_version)
+            .field("start_version", &self.start_version)
+            .field("end_version", &self.end_version)
+            .field("start_root_hash", &self.start_root_hash)
+            .field("end_root_hash", &self.end_root_hash)
+            .field("start_proof", &self.start_proof)
+            .field("end_proof", &self.end_proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleUnchangedProof<H> {
+    /// Constructs a new `SparseMerkleUnchangedProof`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        since_version: Version,
+        start_version: Version,
+        end_version: Version,
+        start_root_hash: RootHash,
+        end_root_hash: RootHash,
+        start_proof: SparseMerkleProof<H>,
+        end_proof: SparseMerkleProof<H>,
+    ) -> Self {
+        Self {
+            since_version,
+            start_version,
+            end_version,
+            start_root_hash,
+            end_root_hash,
+            start_proof,
+            end_proof,
+        }
+    }
+
+    /// Returns the version since which the key has had the value.
+    pub fn since_version(&self) -> Version {
+        self.since_version
+    }
+
+    /// Returns the first version at which the key is proven to have the value.
+    pub fn start_version(&self) -> Version {
+        self.start_version
+    }
+
+    /// Returns the last version at which the key is proven to have the value.
+    pub fn end_version(&self) -> Version {
+        self.end_version
+    }
+
+    /// Returns the root hash of the tree at the start version.
+    pub fn start_root_hash(&self) -> RootHash {
+        self.start_root_hash
+    }
+
+    /// Returns the root hash of the tree at the end version.
+    pub fn end_root_hash(&self) -> RootHash {
+        self.end_root_hash
+    }
+
+    /// Returns the proof that the key has the value at the start version.
+    pub fn start_proof(&self) -> &SparseMerkleProof<H> {
+        &self.start_proof
+    }
+
+    /// Returns the proof that the key has the value at the end version.
+    pub fn end_proof(&self) -> &SparseMerkleProof<H> {
+        &self.end_proof
+    }
+
+    /// Verifies that `element_key` had the value `element_value` in the tree whose root hash at
+    /// `start_version` is `expected_start_root_hash`, and was not modified through `end_version`,
+    /// at which the root hash is `expected_end_root_hash`.
+    pub fn verify<V: AsRef<[u8]>>(
+        &self,
+        start_version: Version,
+        expected_start_root_hash: RootHash,
+        end_version: Version,
+        expected_end_root_hash: RootHash,
+        element_key: KeyHash,
+        element_value: V,
+    ) -> Result<()> {
+        ensure_valid_proof!(
+            self.start_version == start_version && self.end_version == end_version,
+            "Versions do not match. Versions in proof: {}..={}. Expected versions: {}..={}.",
+            self.start_version,
+            self.end_version,
+            start_version,
+            end_version,
+        );
+        ensure_valid_proof!(
+            self.since_version <= self.start_version && self.start_version <= self.end_version,
+            "Value set at version {} does not span versions {}..={}.",
+            self.since_version,
+            self.start_version,
+            self.end_version,
+        );
+        ensure_valid_proof!(
+            self.start_root_hash == expected_start_root_hash,
+            "Start root hashes do not match. Root hash in proof: {:?}. Expected root hash: {:?}.",
+            self.start_root_hash,
+            expected_start_root_hash,
+        );
+        ensure_valid_proof!(
+            self.end_root_hash == expected_end_root_hash,
+            "End root hashes do not match. Root hash in proof: {:?}. Expected root hash: {:?}.",
+            self.end_root_hash,
+            expected_end_root_hash,
+        );
+        self.start_proof
+            .verify_existence(self.start_root_hash, element_key, &element_value)?;
+        self.end_proof
+            .verify_existence(self.end_root_hash, element_key, element_value)
+    }
+}
+
+/// The preimage of a node of the sparse Merkle tree: either a leaf, or an internal node with the
+/// hashes of its two children.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SparseMerkleNodePreimage {
+    /// A leaf node.
+    Leaf(SparseMerkleLeafNode),
+    /// An internal node, with the hashes of its left and right children.
+    Internal {
+        /// The hash of the left child.
+        #[serde(with = "crate::hex_serde::hash")]
+        left: [u8; 32],
+        /// The hash of the right child.
+        #[serde(with = "crate::hex_serde::hash")]
+        right: [u8; 32],
+    },
+}
+
+impl SparseMerkleNodePreimage {
+    /// Returns the hash of the node.
+    pub fn hash<H: SimpleHasher>(&self) -> [u8; 32] {
+        match self {
+            SparseMerkleNodePreimage::Leaf(leaf) => leaf.hash::<H>(),
+            SparseMerkleNodePreimage::Internal { left, right } => {
+                SparseMerkleInternalNode::new(*left, *right).hash::<H>()
+            }
+        }
+    }
+}
+
+/// The proof of one update in a [`SparseMerkleUpdateProof`].
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+struct SparseMerkleUpdateStep<H: SimpleHasher> {
+    /// The proof of the updated key against the root hash before the update.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    proof: SparseMerkleProof<H>,
+
+    /// When an existing key is deleted, the preimage of its lowest sibling which is not a
+    /// placeholder, if any. It tells whether that sibling is a leaf, which then moves up in place
+    /// of the parent of the deleted leaf.
+    sibling_preimage: Option<SparseMerkleNodePreimage>,
+}
+
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleUpdateStep<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleUpdateStep")
+            .field("proof", &self.proof)
+            .field("sibling_preimage", &self.sibling_preimage)
+            .finish()
+    }
+}
+
+/// A proof that applying a set of updates to the tree whose root hash is some `old_root_hash`
+/// yields the tree whose root hash is some `new_root_hash`, which can be checked without any other
+/// state by [`verify_update`](SparseMerkleUpdateProof::verify_update).
+///
+/// The updates are applied one at a time, in key hash order. For each of them, the proof holds a
+/// [`SparseMerkleProof`] of the updated key against the root hash before that update, from which
+/// the root hash after it is computed.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct SparseMerkleUpdateProof<H: SimpleHasher> {
+    /// One step per updated key, in key hash order.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    steps: Vec<SparseMerkleUpdateStep<H>>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for SparseMerkleUpdateProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SparseMerkleUpdateProof")
+            .field("steps", &self.steps)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleUpdateProof<H> {
+    /// Constructs a new `SparseMerkleUpdateProof` from the proof of each update, in key hash
+    /// order, along with the preimage of the sibling of each deleted leaf.
+    pub(crate) fn new(
+        steps: Vec<(SparseMerkleProof<H>, Option<SparseMerkleNodePreimage>)>,
+    ) -> Self {
+        Self {
+            steps: steps
+                .into_iter()
+                .map(|(proof, sibling_preimage)| SparseMerkleUpdateStep {
+                    proof,
+                    sibling_preimage,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the number of updates proven.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Returns true if the proof does not prove any update.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Verifies that applying `updates` to the tree whose root hash is `old_root_hash` yields the
+    /// tree whose root hash is `new_root_hash`. A `None` value deletes the key.
+    ///
+    /// As with [`JellyfishMerkleTree::put_value_set`](crate::JellyfishMerkleTree::put_value_set),
+    /// the last update of a key repeated in `updates` wins.
+    pub fn verify_update<V: AsRef<[u8]>>(
+        &self,
+        old_root_hash: RootHash,
+        new_root_hash: RootHash,
+        updates: impl IntoIterator<Item = (KeyHash, Option<V>)>,
+    ) -> Result<()> {
+        let updates: alloc::collections::BTreeMap<_, _> = updates.into_iter().collect();
+        ensure_valid_proof!(
+            updates.len() == self.steps.len(),
+            "The proof proves {} updates, but {} were given.",
+            self.steps.len(),
+            updates.len(),
+        );
+
+        let actual_root_hash = updates.iter().zip(&self.steps).try_fold(
+            old_root_hash,
+            |root_hash, ((key, value), step)| {
+                step.apply(
+                    root_hash,
+                    *key,
+                    value.as_ref().map(|value| ValueHash::with::<H>(value)),
+                )
+            },
+        )?;
+        ensure_valid_proof!(
+            actual_root_hash == new_root_hash,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            new_root_hash,
+        );
+        Ok(())
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleUpdateStep<H> {
+    /// Checks the proof of `key` against `root_hash`, and returns the root hash after setting the
+    /// value hash of `key` to `value_hash`, or deleting it if `value_hash` is `None`.
+    fn apply(
+        &self,
+        root_hash: RootHash,
+        key: KeyHash,
+        value_hash: Option<ValueHash>,
+    ) -> Result<RootHash> {
+        let siblings = &self.proof.siblings;
+        ensure_valid_proof!(
+            siblings.len() <= 256,
+            "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+            256,
+            siblings.len(),
+        );
+        let leaf = self.proof.leaf;
+        if let Some(leaf) = leaf {
+            ensure_valid_proof!(
+                leaf.key_hash == key
+                    || key.0.common_prefix_bits_len(&leaf.key_hash.0) >= siblings.len(),
+                "Key would not have ended up in the subtree where the provided key in proof \
+                 is the only existing key, if it existed. So this is not a valid proof.",
+            );
+        }
+        let current_hash = leaf.map_or(H::PLACEHOLDER_HASH, |leaf| leaf.hash::<H>());
+        let actual_root_hash = fold_siblings::<H>(current_hash, key, siblings, 0);
+        ensure_valid_proof!(
+            actual_root_hash == root_hash.0,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            root_hash,
+        );
+
+        let existing_leaf = leaf.filter(|leaf| leaf.key_hash == key);
+        if existing_leaf.is_none() || value_hash.is_some() {
+            ensure_valid_proof!(
+                self.sibling_preimage.is_none(),
+                "Unexpected sibling preimage in the proof of an update which deletes nothing."
+            );
+        }
+
+        let new_root_hash = match (value_hash, leaf) {
+            (Some(value_hash), Some(other_leaf)) if other_leaf.key_hash != key => {
+                // The new leaf and the existing one are split into a subtree, under their common
+                // prefix.
+                let new_leaf_hash = SparseMerkleLeafNode::new(key, value_hash).hash::<H>();
+                let common_prefix_len = key.0.common_prefix_bits_len(&other_leaf.key_hash.0);
+                let mut current_hash = if key.0.get_bit(common_prefix_len) {
+                    SparseMerkleInternalNode::new(other_leaf.hash::<H>(), new_leaf_hash)
+                } else {
+                    SparseMerkleInternalNode::new(new_leaf_hash, other_leaf.hash::<H>())
+                }
+                .hash::<H>();
+                for depth in (siblings.len()..common_prefix_len).rev() {
+                    current_hash = if key.0.get_bit(depth) {
+                        SparseMerkleInternalNode::new(H::PLACEHOLDER_HASH, current_hash)
+                    } else {
+                        SparseMerkleInternalNode::new(current_hash, H::PLACEHOLDER_HASH)
+                    }
+                    .hash::<H>();
+                }
+                fold_siblings::<H>(current_hash, key, siblings, 0)
+            }
+            (Some(value_hash), _) => {
+                // The key is updated in place, or inserted in an empty subtree.
+                let new_leaf_hash = SparseMerkleLeafNode::new(key, value_hash).hash::<H>();
+                fold_siblings::<H>(new_leaf_hash, key, siblings, 0)
+            }
+            (None, _) if existing_leaf.is_none() => {
+                // Deleting a key which does not exist leaves the tree unchanged.
+                root_hash.0
+            }
+            (None, _) => self.delete(key)?,
+        };
+        Ok(RootHash(new_root_hash))
+    }
+
+    /// Returns the root hash after deleting the leaf of `key`, which the proof includes.
+    fn delete(&self, key: KeyHash) -> Result<[u8; 32]> {
+        let siblings = &self.proof.siblings;
+        let lowest_sibling = match (0..siblings.len()).find(|i| siblings[*i] != H::PLACEHOLDER_HASH)
+        {
+            Some(lowest_sibling) => lowest_sibling,
+            None => {
+                // The deleted leaf was the only one in the tree.
+                ensure_valid_proof!(
+                    self.sibling_preimage.is_none(),
+                    "Unexpected sibling preimage: the deleted leaf has no sibling."
+                );
+                return Ok(H::PLACEHOLDER_HASH);
+            }
+        };
+        let sibling_preimage = self.sibling_preimage.ok_or_else(|| {
+            invalid_proof!("Missing the preimage of the sibling of a deleted leaf.")
+        })?;
+        ensure_valid_proof!(
+            sibling_preimage.hash::<H>() == siblings[lowest_sibling],
+            "The sibling preimage does not match the sibling of the deleted leaf."
+        );
+
+        match sibling_preimage {
+            SparseMerkleNodePreimage::Leaf(_) => {
+                // The sibling leaf moves up until it has a sibling which is not a placeholder.
+                let next_sibling = (lowest_sibling + 1..siblings.len())
+                    .find(|i| siblings[*i] != H::PLACEHOLDER_HASH)
+                    .unwrap_or(siblings.len());
+                Ok(fold_siblings::<H>(
+                    siblings[lowest_sibling],
+                    key,
+                    siblings,
+                    next_sibling,
+                ))
+            }
+            SparseMerkleNodePreimage::Internal { .. } => {
+                let depth = siblings.len() - 1 - lowest_sibling;
+                let current_hash = if key.0.get_bit(depth) {
+                    SparseMerkleInternalNode::new(siblings[lowest_sibling], H::PLACEHOLDER_HASH)
+                } else {
+                    SparseMerkleInternalNode::new(H::PLACEHOLDER_HASH, siblings[lowest_sibling])
+                }
+                .hash::<H>();
+                Ok(fold_siblings::<H>(
+                    current_hash,
+                    key,
+                    siblings,
+                    lowest_sibling + 1,
+                ))
+            }
+        }
+    }
+}
+
+/// Hashes `current_hash` up to the root with `siblings[start..]`, which are ordered from the
+/// bottom level to the root level, along the path of `key`.
+fn fold_siblings<H: SimpleHasher>(
+    current_hash: [u8; 32],
+    key: KeyHash,
+    siblings: &[[u8; 32]],
+    start: usize,
+) -> [u8; 32] {
+    siblings[start..]
+        .iter()
+        .enumerate()
+        .fold(current_hash, |hash, (i, sibling_hash)| {
+            let depth = siblings.len() - 1 - (start + i);
+            if key.0.get_bit(depth) {
+                SparseMerkleInternalNode::new(*sibling_hash, hash).hash::<H>()
+            } else {
+                SparseMerkleInternalNode::new(hash, *sibling_hash).hash::<H>()
+            }
+        })
+}
+
+/// A proof that a key does not exist in the tree, made of inclusion proofs of its neighbors: the
+/// leaves with the closest key hashes on either side of the missing key.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionProof<H: SimpleHasher> {
+    /// The missing key is before all the keys of the tree.
+    Leftmost {
+        /// The inclusion proof of the first key of the tree, on the right of the missing key.
+        #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+        leftmost_right_proof: SparseMerkleProof<H>,
+    },
+    /// The missing key is between two keys of the tree.
+    Middle {
+        /// The inclusion proof of the first key on the right of the missing key.
+        #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+        leftmost_right_proof: SparseMerkleProof<H>,
+        /// The inclusion proof of the last key on the left of the missing key.
+        #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+        rightmost_left_proof: SparseMerkleProof<H>,
+    },
+    /// The missing key is after all the keys of the tree.
+    Rightmost {
+        /// The inclusion proof of the last key of the tree, on the left of the missing key.
+        #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+        rightmost_left_proof: SparseMerkleProof<H>,
+    },
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for ExclusionProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExclusionProof::Leftmost {
+                leftmost_right_proof,
+            } => f
+                .debug_struct("Leftmost")
+                .field("leftmost_right_proof", leftmost_right_proof)
+                .finish(),
+            ExclusionProof::Middle {
+                leftmost_right_proof,
+                rightmost_left_proof,
+            } => f
+                .debug_struct("Middle")
+                .field("leftmost_right_proof", leftmost_right_proof)
+                .field("rightmost_left_proof", rightmost_left_proof)
+                .finish(),
+            ExclusionProof::Rightmost {
+                rightmost_left_proof,
+            } => f
+                .debug_struct("Rightmost")
+                .field("rightmost_left_proof", rightmost_left_proof)
+                .finish(),
+        }
+    }
+}
+
+impl<H: SimpleHasher> ExclusionProof<H> {
+    /// Verifies that `key_hash` does not exist in the tree whose root hash is `expected_root_hash`.
+    ///
+    /// This checks that each neighbor proof is a valid inclusion proof, that `key_hash` is
+    /// strictly between the neighbors, and that the neighbors are adjacent: the left neighbor is
+    /// the last leaf before `key_hash` and the right neighbor the first leaf after it, or the
+    /// first or last leaf of the whole tree for the leftmost and rightmost variants.
+    pub fn verify(&self, expected_root_hash: RootHash, key_hash: KeyHash) -> Result<()> {
+        match self {
+            ExclusionProof::Leftmost {
+                leftmost_right_proof,
+            } => {
+                let right_leaf = leftmost_right_proof.verify_leaf(expected_root_hash)?;
+                ensure_valid_proof!(
+                    key_hash < right_leaf.key_hash,
+                    "The key is not on the left of its right neighbor."
+                );
+                ensure_valid_proof!(
+                    leftmost_right_proof.has_no_sibling_beside(right_leaf.key_hash, true, 0),
+                    "The right neighbor is not the leftmost leaf of the tree."
+                );
+            }
+            ExclusionProof::Middle {
+                leftmost_right_proof,
+                rightmost_left_proof,
+            } => {
+                let right_leaf = leftmost_right_proof.verify_leaf(expected_root_hash)?;
+                let left_leaf = rightmost_left_proof.verify_leaf(expected_root_hash)?;
+                ensure_valid_proof!(
+                    left_leaf.key_hash < key_hash && key_hash < right_leaf.key_hash,
+                    "The key is not between its neighbors."
+                );
+                // Below the node where the paths of the neighbors diverge, there must be nothing on
+                // the right of the left neighbor and nothing on the left of the right neighbor.
+                let divergence_depth = left_leaf
+                    .key_hash
+                    .0
+                    .common_prefix_bits_len(&right_leaf.key_hash.0);
+                ensure_valid_proof!(
+                    rightmost_left_proof.has_no_sibling_beside(
+                        left_leaf.key_hash,
+                        false,
+                        divergence_depth + 1
+                    ) && leftmost_right_proof.has_no_sibling_beside(
+                        right_leaf.key_hash,
+                        true,
+                        divergence_depth + 1
+                    ),
+                    "The neighbors are not adjacent leaves."
+                );
+            }
+            ExclusionProof::Rightmost {
+                rightmost_left_proof,
+            } => {
+                let left_leaf = rightmost_left_proof.verify_leaf(expected_root_hash)?;
+                ensure_valid_proof!(
+                    left_leaf.key_hash < key_hash,
+                    "The key is not on the right of its left neighbor."
+                );
+                ensure_valid_proof!(
+                    rightmost_left_proof.has_no_sibling_beside(left_leaf.key_hash, false, 0),
+                    "The left neighbor is not the rightmost leaf of the tree."
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A proof that a key does not exist in the tree, which holds the key hash it is about along with
+/// the [`ExclusionProof`] of its neighbors, so that it can be checked without knowing which of
+/// the neighbors exist.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct NonInclusionProof<H: SimpleHasher> {
+    /// The key hash whose absence is proven.
+    key_hash: KeyHash,
+
+    /// The inclusion proofs of the neighbors of the key.
+    #[cfg_attr(feature = "borsh", borsh(bound(serialize = "", deserialize = "")))]
+    exclusion_proof: ExclusionProof<H>,
+}
+
+// Deriving Debug fails since H is not Debug though phantom_hasher implements it
+// generically. Implement Debug manually as a workaround to enable Proptest
+impl<H: SimpleHasher> core::fmt::Debug for NonInclusionProof<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NonInclusionProof")
+            .field("key_hash", &self.key_hash)
+            .field("exclusion_proof", &self.exclusion_proof)
+            .finish()
+    }
+}
+
+impl<H: SimpleHasher> NonInclusionProof<H> {
+    /// Constructs a new `NonInclusionProof` of `key_hash` from the exclusion proof of its
+    /// neighbors.
+    pub(crate) fn new(key_hash: KeyHash, exclusion_proof: ExclusionProof<H>) -> Self {
+        NonInclusionProof {
+            key_hash,
+            exclusion_proof,
+        }
+    }
+
+    /// Returns the key hash whose absence is proven.
+    pub fn key_hash(&self) -> KeyHash {
+        self.key_hash
+    }
+
+    /// Returns the leaf with the closest key hash before the key, if any.
+    pub fn left_neighbor(&self) -> Option<SparseMerkleLeafNode> {
+        match &self.exclusion_proof {
+            ExclusionProof::Leftmost { .. } => None,
+            ExclusionProof::Middle {
+                rightmost_left_proof,
+                ..
+            }
+            | ExclusionProof::Rightmost {
+                rightmost_left_proof,
+            } => rightmost_left_proof.leaf,
+        }
+    }
+
+    /// Returns the leaf with the closest key hash after the key, if any.
+    pub fn right_neighbor(&self) -> Option<SparseMerkleLeafNode> {
+        match &self.exclusion_proof {
+            ExclusionProof::Leftmost {
+                leftmost_right_proof,
+            }
+            | ExclusionProof::Middle {
+                leftmost_right_proof,
+                ..
+            } => leftmost_right_proof.leaf,
+            ExclusionProof::Rightmost { .. } => None,
+        }
+    }
+
+    /// Returns the exclusion proof made of the inclusion proofs of the neighbors.
+    pub fn exclusion_proof(&self) -> &ExclusionProof<H> {
+        &self.exclusion_proof
+    }
+
+    /// Verifies that `key_hash` is the key hash of this proof, and that it does not exist in the
+    /// tree whose root hash is `expected_root_hash`.
+    pub fn verify(&self, expected_root_hash: RootHash, key_hash: KeyHash) -> Result<()> {
+        ensure_valid_proof!(
+            self.key_hash == key_hash,
+            "The proof is about key hash {:?}, not {:?}.",
+            self.key_hash,
+            key_hash
+        );
+        self.exclusion_proof.verify(expected_root_hash, key_hash)
+    }
+}
+
+impl<H: SimpleHasher> SparseMerkleProof<H> {
+    /// Verifies that the leaf of this proof exists in the tree whose root hash is
+    /// `expected_root_hash`, and returns it.
+    fn verify_leaf(&self, expected_root_hash: RootHash) -> Result<SparseMerkleLeafNode> {
+        let leaf = self.leaf.ok_or_else(|| {
+            invalid_proof!("Expected inclusion proof. Found empty subtree proof.")
+        })?;
+        ensure_valid_proof!(
+            self.siblings.len() <= 256,
+            "Sparse Merkle Tree proof has more than {} ({}) siblings.",
+            256,
+            self.siblings.len(),
+        );
+        let actual_root_hash =
+            fold_siblings::<H>(leaf.hash::<H>(), leaf.key_hash, &self.siblings, 0);
+        ensure_valid_proof!(
+            actual_root_hash == expected_root_hash.0,
+            "Root hashes do not match. Actual root hash: {:?}. Expected root hash: {:?}.",
+            actual_root_hash,
+            expected_root_hash,
+        );
+        Ok(leaf)
+    }
+
+    /// Returns true if the path of `key` has no sibling other than placeholders on its left, if
+    /// `left` is true, or on its right otherwise, from depth `min_depth` down.
+    fn has_no_sibling_beside(&self, key: KeyHash, left: bool, min_depth: usize) -> bool {
+        self.siblings.iter().enumerate().all(|(i, sibling)| {
+            // The sibling at index `i` is at depth `len - i`, where the path goes down along the
+            // bit at position `len - 1 - i`; a path going right has its sibling on the left.
+            let bit_position = self.siblings.len() - 1 - i;
+            bit_position < min_depth
+                || key.0.get_bit(bit_position) != left
+                || *sibling == H::PLACEHOLDER_HASH
+        })
+    }
+}
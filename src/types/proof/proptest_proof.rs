@@ -52,7 +52,7 @@ impl<H: SimpleHasher> Arbitrary for SparseMerkleProof<H> {
     }
 }
 
-impl Arbitrary for SparseMerkleRangeProof {
+impl<H: SimpleHasher + 'static> Arbitrary for SparseMerkleRangeProof<H> {
     type Parameters = ();
     type Strategy = BoxedStrategy<Self>;
 
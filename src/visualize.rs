@@ -0,0 +1,158 @@
+//! Renderings of a tree, for debugging.
+//!
+//! [`JellyfishMerkleTree::render`] and [`JellyfishMerkleTree::render_subtree`] draw the nodes of a
+//! tree at a version, either as a [Graphviz](https://graphviz.org) DOT graph or as indented ASCII.
+//! Every node is labelled with its kind, its nibble path, its version and a truncated hash; leaves
+//! also show their truncated key hash and value hash.
+//!
+//! ```text
+//! internal [] v1 #69c7d7b8
+//!   0: leaf [0] v1 #c429018c key 00000000 value 6e340b9c
+//!   1: internal [1] v1 #c4471481
+//!     1: leaf [11] v1 #1e6f147d key 11111111 value 4bf5122f
+//!     2: leaf [12] v1 #70a699de key 12121212 value dbc1b4c9
+//! ```
+
+use std::{collections::BTreeMap, fmt::Write, marker::PhantomData};
+
+use anyhow::{format_err, Result};
+
+use crate::{
+    node_type::{Node, NodeKey},
+    storage::{NibblePath, TreeReader},
+    JellyfishMerkleTree, SimpleHasher, Version,
+};
+
+/// The number of bytes of each hash shown in a rendering.
+const HASH_PREFIX_LEN: usize = 4;
+
+/// The format of a rendering of a tree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RenderFormat {
+    /// A Graphviz DOT digraph, with an edge from each internal node to each of its children,
+    /// labelled with the nibble of the child.
+    Dot,
+    /// One line per node, indented by its depth below the root of the rendering and prefixed
+    /// with its nibble in its parent.
+    Ascii,
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Renders all the nodes of the tree at `version` in the given format.
+    pub fn render(&self, version: Version, format: RenderFormat) -> Result<String> {
+        self.render_subtree(&NibblePath::from_bytes(&[]), version, format)
+    }
+
+    /// Renders the nodes of the tree at `version` below `prefix` in the given format, as exported
+    /// by [`export_subtree`](JellyfishMerkleTree::export_subtree).
+    pub fn render_subtree(
+        &self,
+        prefix: &NibblePath,
+        version: Version,
+        format: RenderFormat,
+    ) -> Result<String> {
+        let subtree = self.export_subtree(prefix, version)?;
+        let nodes: BTreeMap<&NodeKey, &Node> = subtree.nodes.iter().map(|(k, n)| (k, n)).collect();
+        let mut rendering = Renderer::<H> {
+            nodes: &nodes,
+            format,
+            output: String::new(),
+            num_nodes: 0,
+            _hasher: PhantomData,
+        };
+        if format == RenderFormat::Dot {
+            rendering
+                .output
+                .push_str("digraph jmt {\n    node [shape=box];\n");
+        }
+        if let Some((root_key, _)) = subtree.nodes.first() {
+            rendering.render_node(root_key, None, 0)?;
+        }
+        if format == RenderFormat::Dot {
+            rendering.output.push_str("}\n");
+        }
+        Ok(rendering.output)
+    }
+}
+
+struct Renderer<'n, H> {
+    nodes: &'n BTreeMap<&'n NodeKey, &'n Node>,
+    format: RenderFormat,
+    output: String,
+    num_nodes: usize,
+    _hasher: PhantomData<H>,
+}
+
+impl<H: SimpleHasher> Renderer<'_, H> {
+    /// Renders a node and its descendants, and returns the DOT identifier of the node.
+    fn render_node(
+        &mut self,
+        node_key: &NodeKey,
+        nibble: Option<u8>,
+        depth: usize,
+    ) -> Result<usize> {
+        let node = self
+            .nodes
+            .get(node_key)
+            .ok_or_else(|| format_err!("Missing node {:?}.", node_key))?;
+        let id = self.num_nodes;
+        self.num_nodes += 1;
+
+        let mut label = match node {
+            Node::Internal(_) => String::from("internal"),
+            Node::Leaf(_) => String::from("leaf"),
+            Node::Null => String::from("null"),
+        };
+        write!(
+            label,
+            " [{:?}] v{} #{}",
+            node_key.nibble_path(),
+            node_key.version(),
+            truncated(&node.hash::<H>())
+        )?;
+        if let Node::Leaf(leaf) = node {
+            write!(
+                label,
+                " key {} value {}",
+                truncated(&leaf.key_hash().0),
+                truncated(&leaf.value_hash().0)
+            )?;
+        }
+
+        match self.format {
+            RenderFormat::Dot => writeln!(self.output, "    n{} [label=\"{}\"];", id, label)?,
+            RenderFormat::Ascii => {
+                let indent = "  ".repeat(depth);
+                match nibble {
+                    Some(nibble) => writeln!(self.output, "{}{:x}: {}", indent, nibble, label)?,
+                    None => writeln!(self.output, "{}{}", indent, label)?,
+                }
+            }
+        }
+
+        if let Node::Internal(internal) = node {
+            for (nibble, child) in internal.children_sorted() {
+                let child_key = node_key.gen_child_node_key(child.version, nibble);
+                let child_id = self.render_node(&child_key, Some(u8::from(nibble)), depth + 1)?;
+                if self.format == RenderFormat::Dot {
+                    writeln!(
+                        self.output,
+                        "    n{} -> n{} [label=\"{:x}\"];",
+                        id,
+                        child_id,
+                        u8::from(nibble)
+                    )?;
+                }
+            }
+        }
+        Ok(id)
+    }
+}
+
+fn truncated(hash: &[u8; 32]) -> String {
+    hex::encode(&hash[..HASH_PREFIX_LEN])
+}
@@ -0,0 +1,368 @@
+//! Stateless execution of reads and updates against a partial tree.
+//!
+//! A [`WitnessTree`] holds only the nodes and values of a tree that a state transition needs, its
+//! witness, rather than reading them from a [`TreeReader`]. It supports the reads and updates of
+//! [`JellyfishMerkleTree`] over the witnessed keys, and fails as soon as they touch a node or value
+//! which is not witnessed. This makes it possible to re-execute a state transition without the
+//! storage of the tree, e.g. in a zkVM guest or to settle a fraud proof.
+//!
+//! The witness is checked when the tree is built: every node must hash to the child it stands for
+//! in its parent, all the way up to the root, and every value must hash to the value hash of its
+//! leaf. The [`root_hash`](WitnessTree::root_hash) of the tree therefore commits to the whole
+//! witness, and must still be compared to a trusted root hash.
+//!
+//! The witness of a state transition is generated from the full tree with
+//! [`JellyfishMerkleTree::generate_witness`], as a serializable [`Witness`]. When only the new root
+//! hash matters, [`compute_root_from_witness`] derives it from the witness and the updates alone.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+use anyhow::{bail, ensure, format_err, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    storage::TreeReader,
+    types::PRE_GENESIS_VERSION,
+    JellyfishMerkleTree, KeyHash, OwnedValue, PhantomHasher, RootHash, SimpleHasher, ValueHash,
+    Version,
+};
+
+/// The nodes and values of a tree at some version which a state transition touches, as generated
+/// by [`JellyfishMerkleTree::generate_witness`] and executed by [`WitnessTree::from_witness`].
+///
+/// Each node is included once, however many keys it is on the path of, and nodes and their keys
+/// are kept in their compact binary encoding.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct Witness {
+    version: Version,
+    /// The encoded node keys and nodes, in node key order.
+    nodes: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The values of the leaves read, in key hash order.
+    values: Vec<(KeyHash, OwnedValue)>,
+}
+
+impl Witness {
+    /// Returns the version of the tree the witness is taken from.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the number of witnessed nodes.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Decodes the witnessed nodes.
+    pub fn nodes(&self) -> Result<Vec<(NodeKey, Node)>> {
+        self.nodes
+            .iter()
+            .map(|(node_key, node)| Ok((NodeKey::decode(node_key)?, Node::decode(node)?)))
+            .collect()
+    }
+
+    /// Returns the witnessed values.
+    pub fn values(&self) -> &[(KeyHash, OwnedValue)] {
+        &self.values
+    }
+
+    /// Returns the root hash of the tree the witness is taken from, which must be compared to a
+    /// trusted root hash before relying on anything derived from the witness.
+    pub fn root_hash<H: SimpleHasher>(&self) -> Result<RootHash> {
+        let root_key = NodeKey::new_empty_path(self.version).encode()?;
+        let (_, root) = self
+            .nodes
+            .iter()
+            .find(|(node_key, _)| *node_key == root_key)
+            .ok_or_else(|| format_err!("The root at version {} is not witnessed.", self.version))?;
+        Ok(RootHash(Node::decode(root)?.hash::<H>()))
+    }
+}
+
+/// Returns the root hash of the tree of `witness` once `value_set` is applied at the version
+/// following that of the witness, without any storage.
+///
+/// Fails if the witness is inconsistent, or if the updates touch a node it does not include. The
+/// result is only as trustworthy as the [`root_hash`](Witness::root_hash) of the witness.
+pub fn compute_root_from_witness<H: SimpleHasher>(
+    witness: &Witness,
+    value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+) -> Result<RootHash> {
+    WitnessTree::<H>::from_witness(witness)?.put_value_set(value_set, witness.version + 1)
+}
+
+impl<'a, R, H> JellyfishMerkleTree<'a, R, H>
+where
+    R: 'a + TreeReader,
+    H: SimpleHasher,
+{
+    /// Returns the witness needed to re-execute, with a [`WitnessTree`], the reads of `reads` at
+    /// `version` followed by the update of `value_set` at `version + 1`: the nodes and values
+    /// these operations read from the tree, and nothing else.
+    pub fn generate_witness(
+        &self,
+        version: Version,
+        reads: &[KeyHash],
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+    ) -> Result<Witness> {
+        let recorder = WitnessRecorder {
+            reader: self.reader(),
+            nodes: Default::default(),
+            values: Default::default(),
+        };
+        let tree = JellyfishMerkleTree::<_, H>::new(&recorder);
+        tree.get_root_hash(version)?;
+        tree.multi_get_with_proof(reads, version)?;
+        tree.put_value_set(value_set, version + 1)?;
+
+        let nodes = recorder
+            .nodes
+            .into_inner()
+            .into_iter()
+            .map(|(node_key, node)| Ok((node_key.encode()?, node.encode()?)))
+            .collect::<Result<_>>()?;
+        Ok(Witness {
+            version,
+            nodes,
+            values: recorder.values.into_inner().into_iter().collect(),
+        })
+    }
+}
+
+/// A [`TreeReader`] recording the nodes and values read from another one.
+struct WitnessRecorder<'a, R> {
+    reader: &'a R,
+    nodes: RefCell<BTreeMap<NodeKey, Node>>,
+    values: RefCell<BTreeMap<KeyHash, OwnedValue>>,
+}
+
+impl<R: TreeReader> TreeReader for WitnessRecorder<'_, R> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        let node = self.reader.get_node_option(node_key)?;
+        if let Some(node) = &node {
+            self.nodes
+                .borrow_mut()
+                .insert(node_key.clone(), node.clone());
+        }
+        Ok(node)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        let value = self.reader.get_value_option(max_version, key_hash)?;
+        if let Some(value) = &value {
+            self.values.borrow_mut().insert(key_hash, value.clone());
+        }
+        Ok(value)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.reader.get_rightmost_leaf()
+    }
+}
+
+/// A partial tree made of the witnessed nodes and values of a tree at some version, see the
+/// [module](self) documentation.
+pub struct WitnessTree<H: SimpleHasher> {
+    reader: WitnessReader,
+    /// The latest version of the tree, or `None` before genesis.
+    version: Option<Version>,
+    _phantom_hasher: PhantomHasher<H>,
+}
+
+impl<H: SimpleHasher> WitnessTree<H> {
+    /// Creates an empty tree, whose first version is 0.
+    pub fn empty() -> Self {
+        Self {
+            reader: WitnessReader::default(),
+            version: None,
+            _phantom_hasher: Default::default(),
+        }
+    }
+
+    /// Creates a partial tree at `version` from the witnessed `nodes`, which must include the
+    /// root at `version`, and the witnessed `values` of the leaves among them.
+    ///
+    /// Fails if a node does not hash to the child it stands for in its parent, if a node is not
+    /// reachable from the root, or if a value does not hash to the value hash of its leaf.
+    pub fn new(
+        version: Version,
+        nodes: impl IntoIterator<Item = (NodeKey, Node)>,
+        values: impl IntoIterator<Item = (KeyHash, OwnedValue)>,
+    ) -> Result<Self> {
+        let tree = Self {
+            reader: WitnessReader {
+                nodes: nodes.into_iter().collect(),
+                values: values.into_iter().collect(),
+            },
+            version: Some(version),
+            _phantom_hasher: Default::default(),
+        };
+        tree.check(version)?;
+        Ok(tree)
+    }
+
+    /// Creates a partial tree from `witness`, see [`new`](WitnessTree::new).
+    pub fn from_witness(witness: &Witness) -> Result<Self> {
+        Self::new(
+            witness.version,
+            witness.nodes()?,
+            witness.values.iter().cloned(),
+        )
+    }
+
+    fn check(&self, version: Version) -> Result<()> {
+        let WitnessReader { nodes, values } = &self.reader;
+        let root_key = NodeKey::new_empty_path(version);
+        ensure!(
+            nodes.contains_key(&root_key),
+            "The root at version {} is not witnessed.",
+            version
+        );
+        let mut reachable = HashSet::new();
+        let mut leaves = HashMap::new();
+        let mut stack = vec![root_key];
+        while let Some(node_key) = stack.pop() {
+            match &nodes[&node_key] {
+                Node::Internal(internal) => {
+                    for (nibble, child) in internal.children_sorted() {
+                        let child_key = node_key.gen_child_node_key(child.version, nibble);
+                        if let Some(child_node) = nodes.get(&child_key) {
+                            ensure!(
+                                child_node.hash::<H>() == child.hash,
+                                "The witnessed node {:?} does not match its parent.",
+                                child_key
+                            );
+                            stack.push(child_key);
+                        }
+                    }
+                }
+                Node::Leaf(leaf) => {
+                    leaves.insert(leaf.key_hash(), leaf.value_hash());
+                }
+                Node::Null => {}
+            }
+            reachable.insert(node_key);
+        }
+        ensure!(
+            reachable.len() == nodes.len(),
+            "{} witnessed nodes are not reachable from the root.",
+            nodes.len() - reachable.len()
+        );
+        for (key_hash, value) in values {
+            ensure!(
+                leaves.get(key_hash) == Some(&ValueHash::with::<H>(value)),
+                "The witnessed value of {:?} does not match its leaf.",
+                key_hash
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the latest version of the tree, or `None` if it is empty.
+    pub fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// Returns the root hash of the tree at its latest version.
+    pub fn root_hash(&self) -> Result<RootHash> {
+        match self.version {
+            Some(version) => self.tree().get_root_hash(version),
+            None => Ok(RootHash(H::PLACEHOLDER_HASH)),
+        }
+    }
+
+    /// Returns the value of `key` at the latest version of the tree, or `None` if the tree proves
+    /// that it has none. Fails if the path to `key` or its value is not witnessed.
+    pub fn get(&self, key: KeyHash) -> Result<Option<OwnedValue>> {
+        match self.version {
+            // The path to the key is walked, so that an absent value is proven by the nodes.
+            Some(version) => Ok(self.tree().get_with_proof(key, version)?.0),
+            None => Ok(None),
+        }
+    }
+
+    /// Applies `value_set` at `version`, which must follow the latest version of the tree, and
+    /// returns the new root hash. Fails, leaving the tree unchanged, if the update touches a node
+    /// which is not witnessed.
+    pub fn put_value_set(
+        &mut self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<RootHash> {
+        let next_version = self.version.map_or(0, |version| version + 1);
+        ensure!(
+            version == next_version,
+            "Expected version {}, got {}.",
+            next_version,
+            version
+        );
+        let (root_hash, batch) = self.tree().put_value_set(value_set, version)?;
+        self.reader.nodes.extend(
+            batch
+                .node_batch
+                .nodes()
+                .iter()
+                .map(|(node_key, node)| (node_key.clone(), node.clone())),
+        );
+        for ((_, key_hash), value) in batch.node_batch.values() {
+            match value {
+                Some(value) => self.reader.values.insert(*key_hash, value.to_vec()),
+                None => self.reader.values.remove(key_hash),
+            };
+        }
+        self.version = Some(version);
+        Ok(root_hash)
+    }
+
+    fn tree(&self) -> JellyfishMerkleTree<'_, WitnessReader, H> {
+        JellyfishMerkleTree::new(&self.reader)
+    }
+}
+
+/// The [`TreeReader`] the operations of a [`WitnessTree`] run against, which fails on the nodes
+/// and values which are not witnessed.
+#[derive(Default)]
+struct WitnessReader {
+    nodes: HashMap<NodeKey, Node>,
+    /// The values of the leaves at the latest version, which are only read once their leaf was
+    /// found.
+    values: HashMap<KeyHash, OwnedValue>,
+}
+
+impl TreeReader for WitnessReader {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        match self.nodes.get(node_key) {
+            Some(node) => Ok(Some(node.clone())),
+            // The tree checks whether there is a pre-genesis root before its first version.
+            None if *node_key == NodeKey::new_empty_path(PRE_GENESIS_VERSION) => Ok(None),
+            None => bail!("The node {:?} is not witnessed.", node_key),
+        }
+    }
+
+    fn get_value_option(
+        &self,
+        _max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        self.values
+            .get(&key_hash)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| format_err!("The value of {:?} is not witnessed.", key_hash))
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        bail!("A witness tree cannot look up its rightmost leaf.")
+    }
+}
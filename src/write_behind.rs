@@ -0,0 +1,311 @@
+//! Commits whose writes to storage happen in the background.
+//!
+//! A [`WriteBehindTree`] returns the root hash of each version as soon as it is computed, and hands
+//! its [`NodeBatch`] to a background thread writing it to the underlying [`TreeWriter`], so that the
+//! latency of commits does not depend on that of the storage. Until they are written, the batches
+//! are kept in memory, and reads go to them first and to the storage second, as with an
+//! [`OverlayTree`](crate::overlay::OverlayTree).
+//!
+//! At most a configured number of batches wait to be written: beyond that, commits block until the
+//! storage catches up. If a write fails, the background thread stops, and every later commit or
+//! flush returns the error; the batches not written yet are then lost.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+};
+
+use anyhow::{bail, format_err, Result};
+
+use crate::{
+    node_type::{LeafNode, Node, NodeKey},
+    overlay::pending_value,
+    reader::read_missing_nodes,
+    storage::{NodeBatch, StaleNodeIndexBatch, TreeReader, TreeWriter},
+    JellyfishMerkleTree, KeyHash, OwnedValue, PhantomHasher, RootHash, SimpleHasher, Version,
+};
+
+/// A tree writing its batches to storage in the background, see the [module](self)
+/// documentation.
+///
+/// `WriteBehindTree` is itself a [`TreeReader`] over the batches waiting to be written and the
+/// underlying storage, so [`tree`](WriteBehindTree::tree) gives a [`JellyfishMerkleTree`] reading
+/// every committed version, written or not.
+pub struct WriteBehindTree<S, H: SimpleHasher> {
+    store: Arc<S>,
+    shared: Arc<Shared>,
+    max_pending: usize,
+    /// Held while committing, so that versions are committed one at a time.
+    commit_lock: Mutex<()>,
+    worker: Option<JoinHandle<()>>,
+    _phantom_hasher: PhantomHasher<H>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Notified whenever a batch is queued or written, and when the tree is dropped.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// The batches waiting to be written, oldest first. A batch is only removed once written, so
+    /// that it can be read until the storage serves it.
+    pending: VecDeque<Arc<NodeBatch>>,
+    /// The error the background thread stopped on, if any.
+    error: Option<String>,
+    /// Whether the tree was dropped, after which the background thread stops once it has written
+    /// every pending batch.
+    closed: bool,
+}
+
+impl<S, H> WriteBehindTree<S, H>
+where
+    S: TreeReader + TreeWriter + Send + Sync + 'static,
+    H: SimpleHasher,
+{
+    /// Creates a tree on top of `store`, and spawns the thread writing its batches, at most
+    /// `max_pending` of which wait to be written at any time.
+    pub fn new(store: Arc<S>, max_pending: usize) -> Self {
+        assert!(
+            max_pending > 0,
+            "The maximum number of pending batches must be positive."
+        );
+        let shared = Arc::new(Shared {
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let worker = {
+            let store = store.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || write_batches(&*store, &shared))
+        };
+        Self {
+            store,
+            shared,
+            max_pending,
+            commit_lock: Mutex::default(),
+            worker: Some(worker),
+            _phantom_hasher: Default::default(),
+        }
+    }
+
+    /// Returns a tree reading the batches waiting to be written on top of the underlying storage.
+    pub fn tree(&self) -> JellyfishMerkleTree<'_, Self, H> {
+        JellyfishMerkleTree::new(self)
+    }
+
+    /// Applies `value_set` at `version`, as [`JellyfishMerkleTree::put_value_set`] does, and
+    /// queues the resulting batch to be written in the background. Returns the root hash of the
+    /// tree at `version`, and the indices of the nodes it made stale, which are left to the
+    /// caller to record for pruning.
+    ///
+    /// Blocks while the maximum number of batches are waiting to be written.
+    pub fn put_value_set(
+        &self,
+        value_set: impl IntoIterator<Item = (KeyHash, Option<OwnedValue>)>,
+        version: Version,
+    ) -> Result<(RootHash, StaleNodeIndexBatch)> {
+        let _commit_guard = self
+            .commit_lock
+            .lock()
+            .map_err(|_| format_err!("The commit lock is poisoned."))?;
+        self.shared.check_error(&*self.shared.lock()?)?;
+        let (root_hash, batch) = self.tree().put_value_set(value_set, version)?;
+
+        let mut state = self.shared.lock()?;
+        while state.pending.len() >= self.max_pending && state.error.is_none() {
+            state = self.shared.wait(state)?;
+        }
+        self.shared.check_error(&state)?;
+        state.pending.push_back(Arc::new(batch.node_batch));
+        self.shared.changed.notify_all();
+        Ok((root_hash, batch.stale_node_index_batch))
+    }
+
+    /// Returns the number of batches waiting to be written.
+    pub fn num_pending(&self) -> Result<usize> {
+        Ok(self.shared.lock()?.pending.len())
+    }
+
+    /// Blocks until every batch committed so far is written, or returns the error which stopped
+    /// the writes.
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.shared.lock()?;
+        while !state.pending.is_empty() && state.error.is_none() {
+            state = self.shared.wait(state)?;
+        }
+        self.shared.check_error(&state)
+    }
+
+    /// Returns the underlying storage.
+    pub fn inner(&self) -> &Arc<S> {
+        &self.store
+    }
+}
+
+impl<S, H: SimpleHasher> WriteBehindTree<S, H> {
+    /// Returns the batches waiting to be written, newest first.
+    fn pending(&self) -> Result<Vec<Arc<NodeBatch>>> {
+        Ok(self.shared.lock()?.pending.iter().rev().cloned().collect())
+    }
+}
+
+impl<S, H: SimpleHasher> Drop for WriteBehindTree<S, H> {
+    /// Waits for the pending batches to be written.
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.lock() {
+            state.closed = true;
+            self.shared.changed.notify_all();
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> Result<MutexGuard<'_, State>> {
+        self.state
+            .lock()
+            .map_err(|_| format_err!("The write-behind lock is poisoned."))
+    }
+
+    fn wait<'s>(&self, state: MutexGuard<'s, State>) -> Result<MutexGuard<'s, State>> {
+        self.changed
+            .wait(state)
+            .map_err(|_| format_err!("The write-behind lock is poisoned."))
+    }
+
+    fn check_error(&self, state: &State) -> Result<()> {
+        match &state.error {
+            Some(error) => bail!("Writing a batch in the background failed: {}", error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes the pending batches of `shared` to `store` in order, until the tree is dropped or a
+/// write fails.
+fn write_batches<S: TreeWriter>(store: &S, shared: &Shared) {
+    loop {
+        let batch = {
+            let Ok(mut state) = shared.lock() else {
+                return;
+            };
+            loop {
+                if let Some(batch) = state.pending.front() {
+                    break batch.clone();
+                }
+                if state.closed {
+                    return;
+                }
+                state = match shared.wait(state) {
+                    Ok(state) => state,
+                    Err(_) => return,
+                };
+            }
+        };
+        let result = store.write_node_batch(&batch);
+        let Ok(mut state) = shared.lock() else {
+            return;
+        };
+        match result {
+            Ok(()) => {
+                state.pending.pop_front();
+            }
+            Err(error) => state.error = Some(error.to_string()),
+        }
+        shared.changed.notify_all();
+        if state.error.is_some() {
+            return;
+        }
+    }
+}
+
+impl<S: TreeReader, H: SimpleHasher> TreeReader for WriteBehindTree<S, H> {
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node>> {
+        for batch in self.pending()? {
+            if let Some(node) = batch.get_node(node_key) {
+                return Ok(Some(node.clone()));
+            }
+        }
+        self.store.get_node_option(node_key)
+    }
+
+    fn get_nodes(&self, node_keys: &[NodeKey]) -> Result<Vec<Option<Node>>> {
+        let pending = self.pending()?;
+        let mut nodes: Vec<Option<Node>> = node_keys
+            .iter()
+            .map(|node_key| {
+                pending
+                    .iter()
+                    .find_map(|batch| batch.get_node(node_key))
+                    .cloned()
+            })
+            .collect();
+        read_missing_nodes(&*self.store, node_keys, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    fn get_encoded_node_option(&self, node_key: &NodeKey) -> Result<Option<Vec<u8>>> {
+        // Pending nodes are not kept encoded: returning `None` makes the read path fall back to
+        // `get_node_option`.
+        if self
+            .pending()?
+            .iter()
+            .any(|batch| batch.get_node(node_key).is_some())
+        {
+            return Ok(None);
+        }
+        self.store.get_encoded_node_option(node_key)
+    }
+
+    fn get_value_option(
+        &self,
+        max_version: Version,
+        key_hash: KeyHash,
+    ) -> Result<Option<OwnedValue>> {
+        // Batches are of increasing versions, so the newest one holding a value of the key at or
+        // before `max_version` holds its newest value.
+        for batch in self.pending()? {
+            if let Some(value) = pending_value(&batch, max_version, key_hash) {
+                return Ok(value.as_deref().map(<[u8]>::to_vec));
+            }
+        }
+        self.store.get_value_option(max_version, key_hash)
+    }
+
+    fn get_rightmost_leaf(&self) -> Result<Option<(NodeKey, LeafNode)>> {
+        self.store.get_rightmost_leaf()
+    }
+
+    fn get_indexed_root_hash(&self, version: Version) -> Result<Option<RootHash>> {
+        for batch in self.pending()? {
+            if let Some(root_hash) = batch.root_hashes().get(&version) {
+                return Ok(Some(*root_hash));
+            }
+        }
+        self.store.get_indexed_root_hash(version)
+    }
+
+    fn get_indexed_root_hashes(
+        &self,
+        versions: Range<Version>,
+        limit: usize,
+    ) -> Result<Vec<(Version, RootHash)>> {
+        // Pending root hashes take precedence over those of the storage for the same version.
+        let pending = self.pending()?;
+        let mut root_hashes: BTreeMap<Version, RootHash> = self
+            .store
+            .get_indexed_root_hashes(versions.clone(), limit)?
+            .into_iter()
+            .collect();
+        for batch in pending.iter().rev() {
+            root_hashes.extend(batch.root_hashes().range(versions.clone()));
+        }
+        Ok(root_hashes.into_iter().take(limit).collect())
+    }
+}
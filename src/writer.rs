@@ -1,35 +1,67 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use anyhow::Result;
+use bytes::Bytes;
 #[cfg(any(test, feature = "fuzzing"))]
 use proptest_derive::Arbitrary;
 
 use crate::{
     node_type::{Node, NodeKey},
-    types::Version,
-    KeyHash, OwnedValue,
+    types::{nibble::nibble_path::NibblePath, Version},
+    KeyHash, RootHash, SimpleHasher, ValueHash,
 };
 
 /// Defines the interface used to write a batch of updates from a
 /// [`JellyfishMerkleTree`](crate::JellyfishMerkleTree)
 /// to the underlying storage holding nodes.
 pub trait TreeWriter {
-    /// Writes a node batch into storage.
+    /// Writes a node batch into storage, together with the root hashes it records (see
+    /// [`NodeBatch::root_hashes`]) if the storage keeps an index of root hashes.
     fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()>;
 }
 
+impl<W: TreeWriter + ?Sized> TreeWriter for &W {
+    fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        (**self).write_node_batch(node_batch)
+    }
+}
+
+/// The asynchronous counterpart of [`TreeWriter`], for storage that can only be written
+/// asynchronously.
+///
+/// Every [`TreeWriter`] which is `Sync` is also an `AsyncTreeWriter`.
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+pub trait AsyncTreeWriter: Sync {
+    /// Writes a node batch into storage.
+    async fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()>;
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl<W: TreeWriter + Sync> AsyncTreeWriter for W {
+    async fn write_node_batch(&self, node_batch: &NodeBatch) -> Result<()> {
+        TreeWriter::write_node_batch(self, node_batch)
+    }
+}
+
 /// Node batch that will be written into db atomically with other batches.
+///
+/// Values are held as [`Bytes`], so that storage layers keeping them in memory, such as caches
+/// or write buffers, share them with the batch rather than copying them.
 #[derive(Debug, Clone, PartialEq, Default, Eq)]
 pub struct NodeBatch {
     nodes: BTreeMap<NodeKey, Node>,
-    values: BTreeMap<(Version, KeyHash), Option<OwnedValue>>,
+    values: BTreeMap<(Version, KeyHash), Option<Bytes>>,
+    root_hashes: BTreeMap<Version, RootHash>,
 }
 
 impl NodeBatch {
     /// Reset a NodeBatch to its empty state.
     pub fn clear(&mut self) {
         self.nodes.clear();
-        self.values.clear()
+        self.values.clear();
+        self.root_hashes.clear()
     }
 
     /// Get a node by key.
@@ -47,21 +79,71 @@ impl NodeBatch {
         self.nodes.insert(node_key, node)
     }
 
-    /// Insert a node into the batch.
-    pub fn insert_value(&mut self, version: Version, key_hash: KeyHash, value: OwnedValue) {
-        self.values.insert((version, key_hash), Some(value));
+    /// Insert a value into the batch.
+    pub fn insert_value(&mut self, version: Version, key_hash: KeyHash, value: impl Into<Bytes>) {
+        self.values.insert((version, key_hash), Some(value.into()));
     }
 
-    /// Returns a reference to the current set of nodes.
-    pub fn values(&self) -> &BTreeMap<(Version, KeyHash), std::option::Option<Vec<u8>>> {
+    /// Returns a reference to the current set of values, keyed by version and key hash. `None`
+    /// marks a key deleted at that version.
+    ///
+    /// Values are kept apart from the nodes, which only commit to their hashes, so that storage
+    /// can write them to a separate value store.
+    pub fn values(&self) -> &BTreeMap<(Version, KeyHash), Option<Bytes>> {
         &self.values
     }
 
+    /// Returns the nodes of the batch in the order of their keys, with the metadata needed to
+    /// index them without decoding them: their path, hash, kind and children.
+    pub fn entries<H: SimpleHasher>(&self) -> impl Iterator<Item = NodeBatchEntry<'_>> {
+        self.nodes.iter().map(|(node_key, node)| {
+            let (kind, children) = match node {
+                Node::Null => (NodeKind::Null, vec![]),
+                Node::Internal(internal) => (
+                    NodeKind::Internal,
+                    internal
+                        .children_sorted()
+                        .map(|(nibble, child)| node_key.gen_child_node_key(child.version, nibble))
+                        .collect(),
+                ),
+                Node::Leaf(leaf) => (
+                    NodeKind::Leaf {
+                        key_hash: leaf.key_hash(),
+                        value_hash: leaf.value_hash(),
+                    },
+                    vec![],
+                ),
+            };
+            NodeBatchEntry {
+                node_key,
+                node,
+                nibble_path: node_key.nibble_path(),
+                hash: node.hash::<H>(),
+                kind,
+                children,
+            }
+        })
+    }
+
+    /// Returns the root hash of each version committed by the batch.
+    ///
+    /// Storage keeping an index of root hashes should record them when writing the batch, so that
+    /// [`TreeReader::get_indexed_root_hash`](crate::storage::TreeReader::get_indexed_root_hash)
+    /// serves them without reading the root nodes.
+    pub fn root_hashes(&self) -> &BTreeMap<Version, RootHash> {
+        &self.root_hashes
+    }
+
+    /// Records the root hash of a version committed by the batch.
+    pub fn insert_root_hash(&mut self, version: Version, root_hash: RootHash) -> Option<RootHash> {
+        self.root_hashes.insert(version, root_hash)
+    }
+
     /// Extend a node batch.
     pub fn extend(
         &mut self,
         nodes: impl IntoIterator<Item = (NodeKey, Node)>,
-        values: impl IntoIterator<Item = ((Version, KeyHash), Option<OwnedValue>)>,
+        values: impl IntoIterator<Item = ((Version, KeyHash), Option<Bytes>)>,
     ) {
         self.nodes.extend(nodes);
         self.values.extend(values);
@@ -69,14 +151,47 @@ impl NodeBatch {
 
     /// Merge two NodeBatches into a single one.
     pub fn merge(&mut self, rhs: Self) {
-        self.extend(rhs.nodes, rhs.values)
+        self.extend(rhs.nodes, rhs.values);
+        self.root_hashes.extend(rhs.root_hashes);
     }
 
     /// Check if the node batch contains any items.
     pub fn is_empty(&self) -> bool {
-        self.nodes.is_empty() && self.values.is_empty()
+        self.nodes.is_empty() && self.values.is_empty() && self.root_hashes.is_empty()
     }
 }
+/// The kind of a node, see [`NodeBatchEntry::kind`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NodeKind {
+    /// The root of an empty tree.
+    Null,
+    /// An internal node.
+    Internal,
+    /// A leaf, holding the hash of a value.
+    Leaf {
+        key_hash: KeyHash,
+        value_hash: ValueHash,
+    },
+}
+
+/// A node of a [`NodeBatch`], with its metadata, as returned by [`NodeBatch::entries`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NodeBatchEntry<'a> {
+    /// The key of the node.
+    pub node_key: &'a NodeKey,
+    /// The node.
+    pub node: &'a Node,
+    /// The path of nibbles from the root to the node.
+    pub nibble_path: &'a NibblePath,
+    /// The hash of the node.
+    pub hash: [u8; 32],
+    /// The kind of the node.
+    pub kind: NodeKind,
+    /// The keys of the children of the node, in nibble order, which are either in the same batch
+    /// or were written by an earlier one. Empty unless the node is internal.
+    pub children: Vec<NodeKey>,
+}
+
 /// [`StaleNodeIndex`](struct.StaleNodeIndex.html) batch that will be written into db atomically
 /// with other batches.
 pub type StaleNodeIndexBatch = BTreeSet<StaleNodeIndex>;
@@ -98,6 +213,39 @@ pub struct StaleNodeIndex {
     /// The [`NodeKey`](node_type/struct.NodeKey.html) identifying the node associated with this
     /// record.
     pub node_key: NodeKey,
+    /// The kind of the node, so that data kept alongside stale leaves, such as the preimages of
+    /// their keys, can be cleaned up when pruning without reading the node.
+    pub node_kind: StaleNodeKind,
+}
+
+/// The kind of a stale node, see [`StaleNodeIndex::node_kind`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(any(test, feature = "fuzzing"), derive(Arbitrary))]
+pub enum StaleNodeKind {
+    /// An internal node, or the null root of an empty tree.
+    Internal,
+    /// A leaf.
+    Leaf {
+        /// The key hash of the leaf.
+        key_hash: KeyHash,
+    },
+}
+
+impl StaleNodeKind {
+    /// Returns the kind of `node`.
+    pub(crate) fn of(node: &Node) -> Self {
+        match node {
+            Node::Leaf(leaf_node) => StaleNodeKind::Leaf {
+                key_hash: leaf_node.key_hash(),
+            },
+            Node::Internal(_) | Node::Null => StaleNodeKind::Internal,
+        }
+    }
+
+    /// Returns true if the stale node is a leaf.
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, StaleNodeKind::Leaf { .. })
+    }
 }
 
 /// This is a wrapper of [`NodeBatch`](type.NodeBatch.html),
@@ -110,3 +258,11 @@ pub struct TreeUpdateBatch {
     pub stale_node_index_batch: StaleNodeIndexBatch,
     pub node_stats: Vec<NodeStats>,
 }
+
+impl TreeUpdateBatch {
+    /// Returns the root hash of each version committed by the batch, which are held by its
+    /// [`NodeBatch`] so that [`TreeWriter`] implementations can persist them.
+    pub fn root_hashes(&self) -> &BTreeMap<Version, RootHash> {
+        self.node_batch.root_hashes()
+    }
+}